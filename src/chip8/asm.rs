@@ -0,0 +1,504 @@
+use super::{MAX_ROM_SIZE, PROGRAM_START};
+use super::disasm;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// What is wrong with a line of source.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AsmProblem {
+    // A mnemonic or directive that does not exist. Holds it.
+    UnknownInstruction(String),
+    // Operands the instruction does not take. Holds the instruction.
+    InvalidOperands(String),
+    // An operand that is neither a number, a register nor a label. Holds
+    // the operand.
+    InvalidOperand(String),
+    // A number that does not fit where it is used. Holds the number and
+    // the largest that fits.
+    OutOfRange(u32, u32),
+    // A label that is used but never defined.
+    UnknownLabel(String),
+    // A label that is defined twice.
+    DuplicateLabel(String),
+    // A label that is not a name of letters, digits and underscores, or
+    // that is named like a register.
+    InvalidLabel(String),
+    // The program does not fit into memory.
+    TooLarge,
+}
+
+impl fmt::Display for AsmProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AsmProblem::UnknownInstruction(ref mnemonic) =>
+                write!(f, "unknown instruction {}", mnemonic),
+            AsmProblem::InvalidOperands(ref mnemonic) =>
+                write!(f, "invalid operands for {}", mnemonic),
+            AsmProblem::InvalidOperand(ref operand) =>
+                write!(f, "invalid operand {:?}", operand),
+            AsmProblem::OutOfRange(value, max) =>
+                write!(f, "{:#x} does not fit, the largest is {:#x}",
+                       value, max),
+            AsmProblem::UnknownLabel(ref label) =>
+                write!(f, "unknown label {}", label),
+            AsmProblem::DuplicateLabel(ref label) =>
+                write!(f, "label {} is already defined", label),
+            AsmProblem::InvalidLabel(ref label) =>
+                write!(f, "invalid label {:?}", label),
+            AsmProblem::TooLarge =>
+                write!(f, "program is larger than {} bytes", MAX_ROM_SIZE),
+        }
+    }
+}
+
+// Why source can not be assembled, and where: the line and column, both
+// counted from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub problem: AsmProblem,
+}
+
+impl AsmError {
+
+    fn new(line: usize, column: usize, problem: AsmProblem) -> Self {
+        AsmError { line: line, column: column, problem: problem }
+    }
+
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.problem)
+    }
+}
+
+impl Error for AsmError {
+    fn description(&self) -> &str {
+        "invalid assembly source"
+    }
+}
+
+// An assembled program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    // Each line of source that defines a label or assembles to bytes,
+    // after its address and bytes, as in the listings of disasm.
+    pub listing: String,
+}
+
+// Assemble source written with the mnemonics that disasm prints, into a
+// rom loaded at 0x200:
+//
+//     ; Draw a 0 in the top left corner.
+//             LD I, zero      ; a label defined further down
+//             LD V0, 0
+//             DRW V0, V0, 5
+//     stop:   JP stop
+//     zero:   .byte 0xf0, 0x90, 0x90, 0x90, 0xf0
+//
+// Mnemonics and registers are not case sensitive, labels are. Numbers are
+// decimal, or hex and binary after 0x and 0b. Besides instructions, lines
+// may hold data: `.byte` (or DB) lists bytes and `.word` (or DW) lists
+// words, high byte first. Anything after a `;` is a comment.
+pub fn assemble(source: &str) -> Result<Assembly, AsmError> {
+    // Find the statements and where each label points first, so that
+    // labels can be used before they are defined.
+    let mut statements = Vec::new();
+    let mut labels = HashMap::new();
+    let mut listed = Vec::new();
+    let mut addr = PROGRAM_START;
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let (defined, statement) = parse_line(line, text)?;
+        for &(column, label) in &defined {
+            if !is_label(label) {
+                return Err(AsmError::new(line, column,
+                    AsmProblem::InvalidLabel(label.to_string())));
+            }
+            if labels.insert(label, addr as u16).is_some() {
+                return Err(AsmError::new(line, column,
+                    AsmProblem::DuplicateLabel(label.to_string())));
+            }
+        }
+        match statement {
+            Some(statement) => {
+                listed.push((addr, text, Some(statements.len())));
+                addr += statement.size();
+                if addr > PROGRAM_START + MAX_ROM_SIZE {
+                    return Err(AsmError::new(line, statement.column,
+                                             AsmProblem::TooLarge));
+                }
+                statements.push(statement);
+            },
+            None if !defined.is_empty() => listed.push((addr, text, None)),
+            None => {},
+        }
+    }
+    let mut rom = Vec::new();
+    let mut ranges = Vec::new();
+    for statement in &statements {
+        let start = rom.len();
+        statement.assemble(&labels, &mut rom)?;
+        ranges.push(start..rom.len());
+    }
+    let mut listing = String::new();
+    for (addr, text, statement) in listed {
+        let bytes = match statement {
+            Some(i) => &rom[ranges[i].clone()],
+            None => &[],
+        };
+        listing.push_str(&disasm::listing_line(addr as u16, bytes,
+                                               text.trim_right()));
+    }
+    Ok(Assembly { rom: rom, listing: listing })
+}
+
+// The mnemonics of the instructions, in upper case.
+const MNEMONICS: [&str; 26] = [
+    "CLS", "RET", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP",
+    "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR",
+    "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP",
+];
+
+// Operands that name something other than a V register, in upper case.
+const NAMES: [&str; 9] = ["I", "[I]", "DT", "ST", "K", "F", "HF", "B", "R"];
+
+// An instruction or data directive, with its operands as written.
+struct Statement<'a> {
+    line: usize,
+    column: usize,
+    mnemonic: String,
+    operands: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Statement<'a> {
+
+    // The number of bytes it assembles to.
+    fn size(&self) -> usize {
+        match &self.mnemonic[..] {
+            ".BYTE" | "DB" => self.operands.len(),
+            ".WORD" | "DW" => 2 * self.operands.len(),
+            _ => 2,
+        }
+    }
+
+    fn assemble(&self, labels: &HashMap<&str, u16>, rom: &mut Vec<u8>)
+                -> Result<(), AsmError> {
+        let mut args = Vec::new();
+        for &(column, operand) in &self.operands {
+            args.push(self.arg(labels, column, operand)?);
+        }
+        match &self.mnemonic[..] {
+            ".BYTE" | "DB" => for &arg in &args {
+                rom.push(self.fit(arg, 0xff)? as u8);
+            },
+            ".WORD" | "DW" => for &arg in &args {
+                let word = self.fit(arg, 0xffff)?;
+                rom.extend(&[(word >> 8) as u8, word as u8]);
+            },
+            _ => {
+                let opcode = self.encode(&args)?;
+                rom.extend(&[(opcode >> 8) as u8, opcode as u8]);
+            },
+        }
+        Ok(())
+    }
+
+    fn encode(&self, args: &[Arg]) -> Result<u16, AsmError> {
+        use self::Arg::{Name, V};
+        let arg = |i: usize| args.get(i).cloned();
+        let opcode = match (&self.mnemonic[..], args.len(), arg(0), arg(1),
+                            arg(2)) {
+            ("CLS", 0, ..) => 0x00e0,
+            ("RET", 0, ..) => 0x00ee,
+            ("SCD", 1, Some(n), ..) => 0x00c0 | self.fit(n, 0xf)?,
+            ("SCR", 0, ..) => 0x00fb,
+            ("SCL", 0, ..) => 0x00fc,
+            ("EXIT", 0, ..) => 0x00fd,
+            ("LOW", 0, ..) => 0x00fe,
+            ("HIGH", 0, ..) => 0x00ff,
+            ("SYS", 1, Some(nnn), ..) => self.fit(nnn, 0xfff)?,
+            ("JP", 1, Some(nnn), ..) => 0x1000 | self.fit(nnn, 0xfff)?,
+            ("JP", 2, Some(V(0)), Some(nnn), _) =>
+                0xb000 | self.fit(nnn, 0xfff)?,
+            ("CALL", 1, Some(nnn), ..) => 0x2000 | self.fit(nnn, 0xfff)?,
+            ("SE", 2, Some(V(x)), Some(V(y)), _) => xy(0x5000, x, y),
+            ("SE", 2, Some(V(x)), Some(nn), _) =>
+                xy(0x3000, x, 0) | self.fit(nn, 0xff)?,
+            ("SNE", 2, Some(V(x)), Some(V(y)), _) => xy(0x9000, x, y),
+            ("SNE", 2, Some(V(x)), Some(nn), _) =>
+                xy(0x4000, x, 0) | self.fit(nn, 0xff)?,
+            ("LD", 2, Some(V(x)), Some(V(y)), _) => xy(0x8000, x, y),
+            ("LD", 2, Some(V(x)), Some(Name(name)), _) => match name {
+                "DT" => xy(0xf007, x, 0),
+                "K" => xy(0xf00a, x, 0),
+                "[I]" => xy(0xf065, x, 0),
+                "R" => xy(0xf085, x, 0),
+                _ => return Err(self.invalid_operands()),
+            },
+            ("LD", 2, Some(V(x)), Some(nn), _) =>
+                xy(0x6000, x, 0) | self.fit(nn, 0xff)?,
+            ("LD", 2, Some(Name("I")), Some(nnn), _) =>
+                0xa000 | self.fit(nnn, 0xfff)?,
+            ("LD", 2, Some(Name(name)), Some(V(x)), _) => match name {
+                "DT" => xy(0xf015, x, 0),
+                "ST" => xy(0xf018, x, 0),
+                "F" => xy(0xf029, x, 0),
+                "HF" => xy(0xf030, x, 0),
+                "B" => xy(0xf033, x, 0),
+                "[I]" => xy(0xf055, x, 0),
+                "R" => xy(0xf075, x, 0),
+                _ => return Err(self.invalid_operands()),
+            },
+            ("ADD", 2, Some(V(x)), Some(V(y)), _) => xy(0x8004, x, y),
+            ("ADD", 2, Some(V(x)), Some(nn), _) =>
+                xy(0x7000, x, 0) | self.fit(nn, 0xff)?,
+            ("ADD", 2, Some(Name("I")), Some(V(x)), _) => xy(0xf01e, x, 0),
+            ("OR", 2, Some(V(x)), Some(V(y)), _) => xy(0x8001, x, y),
+            ("AND", 2, Some(V(x)), Some(V(y)), _) => xy(0x8002, x, y),
+            ("XOR", 2, Some(V(x)), Some(V(y)), _) => xy(0x8003, x, y),
+            ("SUB", 2, Some(V(x)), Some(V(y)), _) => xy(0x8005, x, y),
+            ("SHR", 2, Some(V(x)), Some(V(y)), _) => xy(0x8006, x, y),
+            ("SUBN", 2, Some(V(x)), Some(V(y)), _) => xy(0x8007, x, y),
+            ("SHL", 2, Some(V(x)), Some(V(y)), _) => xy(0x800e, x, y),
+            // Shifting a register on its own uses it as vy as well, so
+            // that it shifts the same with or without the shift quirk.
+            ("SHR", 1, Some(V(x)), ..) => xy(0x8006, x, x),
+            ("SHL", 1, Some(V(x)), ..) => xy(0x800e, x, x),
+            ("RND", 2, Some(V(x)), Some(nn), _) =>
+                xy(0xc000, x, 0) | self.fit(nn, 0xff)?,
+            ("DRW", 3, Some(V(x)), Some(V(y)), Some(n)) =>
+                xy(0xd000, x, y) | self.fit(n, 0xf)?,
+            ("SKP", 1, Some(V(x)), ..) => xy(0xe09e, x, 0),
+            ("SKNP", 1, Some(V(x)), ..) => xy(0xe0a1, x, 0),
+            (mnemonic, ..) if MNEMONICS.contains(&mnemonic) =>
+                return Err(self.invalid_operands()),
+            (mnemonic, ..) => return Err(AsmError::new(
+                self.line, self.column,
+                AsmProblem::UnknownInstruction(mnemonic.to_string()))),
+        };
+        Ok(opcode)
+    }
+
+    // What the operand at the column is: a register, a name such as DT,
+    // a number or the address of a label.
+    fn arg(&self, labels: &HashMap<&str, u16>, column: usize, operand: &str)
+           -> Result<Arg, AsmError> {
+        let upper = operand.to_uppercase();
+        if upper.len() == 2 && upper.starts_with('V') {
+            if let Ok(x) = u8::from_str_radix(&upper[1..], 16) {
+                return Ok(Arg::V(x));
+            }
+        }
+        if let Some(&name) = NAMES.iter().find(|&&name| name == upper) {
+            return Ok(Arg::Name(name));
+        }
+        let error = |problem| Err(AsmError::new(self.line, column, problem));
+        if operand.starts_with(|c: char| c.is_digit(10)) {
+            match number(operand) {
+                Some(value) => Ok(Arg::Value(value, column)),
+                None => error(AsmProblem::InvalidOperand(operand.to_string())),
+            }
+        } else if is_label(operand) {
+            match labels.get(operand) {
+                Some(&addr) => Ok(Arg::Value(addr as u32, column)),
+                None => error(AsmProblem::UnknownLabel(operand.to_string())),
+            }
+        } else {
+            error(AsmProblem::InvalidOperand(operand.to_string()))
+        }
+    }
+
+    // The value of the operand, if it is a number no larger than max.
+    fn fit(&self, arg: Arg, max: u32) -> Result<u16, AsmError> {
+        match arg {
+            Arg::Value(value, column) if value > max => Err(AsmError::new(
+                self.line, column, AsmProblem::OutOfRange(value, max))),
+            Arg::Value(value, _) => Ok(value as u16),
+            _ => Err(self.invalid_operands()),
+        }
+    }
+
+    fn invalid_operands(&self) -> AsmError {
+        AsmError::new(self.line, self.column,
+                      AsmProblem::InvalidOperands(self.mnemonic.clone()))
+    }
+
+}
+
+// An operand, once its labels are known.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Arg {
+    // One of the registers V0 to VF.
+    V(u8),
+    // One of the NAMES.
+    Name(&'static str),
+    // A number, or the address of a label, and the column it is at.
+    Value(u32, usize),
+}
+
+// The opcode with x and y in its second and third nibbles.
+fn xy(opcode: u16, x: u8, y: u8) -> u16 {
+    opcode | (x as u16) << 8 | (y as u16) << 4
+}
+
+// Split a line into the labels it defines and the statement it holds,
+// each along with the column it starts at.
+fn parse_line<'a>(line: usize, text: &'a str)
+    -> Result<(Vec<(usize, &'a str)>, Option<Statement<'a>>), AsmError> {
+    let code = text.split(';').next().unwrap();
+    let mut labels = Vec::new();
+    let mut start = 0;
+    loop {
+        start += code[start..].len() - code[start..].trim_left().len();
+        let rest = &code[start..];
+        let word_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        match rest[..word_end].find(':') {
+            Some(colon) => {
+                labels.push((start + 1, &rest[..colon]));
+                start += colon + 1;
+            },
+            None if word_end == 0 => return Ok((labels, None)),
+            None => {
+                let operands = split_operands(&rest[word_end..],
+                                              start + word_end);
+                let statement = Statement {
+                    line: line,
+                    column: start + 1,
+                    mnemonic: rest[..word_end].to_uppercase(),
+                    operands: operands,
+                };
+                return Ok((labels, Some(statement)));
+            },
+        }
+    }
+}
+
+// The text split at commas, each operand trimmed and along with its
+// column, given the offset of the text into its line.
+fn split_operands(text: &str, offset: usize) -> Vec<(usize, &str)> {
+    let mut operands = Vec::new();
+    if text.trim().is_empty() {
+        return operands;
+    }
+    let mut start = offset;
+    for operand in text.split(',') {
+        let trimmed = operand.trim_left();
+        operands.push((start + operand.len() - trimmed.len() + 1,
+                       trimmed.trim_right()));
+        start += operand.len() + 1;
+    }
+    operands
+}
+
+// Whether the text can name a label: a name of letters, digits and
+// underscores that does not start with a digit, and is not a register.
+fn is_label(text: &str) -> bool {
+    let upper = text.to_uppercase();
+    let register = upper.len() == 2 && upper.starts_with('V') &&
+                   u8::from_str_radix(&upper[1..], 16).is_ok();
+    !text.is_empty() && !register && !NAMES.contains(&&upper[..]) &&
+    !text.starts_with(|c: char| c.is_digit(10)) &&
+    text.chars().all(|c| c.is_digit(36) || c == '_')
+}
+
+// A number in decimal, or in hex or binary after 0x or 0b.
+fn number(text: &str) -> Option<u32> {
+    let lower = text.to_lowercase();
+    if lower.starts_with("0x") {
+        u32::from_str_radix(&lower[2..], 16).ok()
+    } else if lower.starts_with("0b") {
+        u32::from_str_radix(&lower[2..], 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{assemble, AsmError, AsmProblem};
+    use super::super::disasm::{disassemble, is_instruction};
+
+    fn rom(source: &str) -> Vec<u8> {
+        assemble(source).unwrap().rom
+    }
+
+    fn error(source: &str) -> AsmError {
+        assemble(source).unwrap_err()
+    }
+
+    #[test]
+    fn test_assemble_labels_and_data() {
+        //when
+        let assembly = assemble("; A comment on its own\n\
+                                 \n\
+                                 start:  ld i, sprite  ; forward\n\
+                                 loop:\n\
+                                 \x20       JP loop\n\
+                                 sprite: .byte 0xf0, 0b1001, 144\n\
+                                 \x20       DW 0x1234, start\n").unwrap();
+        //then
+        assert_eq!(vec![0xa2, 0x04, 0x12, 0x02, 0xf0, 0x09, 0x90,
+                        0x12, 0x34, 0x02, 0x00],
+                   assembly.rom);
+        assert_eq!("200  a2 04                   \
+                    start:  ld i, sprite  ; forward\n\
+                    202                          loop:\n\
+                    202  12 02                           JP loop\n\
+                    204  f0 09 90                \
+                    sprite: .byte 0xf0, 0b1001, 144\n\
+                    207  12 34 02 00                     DW 0x1234, start\n",
+                   assembly.listing);
+    }
+
+    #[test]
+    fn test_assemble_what_disasm_prints() {
+        for opcode in (0..0x10000).map(|opcode| opcode as u16)
+                                  .filter(|&opcode| is_instruction(opcode)) {
+            let source = disassemble(opcode);
+            assert_eq!(vec![(opcode >> 8) as u8, opcode as u8], rom(&source),
+                       "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_assemble_shorthands() {
+        assert_eq!(vec![0x83, 0x36, 0x8a, 0xae], rom("SHR V3\nSHL VA"));
+        assert_eq!(vec![0xd1, 0x2f], rom("drw v1, v2, 0xF"));
+        assert!(rom("").is_empty());
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(AsmError::new(2, 3, AsmProblem::UnknownInstruction(
+                       "MOV".to_string())),
+                   error("CLS\n  mov V0, 1\n"));
+        assert_eq!(AsmError::new(1, 1, AsmProblem::InvalidOperands(
+                       "DRW".to_string())),
+                   error("DRW V0, 5"));
+        assert_eq!(AsmError::new(1, 9, AsmProblem::OutOfRange(0x100, 0xff)),
+                   error("LD V0,  256"));
+        assert_eq!(AsmError::new(1, 4, AsmProblem::UnknownLabel(
+                       "nowhere".to_string())),
+                   error("JP nowhere"));
+        assert_eq!(AsmError::new(1, 8, AsmProblem::InvalidOperand(
+                       "0xg".to_string())),
+                   error("LD V0, 0xg"));
+        assert_eq!(AsmError::new(2, 1, AsmProblem::DuplicateLabel(
+                       "a".to_string())),
+                   error("a: CLS\na: CLS"));
+        assert_eq!(AsmError::new(1, 2, AsmProblem::InvalidLabel(
+                       "VF".to_string())),
+                   error(" VF: CLS"));
+        assert_eq!(AsmError::new(1, 1, AsmProblem::TooLarge),
+                   error(&format!(".byte {}0", "0, ".repeat(4000))));
+        assert_eq!("2:3: unknown instruction MOV",
+                   error("CLS\n  mov V0, 1\n").to_string());
+    }
+
+}
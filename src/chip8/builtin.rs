@@ -0,0 +1,168 @@
+use super::emu::Emu;
+
+// A rom built into the emulator, so that there is always something to run.
+pub struct BuiltinRom {
+    pub name: &'static str,
+    pub rom: &'static [u8],
+}
+
+// Brix, from the roms folder.
+pub const BRIX: &'static [u8] = include_bytes!("../../roms/brix.ch8");
+
+// Shows the hexadecimal digit of the key held down, for checking the
+// keyboard mapping.
+pub const KEYPAD_TEST: &'static [u8] = &[
+    0x00, 0xe0, // 200 CLS
+    0xf0, 0x0a, // 202 wait: LD V0, K
+    0x00, 0xe0, // 204 CLS
+    0xf0, 0x29, // 206 LD F, V0
+    0x61, 0x1e, // 208 LD V1, 30
+    0x62, 0x0d, // 20a LD V2, 13
+    0xd1, 0x25, // 20c DRW V1, V2, 5
+    0xe0, 0xa1, // 20e held: SKNP V0
+    0x12, 0x0e, // 210 JP held
+    0x12, 0x02, // 212 JP wait
+];
+
+// The roms offered by the menu, in the order of their keys.
+pub const BUILTIN_ROMS: [BuiltinRom; 2] = [
+    BuiltinRom { name: "Brix", rom: BRIX },
+    BuiltinRom { name: "Keypad test", rom: KEYPAD_TEST },
+];
+
+// Lists the built in roms as "1 BRIX" and "2 KEYS", and waits for key 1 or
+// 2 to be pressed. It then loops at MENU_CHOSEN_ADDR with the key in v0,
+// which is the cue for the frontend to start the chosen rom.
+pub const MENU: &'static [u8] = &[
+    0x00, 0xe0, // 200 CLS
+    0x61, 0x0c, // 202 LD V1, 12
+    0x62, 0x08, // 204 LD V2, 8
+    0xa0, 0x05, // 206 LD I, font 1
+    0xd1, 0x25, // 208 DRW V1, V2, 5
+    0x71, 0x05, // 20a ADD V1, 5
+    0x71, 0x05, // 20c ADD V1, 5
+    0xa0, 0x37, // 20e LD I, font B
+    0xd1, 0x25, // 210 DRW V1, V2, 5
+    0x71, 0x05, // 212 ADD V1, 5
+    0xa2, 0x58, // 214 LD I, R
+    0xd1, 0x25, // 216 DRW V1, V2, 5
+    0x71, 0x05, // 218 ADD V1, 5
+    0xa2, 0x5d, // 21a LD I, I
+    0xd1, 0x25, // 21c DRW V1, V2, 5
+    0x71, 0x05, // 21e ADD V1, 5
+    0xa2, 0x62, // 220 LD I, X
+    0xd1, 0x25, // 222 DRW V1, V2, 5
+    0x71, 0x05, // 224 ADD V1, 5
+    0x61, 0x0c, // 226 LD V1, 12
+    0x62, 0x12, // 228 LD V2, 18
+    0xa0, 0x0a, // 22a LD I, font 2
+    0xd1, 0x25, // 22c DRW V1, V2, 5
+    0x71, 0x05, // 22e ADD V1, 5
+    0x71, 0x05, // 230 ADD V1, 5
+    0xa2, 0x67, // 232 LD I, K
+    0xd1, 0x25, // 234 DRW V1, V2, 5
+    0x71, 0x05, // 236 ADD V1, 5
+    0xa0, 0x46, // 238 LD I, font E
+    0xd1, 0x25, // 23a DRW V1, V2, 5
+    0x71, 0x05, // 23c ADD V1, 5
+    0xa2, 0x6c, // 23e LD I, Y
+    0xd1, 0x25, // 240 DRW V1, V2, 5
+    0x71, 0x05, // 242 ADD V1, 5
+    0xa2, 0x71, // 244 LD I, S
+    0xd1, 0x25, // 246 DRW V1, V2, 5
+    0x71, 0x05, // 248 ADD V1, 5
+    0xf0, 0x0a, // 24a wait: LD V0, K
+    0x40, 0x01, // 24c SNE V0, 1
+    0x12, 0x56, // 24e JP chosen
+    0x40, 0x02, // 250 SNE V0, 2
+    0x12, 0x56, // 252 JP chosen
+    0x12, 0x4a, // 254 JP wait
+    0x12, 0x56, // 256 chosen: JP chosen
+    0xe0, 0x90, 0xe0, 0xa0, 0x90, // 258 R
+    0xe0, 0x40, 0x40, 0x40, 0xe0, // 25d I
+    0x90, 0x90, 0x60, 0x90, 0x90, // 262 X
+    0x90, 0xa0, 0xc0, 0xa0, 0x90, // 267 K
+    0xa0, 0xa0, 0x40, 0x40, 0x40, // 26c Y
+    0x70, 0x80, 0x60, 0x10, 0xe0, // 271 S
+];
+
+pub const MENU_CHOSEN_ADDR: u16 = 0x256;
+
+// The built in rom picked from the menu, once the menu is done.
+pub fn menu_choice(emu: &Emu) -> Option<&'static BuiltinRom> {
+    if emu.rom() != MENU || emu.pc() != MENU_CHOSEN_ADDR {
+        return None;
+    }
+    (emu.register(0) as usize).checked_sub(1)
+        .and_then(|i| BUILTIN_ROMS.get(i))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{menu_choice, BRIX, KEYPAD_TEST, MENU};
+    use super::super::display::StringDisplay;
+    use super::super::emu::Emu;
+
+    fn boot(rom: &[u8]) -> Emu {
+        let mut emu = Emu::new();
+        emu.load_rom(rom.to_vec());
+        emu
+    }
+
+    #[test]
+    fn test_menu_lists_roms_and_waits() {
+        let mut emu = boot(MENU);
+        //when
+        emu.run_frame(100);
+        //then
+        let text = StringDisplay::new(&emu).to_string();
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!("..#.......###..###..###..#..#", &rows[8][12..41]);
+        assert_eq!("####......#..#.####.#.#...###", &rows[18][12..41]);
+        assert!(menu_choice(&emu).is_none());
+    }
+
+    #[test]
+    fn test_menu_choice() {
+        for &(key, name) in &[(1, "Brix"), (2, "Keypad test")] {
+            let mut emu = boot(MENU);
+            emu.run_frame(100);
+            //when
+            emu.keys[key] = true;
+            emu.run_frame(10);
+            //then
+            assert_eq!(Some(name), menu_choice(&emu).map(|rom| rom.name));
+        }
+        // Other keys are ignored.
+        let mut emu = boot(MENU);
+        emu.keys[3] = true;
+        emu.run_frame(100);
+        assert!(menu_choice(&emu).is_none());
+    }
+
+    #[test]
+    fn test_keypad_test_shows_key() {
+        let mut emu = boot(KEYPAD_TEST);
+        //when
+        emu.keys[0xa] = true;
+        emu.run_frame(20);
+        //then
+        let text = StringDisplay::new(&emu).to_string();
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!("####", &rows[13][30..34]);
+        assert_eq!("#..#", &rows[17][30..34]);
+        assert_eq!(14, text.matches('#').count());
+    }
+
+    #[test]
+    fn test_brix_runs() {
+        let mut emu = boot(BRIX);
+        //when
+        emu.run_frame(1000);
+        //then
+        assert!(emu.last_error().is_none());
+        assert!(StringDisplay::new(&emu).to_string().contains('#'));
+    }
+
+}
@@ -0,0 +1,370 @@
+use super::PROGRAM_START;
+use super::asm::{self, AsmError};
+use super::disasm;
+use super::info::RomInfo;
+use super::log::{Level, Log};
+use super::options::OptionsError;
+use super::rom::{self, RomError};
+use super::romdb::RomDb;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// Reasons a subcommand fails.
+#[derive(Debug)]
+pub enum CliError {
+    // The arguments are not understood.
+    Usage(OptionsError),
+    // The rom can not be read.
+    Rom(RomError),
+    // The source file can not be read. Holds its path.
+    Read(String, io::Error),
+    // The source can not be assembled. Holds the path of the source.
+    Asm(String, AsmError),
+    // The file to write already exists, and may not be overwritten.
+    Exists(String),
+    // The file can not be written. Holds its path.
+    Write(String, io::Error),
+    // The output can not be written.
+    Io(io::Error),
+}
+
+impl CliError {
+
+    // The status to exit with: 2 for arguments that are not understood,
+    // as for the emulator's options, and 1 otherwise.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            CliError::Usage(_) => 2,
+            _ => 1,
+        }
+    }
+
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CliError::Usage(ref err) => write!(f, "{}", err),
+            CliError::Rom(ref err) => write!(f, "{}", err),
+            CliError::Read(ref path, ref err) =>
+                write!(f, "Can not read {}: {}", path, err),
+            CliError::Asm(ref path, ref err) => write!(f, "{}:{}", path, err),
+            CliError::Exists(ref path) =>
+                write!(f, "{} already exists, use --force to overwrite it",
+                       path),
+            CliError::Write(ref path, ref err) =>
+                write!(f, "Can not write {}: {}", path, err),
+            CliError::Io(ref err) => write!(f, "Can not write output: {}", err),
+        }
+    }
+}
+
+// Run the subcommand named by the first argument, such as asm, writing
+// what it prints to out. Returns None if the arguments do not start with
+// a subcommand, leaving them to the emulator.
+pub fn run<W: Write>(args: &[String], out: &mut W)
+                     -> Option<Result<(), CliError>> {
+    let (command, args) = match args.split_first() {
+        Some(split) => split,
+        None => return None,
+    };
+    match &command[..] {
+        "asm" => Some(asm(args, out)),
+        "disasm" => Some(disasm(args, out)),
+        "info" => Some(info(args, out)),
+        _ => None,
+    }
+}
+
+// Assemble the source into a rom, which is not written over an existing
+// file unless forced to. With --listing the addresses and bytes of each
+// line are printed too:
+//
+//     chip8 asm SOURCE -o PATH_TO_ROM [--force] [--listing]
+pub fn asm<W: Write>(args: &[String], out: &mut W) -> Result<(), CliError> {
+    let mut source = None;
+    let mut path = None;
+    let mut force = false;
+    let mut listing = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "-o" => path = Some(value(arg, args.next())?),
+            "--force" => force = true,
+            "--listing" => listing = true,
+            _ if source.is_none() && !arg.starts_with('-') =>
+                source = Some(arg),
+            _ => return Err(usage(OptionsError::Unexpected(arg.clone()))),
+        }
+    }
+    let source = source.ok_or_else(|| {
+        usage(OptionsError::Requires("asm", "a source file"))
+    })?;
+    let path = path.ok_or_else(|| usage(OptionsError::Requires("asm", "-o")))?;
+    let mut text = String::new();
+    File::open(source)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|err| CliError::Read(source.clone(), err))?;
+    let assembly = asm::assemble(&text)
+        .map_err(|err| CliError::Asm(source.clone(), err))?;
+    let mut options = OpenOptions::new();
+    if force {
+        options.write(true).create(true).truncate(true);
+    } else {
+        options.write(true).create_new(true);
+    }
+    options.open(path)
+        .and_then(|mut file| file.write_all(&assembly.rom))
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => CliError::Exists(path.clone()),
+            _ => CliError::Write(path.clone(), err),
+        })?;
+    if listing {
+        out.write_all(assembly.listing.as_bytes()).map_err(CliError::Io)?;
+    }
+    Ok(())
+}
+
+// Print a listing of the rom, by default of the instructions reached from
+// the start address, or with --raw of every word decoded in turn:
+//
+//     chip8 disasm PATH_TO_ROM [--start ADDRESS] [--raw]
+pub fn disasm<W: Write>(args: &[String], out: &mut W)
+                        -> Result<(), CliError> {
+    let mut path = None;
+    let mut start = PROGRAM_START as u16;
+    let mut raw = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--start" => start = parse_address(arg, args.next())?,
+            "--raw" => raw = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(usage(OptionsError::Unexpected(arg.clone()))),
+        }
+    }
+    let path = path.ok_or_else(|| {
+        usage(OptionsError::Requires("disasm", "a rom"))
+    })?;
+    let rom = rom::read_rom(Path::new(path)).map_err(CliError::Rom)?.rom;
+    let listing = if raw {
+        disasm::raw_listing(&rom, start)
+    } else {
+        disasm::listing(&rom, start)
+    };
+    out.write_all(listing.as_bytes()).map_err(CliError::Io)
+}
+
+// Print what can be told about the rom without running it: its size and
+// hash, the mode it was detected to be written for, the opcodes it uses
+// that only exist in SUPER mode or XO-CHIP, whether it calls machine code,
+// how many of its bytes are code and whether the rom database has a
+// profile for it. With --json as a JSON object instead. As for the 
+// emulator, --no-rom-db leaves out the database:
+//
+//     chip8 info PATH_TO_ROM [--json] [--no-rom-db]
+pub fn info<W: Write>(args: &[String], out: &mut W) -> Result<(), CliError> {
+    let mut path = None;
+    let mut json = false;
+    let mut no_rom_db = false;
+    for arg in args {
+        match &arg[..] {
+            "--json" => json = true,
+            "--no-rom-db" => no_rom_db = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => return Err(usage(OptionsError::Unexpected(arg.clone()))),
+        }
+    }
+    let path = path.ok_or_else(|| {
+        usage(OptionsError::Requires("info", "a rom"))
+    })?;
+    let rom = rom::read_rom(Path::new(path)).map_err(CliError::Rom)?.rom;
+    // A database that can not be read is warned about on standard error.
+    let db = if no_rom_db {
+        RomDb::new()
+    } else {
+        let log = Log::open(Level::Warn, None).map_err(CliError::Io)?;
+        RomDb::load_default(&log)
+    };
+    let info = RomInfo::inspect(&rom, &db);
+    let text = if json {info.to_json()} else {info.to_text()};
+    out.write_all(text.as_bytes()).map_err(CliError::Io)
+}
+
+fn usage(err: OptionsError) -> CliError {
+    CliError::Usage(err)
+}
+
+// The value given after the option.
+fn value<'a>(option: &str, value: Option<&'a String>)
+             -> Result<&'a String, CliError> {
+    value.ok_or_else(|| usage(OptionsError::MissingValue(option.to_string())))
+}
+
+// An address in hex, with or without 0x in front, as in 0x200.
+fn parse_address(option: &str, value: Option<&String>)
+                 -> Result<u16, CliError> {
+    let value = self::value(option, value)?;
+    let hex = if value.starts_with("0x") {&value[2..]} else {&value[..]};
+    u16::from_str_radix(hex, 16).map_err(|_| {
+        usage(OptionsError::InvalidValue(option.to_string(), value.clone()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{run, CliError};
+    use super::super::asm::AsmProblem;
+    use super::super::info::RomInfo;
+    use super::super::options::OptionsError;
+    use super::super::romdb::RomDb;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+
+    fn run_args(args: &str) -> Option<Result<String, CliError>> {
+        let args: Vec<String> = args.split_whitespace()
+                                    .map(|arg| arg.to_string()).collect();
+        let mut out = Vec::new();
+        run(&args, &mut out).map(|result| {
+            result.map(|_| String::from_utf8(out).unwrap())
+        })
+    }
+
+    #[test]
+    fn test_not_a_subcommand() {
+        assert!(run_args("").is_none());
+        assert!(run_args("roms/brix.ch8").is_none());
+        assert!(run_args("--mode schip disasm").is_none());
+    }
+
+    #[test]
+    fn test_disasm_start_and_raw() {
+        //when
+        let listing = run_args("disasm roms/brix.ch8 --raw --start 0x202")
+            .unwrap().unwrap();
+        //then
+        let lines: Vec<&str> = listing.lines().collect();
+        assert!(lines[0].starts_with("200  "));
+        assert!(lines[0].contains(".byte"));
+        assert!(lines[1].starts_with("202  "));
+        assert!(!lines[1].contains(".byte"));
+    }
+
+    #[test]
+    fn test_disasm_errors() {
+        let error = |args| match run_args(args) {
+            Some(Err(err)) => err,
+            _ => panic!("expected {} to fail", args),
+        };
+        match error("disasm roms/missing.ch8") {
+            err @ CliError::Rom(_) => assert_eq!(1, err.exit_code()),
+            other => panic!("unexpected {:?}", other),
+        }
+        let usage = |args| match error(args) {
+            CliError::Usage(err) => err,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(OptionsError::Requires("disasm", "a rom"),
+                   usage("disasm --raw"));
+        assert_eq!(OptionsError::Unexpected("b.ch8".to_string()),
+                   usage("disasm a.ch8 b.ch8"));
+        assert_eq!(OptionsError::MissingValue("--start".to_string()),
+                   usage("disasm a.ch8 --start"));
+        assert_eq!(OptionsError::InvalidValue("--start".to_string(),
+                                              "0xg00".to_string()),
+                   usage("disasm a.ch8 --start 0xg00"));
+        assert_eq!(2, error("disasm").exit_code());
+    }
+
+    #[test]
+    fn test_info() {
+        // Without the database, which would be that of whoever runs the 
+        // tests.
+        let info = |args| run_args(args).unwrap().unwrap();
+        //when
+        let text = info("info roms/brix.ch8 --no-rom-db");
+        let json = info("info roms/brix.ch8 --json --no-rom-db");
+        //then
+        assert!(text.contains("Mode:            chip8\n"));
+        assert!(text.contains("Profile:         none\n"));
+        assert!(json.starts_with("{\"size\": "));
+        assert!(json.contains("\"profile\": null"));
+        let brix = include_bytes!("../../roms/brix.ch8");
+        assert_eq!(Some("BRIX".to_string()),
+                   RomInfo::inspect(brix, &RomDb::builtin()).profile);
+        match run_args("info") {
+            Some(Err(CliError::Usage(err))) =>
+                assert_eq!(OptionsError::Requires("info", "a rom"), err),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_asm() {
+        //given
+        let dir = env::temp_dir().join("chip8_cli_asm");
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("stop.asm");
+        let rom = dir.join("stop.ch8");
+        let _ = fs::remove_file(&rom);
+        File::create(&source).unwrap()
+            .write_all(b"stop: JP stop\n").unwrap();
+        let args = format!("asm {} -o {} --listing", source.display(),
+                           rom.display());
+        //when
+        let listing = run_args(&args).unwrap().unwrap();
+        //then
+        let mut bytes = Vec::new();
+        File::open(&rom).unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(vec![0x12, 0x00], bytes);
+        assert!(listing.starts_with("200  12 00 "));
+        // The rom is only written over when forced to.
+        match run_args(&args) {
+            Some(Err(CliError::Exists(ref path))) =>
+                assert_eq!(&rom.display().to_string(), path),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(run_args(&format!("{} --force", args)).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_asm_errors() {
+        //given
+        let dir = env::temp_dir().join("chip8_cli_asm_errors");
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("bad.asm");
+        File::create(&source).unwrap()
+            .write_all(b"CLS\nJP nowhere\n").unwrap();
+        let rom = dir.join("bad.ch8");
+        let _ = fs::remove_file(&rom);
+        //when
+        let result = run_args(&format!("asm {} -o {}", source.display(),
+                                       rom.display()));
+        //then
+        match result {
+            Some(Err(CliError::Asm(ref path, ref err))) => {
+                assert_eq!(&source.display().to_string(), path);
+                assert_eq!((2, 4), (err.line, err.column));
+                assert_eq!(AsmProblem::UnknownLabel("nowhere".to_string()),
+                           err.problem);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(!rom.exists());
+        match run_args("asm -o a.ch8") {
+            Some(Err(CliError::Usage(err))) => assert_eq!(
+                OptionsError::Requires("asm", "a source file"), err),
+            other => panic!("unexpected {:?}", other),
+        }
+        match run_args("asm a.asm") {
+            Some(Err(CliError::Usage(err))) =>
+                assert_eq!(OptionsError::Requires("asm", "-o"), err),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+}
@@ -0,0 +1,148 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// A source of time. Time dependent behaviour takes a clock rather than
+// asking the system for the time, so that it can be tested without
+// waiting. A frontend without threads to block, e.g. in a browser, can
+// drive its own clock from the frames it is asked to draw.
+pub trait Clock {
+    // The time elapsed since some fixed point, e.g. the creation of the
+    // clock.
+    fn now(&self) -> Duration;
+    // Wait for the duration to pass. May wake up late, by as much as the
+    // scheduler of the OS pleases.
+    fn sleep(&self, duration: Duration);
+    // Wait a moment, for waiting on a time too close to sleep until. The
+    // thread only yields to others ready to run, so that it is back soon.
+    fn spin(&self) {
+        thread::yield_now();
+    }
+}
+
+// The real, monotonic time.
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+
+    pub fn new() -> Self {
+        SystemClock { start: Instant::now() }
+    }
+
+}
+
+impl Clock for SystemClock {
+
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+}
+
+// How far spinning moves a MockClock on.
+pub const MOCK_SPIN: u64 = 10;
+
+// A clock for tests, that only moves when told to. Sleeping moves it on at
+// once, and is recorded. Spinning moves it on by MOCK_SPIN microseconds.
+pub struct MockClock {
+    now: Mutex<Duration>,
+    sleeps: Mutex<Vec<Duration>>,
+    // How much longer than asked the next sleeps take, in turn.
+    oversleeps: Mutex<VecDeque<Duration>>,
+}
+
+impl MockClock {
+
+    pub fn new() -> Self {
+        MockClock { now: Mutex::new(Duration::from_secs(0)), 
+                    sleeps: Mutex::new(Vec::new()),
+                    oversleeps: Mutex::new(VecDeque::new()) }
+    }
+
+    // Make the next sleeps wake up late by the given durations, in turn, 
+    // like real sleeps do.
+    pub fn oversleep(&self, late: &[Duration]) {
+        self.oversleeps.lock().unwrap().extend(late);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+
+    // The durations slept for so far, in order.
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.sleeps.lock().unwrap().clone()
+    }
+
+}
+
+impl Clock for MockClock {
+
+    fn now(&self) -> Duration {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.sleeps.lock().unwrap().push(duration);
+        let late = self.oversleeps.lock().unwrap().pop_front();
+        self.advance(duration + late.unwrap_or(Duration::from_secs(0)));
+    }
+
+    fn spin(&self) {
+        self.advance(Duration::new(0, MOCK_SPIN as u32 * 1000));
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Clock, MockClock};
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_clock_only_moves_when_advanced() {
+        let clock = MockClock::new();
+        assert_eq!(Duration::from_secs(0), clock.now());
+        //when
+        clock.advance(Duration::from_millis(5));
+        clock.advance(Duration::from_millis(7));
+        //then
+        assert_eq!(Duration::from_millis(12), clock.now());
+    }
+
+    #[test]
+    fn test_mock_clock_records_sleeps() {
+        let clock = MockClock::new();
+        //when
+        clock.sleep(Duration::from_millis(16));
+        clock.advance(Duration::from_millis(1));
+        clock.sleep(Duration::from_millis(3));
+        //then
+        assert_eq!(vec![Duration::from_millis(16), Duration::from_millis(3)],
+                   clock.sleeps());
+        assert_eq!(Duration::from_millis(20), clock.now());
+    }
+
+    #[test]
+    fn test_mock_clock_oversleeps() {
+        let clock = MockClock::new();
+        //given
+        clock.oversleep(&[Duration::from_millis(2)]);
+        //when
+        clock.sleep(Duration::from_millis(10));
+        clock.sleep(Duration::from_millis(10));
+        clock.spin();
+        //then
+        assert_eq!(vec![Duration::from_millis(10); 2], clock.sleeps());
+        assert_eq!(Duration::new(0, 22_010_000), clock.now());
+    }
+
+}
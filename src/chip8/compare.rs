@@ -0,0 +1,171 @@
+use super::emu::{Emu, Quirks};
+use super::log::Log;
+use super::metro::CycleBudget;
+
+// The quirk sets that can be compared, by name.
+pub fn quirks_named(name: &str) -> Option<Quirks> {
+    match name {
+        "default" => Some(Quirks::default()),
+        "vip" => Some(Quirks::cosmac_vip()),
+        "schip" => Some(Quirks::schip()),
+        "modern" => Some(Quirks::modern()),
+        _ => None,
+    }
+}
+
+// Parse a pair of quirk set names separated by a comma, e.g. "default,vip".
+pub fn parse_quirk_pair(arg: &str) -> Option<(Quirks, Quirks)> {
+    let names: Vec<&str> = arg.split(',').map(|name| name.trim()).collect();
+    if names.len() != 2 {
+        return None;
+    }
+    match (quirks_named(names[0]), quirks_named(names[1])) {
+        (Some(a), Some(b)) => Some((a, b)),
+        _ => None,
+    }
+}
+
+// Runs the same rom on two emulators with different quirks, frame by frame
+// with the same keys and random numbers, to find where a rom starts to
+// depend on a quirk. Any difference in behaviour shows up sooner or later
+// as a difference in what is drawn, so the displays are compared after
+// every frame. Both run the cycles the budget has for each frame.
+pub struct Lockstep {
+    a: Emu,
+    b: Emu,
+    seed: u64,
+    budget: CycleBudget,
+    // The first frame after which the displays differed.
+    diverged_at: Option<u64>,
+}
+
+impl Lockstep {
+
+    pub fn new(rom: &[u8], quirks: (Quirks, Quirks), seed: u64,
+               budget: CycleBudget) -> Self {
+        let boot = |quirks| {
+            let mut emu = Emu::new();
+            emu.quirks = quirks;
+            emu.seed_rng(seed);
+            emu.load_rom(rom.to_vec());
+            emu
+        };
+        Lockstep { a: boot(quirks.0), b: boot(quirks.1), seed: seed,
+                   budget: budget, diverged_at: None }
+    }
+
+    // The two emulators, in the order of their quirks.
+    pub fn emus(&self) -> (&Emu, &Emu) {
+        (&self.a, &self.b)
+    }
+
+    // Both emulators log to the log from now on.
+    pub fn set_log(&mut self, log: &Log) {
+        self.a.set_log(log.clone());
+        self.b.set_log(log.clone());
+    }
+
+    // Both emulators see the same keys held down.
+    pub fn set_keys(&mut self, keys: [bool; 16]) {
+        self.a.keys = keys;
+        self.b.keys = keys;
+    }
+
+    // Run a frame on both emulators. Returns true if their displays differ
+    // for the first time.
+    pub fn run_frame(&mut self) -> bool {
+        let cycles = self.budget.next_frame();
+        self.a.run_frame(cycles);
+        self.b.run_frame(cycles);
+        if self.diverged_at.is_some() ||
+           self.a.display_hash() == self.b.display_hash() {
+            return false;
+        }
+        self.diverged_at = Some(self.a.frame_count());
+        true
+    }
+
+    pub fn diverged_at(&self) -> Option<u64> {
+        self.diverged_at
+    }
+
+    // Start both over, with the same random numbers as the first time.
+    pub fn reset(&mut self) {
+        for emu in &mut [&mut self.a, &mut self.b] {
+            emu.reset();
+            emu.seed_rng(self.seed);
+        }
+        self.diverged_at = None;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{parse_quirk_pair, quirks_named, Lockstep};
+    use super::super::metro::CycleBudget;
+
+    // Waits for a key, then ORs v0 with v1 and draws the digit in vf. The
+    // digit is 5, unless the OR resets vf to 0.
+    const VF_RESET_ROM: [u8; 12] = [0xf2, 0x0a, 0x6f, 0x05, 0x80, 0x11,
+                                    0xff, 0x29, 0xd0, 0x05, 0x12, 0x0a];
+
+    fn lockstep(pair: &str) -> Lockstep {
+        Lockstep::new(&VF_RESET_ROM, parse_quirk_pair(pair).unwrap(), 1, 
+                      CycleBudget::new(480, 60))
+    }
+
+    #[test]
+    fn test_parse_quirk_pair() {
+        assert!(quirks_named("vip").unwrap().logic_resets_vf);
+        assert!(!quirks_named("default").unwrap().logic_resets_vf);
+        assert!(quirks_named("schip").unwrap().jump_uses_vx);
+        assert!(parse_quirk_pair("default, vip").is_some());
+        assert!(parse_quirk_pair("default").is_none());
+        assert!(parse_quirk_pair("default,vip,vip").is_none());
+        assert!(parse_quirk_pair("default,amiga").is_none());
+    }
+
+    #[test]
+    fn test_divergence_found_at_first_differing_frame() {
+        let mut lockstep = lockstep("default,vip");
+        for _ in 0..3 { assert!(!lockstep.run_frame()); }
+        //when
+        let mut keys = [false; 16];
+        keys[0x4] = true;
+        lockstep.set_keys(keys);
+        let diverged = lockstep.run_frame();
+        //then
+        assert!(diverged);
+        assert_eq!(Some(4), lockstep.diverged_at());
+        assert!(!lockstep.run_frame());
+        assert_eq!(Some(4), lockstep.diverged_at());
+        let (a, b) = lockstep.emus();
+        assert!(a.display_hash() != b.display_hash());
+    }
+
+    #[test]
+    fn test_same_quirks_never_diverge() {
+        let mut lockstep = lockstep("vip,vip");
+        lockstep.set_keys([true; 16]);
+        //when
+        for _ in 0..10 { lockstep.run_frame(); }
+        //then
+        assert_eq!(None, lockstep.diverged_at());
+    }
+
+    #[test]
+    fn test_reset_forgets_divergence() {
+        let mut lockstep = lockstep("default,vip");
+        lockstep.set_keys([true; 16]);
+        lockstep.run_frame();
+        //when
+        lockstep.reset();
+        //then
+        assert_eq!(None, lockstep.diverged_at());
+        assert_eq!((0, 0), (lockstep.emus().0.frame_count(),
+                            lockstep.emus().1.frame_count()));
+    }
+
+}
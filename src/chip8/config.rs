@@ -0,0 +1,363 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Problems reading or interpreting the configuration file.
+#[derive(Debug)]
+pub enum ConfigError {
+    // The file exists but could not be read.
+    Io(PathBuf, io::Error),
+    // A line that is neither a section header nor a setting. Holds the
+    // line number.
+    Syntax(usize),
+    // A setting with an unknown key or an invalid value. Holds the line
+    // number, the key and a description of the problem.
+    Setting(usize, String, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref path, ref err) =>
+                write!(f, "Can not read {}: {}", path.display(), err),
+            ConfigError::Syntax(line) =>
+                write!(f, "Config line {}: expected [section] or key = value",
+                       line),
+            ConfigError::Setting(line, ref key, ref problem) =>
+                write!(f, "Config line {}: {}: {}", line, key, problem),
+        }
+    }
+}
+
+// A single `key = value` line of the configuration file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setting {
+    pub line: usize,
+    pub key: String,
+    pub value: String,
+}
+
+impl Setting {
+
+    // The error to report when this setting can not be used.
+    pub fn error(&self, problem: &str) -> ConfigError {
+        ConfigError::Setting(self.line, self.key.clone(), problem.to_string())
+    }
+
+    // The value as a number, a boolean (true or false) or any other type
+    // that can be parsed from a string.
+    pub fn parse<T: FromStr>(&self) -> Result<T, ConfigError> {
+        self.value.parse()
+            .map_err(|_| self.error(&format!("invalid value {}", self.value)))
+    }
+
+}
+
+// The settings found under a `[name]` header. Settings before the first
+// header belong to the section with the empty name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    // The line of the header, 0 for the section with the empty name.
+    pub line: usize,
+    pub settings: Vec<Setting>,
+}
+
+// The configuration file, written in a small subset of TOML: `[section]`
+// headers, `key = value` settings and `#` comments. Values may be quoted.
+//
+//     [gamepad]
+//     a = 5
+//
+//     [gamepad.pong]
+//     dpad_up = 1
+//     dpad_down = 4
+#[derive(Clone, Debug, PartialEq)]
+pub struct Config {
+    sections: Vec<Section>,
+}
+
+// The directory holding the files of the user: $XDG_CONFIG_HOME/chip8 or
+// ~/.config/chip8.
+pub fn config_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        .or_else(|| env::home_dir().map(|home| home.join(".config")));
+    dir.map(|dir| dir.join("chip8"))
+}
+
+impl Config {
+
+    // A configuration without any settings, meaning that the built in
+    // defaults apply.
+    pub fn new() -> Self {
+        Config { sections: Vec::new() }
+    }
+
+    // Where the configuration file lives.
+    pub fn default_path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join("config.toml"))
+    }
+
+    // Read the configuration file. A missing file is not an error, it
+    // simply leaves everything at its default.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let mut text = String::new();
+        let read = File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text));
+        match read {
+            Ok(_) => Config::parse(&text),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound =>
+                Ok(Config::new()),
+            Err(err) => Err(ConfigError::Io(path.to_path_buf(), err)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        let mut sections = vec![Section { name: String::new(), line: 0,
+                                          settings: Vec::new() }];
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = unquote(line[1..line.len() - 1].trim());
+                sections.push(Section { name: name, line: line_no,
+                                        settings: Vec::new() });
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap().trim();
+            let value = match parts.next() {
+                Some(value) if !key.is_empty() => value.trim(),
+                _ => return Err(ConfigError::Syntax(line_no)),
+            };
+            sections.last_mut().unwrap().settings.push(Setting {
+                line: line_no,
+                key: key.to_string(),
+                value: unquote(value),
+            });
+        }
+        Ok(Config { sections: sections })
+    }
+
+    // The settings of the named section, in the order they appear. A
+    // section that appears more than once has its settings combined.
+    pub fn section(&self, name: &str) -> Vec<&Setting> {
+        self.sections.iter()
+            .filter(|section| section.name == name)
+            .flat_map(|section| section.settings.iter())
+            .collect()
+    }
+
+    // Every section in the order they appear, starting with the one with
+    // the empty name.
+    pub fn sections(&self) -> &[Section] {
+        &self.sections
+    }
+
+}
+
+// Change a single setting in the configuration file, e.g. to remember a
+// choice made in the ui. The rest of the file is kept as written.
+pub fn save_setting(path: &Path, section: &str, key: &str, 
+                    value: &str) -> Result<(), ConfigError> {
+    let mut text = String::new();
+    let read = File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut text));
+    if let Err(err) = read {
+        if err.kind() != io::ErrorKind::NotFound {
+            return Err(ConfigError::Io(path.to_path_buf(), err));
+        }
+    }
+    let text = set_setting(&text, section, key, value);
+    path.parent().map_or(Ok(()), |dir| fs::create_dir_all(dir))
+        .and_then(|_| File::create(path))
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|err| ConfigError::Io(path.to_path_buf(), err))
+}
+
+// Set the key of the section to the value in the text of a configuration 
+// file, keeping other lines, comments included, as they are. A setting
+// that is not there yet is added at the end of its section, and a section
+// that is not there yet at the end of the text.
+pub fn set_setting(text: &str, section: &str, key: &str, 
+                   value: &str) -> String {
+    let setting = format!("{} = {}", key, value);
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+    let mut current = String::new();
+    // The index after the last line of the section, if it was seen.
+    let mut section_end = if section.is_empty() {Some(0)} else {None};
+    for i in 0..lines.len() {
+        let line = strip_comment(&lines[i]).trim().to_string();
+        if line.starts_with('[') && line.ends_with(']') {
+            current = unquote(line[1..line.len() - 1].trim());
+            if current == section { section_end = Some(i + 1); }
+            continue;
+        }
+        if current != section || line.is_empty() {
+            continue;
+        }
+        section_end = Some(i + 1);
+        if line.splitn(2, '=').next().unwrap().trim() == key {
+            lines[i] = setting;
+            return join_lines(&lines);
+        }
+    }
+    match section_end {
+        Some(i) => lines.insert(i, setting),
+        None => {
+            if lines.last().map_or(false, |line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(format!("[{}]", section));
+            lines.push(setting);
+        },
+    }
+    join_lines(&lines)
+}
+
+// Set several keys of the section at once, as set_setting does, e.g. to
+// write out the settings in effect with those a section has to offer.
+pub fn set_settings(text: &str, section: &str, 
+                    settings: &[(&str, String)]) -> String {
+    settings.iter().fold(text.to_string(), |text, &(key, ref value)| {
+        set_setting(&text, section, key, value)
+    })
+}
+
+fn join_lines(lines: &[String]) -> String {
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+// Remove a trailing comment, leaving '#' inside quotes alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted ^= true,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn unquote(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        value[1..value.len() - 1].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{set_setting, set_settings, Config, ConfigError};
+
+    #[test]
+    fn test_parse_sections() {
+        //given
+        let text = "speed = 500\n\
+                    # Gamepad buttons\n\
+                    [gamepad]\n\
+                    a = 5   # fire\n\
+                    \n\
+                    [\"gamepad.pong\"]\n\
+                    dpad_up = \"1\"\n";
+        //when
+        let config = Config::parse(text).unwrap();
+        //then
+        let top = config.section("");
+        assert_eq!(1, top.len());
+        assert_eq!("speed", top[0].key);
+        let gamepad = config.section("gamepad");
+        assert_eq!(1, gamepad.len());
+        assert_eq!(("a", "5", 4), (&gamepad[0].key[..],
+                                   &gamepad[0].value[..], gamepad[0].line));
+        assert_eq!("1", config.section("gamepad.pong")[0].value);
+        assert!(config.section("missing").is_empty());
+        let headers: Vec<_> = config.sections().iter()
+            .map(|section| (&section.name[..], section.line)).collect();
+        assert_eq!(vec![("", 0), ("gamepad", 3), ("gamepad.pong", 6)],
+                   headers);
+    }
+
+    #[test]
+    fn test_parse_setting_value() {
+        //given
+        let config = Config::parse("[display]\n\
+                                    decay = 0.5\n\
+                                    scanlines = yes\n").unwrap();
+        let display = config.section("display");
+        //then
+        assert_eq!(Ok(0.5), display[0].parse::<f32>().map_err(|_| ()));
+        match display[1].parse::<bool>() {
+            Err(ConfigError::Setting(line, ref key, _)) => {
+                assert_eq!(3, line);
+                assert_eq!("scanlines", key);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_syntax_error_names_line() {
+        //when
+        match Config::parse("[gamepad]\na = 5\nnonsense\n") {
+            //then
+            Err(ConfigError::Syntax(line)) => assert_eq!(3, line),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_set_setting_replaces_value_and_keeps_comments() {
+        //given
+        let text = "# Window\n[window]\nscale = 4  # big\nvsync = true\n";
+        //when
+        let text = set_setting(text, "window", "scale", "8");
+        //then
+        assert_eq!("# Window\n[window]\nscale = 8\nvsync = true\n", text);
+    }
+
+    #[test]
+    fn test_set_setting_adds_setting_and_section() {
+        //given
+        let text = "[window]\nvsync = true\n\n[display]\nscanlines = true";
+        //when
+        let added = set_setting(text, "window", "scale", "2");
+        //then
+        assert_eq!("[window]\nvsync = true\nscale = 2\n\n\
+                    [display]\nscanlines = true\n", added);
+        assert_eq!("[display]\nscanlines = true\n\n[window]\nscale = 2\n",
+                   set_setting("[display]\nscanlines = true", "window", 
+                               "scale", "2"));
+        assert_eq!("[window]\nscale = 2\n", 
+                   set_setting("", "window", "scale", "2"));
+        let config = Config::parse(&added).unwrap();
+        assert_eq!("2", config.section("window")[1].value);
+    }
+
+    #[test]
+    fn test_set_settings() {
+        //given
+        let settings = [("scale", "2".to_string()), 
+                        ("vsync", "true".to_string())];
+        //when
+        let text = set_settings("[window]\n\
+                                 vsync = false\n", "window", &settings);
+        //then
+        assert_eq!("[window]\n\
+                    vsync = true\n\
+                    scale = 2\n", text);
+    }
+
+}
@@ -0,0 +1,145 @@
+use super::NUM_REGISTERS;
+use super::disasm;
+use super::emu::Emu;
+use std::cmp;
+
+// Number of instructions shown in the disassembly window.
+const CODE_WINDOW: usize = 10;
+// Number of instructions shown in the window before the current one.
+const CODE_WINDOW_BEFORE: usize = 4;
+
+// A copy of the machine state shown by the debugger panel. Taken on the
+// emulator thread and handed over to the ui.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugSnapshot {
+    pub v: [u8; NUM_REGISTERS],
+    pub ram_idx: u16,
+    pub pc: u16,
+    pub sp: usize,
+    pub dt: u8,
+    pub st: u8,
+    pub stack: Vec<u16>,
+    // The (address, opcode) pairs making up the disassembly window.
+    pub code: Vec<(u16, u16)>,
+}
+
+impl DebugSnapshot {
+
+    // Capture the state of the emulator.
+    pub fn capture(emu: &Emu) -> Self {
+        let v = *emu.registers();
+        let ram = emu.ram();
+        let pc = emu.pc() as usize;
+        let start = pc - cmp::min(pc, CODE_WINDOW_BEFORE * 2);
+        let mut code = Vec::with_capacity(CODE_WINDOW);
+        let mut addr = start;
+        while code.len() < CODE_WINDOW && addr + 1 < ram.len() {
+            let opcode = (ram[addr] as u16) << 8 | ram[addr + 1] as u16;
+            code.push((addr as u16, opcode));
+            addr += 2;
+        }
+        DebugSnapshot {
+            v: v,
+            ram_idx: emu.ram_idx(),
+            pc: emu.pc(),
+            sp: emu.sp(),
+            dt: emu.delay_timer(),
+            st: emu.sound_timer(),
+            stack: emu.call_stack().to_vec(),
+            code: code,
+        }
+    }
+
+}
+
+// Lay out the debugger panel as lines of text. The line of the current
+// instruction starts with '>' so that it may be highlighted.
+pub fn panel_lines(snapshot: &DebugSnapshot) -> Vec<String> {
+    let mut lines = Vec::new();
+    for row in 0..NUM_REGISTERS / 4 {
+        let regs: Vec<String> = (row * 4..row * 4 + 4)
+            .map(|x| format!("V{:X} {:02X}", x, snapshot.v[x]))
+            .collect();
+        lines.push(regs.join(" "));
+    }
+    lines.push(format!("I {:04X} PC {:04X} SP {:X}",
+                       snapshot.ram_idx, snapshot.pc, snapshot.sp));
+    lines.push(format!("DT {:02X} ST {:02X}", snapshot.dt, snapshot.st));
+    lines.push("STACK".to_string());
+    if snapshot.stack.is_empty() {
+        lines.push("  -".to_string());
+    }
+    for chunk in snapshot.stack.chunks(4) {
+        let addrs: Vec<String> = chunk.iter()
+            .map(|addr| format!("{:04X}", addr))
+            .collect();
+        lines.push(format!("  {}", addrs.join(" ")));
+    }
+    lines.push(String::new());
+    for &(addr, opcode) in &snapshot.code {
+        let marker = if addr == snapshot.pc {'>'} else {' '};
+        lines.push(format!("{} {:04X} {:04X} {}", marker, addr, opcode,
+                           disasm::disassemble(opcode)));
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{panel_lines, DebugSnapshot};
+    use super::super::emu::Emu;
+
+    #[test]
+    fn test_capture_code_window_centred_on_pc() {
+        let mut emu = Emu::new();
+        //given
+        let rom: Vec<u8> = (0..40).map(|i| if i % 2 == 0 {0x60} else {0x00})
+                                  .collect();
+        emu.load_rom(rom);
+        for _ in 0..6 { emu.execute_cycle(); }
+        //when
+        let snapshot = DebugSnapshot::capture(&emu);
+        //then
+        assert_eq!(10, snapshot.code.len());
+        assert_eq!(0x0204, snapshot.code[0].0);
+        assert_eq!(0x020c, snapshot.code[4].0);
+        assert_eq!(0x020c, snapshot.pc);
+    }
+
+    #[test]
+    fn test_panel_lines() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x6a, 0x02, 0x22, 0x06, 0x00, 0x00, 0x00, 0xee]);
+        emu.execute_cycle();
+        emu.execute_cycle();
+        //when
+        let lines = panel_lines(&DebugSnapshot::capture(&emu));
+        //then
+        assert_eq!("V0 00 V1 00 V2 00 V3 00", lines[0]);
+        assert_eq!("V8 00 V9 00 VA 02 VB 00", lines[2]);
+        assert_eq!("I 0000 PC 0206 SP 1", lines[4]);
+        assert_eq!("DT 00 ST 00", lines[5]);
+        assert_eq!("STACK", lines[6]);
+        assert_eq!("  0202", lines[7]);
+        assert_eq!("", lines[8]);
+        assert_eq!("  01FE 0000 SYS 0x000", lines[9]);
+        assert_eq!("  0200 6A02 LD VA, 0x02", lines[10]);
+        assert_eq!("  0202 2206 CALL 0x206", lines[11]);
+        assert_eq!("> 0206 00EE RET", lines[13]);
+        assert_eq!(19, lines.len());
+    }
+
+    #[test]
+    fn test_panel_lines_empty_stack() {
+        let emu = Emu::new();
+        //when
+        let lines = panel_lines(&DebugSnapshot::capture(&emu));
+        //then
+        assert_eq!("STACK", lines[6]);
+        assert_eq!("  -", lines[7]);
+        assert_eq!(1, lines.iter().filter(|l| l.starts_with('>')).count());
+    }
+
+}
@@ -0,0 +1,125 @@
+use std::path::{Path, PathBuf};
+
+// How long each rom runs for in demo mode unless told otherwise.
+pub const DEFAULT_DEMO_SECS: u64 = 30;
+
+// The outcome of moving on to the next rom.
+pub struct Switch<T, E> {
+    // The rom switched to, unless none of the roms could be loaded.
+    pub loaded: Option<(PathBuf, T)>,
+    // The roms passed over because they could not be loaded, and why.
+    pub skipped: Vec<(PathBuf, E)>,
+}
+
+// Shows off the roms of a directory by running each in turn for a while,
+// going round forever. Driven by a frame counter, so that time spent
+// paused does not count.
+pub struct DemoRotation {
+    roms: Vec<PathBuf>,
+    // The rom to try next.
+    next: usize,
+    frames_per_rom: u64,
+    // The frame at which to move on to the next rom.
+    switch_at: u64,
+}
+
+impl DemoRotation {
+
+    // The first rom is due straight away, at frame 0.
+    pub fn new(roms: Vec<PathBuf>, frames_per_rom: u64) -> Self {
+        DemoRotation { roms: roms, next: 0, frames_per_rom: frames_per_rom,
+                       switch_at: 0 }
+    }
+
+    // Move on to the next rom if one is due at the frame. Roms are loaded
+    // with load, and those that fail to load are skipped, trying each rom
+    // at most once. Returns None while the current rom keeps running.
+    pub fn frame<T, E, F>(&mut self, frame: u64,
+                          mut load: F) -> Option<Switch<T, E>>
+        where F: FnMut(&Path) -> Result<T, E> {
+        if frame < self.switch_at || self.roms.is_empty() {
+            return None;
+        }
+        self.switch_at = frame + self.frames_per_rom;
+        let mut skipped = Vec::new();
+        for _ in 0..self.roms.len() {
+            let path = self.roms[self.next].clone();
+            self.next = (self.next + 1) % self.roms.len();
+            match load(&path) {
+                Ok(loaded) => {
+                    return Some(Switch { loaded: Some((path, loaded)),
+                                         skipped: skipped });
+                },
+                Err(err) => skipped.push((path, err)),
+            }
+        }
+        Some(Switch { loaded: None, skipped: skipped })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::DemoRotation;
+    use std::path::{Path, PathBuf};
+
+    fn rotation(names: &[&str], frames_per_rom: u64) -> DemoRotation {
+        DemoRotation::new(names.iter().map(PathBuf::from).collect(),
+                          frames_per_rom)
+    }
+
+    // Roms named "bad" fail to load, the others load as their name.
+    fn load(path: &Path) -> Result<String, String> {
+        let name = path.to_string_lossy().into_owned();
+        if name.starts_with("bad") {Err(name)} else {Ok(name)}
+    }
+
+    // Run the frames, returning the rom switched to at each switch.
+    fn run(demo: &mut DemoRotation, frames: u64) -> Vec<(u64, String)> {
+        (0..frames)
+            .filter_map(|frame| demo.frame(frame, load)
+                .and_then(|switch| switch.loaded)
+                .map(|(_, name)| (frame, name)))
+            .collect()
+    }
+
+    #[test]
+    fn test_roms_take_turns_forever() {
+        let mut demo = rotation(&["pong", "brix"], 10);
+        //when
+        let switches = run(&mut demo, 35);
+        //then
+        assert_eq!(vec![(0, "pong".to_string()), (10, "brix".to_string()),
+                        (20, "pong".to_string()), (30, "brix".to_string())],
+                   switches);
+    }
+
+    #[test]
+    fn test_roms_that_fail_to_load_are_skipped() {
+        let mut demo = rotation(&["pong", "bad1", "bad2", "brix"], 10);
+        demo.frame(0, load);
+        //when
+        let switch = demo.frame(10, load).unwrap();
+        //then
+        assert_eq!(Some((PathBuf::from("brix"), "brix".to_string())),
+                   switch.loaded);
+        let skipped: Vec<String> = switch.skipped.into_iter()
+            .map(|(_, err)| err).collect();
+        assert_eq!(vec!["bad1", "bad2"], skipped);
+        assert_eq!(vec![(20, "pong".to_string())], run(&mut demo, 21));
+    }
+
+    #[test]
+    fn test_no_loadable_roms() {
+        let mut demo = rotation(&["bad1", "bad2"], 10);
+        //when
+        let switch = demo.frame(0, load).unwrap();
+        //then
+        assert_eq!(None, switch.loaded);
+        assert_eq!(2, switch.skipped.len());
+        assert!(demo.frame(5, load).is_none());
+        assert!(rotation(&[], 10).frame(0, load).is_none());
+    }
+
+}
@@ -0,0 +1,361 @@
+use super::PROGRAM_START;
+
+// Translates opcodes into human readable mnemonics. The mnemonics follow
+// Cowgod's Chip-8 Technical Reference, extended with the SUPER mode
+// instructions described by Erik Bryntse.
+//
+// Opcodes that do not map onto any known instruction are rendered as a
+// raw data word, e.g. DW 0x5121.
+pub fn disassemble(opcode: u16) -> String {
+    let (_, x, y, n) = opcode_to_nibbles(opcode);
+    let nn = opcode & 0x00ff;
+    let nnn = opcode & 0x0fff;
+    match opcode & 0xf000 {
+        0x0000 => match opcode {
+            0x00e0 => "CLS".to_string(),
+            0x00ee => "RET".to_string(),
+            0x00fb => "SCR".to_string(),
+            0x00fc => "SCL".to_string(),
+            0x00fd => "EXIT".to_string(),
+            0x00fe => "LOW".to_string(),
+            0x00ff => "HIGH".to_string(),
+            _ if opcode & 0xfff0 == 0x00c0 => format!("SCD {}", n),
+            _ => format!("SYS {:#05x}", nnn),
+        },
+        0x1000 => format!("JP {:#05x}", nnn),
+        0x2000 => format!("CALL {:#05x}", nnn),
+        0x3000 => format!("SE V{:X}, {:#04x}", x, nn),
+        0x4000 => format!("SNE V{:X}, {:#04x}", x, nn),
+        0x5000 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, {:#04x}", x, nn),
+        0x7000 => format!("ADD V{:X}, {:#04x}", x, nn),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xe => format!("SHL V{:X}, V{:X}", x, y),
+            _ => data_word(opcode),
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xa000 => format!("LD I, {:#05x}", nnn),
+        0xb000 => format!("JP V0, {:#05x}", nnn),
+        0xc000 => format!("RND V{:X}, {:#04x}", x, nn),
+        0xd000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xe000 => match nn {
+            0x9e => format!("SKP V{:X}", x),
+            0xa1 => format!("SKNP V{:X}", x),
+            _ => data_word(opcode),
+        },
+        0xf000 => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0a => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1e => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x30 => format!("LD HF, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            0x75 => format!("LD R, V{:X}", x),
+            0x85 => format!("LD V{:X}, R", x),
+            _ => data_word(opcode),
+        },
+        _ => data_word(opcode),
+    }
+}
+
+// Combine four nibbles into an opcode, highest first, e.g. (0xd, 0x1, 0x2,
+// 0x5) into 0xd125. Only the low 4 bits of each are used.
+pub fn opcode_from_nibbles(a: u8, b: u8, c: u8, d: u8) -> u16 {
+    (a as u16 & 0xf) << 12 | (b as u16 & 0xf) << 8 | 
+    (c as u16 & 0xf) << 4 | d as u16 & 0xf
+}
+
+// Split an opcode into its four nibbles, highest first.
+pub fn opcode_to_nibbles(opcode: u16) -> (u8, u8, u8, u8) {
+    ((opcode >> 12) as u8, (opcode >> 8 & 0xf) as u8, 
+     (opcode >> 4 & 0xf) as u8, (opcode & 0xf) as u8)
+}
+
+// Render an opcode that is not a known instruction as raw data.
+fn data_word(opcode: u16) -> String {
+    format!("DW {:#06x}", opcode)
+}
+
+// Render a lone byte, e.g. at the end of a rom of odd length, as raw data.
+pub fn data_byte(byte: u8) -> String {
+    format!("DB {:#04x}", byte)
+}
+
+// Whether the opcode is a known instruction, rather than data.
+pub fn is_instruction(opcode: u16) -> bool {
+    !disassemble(opcode).starts_with("DW ")
+}
+
+// The bytes of the rom at which instructions start when running it from 
+// the address, following jumps, calls and skips. Jumps through V0 (Bnnn)
+// are followed to their base address only. Anything reached no other way,
+// e.g. by self modifying code, is taken for data, as are unknown opcodes.
+pub fn reachable(rom: &[u8], start: u16) -> Vec<bool> {
+    let mut starts = vec![false; rom.len()];
+    let mut pending = vec![start];
+    while let Some(addr) = pending.pop() {
+        let i = match rom_index(rom, addr) {
+            Some(i) if i + 1 < rom.len() && !starts[i] => i,
+            _ => continue,
+        };
+        let opcode = word_at(rom, i);
+        if !is_instruction(opcode) {
+            continue;
+        }
+        starts[i] = true;
+        let next = addr.wrapping_add(2);
+        match opcode & 0xf000 {
+            0x0000 if opcode == 0x00ee || opcode == 0x00fd => {},
+            0x1000 | 0xb000 => pending.push(opcode & 0x0fff),
+            0x2000 => pending.extend(&[opcode & 0x0fff, next]),
+            0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xe000 => 
+                pending.extend(&[next, next.wrapping_add(2)]),
+            _ => pending.push(next),
+        }
+    }
+    starts
+}
+
+// A listing of the rom, with the instructions reached from the start 
+// address disassembled and everything else dumped as data. Addresses that
+// are jumped to, called or pointed to with LD I get a label:
+//
+//     L206:
+//     206  22 10                   CALL 0x210
+//     208  30 05                   SE V0, 0x05
+pub fn listing(rom: &[u8], start: u16) -> String {
+    let starts = reachable(rom, start);
+    let mut labels = vec![false; rom.len()];
+    for i in (0..rom.len()).filter(|&i| starts[i]) {
+        let opcode = word_at(rom, i);
+        match opcode & 0xf000 {
+            0x1000 | 0x2000 | 0xa000 | 0xb000 => 
+                if let Some(target) = rom_index(rom, opcode & 0x0fff) {
+                    labels[target] = true;
+                },
+            _ => {},
+        }
+    }
+    let mut text = String::new();
+    let mut i = 0;
+    while i < rom.len() {
+        if labels[i] {
+            text.push_str(&format!("L{:03x}:\n", address(i)));
+        }
+        if starts[i] {
+            text.push_str(&instruction_line(rom, i));
+            i += 2;
+        } else {
+            let end = data_end(i, rom.len(), |j| starts[j] || labels[j]);
+            text.push_str(&data_line(i, &rom[i..end]));
+            i = end;
+        }
+    }
+    text
+}
+
+// A listing of the rom that decodes every word from the start address on
+// as an instruction, without following the program. Bytes before the 
+// start, and a lone byte at the end, are dumped as data.
+pub fn raw_listing(rom: &[u8], start: u16) -> String {
+    let first = rom_index(rom, start).unwrap_or(rom.len());
+    let mut text = String::new();
+    let mut i = 0;
+    while i < rom.len() {
+        if i >= first && i + 1 < rom.len() {
+            text.push_str(&instruction_line(rom, i));
+            i += 2;
+        } else {
+            let end = data_end(i, rom.len(), |j| j == first);
+            text.push_str(&data_line(i, &rom[i..end]));
+            i = end;
+        }
+    }
+    text
+}
+
+// Data is dumped this many bytes to a line at most.
+const DATA_LINE_BYTES: usize = 8;
+
+// Where a line of data starting at i ends: before the next byte that 
+// starts something else, and after DATA_LINE_BYTES at most.
+fn data_end<F: Fn(usize) -> bool>(i: usize, len: usize, stop: F) -> usize {
+    let mut end = i + 1;
+    while end < len && end - i < DATA_LINE_BYTES && !stop(end) {
+        end += 1;
+    }
+    end
+}
+
+// A line of a listing: the address, the bytes and what they are, in fixed
+// columns.
+pub fn listing_line(addr: u16, bytes: &[u8], text: &str) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b))
+                                .collect();
+    format!("{:03x}  {:<24}{}\n", addr, hex.join(" "), text)
+}
+
+fn line(i: usize, bytes: &[u8], text: &str) -> String {
+    listing_line(address(i), bytes, text)
+}
+
+fn instruction_line(rom: &[u8], i: usize) -> String {
+    line(i, &rom[i..i + 2], &disassemble(word_at(rom, i)))
+}
+
+fn data_line(i: usize, bytes: &[u8]) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("{:#04x}", b))
+                                   .collect();
+    line(i, bytes, &format!(".byte {}", values.join(", ")))
+}
+
+// The address at which the byte of the rom is loaded.
+fn address(i: usize) -> u16 {
+    (PROGRAM_START + i) as u16
+}
+
+// The byte of the rom that is loaded at the address, if any.
+fn rom_index(rom: &[u8], addr: u16) -> Option<usize> {
+    match (addr as usize).checked_sub(PROGRAM_START) {
+        Some(i) if i < rom.len() => Some(i),
+        _ => None,
+    }
+}
+
+fn word_at(rom: &[u8], i: usize) -> u16 {
+    (rom[i] as u16) << 8 | rom[i + 1] as u16
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{data_byte, disassemble, opcode_from_nibbles};
+    use super::{is_instruction, listing, opcode_to_nibbles, raw_listing};
+    use super::reachable;
+
+    #[test]
+    fn test_disassemble_control_opcodes() {
+        assert_eq!("CLS", disassemble(0x00e0));
+        assert_eq!("RET", disassemble(0x00ee));
+        assert_eq!("SCD 3", disassemble(0x00c3));
+        assert_eq!("SCR", disassemble(0x00fb));
+        assert_eq!("SCL", disassemble(0x00fc));
+        assert_eq!("EXIT", disassemble(0x00fd));
+        assert_eq!("LOW", disassemble(0x00fe));
+        assert_eq!("HIGH", disassemble(0x00ff));
+        assert_eq!("SYS 0x123", disassemble(0x0123));
+    }
+
+    #[test]
+    fn test_disassemble_register_opcodes() {
+        assert_eq!("JP 0x2a4", disassemble(0x12a4));
+        assert_eq!("CALL 0x300", disassemble(0x2300));
+        assert_eq!("SE VA, 0x23", disassemble(0x3a23));
+        assert_eq!("LD V1, 0x0a", disassemble(0x610a));
+        assert_eq!("SUBN V3, VF", disassemble(0x83f7));
+        assert_eq!("LD I, 0x222", disassemble(0xa222));
+        assert_eq!("DRW V1, V2, 5", disassemble(0xd125));
+        assert_eq!("SKNP V4", disassemble(0xe4a1));
+        assert_eq!("LD [I], V3", disassemble(0xf355));
+    }
+
+    #[test]
+    fn test_disassemble_unknown_opcode_as_data() {
+        assert_eq!("DW 0x5121", disassemble(0x5121));
+        assert_eq!("DW 0xe4ff", disassemble(0xe4ff));
+        assert_eq!("DW 0xf0ff", disassemble(0xf0ff));
+        assert_eq!("DB 0x0a", data_byte(0x0a));
+    }
+
+    #[test]
+    fn test_opcode_nibbles_round_trip() {
+        for &opcode in &[0x0000, 0x00e0, 0x1234, 0x8ab4, 0xd125, 0xf065, 
+                         0xffff] {
+            let (a, b, c, d) = opcode_to_nibbles(opcode);
+            assert_eq!(opcode, opcode_from_nibbles(a, b, c, d));
+        }
+        assert_eq!((0xd, 0x1, 0x2, 0x5), opcode_to_nibbles(0xd125));
+        assert_eq!(0xd125, opcode_from_nibbles(0xd, 0x1, 0x2, 0x5));
+        // Only the low nibble of each value is used.
+        assert_eq!(0xd125, opcode_from_nibbles(0xfd, 0x11, 0x42, 0x35));
+    }
+
+    #[test]
+    fn test_is_instruction() {
+        assert!(is_instruction(0x00e0));
+        assert!(is_instruction(0xd125));
+        assert!(!is_instruction(0x5121));
+    }
+
+    #[test]
+    fn test_reachable_follows_jumps_calls_and_skips() {
+        //given
+        let rom = [0x22, 0x08,   // 200 CALL 0x208
+                   0x30, 0x01,   // 202 SE V0, 0x01
+                   0x12, 0x0c,   // 204 JP 0x20c
+                   0x12, 0x06,   // 206 JP 0x206
+                   0x00, 0xee,   // 208 RET
+                   0xff, 0xff,   // 20a data
+                   0x12, 0x0c];  // 20c JP 0x20c
+        //when
+        let starts = reachable(&rom, 0x200);
+        //then
+        let reached: Vec<usize> = (0..rom.len()).filter(|&i| starts[i])
+                                                .map(|i| 0x200 + i).collect();
+        assert_eq!(vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20c], reached);
+        assert!(reachable(&rom, 0x20c)[0xc]);
+        assert!(!reachable(&rom, 0x20c)[0]);
+        assert!(reachable(&rom, 0x100).iter().all(|&start| !start));
+    }
+
+    #[test]
+    fn test_listing_dumps_data_after_labels() {
+        //given
+        let rom = [0xa2, 0x04, 0x12, 0x02, 0xf0, 0x90, 0xf0];
+        //then
+        assert_eq!("200  a2 04                   LD I, 0x204\n\
+                    L202:\n\
+                    202  12 02                   JP 0x202\n\
+                    L204:\n\
+                    204  f0 90 f0                .byte 0xf0, 0x90, 0xf0\n",
+                   listing(&rom, 0x200));
+    }
+
+    #[test]
+    fn test_listing_splits_long_data() {
+        //given
+        let mut rom = vec![0x12, 0x00];
+        rom.extend(0..10);
+        //then
+        let text = listing(&rom, 0x200);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(4, lines.len());
+        assert!(lines[2].starts_with("202  00 01 02 03 04 05 06 07 "));
+        assert!(lines[3].ends_with(".byte 0x08, 0x09"));
+    }
+
+    #[test]
+    fn test_raw_listing() {
+        //given
+        let rom = [0xff, 0xff, 0x60, 0x05, 0x51, 0x21, 0x0a];
+        //then
+        assert_eq!("200  ff ff                   .byte 0xff, 0xff\n\
+                    202  60 05                   LD V0, 0x05\n\
+                    204  51 21                   DW 0x5121\n\
+                    206  0a                      .byte 0x0a\n",
+                   raw_listing(&rom, 0x202));
+    }
+
+}
@@ -0,0 +1,125 @@
+use super::{GFX_H, GFX_W, Mode};
+use super::emu::Emu;
+use super::window;
+use std::fmt;
+
+// Renders the active region of the emulator's display as text, one line
+// per row, with '#' for pixels that are on and '.' for pixels that are
+// off. Handy for debugging and for tests.
+pub struct StringDisplay<'a> {
+    emu: &'a Emu,
+}
+
+impl<'a> StringDisplay<'a> {
+
+    pub fn new(emu: &'a Emu) -> Self {
+        StringDisplay { emu: emu }
+    }
+
+}
+
+impl<'a> fmt::Display for StringDisplay<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (w, h) = self.emu.display_dimensions();
+        for y in 0..h {
+            let row: String = (0..w)
+                .map(|x| if self.emu.gfx[x][y] {'#'} else {'.'})
+                .collect();
+            writeln!(f, "{}", row)?;
+        }
+        Ok(())
+    }
+}
+
+// Renders the bytes of a sprite as text, one line of 8 pixels per byte,
+// like StringDisplay.
+pub fn render_sprite(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for byte in bytes {
+        for x in 0..8 {
+            text.push(if byte & (0x80 >> x) != 0 {'#'} else {'.'});
+        }
+        text.push('\n');
+    }
+    text
+}
+
+// Scales the active region of the display up by a whole factor for 
+// blitting, each pixel becoming a scale by scale block of the on or off 
+// colour. Returns the width, the height and the pixels as RGBA bytes, row
+// by row.
+pub fn scaled_rgba(mode: Mode, gfx: &[[bool; GFX_H]; GFX_W], scale: usize,
+                   on: [u8; 4], off: [u8; 4]) -> (usize, usize, Vec<u8>) {
+    let (w, h) = window::resolution(mode);
+    let (width, height) = (w as usize * scale, h as usize * scale);
+    let mut rgba = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let lit = gfx[x / scale][y / scale];
+            rgba.extend_from_slice(if lit { &on } else { &off });
+        }
+    }
+    (width, height, rgba)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{render_sprite, scaled_rgba, StringDisplay};
+    use super::super::{GFX_H, GFX_W, Mode};
+    use super::super::emu::Emu;
+
+    #[test]
+    fn test_string_display() {
+        let mut emu = Emu::new();
+        //given
+        emu.gfx[0][0] = true;
+        emu.gfx[63][31] = true;
+        //when
+        let text = StringDisplay::new(&emu).to_string();
+        let rows: Vec<&str> = text.lines().collect();
+        //then
+        assert_eq!(32, rows.len());
+        assert_eq!(64, rows[0].len());
+        assert!(rows[0].starts_with("#."));
+        assert!(rows[31].ends_with(".#"));
+        assert_eq!(2, text.matches('#').count());
+    }
+
+    #[test]
+    fn test_render_font_sprite() {
+        let emu = Emu::new();
+        //when
+        let sprite = emu.read_sprite(8 * 5, 5);
+        //then
+        assert_eq!("####....\n\
+                    #..#....\n\
+                    ####....\n\
+                    #..#....\n\
+                    ####....\n", render_sprite(&sprite));
+    }
+
+    #[test]
+    fn test_scaled_rgba() {
+        let mut gfx = [[false; GFX_H]; GFX_W];
+        //given
+        gfx[1][0] = true;
+        let (on, off) = ([255, 255, 255, 255], [0, 0, 0, 255]);
+        //when
+        let (w, h, rgba) = scaled_rgba(Mode::STANDARD, &gfx, 2, on, off);
+        //then
+        assert_eq!((128, 64), (w, h));
+        assert_eq!(128 * 64 * 4, rgba.len());
+        let pixel = |x: usize, y: usize| &rgba[(y * w + x) * 4..][..4];
+        for &(x, y) in &[(2, 0), (3, 0), (2, 1), (3, 1)] {
+            assert_eq!(&on, pixel(x, y));
+        }
+        for &(x, y) in &[(1, 0), (4, 0), (2, 2), (0, 0)] {
+            assert_eq!(&off, pixel(x, y));
+        }
+        assert_eq!(4, rgba.chunks(4).filter(|p| p == &on).count());
+        let (w, h, _) = scaled_rgba(Mode::SUPER, &gfx, 3, on, off);
+        assert_eq!((384, 192), (w, h));
+    }
+
+}
@@ -0,0 +1,375 @@
+use super::{GFX_H,GFX_W};
+use super::config::{Config, ConfigError};
+
+// The fraction of its brightness a pixel keeps each frame after it has
+// been switched off, unless configured otherwise.
+pub const DEFAULT_DECAY: f32 = 0.6;
+// Pixels dimmer than this are considered off.
+pub const VISIBLE_THRESHOLD: f32 = 0.05;
+// How much the scanlines darken the rows they cover, from 0 to 255.
+pub const SCANLINE_SHADE: u8 = 0x60;
+// The colours of pixels that are on and off, unless configured otherwise.
+pub const DEFAULT_FG: (u8, u8, u8) = (0xff, 0xff, 0xff);
+pub const DEFAULT_BG: (u8, u8, u8) = (0x1c, 0x28, 0x41);
+
+// Ways of hiding the flicker caused by sprites being erased and redrawn.
+// Only one can be used at a time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Flicker {
+    // Show frames as they are.
+    Off,
+    // Pixels fade out over a few frames instead of vanishing instantly.
+    Phosphor,
+    // Pixels are lit if they were lit in either of the last two frames,
+    // which avoids the smearing of a long decay.
+    Blend,
+}
+
+impl Flicker {
+
+    // The name by which the mode is known in the configuration file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Flicker::Off => "off",
+            Flicker::Phosphor => "phosphor",
+            Flicker::Blend => "blend",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Flicker> {
+        [Flicker::Off, Flicker::Phosphor, Flicker::Blend].iter().cloned()
+            .find(|flicker| flicker.name() == name)
+    }
+
+    // The mode that follows when cycling through them with a hotkey.
+    pub fn next(self) -> Flicker {
+        match self {
+            Flicker::Off => Flicker::Phosphor,
+            Flicker::Phosphor => Flicker::Blend,
+            Flicker::Blend => Flicker::Off,
+        }
+    }
+
+}
+
+// Post-processing applied to the frames received from the emulator before
+// they are shown.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Effects {
+    pub flicker: Flicker,
+    // The decay factor of the phosphor.
+    pub decay: f32,
+    // Darken every other row of the window, like an old CRT.
+    pub scanlines: bool,
+    // The colours of pixels that are on and off.
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+}
+
+impl Effects {
+
+    // No effects, showing the frames exactly as the emulator draws them.
+    pub fn new() -> Self {
+        Effects { flicker: Flicker::Off, decay: DEFAULT_DECAY, 
+                  scanlines: false, fg: DEFAULT_FG, bg: DEFAULT_BG }
+    }
+
+    // The effects set in the [display] section of the configuration:
+    //
+    //     [display]
+    //     flicker = phosphor
+    //     decay = 0.6
+    //     scanlines = true
+    //     foreground = ffb000
+    //     background = 000000
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut effects = Effects::new();
+        for setting in config.section("display") {
+            match &setting.key[..] {
+                "flicker" => effects.flicker = 
+                    Flicker::from_name(&setting.value).ok_or_else(|| {
+                        setting.error("expected off, phosphor or blend")
+                    })?,
+                "scanlines" => effects.scanlines = setting.parse()?,
+                "foreground" => effects.fg = 
+                    parse_color(&setting.value).ok_or_else(|| {
+                        setting.error("expected a colour such as ffb000")
+                    })?,
+                "background" => effects.bg = 
+                    parse_color(&setting.value).ok_or_else(|| {
+                        setting.error("expected a colour such as 000000")
+                    })?,
+                "decay" => {
+                    effects.decay = setting.parse()?;
+                    if effects.decay < 0.0 || effects.decay >= 1.0 {
+                        return Err(setting.error(
+                            "expected a decay from 0 up to but excluding 1"));
+                    }
+                },
+                _ => return Err(setting.error("unknown display setting")),
+            }
+        }
+        Ok(effects)
+    }
+
+    // The effects as written in the [display] section, for writing them 
+    // out with config::set_settings.
+    pub fn to_config(&self) -> Vec<(&'static str, String)> {
+        vec![("flicker", self.flicker.name().to_string()),
+             ("decay", self.decay.to_string()),
+             ("scanlines", self.scanlines.to_string()),
+             ("foreground", format_color(self.fg)),
+             ("background", format_color(self.bg))]
+    }
+
+}
+
+// Simulates the afterglow of the phosphor of a CRT by keeping track of the
+// brightness of every pixel.
+pub struct Phosphor {
+    intensity: [[f32; GFX_H]; GFX_W],
+}
+
+impl Phosphor {
+
+    pub fn new() -> Self {
+        Phosphor { intensity: [[0.0; GFX_H]; GFX_W] }
+    }
+
+    // Advance by a frame: pixels that are lit glow at full brightness,
+    // while the others lose brightness by the decay factor.
+    pub fn update(&mut self, gfx: &[[bool; GFX_H]; GFX_W], decay: f32) {
+        for x in 0..GFX_W {
+            for y in 0..GFX_H {
+                let level = &mut self.intensity[x][y];
+                *level = if gfx[x][y] {1.0} else {*level * decay};
+                if *level < VISIBLE_THRESHOLD {
+                    *level = 0.0;
+                }
+            }
+        }
+    }
+
+    // The brightness of the pixel, from 0 (off) to 1 (fully lit).
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        self.intensity[x][y]
+    }
+
+    // Whether any pixel that is not lit in gfx is still fading out, so
+    // that further updates would change the picture.
+    pub fn fading(&self, gfx: &[[bool; GFX_H]; GFX_W]) -> bool {
+        (0..GFX_W).any(|x| (0..GFX_H).any(|y| {
+            !gfx[x][y] && self.intensity[x][y] > 0.0
+        }))
+    }
+
+    // Switch off every pixel at once, e.g. when another rom is loaded.
+    pub fn clear(&mut self) {
+        self.intensity = [[0.0; GFX_H]; GFX_W];
+    }
+
+}
+
+// Keeps the last two frames, to show a pixel as lit if it was lit in 
+// either of them.
+pub struct FrameBlend {
+    previous: [[bool; GFX_H]; GFX_W],
+    current: [[bool; GFX_H]; GFX_W],
+}
+
+impl FrameBlend {
+
+    pub fn new() -> Self {
+        FrameBlend { previous: [[false; GFX_H]; GFX_W], 
+                     current: [[false; GFX_H]; GFX_W] }
+    }
+
+    // Take in a new frame, keeping the one before it.
+    pub fn update(&mut self, gfx: &[[bool; GFX_H]; GFX_W]) {
+        self.previous = self.current;
+        self.current = *gfx;
+    }
+
+    pub fn lit(&self, x: usize, y: usize) -> bool {
+        self.previous[x][y] || self.current[x][y]
+    }
+
+    pub fn clear(&mut self) {
+        *self = FrameBlend::new();
+    }
+
+}
+
+// Mix two colour components, where a level of 0 gives the first and a
+// level of 1 the second.
+pub fn mix(from: u8, to: u8, level: f32) -> u8 {
+    let level = level.max(0.0).min(1.0);
+    (from as f32 + (to as f32 - from as f32) * level).round() as u8
+}
+
+// Parse a colour written as six hex digits, optionally preceded by a #, 
+// as in #ffb000.
+pub fn parse_color(text: &str) -> Option<(u8, u8, u8)> {
+    let hex = text.trim();
+    let hex = if hex.starts_with('#') {&hex[1..]} else {hex};
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_digit(16)) {
+        return None;
+    }
+    let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    match (component(0), component(2), component(4)) {
+        (Some(r), Some(g), Some(b)) => Some((r, g, b)),
+        _ => None,
+    }
+}
+
+// Write a colour as parse_color reads it, without the # so that it is not
+// taken for a comment in the configuration file.
+pub fn format_color(color: (u8, u8, u8)) -> String {
+    format!("{:02x}{:02x}{:02x}", color.0, color.1, color.2)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{format_color, mix, parse_color, Effects, Flicker};
+    use super::{FrameBlend, Phosphor};
+    use super::{DEFAULT_BG, DEFAULT_DECAY, VISIBLE_THRESHOLD};
+    use super::super::{GFX_H, GFX_W};
+    use super::super::config::{self, Config};
+
+    #[test]
+    fn test_pixel_lit_once_fades_out() {
+        let mut phosphor = Phosphor::new();
+        let mut gfx = [[false; GFX_H]; GFX_W];
+        //given
+        gfx[3][4] = true;
+        phosphor.update(&gfx, DEFAULT_DECAY);
+        gfx[3][4] = false;
+        //when
+        for _ in 0..5 {
+            phosphor.update(&gfx, DEFAULT_DECAY);
+        }
+        //then
+        // 0.6^5 is still above the threshold, 0.6^6 is not.
+        assert!(phosphor.intensity(3, 4) >= VISIBLE_THRESHOLD);
+        assert!(phosphor.fading(&gfx));
+        phosphor.update(&gfx, DEFAULT_DECAY);
+        assert_eq!(0.0, phosphor.intensity(3, 4));
+        assert!(!phosphor.fading(&gfx));
+    }
+
+    #[test]
+    fn test_lit_pixel_stays_at_full_brightness() {
+        let mut phosphor = Phosphor::new();
+        let mut gfx = [[false; GFX_H]; GFX_W];
+        //given
+        gfx[0][0] = true;
+        //when
+        for _ in 0..10 {
+            phosphor.update(&gfx, DEFAULT_DECAY);
+        }
+        //then
+        assert_eq!(1.0, phosphor.intensity(0, 0));
+        assert!(!phosphor.fading(&gfx));
+    }
+
+    #[test]
+    fn test_flickering_sprite_stays_visible() {
+        let mut phosphor = Phosphor::new();
+        let mut gfx = [[false; GFX_H]; GFX_W];
+        for frame in 0..20 {
+            //when
+            gfx[7][7] = frame % 2 == 0;
+            phosphor.update(&gfx, DEFAULT_DECAY);
+            //then
+            assert!(phosphor.intensity(7, 7) >= DEFAULT_DECAY);
+        }
+    }
+
+    #[test]
+    fn test_blend_shows_sprite_drawn_on_even_frames_every_frame() {
+        let mut blend = FrameBlend::new();
+        let mut gfx = [[false; GFX_H]; GFX_W];
+        for frame in 0..20 {
+            //when
+            gfx[7][7] = frame % 2 == 0;
+            blend.update(&gfx);
+            //then
+            assert!(blend.lit(7, 7));
+            assert!(!blend.lit(7, 8));
+        }
+    }
+
+    #[test]
+    fn test_blend_drops_pixels_after_two_frames() {
+        let mut blend = FrameBlend::new();
+        let mut gfx = [[false; GFX_H]; GFX_W];
+        //given
+        gfx[1][2] = true;
+        blend.update(&gfx);
+        gfx[1][2] = false;
+        //when
+        blend.update(&gfx);
+        //then
+        assert!(blend.lit(1, 2));
+        blend.update(&gfx);
+        assert!(!blend.lit(1, 2));
+    }
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(0x1c, mix(0x1c, 0xff, 0.0));
+        assert_eq!(0xff, mix(0x1c, 0xff, 1.0));
+        assert_eq!(0x8e, mix(0x1c, 0xff, 0.5));
+        assert_eq!(0xff, mix(0x1c, 0xff, 1.5));
+    }
+
+    #[test]
+    fn test_effects_from_config() {
+        //given
+        let config = Config::parse("[display]\n\
+                                    flicker = blend\n\
+                                    decay = 0.8\n").unwrap();
+        //when
+        let effects = Effects::from_config(&config).unwrap();
+        //then
+        assert_eq!(Flicker::Blend, effects.flicker);
+        assert_eq!(0.8, effects.decay);
+        assert!(!effects.scanlines);
+        assert_eq!(DEFAULT_BG, effects.bg);
+        let config = Config::parse("[display]\nforeground = ffb000")
+                                   .unwrap();
+        assert_eq!((0xff, 0xb0, 0x00), 
+                   Effects::from_config(&config).unwrap().fg);
+        let config = Config::parse("[display]\nbackground = black").unwrap();
+        assert!(Effects::from_config(&config).is_err());
+        let config = Config::parse("[display]\ndecay = 1.5").unwrap();
+        assert!(Effects::from_config(&config).is_err());
+        let config = Config::parse("[display]\nflicker = on").unwrap();
+        assert!(Effects::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(Some((0xff, 0xb0, 0x00)), parse_color("#ffb000"));
+        assert_eq!(Some((0x1c, 0x28, 0x41)), parse_color("1C2841"));
+        assert_eq!(None, parse_color("#fff"));
+        assert_eq!(None, parse_color("#ffb00g"));
+        assert_eq!(None, parse_color("+ffb000"));
+        assert_eq!("1c2841", format_color((0x1c, 0x28, 0x41)));
+    }
+
+    #[test]
+    fn test_effects_round_trip() {
+        //given
+        let effects = Effects { flicker: Flicker::Phosphor, decay: 0.75,
+                                scanlines: true, fg: (0xff, 0xb0, 0x00),
+                                bg: (0, 0, 0) };
+        //when
+        let text = config::set_settings("", "display", &effects.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(effects, Effects::from_config(&config).unwrap());
+    }
+
+}
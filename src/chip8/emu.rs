@@ -1,21 +1,57 @@
 extern crate rand;
 
 use super::{GFX_H,GFX_W,Mode};
+use super::clock::{Clock,SystemClock};
+use super::disasm;
+use super::wav::{self, Tone, Waveform};
+use super::display::StringDisplay;
+use super::log::{Level, Log};
+use super::script::{InputScript, ScriptError};
+use self::rand::{Rng, SeedableRng, XorShiftRng};
 use std::default::Default;
 use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
 use std::mem;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
 const SMALL_GFX_W: usize = 64;
 const SMALL_GFX_H: usize = 32;
-
-const MAX_ROM_SIZE: usize = RAM_SIZE - PROGRAM_START;
-const NUM_REGISTERS: usize = 16;
-const PROGRAM_START: usize = 512; 
-const RAM_SIZE: usize = 4096;
-const STACK_SIZE: usize = 16;
+// The SUPER mode resolution. The gfx buffer is a little wider than this, 
+// but only the leftmost 128 columns are addressable by programs.
+const LARGE_GFX_W: usize = 128;
+const LARGE_GFX_H: usize = GFX_H;
+
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - PROGRAM_START;
+pub const NUM_REGISTERS: usize = 16;
+pub const PROGRAM_START: usize = 512; 
+pub const RAM_SIZE: usize = 4096;
+// The most ram an emulator can be built with, as on XO-CHIP.
+pub const MAX_RAM_SIZE: usize = 65536;
+// Addresses are 12 bits wide, unless the ram is larger.
+const ADDR_MASK: u16 = 0x0fff;
+pub const STACK_SIZE: usize = 16;
+// The number of roms that can be kept in banks to switch between.
+pub const NUM_BANKS: usize = 4;
 const NUM_SUPER_MODE_RPL_FLAGS: usize = 8;
-
-const FONT_MAP: [u8; 5 * 16] = [
+// The bitplane held by gfx, the only one implemented so far. XO-CHIP
+// programs select the planes they draw to with a mask of these bits.
+const FIRST_PLANE: u8 = 0b01;
+// The bitplanes of the display in XO-CHIP mode, as far as implemented.
+const XO_CHIP_PLANES: u8 = FIRST_PLANE;
+// The pitch of the beep, for frontends that synthesize it themselves.
+pub const BEEP_FREQUENCY_HZ: f32 = 440.0;
+// The timers count down at 60Hz.
+const TIMER_PERIOD_NANOS: u32 = 1_000_000_000 / 60;
+// Save states start with these bytes, followed by the format version.
+const STATE_MAGIC: &'static [u8] = b"CH8S";
+const STATE_VERSION: u8 = 2;
+
+pub const FONT_MAP: [u8; 5 * 16] = [
     0xf0, 0x90, 0x90, 0x90, 0xf0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xf0, 0x10, 0xf0, 0x80, 0xf0, // 2
@@ -53,6 +89,434 @@ const SUPER_MODE_FONT_MAP: [u8; 10 * 16] = [
     0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff, 0xc0, 0xc0, 0xc0, 0xc0  // F
 ];
 
+// The instructions implemented for CHIP-8, in the notation of Cowgod's
+// Chip-8 Technical Reference. 0nnn (SYS addr) is not supported.
+const CHIP8_OPCODES: [&'static str; 34] = [
+    "CLS", "RET", "JP addr", "CALL addr", "SE Vx, byte", "SNE Vx, byte",
+    "SE Vx, Vy", "LD Vx, byte", "ADD Vx, byte", "LD Vx, Vy", "OR Vx, Vy",
+    "AND Vx, Vy", "XOR Vx, Vy", "ADD Vx, Vy", "SUB Vx, Vy", "SHR Vx, Vy",
+    "SUBN Vx, Vy", "SHL Vx, Vy", "SNE Vx, Vy", "LD I, addr", "JP V0, addr",
+    "RND Vx, byte", "DRW Vx, Vy, nibble", "SKP Vx", "SKNP Vx", "LD Vx, DT",
+    "LD Vx, K", "LD DT, Vx", "LD ST, Vx", "ADD I, Vx", "LD F, Vx", "LD B, Vx",
+    "LD [I], Vx", "LD Vx, [I]",
+];
+
+// The instructions SUPER mode adds on top of CHIP-8.
+const SUPER_OPCODES: [&'static str; 10] = [
+    "SCD nibble", "SCR", "SCL", "EXIT", "LOW", "HIGH", "DRW Vx, Vy, 0",
+    "LD HF, Vx", "LD R, Vx", "LD Vx, R",
+];
+
+// Return the instructions implemented for the mode, e.g. for tools that 
+// want to know what they can rely on.
+pub fn supported_opcodes(mode: Mode) -> Vec<&'static str> {
+    let mut opcodes = CHIP8_OPCODES.to_vec();
+    if mode == Mode::SUPER {
+        opcodes.extend_from_slice(&SUPER_OPCODES);
+    }
+    opcodes
+}
+
+// The registers along with hashes of the memory and display, which tell
+// apart the end states of two runs without keeping the whole machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MachineFingerprint {
+    pub v: [u8; NUM_REGISTERS],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: usize,
+    pub stack: [u16; STACK_SIZE],
+    pub dt: u8,
+    pub st: u8,
+    pub ram_hash: u64,
+    pub display_hash: u64,
+}
+
+// The registers when the program hit the debug trap (00FA), for test roms
+// to report from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugEvent {
+    // The address of the trap.
+    pub pc: u16,
+    pub v: [u8; NUM_REGISTERS],
+    pub i: u16,
+    pub dt: u8,
+    pub st: u8,
+}
+
+// Facts about the loaded rom, for frontends to show or to key save states
+// and per game settings on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RomInfo {
+    // Size of the rom in bytes.
+    pub len: usize,
+    // CRC-32 of the rom, as computed by zip and most rom databases.
+    pub crc32: u32,
+    // The address execution starts at.
+    pub entry_point: u16,
+}
+
+// Executes a single decoded opcode.
+pub type OpcodeHandler = fn(&mut Emu);
+
+// An entry in the dispatch table. Matches any opcode for which 
+// (opcode & mask) == pattern.
+#[derive(Clone)]
+struct OpcodeEntry {
+    mask: u16,
+    pattern: u16,
+    execute: OpcodeHandler,
+}
+
+// Maps opcodes onto their handlers. Entries are grouped by opcode family,
+// i.e. the highest nibble, so that a lookup only needs to consider the few 
+// entries sharing that family. Within a family, the entry registered last 
+// wins, which allows an instruction set to override the handlers of the 
+// instruction set it extends.
+#[derive(Clone)]
+struct DispatchTable {
+    families: Vec<Vec<OpcodeEntry>>,
+}
+
+impl DispatchTable {
+
+    // Create a table with the CHIP-8 and SUPER mode instruction sets
+    // registered.
+    fn new() -> Self {
+        let mut table = DispatchTable { families: vec![Vec::new(); 16] };
+        table.register_chip8();
+        table.register_super();
+        table
+    }
+
+    fn register(&mut self, mask: u16, pattern: u16, execute: OpcodeHandler) {
+        let family = (pattern >> 12) as usize;
+        let entry = OpcodeEntry { mask: mask, pattern: pattern, 
+                                  execute: execute };
+        if mask & 0xf000 == 0xf000 {
+            self.families[family].push(entry);
+        } else {
+            // The mask does not pin down the family, so the entry applies
+            // to all of them.
+            for family in self.families.iter_mut() {
+                family.push(entry.clone());
+            }
+        }
+    }
+
+    fn lookup(&self, opcode: u16) -> Option<OpcodeHandler> {
+        let family = (opcode >> 12) as usize;
+        self.families[family].iter().rev()
+            .find(|entry| opcode & entry.mask == entry.pattern)
+            .map(|entry| entry.execute)
+    }
+
+    // The control opcodes of the 0 family are matched on all of their bits,
+    // except for the low nibble of 00Cn, so that e.g. 00C3 (scroll down by
+    // 3) can not be mistaken for 00E0 and no 0nnn is mistaken for either.
+    fn register_chip8(&mut self) {
+        self.register(0xffff, 0x00e0, Emu::execute_opcode_00e0);
+        self.register(0xffff, 0x00ee, Emu::execute_opcode_00ee);
+        self.register(0xf000, 0x1000, Emu::execute_opcode_1nnn);
+        self.register(0xf000, 0x2000, Emu::execute_opcode_2nnn);
+        self.register(0xf000, 0x3000, Emu::execute_opcode_3xnn);
+        self.register(0xf000, 0x4000, Emu::execute_opcode_4xnn);
+        self.register(0xf00f, 0x5000, Emu::execute_opcode_5xy0);
+        self.register(0xf000, 0x6000, Emu::execute_opcode_6xnn);
+        self.register(0xf000, 0x7000, Emu::execute_opcode_7xnn);
+        self.register(0xf00f, 0x8000, Emu::execute_opcode_8xy0);
+        self.register(0xf00f, 0x8001, Emu::execute_opcode_8xy1);
+        self.register(0xf00f, 0x8002, Emu::execute_opcode_8xy2);
+        self.register(0xf00f, 0x8003, Emu::execute_opcode_8xy3);
+        self.register(0xf00f, 0x8004, Emu::execute_opcode_8xy4);
+        self.register(0xf00f, 0x8005, Emu::execute_opcode_8xy5);
+        self.register(0xf00f, 0x8006, Emu::execute_opcode_8xy6);
+        self.register(0xf00f, 0x8007, Emu::execute_opcode_8xy7);
+        self.register(0xf00f, 0x800e, Emu::execute_opcode_8xye);
+        self.register(0xf000, 0x9000, Emu::execute_opcode_9xy0);
+        self.register(0xf000, 0xa000, Emu::execute_opcode_annn);
+        self.register(0xf000, 0xb000, Emu::execute_opcode_bnnn);
+        self.register(0xf000, 0xc000, Emu::execute_opcode_cxnn);
+        self.register(0xf000, 0xd000, Emu::execute_opcode_dxyn);
+        self.register(0xf00f, 0xe00e, Emu::execute_opcode_ex9e);
+        self.register(0xf00f, 0xe001, Emu::execute_opcode_exa1);
+        self.register(0xf0ff, 0xf007, Emu::execute_opcode_fx07);
+        self.register(0xf0ff, 0xf00a, Emu::execute_opcode_fx0a);
+        self.register(0xf0ff, 0xf015, Emu::execute_opcode_fx15);
+        self.register(0xf0ff, 0xf018, Emu::execute_opcode_fx18);
+        self.register(0xf0ff, 0xf01e, Emu::execute_opcode_fx1e);
+        self.register(0xf0ff, 0xf029, Emu::execute_opcode_fx29);
+        self.register(0xf0ff, 0xf033, Emu::execute_opcode_fx33);
+        self.register(0xf0ff, 0xf055, Emu::execute_opcode_fx55);
+        self.register(0xf0ff, 0xf065, Emu::execute_opcode_fx65);
+        // Not an instruction of any implementation, see enable_debug_opcode.
+        self.register(0xffff, 0x00fa, Emu::execute_opcode_00fa);
+    }
+
+    fn register_super(&mut self) {
+        self.register(0xfff0, 0x00c0, Emu::execute_opcode_00cn);
+        self.register(0xffff, 0x00fb, Emu::execute_opcode_00fb);
+        self.register(0xffff, 0x00fc, Emu::execute_opcode_00fc);
+        self.register(0xffff, 0x00fd, Emu::execute_opcode_00fd);
+        self.register(0xffff, 0x00fe, Emu::execute_opcode_00fe);
+        self.register(0xffff, 0x00ff, Emu::execute_opcode_00ff);
+        self.register(0xf0ff, 0xf030, Emu::execute_opcode_fx30);
+        self.register(0xf0ff, 0xf075, Emu::execute_opcode_fx75);
+        self.register(0xf0ff, 0xf085, Emu::execute_opcode_fx85);
+    }
+
+}
+
+// Behaviours that differ between CHIP-8 implementations. Some roms depend
+// on the behaviour of a particular implementation. The defaults keep the
+// behaviour this emulator has always had.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Quirks {
+    // The AND, OR and XOR opcodes (8xy1, 8xy2 and 8xy3) reset vf to 0, as
+    // on the original COSMAC VIP.
+    pub logic_resets_vf: bool,
+    // The shift opcodes (8xy6 and 8xye) shift vy into vx, as on the 
+    // COSMAC VIP, instead of shifting vx in place.
+    pub shift_uses_vy: bool,
+    // Storing and loading registers (fx55 and fx65) leaves ram_idx 
+    // pointing past the last register, as on the COSMAC VIP.
+    pub load_store_increments_i: bool,
+    // The jump with offset (bnnn) adds vx, where x is the highest nibble 
+    // of nnn, instead of v0, as on the SCHIP.
+    pub jump_uses_vx: bool,
+    // Drawing (dxyn) waits for the next 60Hz tick of the timers after a
+    // sprite has been drawn, as the COSMAC VIP waits for the vertical 
+    // blank. At most one sprite is drawn per frame.
+    pub display_wait: bool,
+    // Sprites drawn across the right or bottom edge of the display (dxyn)
+    // wrap around to the opposite edge, rather than being clipped. Each
+    // axis is set separately, as some platforms wrap along one only.
+    pub wrap_x: bool,
+    pub wrap_y: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks { logic_resets_vf: false, shift_uses_vy: false, 
+                 load_store_increments_i: false, jump_uses_vx: false,
+                 display_wait: false, wrap_x: true, wrap_y: true }
+    }
+}
+
+impl Quirks {
+
+    // The quirks of the original interpreter on the COSMAC VIP, which 
+    // clips sprites at the edges of the display.
+    pub fn cosmac_vip() -> Self {
+        Quirks { logic_resets_vf: true, shift_uses_vy: true, 
+                 load_store_increments_i: true, jump_uses_vx: false,
+                 display_wait: true, wrap_x: false, wrap_y: false }
+    }
+
+    // The quirks of SCHIP 1.1 on the HP48 calculators, which clips sprites
+    // at the edges of the display too.
+    pub fn schip() -> Self {
+        Quirks { logic_resets_vf: false, shift_uses_vy: false, 
+                 load_store_increments_i: false, jump_uses_vx: true,
+                 display_wait: false, wrap_x: false, wrap_y: false }
+    }
+
+    // The quirks most modern roms are written for, as by XO-CHIP: shifts
+    // in place and logic leaving vf alone as on SCHIP, ram_idx left past
+    // the registers stored and loaded as on the COSMAC VIP, no waiting for
+    // the display and sprites wrapping around the edges.
+    pub fn modern() -> Self {
+        Quirks { logic_resets_vf: false, shift_uses_vy: false, 
+                 load_store_increments_i: true, jump_uses_vx: false,
+                 display_wait: false, wrap_x: true, wrap_y: true }
+    }
+
+    // The names by which the quirks are switched on and off, those of 
+    // their fields.
+    pub fn names() -> &'static [&'static str] {
+        &["logic_resets_vf", "shift_uses_vy", "load_store_increments_i",
+          "jump_uses_vx", "display_wait", "wrap_x", "wrap_y"]
+    }
+
+    // Whether the quirk with the name is on, or None if there is no quirk
+    // by that name.
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let mut quirks = *self;
+        quirks.quirk_mut(name).map(|quirk| *quirk)
+    }
+
+    // Switch the quirk with the name on or off. Returns false if there is
+    // no quirk by that name.
+    pub fn set(&mut self, name: &str, on: bool) -> bool {
+        match self.quirk_mut(name) {
+            Some(quirk) => { *quirk = on; true },
+            None => false,
+        }
+    }
+
+    fn quirk_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let quirk = match name {
+            "logic_resets_vf" => &mut self.logic_resets_vf,
+            "shift_uses_vy" => &mut self.shift_uses_vy,
+            "load_store_increments_i" => &mut self.load_store_increments_i,
+            "jump_uses_vx" => &mut self.jump_uses_vx,
+            "display_wait" => &mut self.display_wait,
+            "wrap_x" => &mut self.wrap_x,
+            "wrap_y" => &mut self.wrap_y,
+            _ => return None,
+        };
+        Some(quirk)
+    }
+
+}
+
+// Faults that stop the cpu from executing an instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CpuError {
+    // No handler is registered for the opcode. Holds the opcode and the
+    // address it was fetched from.
+    UnknownOpcode(u16, u16),
+    // A call with every level of the stack in use. Holds the opcode, its 
+    // address and the stack pointer.
+    StackOverflow(u16, u16, usize),
+    // A return with nothing on the stack. Holds the opcode, its address 
+    // and the stack pointer.
+    StackUnderflow(u16, u16, usize),
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CpuError::UnknownOpcode(opcode, pc) =>
+                write!(f, "Unknown opcode: {:x} at {:03x}", opcode, pc),
+            CpuError::StackOverflow(opcode, pc, sp) =>
+                write!(f, "Stack overflow: {:04x} at {:03x} with sp {}", 
+                       opcode, pc, sp),
+            CpuError::StackUnderflow(opcode, pc, sp) =>
+                write!(f, "Stack underflow: {:04x} at {:03x} with sp {}", 
+                       opcode, pc, sp),
+        }
+    }
+}
+
+impl Error for CpuError {
+    fn description(&self) -> &str {
+        "cpu error"
+    }
+}
+
+// What execute_cycle does when the cpu faults.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ErrorPolicy {
+    // Panic with the error, as the emulator has always done.
+    Panic,
+    // Halt the emulator. Cycles do nothing until it is reset.
+    Halt,
+}
+
+impl Default for ErrorPolicy {
+    fn default() -> Self {
+        ErrorPolicy::Panic
+    }
+}
+
+// Reasons why a save state can not be loaded.
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    // The data is not a save state.
+    BadMagic,
+    // The save state was written in a format this version can not read.
+    UnsupportedVersion(u8),
+    // The save state ends before all of the machine state was read.
+    Truncated,
+    // The save state holds a value that is out of range.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StateError::BadMagic => write!(f, "Not a save state"),
+            StateError::UnsupportedVersion(version) =>
+                write!(f, "Unsupported save state version {}", version),
+            StateError::Truncated => write!(f, "Save state is truncated"),
+            StateError::Invalid(what) =>
+                write!(f, "Save state has an invalid {}", what),
+        }
+    }
+}
+
+impl Error for StateError {
+    fn description(&self) -> &str {
+        "invalid save state"
+    }
+}
+
+// Reasons why a rom can not be loaded.
+#[derive(Debug, PartialEq)]
+pub enum LoadError {
+    // The rom does not fit into memory. Holds the size of the rom.
+    TooLarge(usize),
+    // There is no bank with the number.
+    NoSuchBank(usize),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::TooLarge(size) =>
+                write!(f, "Rom is too large: {} bytes, at most {} fit", 
+                       size, MAX_ROM_SIZE),
+            LoadError::NoSuchBank(bank) =>
+                write!(f, "No rom bank {}, there are {}", bank, NUM_BANKS),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn description(&self) -> &str {
+        "rom can not be loaded"
+    }
+}
+
+// Reads the fields of a save state in order.
+struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.bytes.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let b = self.bytes(2)?;
+        Ok((b[0] as u16) << 8 | b[1] as u16)
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let b = self.bytes(4)?;
+        Ok(b.iter().fold(0, |acc, &byte| acc << 8 | byte as u32))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let b = self.bytes(8)?;
+        Ok(b.iter().fold(0, |acc, &byte| acc << 8 | byte as u64))
+    }
+
+}
+
+#[derive(Clone)]
 pub struct Emu {
     
     // Unlike a typical Intel processor, that uses little endian order for multi byte data types,
@@ -70,10 +534,20 @@ pub struct Emu {
     pub keys: [bool; 16],
     // Set when the graphics state has changed and requires a redraw.
     pub draw: bool,
+    // Implementation specific behaviours to emulate.
+    pub quirks: Quirks,
+    // When set, update_timers only counts down once 1/60s has passed since
+    // it last did, so that it may be called more often than that. 
+    // Otherwise every call counts down, which keeps things deterministic.
+    pub wall_clock_timers: bool,
+    // When set, XO-CHIP behaviour applies where it has been implemented:
+    // clearing the screen only clears the selected planes.
+    pub xo_chip: bool,
     // The program instruction to execute. There are 35 opcodes in total,
     // each 2 bytes long. 
     opcode: u16,
-    // There are 4,096 8-bit memory locations making for a total of 4KB RAM. 
+    // There are 4,096 8-bit memory locations making for a total of 4KB RAM,
+    // unless built with more for extended variants such as XO-CHIP.
     // +---------------------+= 0xfff=4095 
     // |                     |
     // |                     |
@@ -97,16 +571,18 @@ pub struct Emu {
     // |                     | 
     // +---------------------+= 0x000=0000 
     //
-    ram: [u8; RAM_SIZE],  
+    ram: Vec<u8>,  
+    // The byte ram was filled with outside the fonts, kept for reset.
+    ram_fill: u8,
     // There are 16 8-bit registers, referred to as v0 to vf: v0 to vE are
     // general purpose while vf stores the carry flag.
     v: [u8; NUM_REGISTERS],            
     // The special purpose 16-bit index register is used to a memory address.
     // Only the lowest (rightmost) 12 bits are usually used.
-    ram_idx: u16,                
+    ram_idx: Addr,                
     // The program counter is used to store the currently executing address.
     // a 'pseudo register' not directly accessible from programs.
-    pc: u16,                
+    pc: Addr,                
     // Special purpose 8-bit register for the delay timer. When value is non-
     // zero, then decremented at a rate of 60Hz.
     dt: u8,
@@ -122,10 +598,113 @@ pub struct Emu {
     // stack[sp-1] <-- top of the stack (where last entry pushed resides)
     // a 'pseudo register' not directly accessible from programs.
     sp: usize,
+    // The bitplanes selected for drawing and clearing in XO-CHIP mode.
+    plane_mask: u8,
+    // For keys pressed by tap_key, the cycles left until they are released.
+    // Zero for keys that are not being tapped.
+    tap_cycles: [u32; 16],
+    // Key presses played back as the cycles they are due at execute.
+    input_script: Option<InputScript>,
+    // Set once a sprite has been drawn, until the timers next count down.
+    // Only used with the display_wait quirk.
+    drawn_since_tick: bool,
     // Super mode flags used by opcodes fx75 and fx85.
     super_mode_rpl_flags: [u8; NUM_SUPER_MODE_RPL_FLAGS],
     // We cache a copy of the rom to allow for convenient reset.
-    rom: Vec<u8>
+    rom: Vec<u8>,
+    // Roms kept to switch between with select_bank, empty until loaded.
+    banks: Vec<Option<Vec<u8>>>,
+    // Maps opcodes onto the functions that execute them.
+    dispatch: DispatchTable,
+    // Number of frames run by run_frame since the last reset.
+    frames: u64,
+    // When tracking self modifying code, marks the addresses that have 
+    // been fetched as code.
+    fetched_as_code: Option<Vec<bool>>,
+    // Set when a program writes over code it ran, below the instruction 
+    // running.
+    self_modified: bool,
+    // When logging writes, every write to ram since, as (address, value, 
+    // pc of the instruction that wrote it).
+    write_log: Option<Vec<(u16, u8, u16)>>,
+    // When tracking collision rows, the rows of the sprite last drawn that
+    // collided with pixels already on, a bit per row.
+    collision_rows: Option<u16>,
+    // When the debug trap is enabled, the traps hit since last taken.
+    debug_events: Option<Vec<DebugEvent>>,
+    // The clock used by wall clock timers, and when they last counted down.
+    clock: Arc<Clock + Send + Sync>,
+    timers_updated_at: Option<Duration>,
+    // Source of the random numbers of cxnn. Seeded from the system unless
+    // a run needs to be reproducible.
+    rng: XorShiftRng,
+    // What execute_cycle does when the cpu faults, and whether it has
+    // halted as a result.
+    on_error: ErrorPolicy,
+    halted: bool,
+    // Set by an opcode handler that can not execute its instruction.
+    fault: Option<CpuError>,
+    // The most recent fault, kept for debuggers.
+    last_error: Option<CpuError>,
+    // Where problems, loads and, at the trace level, every instruction 
+    // executed are logged. Logs nothing unless set.
+    log: Log,
+}
+
+// Builds an emulator with settings that can only be chosen up front. 
+// Emu::new() builds one with the defaults.
+pub struct EmuBuilder {
+    ram_size: usize,
+    ram_fill: u8,
+    seed: Option<u64>,
+}
+
+impl EmuBuilder {
+
+    pub fn new() -> Self {
+        EmuBuilder { ram_size: RAM_SIZE, ram_fill: 0, seed: None }
+    }
+
+    // The size of ram in bytes: a power of two from the 4KB of CHIP-8 up 
+    // to the 64KB of XO-CHIP.
+    pub fn ram_size(mut self, ram_size: usize) -> Self {
+        if !ram_size.is_power_of_two() || ram_size < RAM_SIZE || 
+           ram_size > MAX_RAM_SIZE {
+            panic!("Unsupported ram size: {}", ram_size);
+        }
+        self.ram_size = ram_size;
+        self
+    }
+
+    // The byte that ram holds wherever neither the fonts nor the rom are,
+    // as ram on real hardware holds whatever it holds when switched on. 
+    // Zero unless chosen otherwise.
+    pub fn ram_fill(mut self, ram_fill: u8) -> Self {
+        self.ram_fill = ram_fill;
+        self
+    }
+
+    // Seed the random numbers of cxnn, so that emulators built with the
+    // same seed draw the same numbers. Seeded from the system otherwise.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(self) -> Emu {
+        let mut emu = Emu::default();
+        emu.ram.resize(self.ram_size, 0);
+        emu.ram_fill = self.ram_fill;
+        let fonts_len = FONT_MAP.len() + SUPER_MODE_FONT_MAP.len();
+        for byte in &mut emu.ram[fonts_len..] {
+            *byte = self.ram_fill;
+        }
+        if let Some(seed) = self.seed {
+            emu.seed_rng(seed);
+        }
+        emu
+    }
+
 }
 
 impl Default for Emu {
@@ -134,10 +713,11 @@ impl Default for Emu {
         let mut emu = Emu {
             mode: Mode::STANDARD,
             opcode: 0,
-            ram: [0; RAM_SIZE],  
+            ram: vec![0; RAM_SIZE],  
+            ram_fill: 0,
             v: [0; NUM_REGISTERS],
-            ram_idx: 0,                
-            pc: PROGRAM_START as u16,                
+            ram_idx: Addr::default(),                
+            pc: Addr::new(PROGRAM_START as u16),                
             gfx: [[false; GFX_H]; GFX_W],
             dt: 0,
             st: 0,
@@ -145,8 +725,31 @@ impl Default for Emu {
             sp: 0, 
             keys: [false; 16],
             draw: false,
+            quirks: Quirks::default(),
+            xo_chip: false,
+            plane_mask: FIRST_PLANE,
+            tap_cycles: [0; 16],
+            input_script: None,
+            drawn_since_tick: false,
             super_mode_rpl_flags: [0; NUM_SUPER_MODE_RPL_FLAGS],
             rom: Vec::with_capacity(MAX_ROM_SIZE),
+            banks: vec![None; NUM_BANKS],
+            dispatch: DispatchTable::new(),
+            frames: 0,
+            fetched_as_code: None,
+            self_modified: false,
+            write_log: None,
+            collision_rows: None,
+            debug_events: None,
+            wall_clock_timers: false,
+            clock: Arc::new(SystemClock::new()),
+            timers_updated_at: None,
+            rng: rand::weak_rng(),
+            on_error: ErrorPolicy::default(),
+            halted: false,
+            fault: None,
+            last_error: None,
+            log: Log::off(),
         };
         let mut i = 0;
         for j in 0..FONT_MAP.len() {
@@ -173,6 +776,7 @@ impl Emu {
         if rom.len() > MAX_ROM_SIZE {
             panic!("Program too large to fit into memory");
         }
+        self.log.info(&format!("Loaded a rom of {} bytes", rom.len()));
         self.rom = rom;
         for i in 0..self.rom.len() {
             self.ram[PROGRAM_START+i] = self.rom[i];
@@ -181,923 +785,3221 @@ impl Emu {
 
     // Reset the program to the initial rom state.
     pub fn reset(&mut self) {
-        let stale = mem::replace(self, Emu::new());
+        let ram_size = self.ram.len();
+        let stale = mem::replace(self, EmuBuilder::new().ram_size(ram_size)
+                                                        .ram_fill(self.ram_fill)
+                                                        .build());
+        self.dispatch = stale.dispatch;
+        self.quirks = stale.quirks;
+        self.wall_clock_timers = stale.wall_clock_timers;
+        self.xo_chip = stale.xo_chip;
+        self.clock = stale.clock;
+        self.rng = stale.rng;
+        self.on_error = stale.on_error;
+        self.log = stale.log;
+        self.banks = stale.banks;
+        self.track_self_modify(stale.fetched_as_code.is_some());
+        if stale.write_log.is_some() {
+            self.enable_write_log();
+        }
+        self.track_collision_rows(stale.collision_rows.is_some());
+        self.enable_debug_opcode(stale.debug_events.is_some());
         self.load_rom(stale.rom);
     }
 
-    // Perform a single fetch-decode-execute cycle.
+    // Switch to another rom. Everything is reset as by reset, and the mode
+    // is kept as well. The emulator is left alone if the rom does not fit.
+    pub fn reset_with_rom(&mut self, rom: Vec<u8>) -> Result<(), LoadError> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(self.log_load_error(LoadError::TooLarge(rom.len())));
+        }
+        let mode = self.mode;
+        self.rom = rom;
+        self.reset();
+        self.mode = mode;
+        Ok(())
+    }
+
+    // Keep a rom in a bank, to switch to later with select_bank without
+    // reading it again. The running rom is left alone.
+    pub fn load_bank(&mut self, bank: usize, rom: &[u8]) 
+                     -> Result<(), LoadError> {
+        if bank >= NUM_BANKS {
+            return Err(self.log_load_error(LoadError::NoSuchBank(bank)));
+        }
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(self.log_load_error(LoadError::TooLarge(rom.len())));
+        }
+        self.banks[bank] = Some(rom.to_vec());
+        Ok(())
+    }
+
+    // Reset and run the rom in the bank. The emulator is left alone if 
+    // nothing was loaded into the bank.
+    pub fn select_bank(&mut self, bank: usize) {
+        if let Some(rom) = self.banks.get(bank).and_then(|rom| rom.clone()) {
+            self.rom = rom;
+            self.reset();
+        }
+    }
+
+    fn log_load_error(&self, err: LoadError) -> LoadError {
+        self.log.warn(&err.to_string());
+        err
+    }
+
+    // Addresses wrap at the end of ram, whose size is a power of two.
+    fn addr_mask(&self) -> u16 {
+        (self.ram.len() - 1) as u16
+    }
+
+    // The address in this emulator's ram, wrapped at the end of it.
+    fn addr(&self, addr: u16) -> Addr {
+        Addr::within(addr, self.addr_mask())
+    }
+
+    // The opcode at the address, the byte after it wrapping at the end of
+    // ram. Uses big-endiannes for multi byte data types.
+    fn opcode_at(&self, addr: Addr) -> u16 {
+        let next = self.addr(addr.get().wrapping_add(1));
+        (self.ram[addr.index()] as u16) << 8 | self.ram[next.index()] as u16
+    }
+
+    // The bitplanes selected for drawing and clearing in XO-CHIP mode, one
+    // bit per plane.
+    pub fn plane_mask(&self) -> u8 {
+        self.plane_mask
+    }
+
+    pub fn set_plane_mask(&mut self, mask: u8) {
+        self.plane_mask = mask;
+    }
+
+    // Perform a single fetch-decode-execute cycle. Faults are handled as
+    // set by set_on_error. Does nothing once halted.
     pub fn execute_cycle(&mut self) {
+        if self.halted {
+            return;
+        }
         self.fetch_opcode();
+        if self.log.enabled(Level::Trace) {
+            self.trace_opcode();
+        }
         self.decode_and_execute_opcode();
+        self.release_taps();
+        self.advance_input_script();
     }
 
-    // Update the delay and sound timers.
-    pub fn update_timers(&mut self) {
-        if self.dt > 0 { self.dt -= 1; }
-        if self.st > 0 { self.st -= 1; }
+    // Perform a single fetch-decode-execute cycle, returning any fault 
+    // regardless of the error policy. The instruction that faulted is not
+    // executed and the program counter stays on it.
+    pub fn try_execute_cycle(&mut self) -> Result<(), CpuError> {
+        self.fetch_opcode();
+        if self.log.enabled(Level::Trace) {
+            self.trace_opcode();
+        }
+        let result = self.try_decode_and_execute_opcode();
+        self.release_taps();
+        self.advance_input_script();
+        result
     }
 
-    // Indicates whether the state justifies a beep at this
-    // exact time.
-    pub fn beeping(&self) -> bool {
-        return self.st > 0;
+    // Execute up to n cycles, for test harnesses, returning how many were
+    // executed. Stops early once the program halts: the emulator halted, 
+    // or an instruction jumped to itself, which counts as executed. An 
+    // opcode that faults stops the run with its error, whatever the error
+    // policy, and is not executed.
+    pub fn step_n(&mut self, n: usize) -> Result<usize, CpuError> {
+        for executed in 0..n {
+            if self.halted {
+                return Ok(executed);
+            }
+            let pc = self.pc.get();
+            self.try_execute_cycle()?;
+            if self.opcode == 0x1000 | pc && self.pc.get() == pc {
+                return Ok(executed + 1);
+            }
+        }
+        Ok(n)
     }
-    
-    // Return the gfx width.
-    fn width(&self) -> usize {
-        match self.mode {
-            Mode::STANDARD => SMALL_GFX_W,
-            Mode::SUPER => GFX_W
+
+    // Execute n cycles as fast as possible, for benchmarking. Bypasses the
+    // debugging aids: fetches are not tracked for self modifying code, 
+    // faults are not kept for last_error or logged, though they are still
+    // handled as set by set_on_error, and instructions are not traced.
+    // Otherwise the same as n calls of execute_cycle.
+    pub fn run_burst(&mut self, n: usize) {
+        for _ in 0..n {
+            if self.halted {
+                return;
+            }
+            self.opcode = self.opcode_at(self.pc);
+            let err = match self.dispatch.lookup(self.opcode) {
+                Some(execute) => {
+                    execute(self);
+                    self.fault.take()
+                },
+                None => Some(CpuError::UnknownOpcode(self.opcode, 
+                                                     self.pc.get())),
+            };
+            if let Some(err) = err {
+                match self.on_error {
+                    ErrorPolicy::Panic => panic!("{}", err),
+                    ErrorPolicy::Halt => self.halted = true,
+                }
+            }
+            self.release_taps();
+            self.advance_input_script();
         }
     }
 
-    // Return the gfx height.
-    fn height(&self) -> usize {
-        match self.mode {
-            Mode::STANDARD => SMALL_GFX_H,
-            Mode::SUPER => GFX_H
+    // Press a key and release it again once hold_cycles more cycles have
+    // executed, for frontends that can not tell when a key goes up.
+    // Tapping a key that is still held restarts its countdown, and a hold
+    // of zero releases it straight away.
+    pub fn tap_key(&mut self, key: u8, hold_cycles: u32) {
+        let key = key as usize & 0xf;
+        self.keys[key] = true;
+        self.tap_cycles[key] = hold_cycles;
+        if hold_cycles == 0 {
+            self.keys[key] = false;
         }
     }
-   
-    // Scroll screen n lines down.
-    fn execute_opcode_00cn(&mut self) {
-        let n = (self.opcode & 0x000f) as usize; 
-        for y in (n..GFX_H).rev() {
-            for x in 0..GFX_W { self.gfx[x][y] = self.gfx[x][y-n]; }
-        } 
-        for y in 0..n {
-            for x in 0..GFX_W { self.gfx[x][y] = false; }
-        } 
-        self.draw = true;
-        self.pc = (self.pc + 2) & 0x0fff; 
-    }  
-    
-    // Clear screen.
-    fn execute_opcode_00e0(&mut self) {
-        for x in 0..GFX_W { for y in 0..GFX_H { self.gfx[x][y] = false; } }
-        self.draw = true;
-        self.pc = (self.pc + 2) & 0x0fff; 
-    }  
-    
-    // Return from last subroutine.
-    fn execute_opcode_00ee(&mut self) {
-        self.sp = (self.sp - 1) & (STACK_SIZE - 1); 
-        self.pc = self.stack[self.sp] as u16; 
-        self.pc = (self.pc + 2) & 0x0fff; 
-    } 
 
-    // Scroll screen 4 pixels right.
-    fn execute_opcode_00fb(&mut self) {
-        for y in 0..GFX_H {
-            for x in (4..GFX_W).rev() { self.gfx[x][y] = self.gfx[x-4][y] }
-            for x in 0..4 { self.gfx[x][y] = false; }
+    // Press and release keys as the script says, as cycles execute from
+    // now on. Events at cycle 0 play straight away. See InputScript for 
+    // the format.
+    pub fn load_input_script(&mut self, script: &str) 
+                             -> Result<(), ScriptError> {
+        let mut script = InputScript::parse(script)?;
+        for event in script.due() {
+            self.keys[event.key as usize] = event.down;
         }
-        self.draw = true;
-        self.pc = (self.pc + 2) & 0x0fff; 
+        self.input_script = Some(script);
+        Ok(())
     }
 
-    // Scroll screen 4 pixels left. 
-    fn execute_opcode_00fc(&mut self) {
-        for y in 0..GFX_H {
-            for x in 0..(GFX_W - 4) { self.gfx[x][y] = self.gfx[x+4][y] }
-            for x in (GFX_W-4)..GFX_W { self.gfx[x][y] = false; }
+    // Count a cycle of the input script, if there is one, and play the key
+    // events now due.
+    fn advance_input_script(&mut self) {
+        if let Some(ref mut script) = self.input_script {
+            script.advance();
+            for event in script.due() {
+                self.keys[event.key as usize] = event.down;
+            }
         }
-        self.draw = true;
-        self.pc = (self.pc + 2) & 0x0fff; 
     }
 
-    // Meant to exit, but we will reset instead.
-    fn execute_opcode_00fd(&mut self) {
-        self.reset();
-    } 
-    
-    // Disable SUPER mode. 
-    fn execute_opcode_00fe(&mut self) {
-        self.mode = Mode::STANDARD;
-        self.pc = (self.pc + 2) & 0x0fff; 
-    } 
-    
-    // Enable SUPER mode. 
-    fn execute_opcode_00ff(&mut self) {
-        self.mode = Mode::SUPER;
-        self.pc = (self.pc + 2) & 0x0fff; 
-    } 
-    
-    // Jump to address nnn.
-    fn execute_opcode_1nnn(&mut self) {
-        let nnn = self.opcode & 0x0fff; 
-        self.pc = nnn; 
+    pub fn is_key_down(&self, key: u8) -> bool {
+        self.keys[key as usize & 0xf]
     }
 
-    // Call subroutine at nnn.
-    fn execute_opcode_2nnn(&mut self) {
-        let nnn = self.opcode & 0x0fff;
-        self.stack[self.sp] = self.pc as u16; 
-        self.sp = (self.sp + 1) & (STACK_SIZE - 1); 
-        self.pc = nnn;
+    // Count down the keys being tapped, releasing those whose time is up.
+    fn release_taps(&mut self) {
+        for key in 0..self.tap_cycles.len() {
+            if self.tap_cycles[key] > 0 {
+                self.tap_cycles[key] -= 1;
+                if self.tap_cycles[key] == 0 {
+                    self.keys[key] = false;
+                }
+            }
+        }
     }
 
-    // Skip the next instruction if vx equals nn.
-    fn execute_opcode_3xnn(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let nn = self.opcode & 0x00ff; 
-        self.pc += if self.v[x as usize] == nn as u8 {4} else {2}; 
+    // Choose between panicking and halting when execute_cycle faults.
+    pub fn set_on_error(&mut self, policy: ErrorPolicy) {
+        self.on_error = policy;
     }
 
-    // Skip the next instruction if vx does not equal nn.
-    fn execute_opcode_4xnn(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let nn = self.opcode & 0x00ff; 
-        self.pc += if self.v[x as usize] != nn as u8 {4} else {2}; 
+    // Whether the emulator halted on a fault. Cleared by a reset.
+    pub fn halted(&self) -> bool {
+        self.halted
     }
 
-    // Skip the next instruction if vx equals vy.
-    fn execute_opcode_5xy0(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.pc += if self.v[x as usize] == self.v[y as usize] {4} else {2};
+    // The most recent fault, whether it was returned by try_execute_cycle
+    // or handled by execute_cycle. Cleared by a reset.
+    pub fn last_error(&self) -> Option<&CpuError> {
+        self.last_error.as_ref()
     }
 
-    // Set vx to nn.
-    fn execute_opcode_6xnn(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let nn = self.opcode & 0x00ff; 
-        self.v[x as usize] = nn as u8; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Seed the random numbers of cxnn, so that runs can be repeated
+    // exactly.
+    pub fn seed_rng(&mut self, seed: u64) {
+        // The xorshift seed must not be all zeros.
+        let (lo, hi) = (seed as u32, (seed >> 32) as u32);
+        self.rng = XorShiftRng::from_seed([lo, hi, lo ^ 0x9e3779b9, 
+                                           hi ^ 0x7f4a7c15]);
     }
 
-    // Add nn to vx.
-    fn execute_opcode_7xnn(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let nn = self.opcode & 0x00ff; 
-        self.v[x as usize] = self.v[x as usize].wrapping_add(nn as u8);
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Summarise the machine state, for comparing runs.
+    pub fn fingerprint(&self) -> MachineFingerprint {
+        MachineFingerprint {
+            v: self.v,
+            i: self.ram_idx.get(),
+            pc: self.pc.get(),
+            sp: self.sp,
+            stack: self.stack,
+            dt: self.dt,
+            st: self.st,
+            ram_hash: fnv1a(&self.ram),
+            display_hash: self.display_hash(),
+        }
     }
 
-    // Set vx to the value of vy.
-    fn execute_opcode_8xy0(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.v[x as usize] = self.v[y as usize]; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Replace the clock used by wall clock timers, e.g. with a mock clock.
+    pub fn set_clock(&mut self, clock: Arc<Clock + Send + Sync>) {
+        self.clock = clock;
+        self.timers_updated_at = None;
     }
 
-    // Set vx to vx OR vy.
-    fn execute_opcode_8xy1(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.v[x as usize] |= self.v[y as usize]; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Log to the logger from now on, up to its level.
+    pub fn set_log(&mut self, log: Log) {
+        self.log = log;
     }
 
-    // Set vx to vx AND vy.
-    fn execute_opcode_8xy2(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.v[x as usize] &= self.v[y as usize]; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    pub fn log(&self) -> &Log {
+        &self.log
     }
 
-    // Set vx to vx XOR vy.
-    fn execute_opcode_8xy3(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.v[x as usize] ^= self.v[y as usize]; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Update the delay and sound timers. With wall clock timers, calls 
+    // made within 1/60s of the last count down are ignored.
+    pub fn update_timers(&mut self) {
+        if self.wall_clock_timers {
+            let period = Duration::new(0, TIMER_PERIOD_NANOS);
+            let now = self.clock.now();
+            if let Some(updated_at) = self.timers_updated_at {
+                let elapsed = now - updated_at;
+                if elapsed < period {
+                    return;
+                }
+                // Stay in step with the 60Hz beat, unless calls have been
+                // so far apart that it was lost.
+                self.timers_updated_at = Some(
+                    if elapsed < period * 2 {updated_at + period} else {now});
+            } else {
+                self.timers_updated_at = Some(now);
+            }
+        }
+        if self.dt > 0 { self.dt -= 1; }
+        if self.st > 0 { self.st -= 1; }
+        self.drawn_since_tick = false;
     }
 
-    // Add vy to vx and set vf to 1 if there was a carry, 0 otherwise. 
-    fn execute_opcode_8xy4(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        let vx = self.v[x as usize]; 
-        let vy = self.v[y as usize]; 
-        self.v[x as usize] = vx.wrapping_add(vy); 
-        let carried = (vx as u16 + vy as u16) > 0xff;
-        self.v[0x0f] = if carried {1} else {0}; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Run a single 60Hz frame: execute the given number of cycles and then
+    // update the timers once.
+    pub fn run_frame(&mut self, cycles_per_frame: usize) {
+        for _ in 0..cycles_per_frame {
+            self.execute_cycle();
+        }
+        self.update_timers();
+        self.frames += 1;
     }
 
-    // Subtract vy from vx. Set vf to 0 if there was a borrow, 1 otherwise.
-    fn execute_opcode_8xy5(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        let vx = self.v[x as usize];
-        let vy = self.v[y as usize];
-        self.v[x as usize] = vx.wrapping_sub(vy); 
-        let borrowed = vy > vx;
-        self.v[0x0f] = if borrowed {0} else {1}; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Run frames until the frame count reaches the given frame. Does 
+    // nothing if it already has. Since no wall clock time is involved, the 
+    // resulting state is reproducible, e.g. for screenshot tests.
+    pub fn run_to_frame(&mut self, frame: u64, cycles_per_frame: usize) {
+        while self.frames < frame {
+            self.run_frame(cycles_per_frame);
+        }
     }
 
-    // There is some difference in opinion on how this opcode should
-    // be implemented. See http://mattmik.com/emu.html
-    //
-    // This implementation mirrors the behavior of the original interpreter.
-    //
-    // Store the value of register vy shifted right one bit in register vx.
-    // Set register vf to the least significant bit prior to the shift.
-    #[allow(dead_code)]
-    fn execute_opcode_8xy6_orig_not_used(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.v[0x0f] = self.v[y as usize] & 0x01;
-        self.v[x as usize] = self.v[y as usize] >> 1; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Execute cycles until something is drawn, for drawing only when 
+    // needed. Returns true once draw is set, leaving it set, or false if 
+    // nothing was drawn within max_cycles. Returns true straight away if 
+    // draw was already set, so callers clear it after drawing.
+    pub fn run_until_draw(&mut self, max_cycles: usize) -> bool {
+        for _ in 0..max_cycles {
+            if self.draw {
+                return true;
+            }
+            self.execute_cycle();
+        }
+        self.draw
     }
 
-    // There is some difference in opinion on how this opcode should
-    // be implemented. See http://mattmik.com/emu.html
-    //
-    // This implementation follows the most recent descriptions of the 
-    // instruction set. This implementation (perhaps erroneous) were
-    // what a majority of programmers had in mind. As a result, it seems
-    // to work with a majority of roms. A significant number of the more
-    // complex roms, e.g. Space Invaders, will ONLY work with this 
-    // implementation.
-    //
-    // Shifts vx right by one. Set vf to the value of the least significant
-    // bit of vx before the shift. 
-    fn execute_opcode_8xy6(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        self.v[0x0f] = self.v[x as usize] & 0b00000001;
-        self.v[x as usize] >>= 1;
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Return the number of frames run since the last reset.
+    pub fn frame_count(&self) -> u64 {
+        self.frames
     }
 
-    // Set vx to vy minus vx. Set vf to 0 if there was a borrow, 1 otherwise.
-    fn execute_opcode_8xy7(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        let vx = self.v[x as usize];
-        let vy = self.v[y as usize];
-        self.v[x as usize] = vy.wrapping_sub(vx); 
-        let borrowed = vx > vy; 
-        self.v[0x0f] = if borrowed {0} else {1}; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Return a hash of the pixels in the active display region, which
+    // makes it easy to compare frames. Uses 64 bit FNV-1a, so hashes are
+    // stable across platforms and versions.
+    pub fn display_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                hash ^= self.gfx[x][y] as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        hash
     }
 
-    // There is some difference in opinion on how this opcode should
-    // be implemented. See http://mattmik.com/emu.html
-    //
-    // This implementation mirrors the behavior of the original interpreter.
-    // 
-    // Store the value of register vy shifted left one bit in register vx.
-    // Set register vf to the most significant bit prior to the shift.
-    #[allow(dead_code)]
-    fn execute_opcode_8xye_orig_not_used(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.v[0x0f] = (self.v[y as usize] >> 7) & 0b00000001;
-        self.v[x as usize] = self.v[y as usize] << 1; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // The smallest box around the pixels that are on in the active display
+    // region, as (min x, min y, max x, max y) with the maxima inclusive, or
+    // None if the display is blank. Lets tests check where a sprite went
+    // without comparing every pixel.
+    pub fn bounding_box(&self) -> Option<(usize, usize, usize, usize)> {
+        let (width, height) = self.display_dimensions();
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        for x in 0..width {
+            for y in (0..height).filter(|&y| self.gfx[x][y]) {
+                bounds = Some(match bounds {
+                    Some((x0, y0, x1, y1)) => 
+                        (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                    None => (x, y, x, y),
+                });
+            }
+        }
+        bounds
     }
 
-    // There is some difference in opinion on how this opcode should
-    // be implemented. See http://mattmik.com/emu.html
-    //
-    // This implementation follows the most recent descriptions of the 
-    // instruction set. This implementation (perhaps erroneous) were
-    // what a majority of programmers had in mind. As a result, it seems
-    // to work with a majority of roms. A significant number of the more
-    // complex roms, e.g. Space Invaders, will ONLY work with this 
-    // implementation.
-    //
-    // Shift vx left by one. Set vf to the value of the most significant bit
-    // of vx before the shift. Notice that vy is completely ignored. 
-    fn execute_opcode_8xye(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        self.v[0x0f] = (self.v[x as usize] >> 7) & 0b00000001; 
-        self.v[x as usize] <<= 1; 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Write the active display region as a binary PBM (P4) image, for 
+    // screenshots. Pixels that are on are black, as PBM has it, and each 
+    // row is packed into bytes with the leftmost pixel in the top bit.
+    pub fn write_pbm<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (width, height) = self.display_dimensions();
+        write!(w, "P4\n{} {}\n", width, height)?;
+        let plane_len = (width + 7) / 8 * height;
+        w.write_all(&self.framebuffer_packed()[..plane_len])
     }
 
-    // Skip the next instruction if vx does not equal vy.
-    fn execute_opcode_9xy0(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let y = (self.opcode & 0x00f0) >> 4; 
-        self.pc += if self.v[x as usize] != self.v[y as usize] {4} else {2};
-        self.pc &= 0x0fff;
+    // The number of bitplanes of the display. CHIP-8 and SCHIP have one,
+    // XO-CHIP more, but only its first is implemented so far.
+    pub fn plane_count(&self) -> u8 {
+        let planes = if self.xo_chip {XO_CHIP_PLANES} else {FIRST_PLANE};
+        planes.count_ones() as u8
     }
 
-    // Set ram_idx to the address nnn.
-    fn execute_opcode_annn(&mut self) {
-        let nnn = self.opcode & 0x0fff; 
-        self.ram_idx = nnn; 
-        self.pc = (self.pc + 2) & 0x0fff; 
-    } 
+    // The active display region packed into bytes, a row at a time with 
+    // the leftmost pixel in the top bit. With more than one bitplane, the
+    // planes follow each other in order.
+    pub fn framebuffer_packed(&self) -> Vec<u8> {
+        let (width, height) = self.display_dimensions();
+        let row_len = (width + 7) / 8;
+        let mut packed = vec![0u8; row_len * height * 
+                                   self.plane_count() as usize];
+        // The first plane is the only one implemented, and is held by gfx.
+        for y in 0..height {
+            for x in 0..width {
+                if self.gfx[x][y] {
+                    packed[y * row_len + x / 8] |= 0x80 >> (x % 8);
+                }
+            }
+        }
+        packed
+    }
 
-    // Jump to the address nnn plus v0.
-    fn execute_opcode_bnnn(&mut self) {
-        let nnn = self.opcode & 0x0fff; 
-        self.pc = (nnn + (self.v[0] as u16)) & 0x0fff; 
-    } 
+    // Start or stop keeping track of the addresses fetched as code, so 
+    // that programs writing over their own code can be detected. Off by 
+    // default, since it costs a little on every cycle.
+    pub fn track_self_modify(&mut self, on: bool) {
+        self.fetched_as_code = if on {
+            Some(vec![false; self.ram.len()])
+        } else {
+            None
+        };
+        self.self_modified = false;
+    }
 
-    // Set vx to a random number and nn.
-    fn execute_opcode_cxnn(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let nn = self.opcode & 0x00ff; 
-        self.v[x as usize] = rand::random::<u8>() & (nn as u8); 
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Return whether the program has written to an address below the 
+    // instruction running that was earlier fetched as code since the last
+    // call, e.g. to let a debugger know that its disassembly is stale. 
+    // Always false unless tracking.
+    pub fn took_self_modify(&mut self) -> bool {
+        mem::replace(&mut self.self_modified, false)
     }
 
-    // Draw 8xn sprite from ram[ram_idx] at gfx[vx][vy]. Set vf to 1 if
-    // any set pixels are changed to unset, and 0 otherwise. If n is 0 and
-    // in SUPER mode, then show 16x16 sprite instead.
-    fn execute_opcode_dxyn(&mut self) {
-        let gfx_start_x = self.v[(self.opcode as usize & 0x0f00) >> 8] as usize;
-        let gfx_start_y = self.v[(self.opcode as usize & 0x00f0) >> 4] as usize;
-        let n = (self.opcode & 0x000f) as usize; 
-        let sprt_w = if n == 0 && self.mode == Mode::SUPER {16} else {8};
-        let sprt_h = if n == 0 {16} else {n};
-        let sprt_bytes_per_row = sprt_w / 8; 
-        self.v[0x0f] = 0x00;
-        for y_offset in 0..sprt_h {
-            for sprt_byte_col_idx in 0..sprt_bytes_per_row {
-                let sprt_byte_ram_idx = self.ram_idx as usize + 
-                    y_offset * sprt_bytes_per_row;
-                let sprt_byte: u8 = self.ram[sprt_byte_ram_idx]; 
-                for sprt_byte_bit_idx in 0..8 as usize {
-                    let x_offset = sprt_byte_col_idx * 8 + sprt_byte_bit_idx;
-                    // Drawing beyond max width and height will wrap.
-                    let gfx_x = (gfx_start_x + x_offset) % self.width();
-                    let gfx_y = (gfx_start_y + y_offset) % self.height(); 
-                    // Mask to obtain single bit from byte. 
-                    let mask = 0b_1000_0000_u8 >> sprt_byte_bit_idx; 
-                    let sprt_pix = sprt_byte & mask != 0;
-                    if sprt_pix == true {
-                        let gfx_pix = &mut self.gfx[gfx_x][gfx_y];
-                        *gfx_pix ^= true;
-                        if *gfx_pix == true {
-                            // Reduce flicker and draw only when pix switched on. 
-                            self.draw = true;
-                        } else {
-                            self.v[0x0f] = 0x01;
-                        } 
-                    }
-                }
-            } 
-        }
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Start logging every write the program makes to ram from now on, 
+    // e.g. for tests to check the memory effects of a routine. Logging 
+    // again starts over with an empty log.
+    pub fn enable_write_log(&mut self) {
+        self.write_log = Some(Vec::new());
     }
-    
-    // Skip the next instruction if the key stored in vx is pressed.
-    fn execute_opcode_ex9e(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let key_pressed = self.keys[self.v[x as usize] as usize];
-        self.pc = (self.pc + if key_pressed {4} else {2}) & 0x0fff;
+
+    // The writes to ram logged since enable_write_log, in the order they
+    // were made, as (address, value, pc). Empty unless logging.
+    pub fn write_log(&self) -> &[(u16, u8, u16)] {
+        self.write_log.as_ref().map_or(&[], |log| &log[..])
     }
 
-    // Skips the next instruction if the key stored in vx is not pressed.
-    fn execute_opcode_exa1(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        let key_pressed = self.keys[self.v[x as usize] as usize];
-        self.pc = (self.pc + if !key_pressed {4} else {2}) & 0x0fff;
+    // Start or stop keeping track of the rows of each sprite drawn that
+    // collide, for looking into where sprites overlap. Off by default.
+    pub fn track_collision_rows(&mut self, on: bool) {
+        self.collision_rows = if on {Some(0)} else {None};
     }
 
-    // Set vx to the value of the delay timer.
-    fn execute_opcode_fx07(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        self.v[x as usize] = self.dt;
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // The rows of the sprite drawn last that collided with pixels already
+    // on, with row 0 in the lowest bit. Always 0 unless tracking.
+    pub fn last_draw_collision_rows(&self) -> u16 {
+        self.collision_rows.unwrap_or(0)
     }
 
-    // Wait for a keypress then store it in vx.
-    // This implementation will only advance the program counter
-    // if a keypress is found. In other words, this opcode will
-    // execute over and over until a keypress is found. This allows
-    // opportunity for a keypress to arrive in between executions.
-    fn execute_opcode_fx0a(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8; 
-        for i in 0..self.keys.len() {
-            if self.keys[i] {
-                self.v[x as usize] = i as u8;
-                self.pc = (self.pc + 2) & 0x0fff; 
+    // Enable or disable the debug trap, 00FA, which is not an instruction 
+    // of any CHIP-8 implementation and can be put into test roms e.g. with
+    // Octo. Each time the program hits it, its registers are queued up for
+    // take_debug_events. Disabled by default, when 00FA is an unknown 
+    // opcode as before, so that it never fires for normal roms.
+    pub fn enable_debug_opcode(&mut self, on: bool) {
+        self.debug_events = if on {Some(Vec::new())} else {None};
+    }
+
+    // The debug traps hit since the last call, oldest first. Always empty
+    // unless the trap is enabled.
+    pub fn take_debug_events(&mut self) -> Vec<DebugEvent> {
+        self.debug_events.as_mut().map_or(Vec::new(), 
+                                          |events| mem::replace(events, 
+                                                                Vec::new()))
+    }
+
+    // Capture the machine state as a save state. Quirks and registered 
+    // opcode handlers are configuration rather than state and are not 
+    // included. Multi byte values are stored big endian.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+        state.push(match self.mode { Mode::STANDARD => 0, Mode::SUPER => 1 });
+        push_u16(&mut state, self.opcode);
+        // The size of ram comes first, as it varies between emulators.
+        push_u32(&mut state, self.ram.len() as u32);
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&self.v);
+        push_u16(&mut state, self.ram_idx.get());
+        push_u16(&mut state, self.pc.get());
+        state.push(self.dt);
+        state.push(self.st);
+        for &addr in self.stack.iter() {
+            push_u16(&mut state, addr);
+        }
+        state.push(self.sp as u8);
+        // Pixels are packed 8 to a byte, column by column.
+        for column in self.gfx.iter() {
+            for pixels in column.chunks(8) {
+                let byte = pixels.iter()
+                                 .fold(0, |acc, &on| acc << 1 | on as u8);
+                state.push(byte);
             }
         }
+        for &key in self.keys.iter() {
+            state.push(key as u8);
+        }
+        state.push(self.draw as u8);
+        state.extend_from_slice(&self.super_mode_rpl_flags);
+        push_u16(&mut state, self.rom.len() as u16);
+        state.extend_from_slice(&self.rom);
+        for shift in (0..8).rev() {
+            state.push((self.frames >> (shift * 8)) as u8);
+        }
+        state
     }
 
-    // Set the delay timer to vx.
-    fn execute_opcode_fx15(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        self.dt = self.v[x as usize];
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Restore the machine state from a save state taken by save_state. The
+    // state is read into a fresh emulator first, so that a truncated or 
+    // corrupt save state leaves this one as it was.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut restored = EmuBuilder::new().ram_size(self.ram.len()).build();
+        restored.read_state(state)?;
+        self.mode = restored.mode;
+        self.opcode = restored.opcode;
+        self.ram = restored.ram;
+        self.v = restored.v;
+        self.ram_idx = restored.ram_idx;
+        self.pc = restored.pc;
+        self.dt = restored.dt;
+        self.st = restored.st;
+        self.stack = restored.stack;
+        self.sp = restored.sp;
+        self.gfx = restored.gfx;
+        self.keys = restored.keys;
+        self.draw = restored.draw;
+        self.super_mode_rpl_flags = restored.super_mode_rpl_flags;
+        self.rom = restored.rom;
+        self.frames = restored.frames;
+        Ok(())
     }
 
-    // Set the sound timer to vx.
-    fn execute_opcode_fx18(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        self.st = self.v[x as usize];
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Read the machine state from a save state, giving up at the first
+    // problem.
+    fn read_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader { bytes: state };
+        if reader.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        self.mode = match reader.u8()? {
+            0 => Mode::STANDARD,
+            1 => Mode::SUPER,
+            _ => return Err(StateError::Invalid("mode")),
+        };
+        self.opcode = reader.u16()?;
+        let ram_size = self.ram.len();
+        if reader.u32()? as usize != ram_size {
+            return Err(StateError::Invalid("ram size"));
+        }
+        self.ram.copy_from_slice(reader.bytes(ram_size)?);
+        self.v.copy_from_slice(reader.bytes(NUM_REGISTERS)?);
+        self.ram_idx = self.addr(reader.u16()?);
+        self.pc = self.addr(reader.u16()?);
+        self.dt = reader.u8()?;
+        self.st = reader.u8()?;
+        for i in 0..STACK_SIZE {
+            self.stack[i] = self.addr(reader.u16()?).get();
+        }
+        self.sp = reader.u8()? as usize;
+        if self.sp > STACK_SIZE {
+            return Err(StateError::Invalid("stack pointer"));
+        }
+        for x in 0..GFX_W {
+            for (i, &byte) in reader.bytes(GFX_H / 8)?.iter().enumerate() {
+                for bit in 0..8 {
+                    self.gfx[x][i * 8 + bit] = byte & (0x80 >> bit) != 0;
+                }
+            }
+        }
+        for i in 0..self.keys.len() {
+            self.keys[i] = reader.u8()? != 0;
+        }
+        self.draw = reader.u8()? != 0;
+        self.super_mode_rpl_flags.copy_from_slice(
+            reader.bytes(NUM_SUPER_MODE_RPL_FLAGS)?);
+        let rom_len = reader.u16()? as usize;
+        if rom_len > MAX_ROM_SIZE {
+            return Err(StateError::Invalid("rom size"));
+        }
+        self.rom = reader.bytes(rom_len)?.to_vec();
+        self.frames = reader.u64()?;
+        Ok(())
     }
 
-    // Add vx to ram_idx. Set vf to 1 if there was a range overflow,
-    // ram_idx + vx > 0x0fff, 0 otherwise.
-    fn execute_opcode_fx1e(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        let sum  = self.ram_idx + self.v[x as usize] as u16;
-        let overflowed = sum > 0x0fff;
-        self.v[0xf as usize] = if overflowed {1} else {0};
-        self.ram_idx = sum % (0x0fff + 1);
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Write a save state to the file at the given path.
+    pub fn save_state_to_path<P: AsRef<Path>>(&self, path: P) 
+                                              -> io::Result<()> {
+        File::create(path)?.write_all(&self.save_state())
     }
 
-    // Set ram_idx to the location of the sprite for the character in vx. 
-    // Characters 0-F are represented by a 4x5 font.
-    fn execute_opcode_fx29(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        let fchar = self.v[x as usize];
-        self.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        self.pc = (self.pc + 2) & 0x0fff; 
-    } 
+    // Restore the machine state from the save state in the file at the
+    // given path. A file that is not a valid save state is reported as
+    // invalid data.
+    pub fn load_state_from_path<P: AsRef<Path>>(&mut self, path: P) 
+                                                -> io::Result<()> {
+        let mut state = Vec::new();
+        File::open(path)?.read_to_end(&mut state)?;
+        self.load_state(&state)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
 
-    // Set ram_idx to the location of the sprite for the character in vx, where
-    // x must be in the range 0 to 9 (inclusive). Characters 0-F are 
-    // represented by a 8x10 font.
-    fn execute_opcode_fx30(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        let fchar = self.v[x as usize];
-        self.ram_idx = 0x0000 + (fchar as u16) * 10; 
-        self.pc = (self.pc + 2) & 0x0fff; 
-    } 
+    // The bytes of a sprite of the given height at the address, as dxyn
+    // would draw it, e.g. for a sprite viewer. Cut short at the end of 
+    // memory.
+    pub fn read_sprite(&self, addr: u16, height: u8) -> Vec<u8> {
+        let start = (addr as usize).min(self.ram.len());
+        let end = (start + height as usize).min(self.ram.len());
+        self.ram[start..end].to_vec()
+    }
 
-    // Store the binary-coded decimal (BCD) representation of vx, with the
-    // most significant of three digits at the address in ram_idx, the middle 
-    // digit at ram_idx plus 1, and the least siginificant digit at ram_idx 
-    // plus 2. In other words, take the decimal representation of vx, place 
-    // the hundreds digit in memory at location in ram_idx, the tens digits 
-    // at location ram_idx+1, and the ones digit at location ram_idx+2.
-    fn execute_opcode_fx33(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        let mut vx = self.v[x as usize];
-        let ones = vx % 10;
-        vx /= 10;
-        let tens = vx % 10;
-        vx /= 10;
-        let hundreds = vx % 10;
-        self.ram[(self.ram_idx+0) as usize] = hundreds as u8;
-        self.ram[(self.ram_idx+1) as usize] = tens as u8;
-        self.ram[(self.ram_idx+2) as usize] = ones as u8;
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // The rom that was loaded, as it was before the program ran.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
     }
 
-    // Store v0 to vx in memory starting at address ram_idx.
-    fn execute_opcode_fx55(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        for i in 0..(x as u16) + 1 {
-            self.ram[(self.ram_idx+i) as usize] = self.v[i as usize];
-        }
-        self.pc = (self.pc + 2) & 0x0fff; 
+    pub fn rom_info(&self) -> RomInfo {
+        RomInfo { len: self.rom.len(), crc32: crc32(&self.rom),
+                  entry_point: PROGRAM_START as u16 }
     }
 
-    // Fill v0 to vx with values from memory starting at address ram_idx.
-    fn execute_opcode_fx65(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        for i in 0..(x as u16) + 1 {
-            self.v[i as usize] = self.ram[(self.ram_idx+i) as usize];
-        }
-        self.pc = (self.pc + 2) & 0x0fff; 
+    // Indicates whether the state justifies a beep at this
+    // exact time.
+    pub fn beeping(&self) -> bool {
+        return self.st > 0;
     }
 
-    // Store v0 to vx in super_mode_rpl_flags user flags (x <= 7).
-    fn execute_opcode_fx75(&mut self) {
-        let x = (self.opcode & 0x0f00) >> 8;
-        for i in 0..(cmp::min(x,7) as u16) + 1 {
-            self.super_mode_rpl_flags[i as usize] = self.v[i as usize];
+    // Return the pitch at which to beep, or None if there should be 
+    // silence. Classic CHIP-8 has a single fixed pitch.
+    pub fn beep_frequency(&self) -> Option<f32> {
+        if self.beeping() {Some(BEEP_FREQUENCY_HZ)} else {None}
+    }
+
+    // Return how many samples at the sample rate the beep lasts for, with
+    // the sound timer counting down at 60Hz. A frontend can queue these
+    // in one go instead of checking for the beep every frame.
+    pub fn beep_samples_this_frame(&self, sample_rate: u32) -> usize {
+        self.st as usize * sample_rate as usize / 60
+    }
+
+    // Return the audio of this frame, ready to be queued: a 60th of a 
+    // second at the sample rate, of a square wave while beeping and of 
+    // silence otherwise. The wave is in phase with that of the frames 
+    // before, so that a beep lasting several frames does not click.
+    pub fn next_audio_frame(&self, sample_rate: u32) -> Vec<i16> {
+        let samples = sample_rate as usize / 60;
+        if !self.beeping() {
+            return vec![0; samples];
         }
-        self.pc = (self.pc + 2) & 0x0fff; 
+        let mut tone = Tone::new(Waveform::Square, BEEP_FREQUENCY_HZ, 
+                                 sample_rate as usize);
+        let periods = (self.frame_count() as f64 * samples as f64) * 
+                      BEEP_FREQUENCY_HZ as f64 / sample_rate as f64;
+        tone.set_phase(periods.fract() as f32);
+        tone.take(samples).map(wav::sample_i16).collect()
     }
 
-    // Fill v0 to vx with values from super_mode_rpl_flags (x <= 7).
+    // Return the value of register vx.
+    pub fn register(&self, x: usize) -> u8 {
+        self.v[x]
+    }
+
+    // Return the registers v0 to vf.
+    pub fn registers(&self) -> &[u8; NUM_REGISTERS] {
+        &self.v
+    }
+
+    // Set the registers v0 to vf, e.g. to start a test from a known state.
+    pub fn set_registers(&mut self, v: [u8; NUM_REGISTERS]) {
+        self.v = v;
+    }
+
+    // Return the index register.
+    pub fn ram_idx(&self) -> u16 {
+        self.ram_idx.get()
+    }
+
+    // Return the program counter.
+    pub fn pc(&self) -> u16 {
+        self.pc.get()
+    }
+
+    // Return the stack pointer.
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+
+    // Return the value of the delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    // Return the value of the sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    // Return how many timer ticks are left until the beep ends. The timers
+    // count down once per tick, so this is the sound timer itself; at 60Hz
+    // a tick lasts a frame.
+    pub fn cycles_until_sound_ends(&self) -> u8 {
+        self.st
+    }
+
+    // Return how many timer ticks are left until the delay timer reaches
+    // zero.
+    pub fn cycles_until_delay_ends(&self) -> u8 {
+        self.dt
+    }
+
+    // Return the whole of ram.
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // Return the return addresses of the subroutines currently being 
+    // executed, outermost call first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..self.sp]
+    }
+
+    // Return the disassembled instruction to which the program counter is
+    // pointing.
+    pub fn current_instruction(&self) -> String {
+        disasm::disassemble(self.opcode_at(self.pc))
+    }
+
+    // Return a listing of the loaded rom as (address, opcode, mnemonic), one
+    // entry per big-endian word from where the rom is loaded. A lone byte
+    // at the end of a rom of odd length is listed as a DB.
+    pub fn disassemble_rom(&self) -> Vec<(u16, u16, String)> {
+        self.rom.chunks(2).enumerate().map(|(i, word)| {
+            let addr = (PROGRAM_START + i * 2) as u16;
+            if word.len() == 2 {
+                let opcode = (word[0] as u16) << 8 | word[1] as u16;
+                (addr, opcode, disasm::disassemble(opcode))
+            } else {
+                (addr, word[0] as u16, disasm::data_byte(word[0]))
+            }
+        }).collect()
+    }
+
+    // Whether the program is stuck on an fx0a with no key held down, so 
+    // that it will not continue until a key is pressed. Lets the frontend
+    // prompt for a key.
+    pub fn is_waiting_for_key(&self) -> bool {
+        let opcode = self.opcode_at(self.pc);
+        opcode & 0xf0ff == 0xf00a && !self.keys.iter().any(|&down| down)
+    }
+
+    // Whether running the program can change nothing but until a key is
+    // pressed, or never: it is waiting for a key on fx0a, stuck on a jump
+    // to itself or halted, and neither timer is running. Lets the frontend
+    // sleep rather than run frames.
+    pub fn is_idle(&self) -> bool {
+        let opcode = self.opcode_at(self.pc);
+        let stuck = opcode == 0x1000 | self.pc.get() || self.halted;
+        (stuck || self.is_waiting_for_key()) && self.dt == 0 && self.st == 0
+    }
+
+    // Describe the whole machine in text: the registers, the current
+    // instruction, the call stack and the screen.
+    pub fn debug_view(&self) -> String {
+        let mut view = String::from("REGISTERS\n");
+        for (row, regs) in self.v.chunks(4).enumerate() {
+            let regs: Vec<String> = regs.iter().enumerate()
+                .map(|(i, vx)| format!("V{:X} {:02X}", row * 4 + i, vx))
+                .collect();
+            view.push_str(&regs.join(" "));
+            view.push('\n');
+        }
+        view.push_str(&format!("I {:04X} PC {:04X} SP {:X} DT {:02X} \
+                                ST {:02X}\n", 
+                               self.ram_idx.get(), self.pc.get(), self.sp, 
+                               self.dt, 
+                               self.st));
+        view.push_str(&format!("\nINSTRUCTION\n{}\n", 
+                               self.current_instruction()));
+        let stack: Vec<String> = self.call_stack().iter()
+            .map(|addr| format!("{:04X}", addr))
+            .collect();
+        view.push_str(&format!("\nCALL STACK\n{}\n", 
+            if stack.is_empty() {"-".to_string()} else {stack.join(" ")}));
+        view.push_str("\nSCREEN\n");
+        view.push_str(&StringDisplay::new(self).to_string());
+        view
+    }
+    
+    // Return the active resolution as (width, height), i.e. 64x32 in 
+    // STANDARD mode and 128x64 in SUPER mode.
+    pub fn display_dimensions(&self) -> (usize, usize) {
+        match self.mode {
+            Mode::STANDARD => (SMALL_GFX_W, SMALL_GFX_H),
+            Mode::SUPER => (LARGE_GFX_W, LARGE_GFX_H)
+        }
+    }
+    
+    // Return the gfx width.
+    fn width(&self) -> usize {
+        match self.mode {
+            Mode::STANDARD => SMALL_GFX_W,
+            Mode::SUPER => GFX_W
+        }
+    }
+
+    // Return the gfx height.
+    fn height(&self) -> usize {
+        match self.mode {
+            Mode::STANDARD => SMALL_GFX_H,
+            Mode::SUPER => LARGE_GFX_H
+        }
+    }
+   
+    // Scroll screen n lines down. Like the other scrolls, this moves the 
+    // pixels of the active resolution only, so that what a later dxyn 
+    // collides with is what is shown, and pixels scrolled off the display 
+    // are gone for good.
+    fn execute_opcode_00cn(&mut self) {
+        let n = (self.opcode & 0x000f) as usize; 
+        let (w, h) = (self.width(), self.height());
+        for y in (cmp::min(n, h)..h).rev() {
+            for x in 0..w { self.gfx[x][y] = self.gfx[x][y-n]; }
+        } 
+        for y in 0..cmp::min(n, h) {
+            for x in 0..w { self.gfx[x][y] = false; }
+        } 
+        self.draw = true;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }  
+    
+    // Clear screen.
+    fn execute_opcode_00e0(&mut self) {
+        // Outside of XO-CHIP mode the mask does not apply.
+        let planes = if self.xo_chip {self.plane_mask} else {FIRST_PLANE};
+        if planes & FIRST_PLANE != 0 {
+            for x in 0..GFX_W { for y in 0..GFX_H { self.gfx[x][y] = false; } }
+        }
+        self.draw = true;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }  
+    
+    // Return from last subroutine.
+    fn execute_opcode_00ee(&mut self) {
+        if self.sp == 0 {
+            self.fault = Some(CpuError::StackUnderflow(self.opcode, 
+                                                       self.pc.get(), self.sp));
+            return;
+        }
+        self.sp -= 1; 
+        self.pc = self.addr(self.stack[self.sp].wrapping_add(2));
+    } 
+
+    // Debug trap: queue up the registers for take_debug_events, if the trap
+    // is enabled. Otherwise an unknown opcode.
+    fn execute_opcode_00fa(&mut self) {
+        let event = DebugEvent { pc: self.pc.get(), v: self.v, 
+                                 i: self.ram_idx.get(), dt: self.dt, 
+                                 st: self.st };
+        match self.debug_events {
+            Some(ref mut events) => events.push(event),
+            None => {
+                self.fault = Some(CpuError::UnknownOpcode(self.opcode, 
+                                                          self.pc.get()));
+                return;
+            },
+        }
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Scroll screen 4 pixels right.
+    fn execute_opcode_00fb(&mut self) {
+        let (w, h) = (self.width(), self.height());
+        for y in 0..h {
+            for x in (4..w).rev() { self.gfx[x][y] = self.gfx[x-4][y] }
+            for x in 0..4 { self.gfx[x][y] = false; }
+        }
+        self.draw = true;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Scroll screen 4 pixels left. 
+    fn execute_opcode_00fc(&mut self) {
+        let (w, h) = (self.width(), self.height());
+        for y in 0..h {
+            for x in 0..(w - 4) { self.gfx[x][y] = self.gfx[x+4][y] }
+            for x in (w - 4)..w { self.gfx[x][y] = false; }
+        }
+        self.draw = true;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Meant to exit, but we will reset instead.
+    fn execute_opcode_00fd(&mut self) {
+        self.reset();
+    } 
+    
+    // Disable SUPER mode. 
+    fn execute_opcode_00fe(&mut self) {
+        self.mode = Mode::STANDARD;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    } 
+    
+    // Enable SUPER mode. 
+    fn execute_opcode_00ff(&mut self) {
+        self.mode = Mode::SUPER;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    } 
+    
+    // Jump to address nnn.
+    fn execute_opcode_1nnn(&mut self) {
+        self.pc = Addr::new(self.opcode);
+    }
+
+    // Call subroutine at nnn.
+    fn execute_opcode_2nnn(&mut self) {
+        if self.sp == STACK_SIZE {
+            self.fault = Some(CpuError::StackOverflow(self.opcode, 
+                                                      self.pc.get(), self.sp));
+            return;
+        }
+        self.stack[self.sp] = self.pc.get(); 
+        self.sp += 1; 
+        self.pc = Addr::new(self.opcode);
+    }
+
+    // Skip the next instruction if vx equals nn.
+    fn execute_opcode_3xnn(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let nn = self.opcode & 0x00ff; 
+        let skip = if self.v[x as usize] == nn as u8 {4} else {2};
+        self.pc = self.addr(self.pc.get().wrapping_add(skip));
+    }
+
+    // Skip the next instruction if vx does not equal nn.
+    fn execute_opcode_4xnn(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let nn = self.opcode & 0x00ff; 
+        let skip = if self.v[x as usize] != nn as u8 {4} else {2};
+        self.pc = self.addr(self.pc.get().wrapping_add(skip));
+    }
+
+    // Skip the next instruction if vx equals vy.
+    fn execute_opcode_5xy0(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        let skip = if self.v[x as usize] == self.v[y as usize] {4} else {2};
+        self.pc = self.addr(self.pc.get().wrapping_add(skip));
+    }
+
+    // Set vx to nn.
+    fn execute_opcode_6xnn(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let nn = self.opcode & 0x00ff; 
+        self.v[x as usize] = nn as u8; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Add nn to vx.
+    fn execute_opcode_7xnn(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let nn = self.opcode & 0x00ff; 
+        self.v[x as usize] = self.v[x as usize].wrapping_add(nn as u8);
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set vx to the value of vy.
+    fn execute_opcode_8xy0(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        self.v[x as usize] = self.v[y as usize]; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set vx to vx OR vy.
+    fn execute_opcode_8xy1(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        self.v[x as usize] |= self.v[y as usize]; 
+        self.logic_quirk();
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set vx to vx AND vy.
+    fn execute_opcode_8xy2(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        self.v[x as usize] &= self.v[y as usize]; 
+        self.logic_quirk();
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set vx to vx XOR vy.
+    fn execute_opcode_8xy3(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        self.v[x as usize] ^= self.v[y as usize]; 
+        self.logic_quirk();
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Reset vf after a logical opcode if the quirk is enabled.
+    fn logic_quirk(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0x0f] = 0;
+        }
+    }
+
+    // Add vy to vx and set vf to 1 if there was a carry, 0 otherwise. 
+    fn execute_opcode_8xy4(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        let (sum, carried) = add_with_carry(self.v[x as usize], 
+                                            self.v[y as usize]);
+        self.v[x as usize] = sum; 
+        self.v[0x0f] = if carried {1} else {0}; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Subtract vy from vx. Set vf to 0 if there was a borrow, 1 otherwise.
+    fn execute_opcode_8xy5(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        let vx = self.v[x as usize];
+        let vy = self.v[y as usize];
+        self.v[x as usize] = vx.wrapping_sub(vy); 
+        let borrowed = vy > vx;
+        self.v[0x0f] = if borrowed {0} else {1}; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // There is some difference in opinion on how this opcode should
+    // be implemented. See http://mattmik.com/emu.html
+    //
+    // This implementation mirrors the behavior of the original interpreter,
+    // and is used with the shift_uses_vy quirk.
+    //
+    // Store the value of register vy shifted right one bit in register vx.
+    // Set register vf to the least significant bit prior to the shift.
+    fn execute_opcode_8xy6_vy(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        self.v[0x0f] = self.v[y as usize] & 0x01;
+        self.v[x as usize] = self.v[y as usize] >> 1; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // There is some difference in opinion on how this opcode should
+    // be implemented. See http://mattmik.com/emu.html
+    //
+    // This implementation follows the most recent descriptions of the 
+    // instruction set. This implementation (perhaps erroneous) were
+    // what a majority of programmers had in mind. As a result, it seems
+    // to work with a majority of roms. A significant number of the more
+    // complex roms, e.g. Space Invaders, will ONLY work with this 
+    // implementation.
+    //
+    // Shifts vx right by one. Set vf to the value of the least significant
+    // bit of vx before the shift. 
+    fn execute_opcode_8xy6(&mut self) {
+        if self.quirks.shift_uses_vy {
+            return self.execute_opcode_8xy6_vy();
+        }
+        let x = (self.opcode & 0x0f00) >> 8; 
+        self.v[0x0f] = self.v[x as usize] & 0b00000001;
+        self.v[x as usize] >>= 1;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set vx to vy minus vx. Set vf to 0 if there was a borrow, 1 otherwise.
+    fn execute_opcode_8xy7(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        let vx = self.v[x as usize];
+        let vy = self.v[y as usize];
+        self.v[x as usize] = vy.wrapping_sub(vx); 
+        let borrowed = vx > vy; 
+        self.v[0x0f] = if borrowed {0} else {1}; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // There is some difference in opinion on how this opcode should
+    // be implemented. See http://mattmik.com/emu.html
+    //
+    // This implementation mirrors the behavior of the original interpreter,
+    // and is used with the shift_uses_vy quirk.
+    // 
+    // Store the value of register vy shifted left one bit in register vx.
+    // Set register vf to the most significant bit prior to the shift.
+    fn execute_opcode_8xye_vy(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        self.v[0x0f] = (self.v[y as usize] >> 7) & 0b00000001;
+        self.v[x as usize] = self.v[y as usize] << 1; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // There is some difference in opinion on how this opcode should
+    // be implemented. See http://mattmik.com/emu.html
+    //
+    // This implementation follows the most recent descriptions of the 
+    // instruction set. This implementation (perhaps erroneous) were
+    // what a majority of programmers had in mind. As a result, it seems
+    // to work with a majority of roms. A significant number of the more
+    // complex roms, e.g. Space Invaders, will ONLY work with this 
+    // implementation.
+    //
+    // Shift vx left by one. Set vf to the value of the most significant bit
+    // of vx before the shift. Notice that vy is completely ignored. 
+    fn execute_opcode_8xye(&mut self) {
+        if self.quirks.shift_uses_vy {
+            return self.execute_opcode_8xye_vy();
+        }
+        let x = (self.opcode & 0x0f00) >> 8; 
+        self.v[0x0f] = (self.v[x as usize] >> 7) & 0b00000001; 
+        self.v[x as usize] <<= 1; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Skip the next instruction if vx does not equal vy.
+    fn execute_opcode_9xy0(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let y = (self.opcode & 0x00f0) >> 4; 
+        let skip = if self.v[x as usize] != self.v[y as usize] {4} else {2};
+        self.pc = self.addr(self.pc.get().wrapping_add(skip));
+    }
+
+    // Set ram_idx to the address nnn.
+    fn execute_opcode_annn(&mut self) {
+        self.ram_idx = Addr::new(self.opcode);
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    } 
+
+    // Jump to the address nnn plus v0, or plus vx with the jump_uses_vx 
+    // quirk.
+    fn execute_opcode_bnnn(&mut self) {
+        let nnn = Addr::new(self.opcode).get();
+        let x = if self.quirks.jump_uses_vx {(nnn >> 8) as usize} else {0};
+        self.pc = self.addr(nnn.wrapping_add(self.v[x] as u16)); 
+    } 
+
+    // Set vx to a random number and nn.
+    fn execute_opcode_cxnn(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let nn = self.opcode & 0x00ff; 
+        self.v[x as usize] = self.rng.gen::<u8>() & (nn as u8); 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Draw 8xn sprite from ram[ram_idx] at gfx[vx][vy]. Set vf to 1 if
+    // any set pixels are changed to unset, and 0 otherwise. If n is 0 and
+    // in SUPER mode, then show 16x16 sprite instead. With the display_wait
+    // quirk, a second sprite in the same frame waits for the next frame by
+    // executing the instruction again. The sprite starts at vx and vy 
+    // wrapped to the display, and its pixels beyond an edge wrap or are 
+    // clipped as the wrap_x and wrap_y quirks say.
+    fn execute_opcode_dxyn(&mut self) {
+        if self.quirks.display_wait && self.drawn_since_tick {
+            return;
+        }
+        self.drawn_since_tick = true;
+        let (w, h) = (self.width(), self.height());
+        let gfx_start_x = 
+            self.v[(self.opcode as usize & 0x0f00) >> 8] as usize % w;
+        let gfx_start_y = 
+            self.v[(self.opcode as usize & 0x00f0) >> 4] as usize % h;
+        let n = (self.opcode & 0x000f) as usize; 
+        let sprt_w = if n == 0 && self.mode == Mode::SUPER {16} else {8};
+        let sprt_h = if n == 0 {16} else {n};
+        let sprt_bytes_per_row = sprt_w / 8; 
+        let sprt_end = self.ram_idx.index() + sprt_h * sprt_bytes_per_row;
+        if sprt_end > self.ram.len() {
+            self.log.warn(&format!("Sprite at {:03x} reads past the end of \
+                                    ram at {:03x}", self.ram_idx.get(), 
+                                   self.pc.get()));
+        }
+        self.v[0x0f] = 0x00;
+        let mut collision_rows = 0u16;
+        for y_offset in 0..sprt_h {
+            for sprt_byte_col_idx in 0..sprt_bytes_per_row {
+                let sprt_byte_ram_idx = self.addr(self.ram_idx.get()
+                    .wrapping_add((y_offset * sprt_bytes_per_row) as u16));
+                let sprt_byte: u8 = self.ram[sprt_byte_ram_idx.index()]; 
+                for sprt_byte_bit_idx in 0..8 as usize {
+                    let x_offset = sprt_byte_col_idx * 8 + sprt_byte_bit_idx;
+                    let (gfx_x, gfx_y) = (gfx_start_x + x_offset, 
+                                          gfx_start_y + y_offset);
+                    if (gfx_x >= w && !self.quirks.wrap_x) || 
+                       (gfx_y >= h && !self.quirks.wrap_y) {
+                        continue;
+                    }
+                    let (gfx_x, gfx_y) = (gfx_x % w, gfx_y % h);
+                    // Mask to obtain single bit from byte. 
+                    let mask = 0b_1000_0000_u8 >> sprt_byte_bit_idx; 
+                    let sprt_pix = sprt_byte & mask != 0;
+                    if sprt_pix == true {
+                        let gfx_pix = &mut self.gfx[gfx_x][gfx_y];
+                        *gfx_pix ^= true;
+                        if *gfx_pix == true {
+                            // Reduce flicker and draw only when pix switched on. 
+                            self.draw = true;
+                        } else {
+                            self.v[0x0f] = 0x01;
+                            collision_rows |= 1 << y_offset;
+                        } 
+                    }
+                }
+            } 
+        }
+        if let Some(ref mut rows) = self.collision_rows {
+            *rows = collision_rows;
+        }
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+    
+    // Skip the next instruction if the key stored in vx is pressed.
+    fn execute_opcode_ex9e(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let key_pressed = self.keys[self.v[x as usize] as usize];
+        let skip = if key_pressed {4} else {2};
+        self.pc = self.addr(self.pc.get().wrapping_add(skip));
+    }
+
+    // Skips the next instruction if the key stored in vx is not pressed.
+    fn execute_opcode_exa1(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        let key_pressed = self.keys[self.v[x as usize] as usize];
+        let skip = if !key_pressed {4} else {2};
+        self.pc = self.addr(self.pc.get().wrapping_add(skip));
+    }
+
+    // Set vx to the value of the delay timer.
+    fn execute_opcode_fx07(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        self.v[x as usize] = self.dt;
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Wait for a keypress then store it in vx.
+    // This implementation will only advance the program counter
+    // if a keypress is found. In other words, this opcode will
+    // execute over and over until a keypress is found. This allows
+    // opportunity for a keypress to arrive in between executions.
+    // When several keys are down, the lowest numbered one is stored. Only
+    // keys 0 to f are considered.
+    fn execute_opcode_fx0a(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8; 
+        if let Some(key) = self.keys.iter().take(16).position(|&down| down) {
+            self.v[x as usize] = key as u8;
+            self.pc = self.addr(self.pc.get().wrapping_add(2));
+        }
+    }
+
+    // Set the delay timer to vx.
+    fn execute_opcode_fx15(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        self.dt = self.v[x as usize];
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set the sound timer to vx.
+    fn execute_opcode_fx18(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        self.st = self.v[x as usize];
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Add vx to ram_idx. Set vf to 1 if there was a range overflow past 
+    // the end of ram, i.e. ram_idx + vx > 0x0fff by default, 0 otherwise.
+    fn execute_opcode_fx1e(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        let (sum, overflowed) = add16_overflow(self.ram_idx.get(), 
+                                               self.v[x as usize] as u16, 
+                                               self.addr_mask());
+        self.v[0xf as usize] = if overflowed {1} else {0};
+        self.ram_idx = self.addr(sum);
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Set ram_idx to the location of the sprite for the character in vx. 
+    // Characters 0-F are represented by a 4x5 font.
+    fn execute_opcode_fx29(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        let fchar = self.v[x as usize];
+        self.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    } 
+
+    // Set ram_idx to the location of the sprite for the character in vx, where
+    // x must be in the range 0 to 9 (inclusive). Characters 0-F are 
+    // represented by a 8x10 font.
+    fn execute_opcode_fx30(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        let fchar = self.v[x as usize];
+        self.ram_idx = Addr::new(0x0000 + (fchar as u16) * 10); 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    } 
+
+    // Store the binary-coded decimal (BCD) representation of vx, with the
+    // most significant of three digits at the address in ram_idx, the middle 
+    // digit at ram_idx plus 1, and the least siginificant digit at ram_idx 
+    // plus 2. In other words, take the decimal representation of vx, place 
+    // the hundreds digit in memory at location in ram_idx, the tens digits 
+    // at location ram_idx+1, and the ones digit at location ram_idx+2.
+    fn execute_opcode_fx33(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        let mut vx = self.v[x as usize];
+        let ones = vx % 10;
+        vx /= 10;
+        let tens = vx % 10;
+        vx /= 10;
+        let hundreds = vx % 10;
+        let i = self.ram_idx.get();
+        for (offset, &digit) in [hundreds, tens, ones].iter().enumerate() {
+            let addr = self.addr(i.wrapping_add(offset as u16));
+            self.write_ram(addr.index(), digit);
+        }
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Write a byte to ram on behalf of the program, noting whether it 
+    // overwrites code already run, below the instruction running.
+    fn write_ram(&mut self, addr: usize, value: u8) {
+        self.ram[addr] = value;
+        if let Some(ref fetched) = self.fetched_as_code {
+            self.self_modified |= addr < self.pc.index() && fetched[addr];
+        }
+        if let Some(ref mut log) = self.write_log {
+            log.push((addr as u16, value, self.pc.get()));
+        }
+    }
+
+    // Store v0 to vx in memory starting at address ram_idx.
+    fn execute_opcode_fx55(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        for i in 0..(x as u16) + 1 {
+            let value = self.v[i as usize];
+            let addr = self.addr(self.ram_idx.get().wrapping_add(i));
+            self.write_ram(addr.index(), value);
+        }
+        self.load_store_quirk(x);
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Fill v0 to vx with values from memory starting at address ram_idx.
+    fn execute_opcode_fx65(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        for i in 0..(x as u16) + 1 {
+            let addr = self.addr(self.ram_idx.get().wrapping_add(i));
+            self.v[i as usize] = self.ram[addr.index()];
+        }
+        self.load_store_quirk(x);
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Move ram_idx past registers v0 to vx after storing or loading them,
+    // if the quirk is enabled.
+    fn load_store_quirk(&mut self, x: u16) {
+        if self.quirks.load_store_increments_i {
+            self.ram_idx = self.addr(self.ram_idx.get().wrapping_add(x + 1));
+        }
+    }
+
+    // Store v0 to vx in super_mode_rpl_flags user flags (x <= 7).
+    fn execute_opcode_fx75(&mut self) {
+        let x = (self.opcode & 0x0f00) >> 8;
+        for i in 0..(cmp::min(x,7) as u16) + 1 {
+            self.super_mode_rpl_flags[i as usize] = self.v[i as usize];
+        }
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+
+    // Fill v0 to vx with values from super_mode_rpl_flags (x <= 7).
     fn execute_opcode_fx85(&mut self) {
         let x = (self.opcode & 0x0f00) >> 8;
         for i in 0..(cmp::min(x,7) as u16) + 1 {
             self.v[i as usize] = self.super_mode_rpl_flags[i as usize];
         }
-        self.pc = (self.pc + 2) & 0x0fff; 
+        self.pc = self.addr(self.pc.get().wrapping_add(2));
+    }
+    
+    // Fetch the opcode at pc. Like the 12-bit address bus of the original
+    // hardware, addresses wrap at the end of ram, 4K by default: execution
+    // that runs off the end of ram continues at address 0.
+    fn fetch_opcode(&mut self) {
+        let next = self.addr(self.pc.get().wrapping_add(1));
+        if let Some(ref mut fetched) = self.fetched_as_code {
+            fetched[self.pc.index()] = true;
+            fetched[next.index()] = true;
+        }
+        self.opcode = self.opcode_at(self.pc);
+    }
+                
+    // Look up the handler of the fetched opcode and execute it, handling a
+    // fault as the error policy says.
+    fn decode_and_execute_opcode(&mut self) {
+        if let Err(err) = self.try_decode_and_execute_opcode() {
+            match self.on_error {
+                ErrorPolicy::Panic => panic!("{}", err),
+                ErrorPolicy::Halt => self.halted = true,
+            }
+        }
+    }
+
+    fn try_decode_and_execute_opcode(&mut self) -> Result<(), CpuError> {
+        let result = match self.dispatch.lookup(self.opcode) {
+            Some(execute) => {
+                execute(self);
+                match self.fault.take() {
+                    Some(err) => Err(err),
+                    None => Ok(()),
+                }
+            },
+            None => Err(CpuError::UnknownOpcode(self.opcode, self.pc.get())),
+        };
+        if let Err(ref err) = result {
+            self.log.warn(&err.to_string());
+            self.last_error = Some(err.clone());
+        }
+        result
+    }
+
+    // Log the fetched instruction, before it executes.
+    fn trace_opcode(&self) {
+        self.log.trace(&format!("{:03x}  {:04x}  {}", self.pc.get(), 
+                                self.opcode, disasm::disassemble(self.opcode)));
+    }
+
+    // Register an additional opcode handler. The handler is executed for 
+    // any opcode for which (opcode & mask) == pattern, taking precedence 
+    // over handlers registered earlier, including the built in ones. 
+    // Handlers are responsible for advancing the program counter.
+    pub fn register_opcode(&mut self, mask: u16, pattern: u16, 
+                           execute: OpcodeHandler) {
+        self.dispatch.register(mask, pattern, execute);
+    }
+
+}
+
+// Two emulators are equal when their machine state is. Quirks count, but
+// registered opcode handlers do not.
+impl PartialEq for Emu {
+    fn eq(&self, other: &Emu) -> bool {
+        self.mode == other.mode &&
+        self.gfx.iter().zip(other.gfx.iter()).all(|(a, b)| a[..] == b[..]) &&
+        self.keys == other.keys &&
+        self.draw == other.draw &&
+        self.quirks == other.quirks &&
+        self.xo_chip == other.xo_chip &&
+        self.plane_mask == other.plane_mask &&
+        self.opcode == other.opcode &&
+        self.ram[..] == other.ram[..] &&
+        self.v == other.v &&
+        self.ram_idx == other.ram_idx &&
+        self.pc == other.pc &&
+        self.dt == other.dt &&
+        self.st == other.st &&
+        self.stack == other.stack &&
+        self.sp == other.sp &&
+        self.super_mode_rpl_flags == other.super_mode_rpl_flags &&
+        self.rom == other.rom &&
+        self.frames == other.frames &&
+        self.halted == other.halted
+    }
+}
+
+impl fmt::Debug for Emu {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Emu {{ mode: {:?}, pc: {:#05x}, opcode: {:#06x}, \
+                   i: {:#05x}, v: {:?}, sp: {}, dt: {}, st: {}, frames: {} }}",
+               self.mode, self.pc.get(), self.opcode, self.ram_idx.get(), 
+               self.v, 
+               self.sp, self.dt, self.st, self.frames)
+    }
+}
+
+// Hash the bytes with 64 bit FNV-1a, like the display hash.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Add two bytes, wrapping around past 0xff. Also returns whether there was
+// a carry.
+fn add_with_carry(a: u8, b: u8) -> (u8, bool) {
+    a.overflowing_add(b)
+}
+
+// An address in ram: the program counter, the index register or a jump
+// target. Addresses are masked as they are made, so that one out of range
+// can not be used to index ram. They are 12 bits wide, like the address
+// bus of the original hardware, unless the ram is larger, in which case
+// they wrap at the end of it. The return addresses on the stack are kept
+// as plain u16s, but they are pushed from the program counter, so they
+// are masked all the same.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Addr(u16);
+
+impl Addr {
+
+    // The 12-bit address, wrapping past 0x0fff.
+    fn new(addr: u16) -> Self {
+        Addr(addr & ADDR_MASK)
+    }
+
+    // The address in a ram whose addresses are masked by mask.
+    fn within(addr: u16, mask: u16) -> Self {
+        Addr(addr & mask)
+    }
+
+    fn get(self) -> u16 {
+        self.0
+    }
+
+    fn index(self) -> usize {
+        self.0 as usize
+    }
+
+}
+
+// Add two values that are kept within 0 to limit, wrapping around past 
+// limit. Also returns whether the sum went past limit.
+fn add16_overflow(a: u16, b: u16, limit: u16) -> (u16, bool) {
+    let sum = a as u32 + b as u32;
+    ((sum % (limit as u32 + 1)) as u16, sum > limit as u32)
+}
+
+// The CRC-32 (IEEE) of the bytes. Computed bit by bit, as roms are small.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let crc = bytes.iter().fold(0xffffffff, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 == 1 {(crc >> 1) ^ 0xedb88320} else {crc >> 1}
+        })
+    });
+    !crc
+}
+
+// Append a 16-bit value in big endian order.
+fn push_u16(bytes: &mut Vec<u8>, value: u16) {
+    bytes.push((value >> 8) as u8);
+    bytes.push(value as u8);
+}
+
+// Append a 32-bit value in big endian order.
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    push_u16(bytes, (value >> 16) as u16);
+    push_u16(bytes, value as u16);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{add_with_carry, add16_overflow, Addr};
+    use super::{crc32, supported_opcodes, CpuError, Emu, MachineFingerprint};
+    use super::{ErrorPolicy, LoadError, Quirks, RomInfo, StateError};
+    use super::NUM_BANKS;
+    use super::STACK_SIZE;
+    use super::{EmuBuilder, MAX_RAM_SIZE, MAX_ROM_SIZE, NUM_REGISTERS};
+    use super::super::disasm;
+    use super::super::log::{Level, Log, MemoryLogger};
+    use super::super::script::ScriptError;
+    use super::super::clock::MockClock;
+    use super::{SMALL_GFX_H, SMALL_GFX_W};
+    use super::super::{Mode, GFX_H, GFX_W};
+    use std::env;
+    use std::fs::File;
+    use std::io::{self, Write};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_display_dimensions() {
+        let mut emu = Emu::new();
+        //given
+        emu.mode = Mode::STANDARD;
+        //then
+        assert_eq!((64, 32), emu.display_dimensions());
+        //given
+        emu.mode = Mode::SUPER;
+        //then
+        assert_eq!((128, 64), emu.display_dimensions());
+    }
+
+    #[test]
+    fn test_call_stack() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0200);
+        //when
+        emu.opcode = 0x2300;
+        emu.decode_and_execute_opcode();
+        emu.opcode = 0x2400;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(&[0x0200, 0x0300], emu.call_stack());
+        assert_eq!(0x0400, emu.pc());
+    }
+
+    #[test]
+    fn test_current_instruction() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x6a, 0x02, 0xd1, 0x25]);
+        //when
+        emu.execute_cycle();
+        //then
+        assert_eq!(0x02, emu.register(0x0a));
+        assert_eq!("DRW V1, V2, 5", emu.current_instruction());
+    }
+
+    #[test]
+    fn test_disassemble_rom() {
+        let mut emu = Emu::new();
+        //given
+        // LD V0, 5; loop: ADD V0, 1; JP loop; and a stray byte.
+        emu.load_rom(vec![0x60, 0x05, 0x70, 0x01, 0x12, 0x02, 0xff]);
+        //when
+        let listing = emu.disassemble_rom();
+        //then
+        assert_eq!(vec![(0x200, 0x6005, "LD V0, 0x05".to_string()),
+                        (0x202, 0x7001, "ADD V0, 0x01".to_string()),
+                        (0x204, 0x1202, "JP 0x202".to_string()),
+                        (0x206, 0x00ff, "DB 0xff".to_string())], listing);
+        emu.load_rom(vec![]);
+        assert!(emu.disassemble_rom().is_empty());
+    }
+
+    #[test]
+    fn test_debug_view() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x6a, 0x02, 0x22, 0x06, 0x00, 0x00, 0xd0, 0x05]);
+        //when
+        emu.execute_cycle();
+        emu.execute_cycle();
+        let view = emu.debug_view();
+        //then
+        assert!(view.contains("V8 00 V9 00 VA 02 VB 00\n"));
+        assert!(view.contains("I 0000 PC 0206 SP 1 DT 00 ST 00\n"));
+        assert!(view.contains("INSTRUCTION\nDRW V0, V0, 5\n"));
+        assert!(view.contains("CALL STACK\n0202\n"));
+        assert!(view.ends_with(&format!("SCREEN\n{}", 
+                                        (".".repeat(64) + "\n").repeat(32))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown opcode: 5121")]
+    fn test_unregistered_opcode_is_unknown() {
+        let mut emu = Emu::new();
+        //when
+        emu.opcode = 0x5121;
+        emu.decode_and_execute_opcode();
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown opcode: 5121 at 200")]
+    fn test_unknown_opcode_panics_by_default() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x51, 0x21]);
+        //when
+        emu.execute_cycle();
+    }
+
+    #[test]
+    fn test_unknown_opcode_halts_with_halt_policy() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x51, 0x21]);
+        emu.set_on_error(ErrorPolicy::Halt);
+        //when
+        emu.execute_cycle();
+        //then
+        assert!(emu.halted());
+        assert_eq!(0x200, emu.pc());
+        emu.execute_cycle();
+        assert_eq!(0x200, emu.pc());
+        emu.reset();
+        assert!(!emu.halted());
+    }
+
+    #[test]
+    fn test_try_execute_cycle_returns_error_under_either_policy() {
+        for &policy in &[ErrorPolicy::Panic, ErrorPolicy::Halt] {
+            let mut emu = Emu::new();
+            //given
+            emu.load_rom(vec![0x60, 0x01, 0x51, 0x21]);
+            emu.set_on_error(policy);
+            //when
+            assert_eq!(Ok(()), emu.try_execute_cycle());
+            let result = emu.try_execute_cycle();
+            //then
+            assert_eq!(Err(CpuError::UnknownOpcode(0x5121, 0x202)), result);
+            assert_eq!(0x202, emu.pc());
+            assert!(!emu.halted());
+        }
+    }
+
+    #[test]
+    fn test_step_n_stops_when_program_halts() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x60, 0x05, 0x61, 0x06, 0x12, 0x04]);
+        //when
+        let result = emu.step_n(10);
+        //then
+        assert_eq!(Ok(3), result);
+        assert_eq!((0x05, 0x06, 0x204), (emu.v[0], emu.v[1], emu.pc()));
+    }
+
+    #[test]
+    fn test_step_n_stops_on_error() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x60, 0x01, 0x51, 0x21]);
+        //when
+        let result = emu.step_n(10);
+        //then
+        assert_eq!(Err(CpuError::UnknownOpcode(0x5121, 0x202)), result);
+        assert_eq!(0x202, emu.pc());
+    }
+
+    #[test]
+    fn test_step_n_runs_at_most_n() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x70, 0x01, 0x12, 0x00]);
+        //then
+        assert_eq!(Ok(5), emu.step_n(5));
+        assert_eq!(3, emu.v[0]);
+        assert_eq!(Ok(0), emu.step_n(0));
+    }
+
+    #[test]
+    fn test_step_n_does_nothing_once_halted() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x51, 0x21]);
+        emu.set_on_error(ErrorPolicy::Halt);
+        emu.execute_cycle();
+        //then
+        assert_eq!(Ok(0), emu.step_n(5));
+    }
+
+    #[test]
+    fn test_last_error_keeps_error_of_try_execute_cycle() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x51, 0x21]);
+        assert_eq!(None, emu.last_error());
+        //when
+        let err = emu.try_execute_cycle().unwrap_err();
+        //then
+        assert_eq!(Some(&err), emu.last_error());
+        assert_eq!(CpuError::UnknownOpcode(0x5121, 0x200), err);
+        emu.reset();
+        assert_eq!(None, emu.last_error());
+    }
+
+    #[test]
+    fn test_stack_errors_carry_stack_pointer() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x22, 0x00]);
+        //when
+        for _ in 0..STACK_SIZE {
+            emu.try_execute_cycle().unwrap();
+        }
+        //then
+        assert_eq!(Err(CpuError::StackOverflow(0x2200, 0x200, STACK_SIZE)),
+                   emu.try_execute_cycle());
+        assert_eq!(STACK_SIZE, emu.sp);
+        let mut emu = Emu::new();
+        emu.load_rom(vec![0x00, 0xee]);
+        assert_eq!(Err(CpuError::StackUnderflow(0x00ee, 0x200, 0)),
+                   emu.try_execute_cycle());
+        assert_eq!(0x200, emu.pc());
+        assert!(emu.last_error().is_some());
+    }
+
+    #[test]
+    fn test_set_registers() {
+        let mut emu = Emu::new();
+        //given
+        let mut v = [0; NUM_REGISTERS];
+        for (x, value) in v.iter_mut().enumerate() {
+            *value = 0x10 * x as u8 + 1;
+        }
+        //when
+        emu.set_registers(v);
+        //then
+        assert_eq!(&v, emu.registers());
+        assert_eq!(0xf1, emu.register(0xf));
+    }
+
+    #[test]
+    fn test_register_opcode() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.register_opcode(0xf00f, 0x5001, |emu| {
+            emu.v[0] = 0xaa;
+            emu.pc = Addr::new(emu.pc() + 2);
+        });
+        //when
+        emu.opcode = 0x5121;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0xaa, emu.v[0]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    fn test_register_opcode_overrides_builtin() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.register_opcode(0xffff, 0x00e0, 
+                           |emu| emu.pc = Addr::new(emu.pc() + 4));
+        emu.gfx[0][0] = true;
+        //when
+        emu.opcode = 0x00e0;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(true, emu.gfx[0][0]);
+        assert_eq!(0x0000+4, emu.pc());
+    }
+
+    #[test]
+    fn test_register_opcode_survives_reset() {
+        let mut emu = Emu::new();
+        //given
+        emu.register_opcode(0xf00f, 0x5001, 
+                           |emu| emu.pc = Addr::new(emu.pc() + 2));
+        //when
+        emu.reset();
+        emu.opcode = 0x5121;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0200+2, emu.pc());
+    }
+
+    #[test]
+    fn test_update_timers_counts_down_on_every_call() {
+        let mut emu = Emu::new();
+        //given
+        emu.dt = 100;
+        //when
+        for _ in 0..10 { emu.update_timers(); }
+        //then
+        assert_eq!(90, emu.dt);
+    }
+
+    #[test]
+    fn test_cycles_until_timers_end() {
+        let mut emu = Emu::new();
+        //given
+        emu.st = 30;
+        emu.dt = 7;
+        //when
+        emu.update_timers();
+        //then
+        assert_eq!(29, emu.cycles_until_sound_ends());
+        assert_eq!(6, emu.cycles_until_delay_ends());
+        for _ in 0..6 { emu.update_timers(); }
+        assert_eq!(23, emu.cycles_until_sound_ends());
+        assert_eq!(0, emu.cycles_until_delay_ends());
+    }
+
+    #[test]
+    fn test_wall_clock_timers_count_down_at_60hz() {
+        let clock = Arc::new(MockClock::new());
+        let mut emu = Emu::new();
+        //given
+        emu.wall_clock_timers = true;
+        emu.set_clock(clock.clone());
+        emu.dt = 200;
+        emu.st = 200;
+        //when
+        // Called every millisecond for a second.
+        for _ in 0..1000 {
+            emu.update_timers();
+            emu.update_timers();
+            clock.advance(Duration::from_millis(1));
+        }
+        //then
+        assert_eq!(200 - 60, emu.dt);
+        assert_eq!(200 - 60, emu.st);
+    }
+
+    #[test]
+    fn test_wall_clock_timers_do_not_catch_up_after_a_gap() {
+        let clock = Arc::new(MockClock::new());
+        let mut emu = Emu::new();
+        //given
+        emu.wall_clock_timers = true;
+        emu.set_clock(clock.clone());
+        emu.dt = 200;
+        emu.update_timers();
+        //when
+        clock.advance(Duration::from_secs(1));
+        for _ in 0..10 { emu.update_timers(); }
+        //then
+        assert_eq!(198, emu.dt);
+    }
+
+    #[test]
+    fn test_beep_frequency() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[3] = 0x02;
+        assert_eq!(None, emu.beep_frequency());
+        //when
+        emu.opcode = 0xf318;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(Some(440.0), emu.beep_frequency());
+        emu.update_timers();
+        assert_eq!(Some(440.0), emu.beep_frequency());
+        emu.update_timers();
+        assert_eq!(None, emu.beep_frequency());
+    }
+
+    #[test]
+    fn test_beep_samples_this_frame() {
+        let mut emu = Emu::new();
+        assert_eq!(0, emu.beep_samples_this_frame(48000));
+        //when
+        emu.st = 30;
+        //then
+        assert_eq!(30 * 48000 / 60, emu.beep_samples_this_frame(48000));
+    }
+
+    #[test]
+    fn test_next_audio_frame() {
+        let mut emu = Emu::new();
+        //given
+        let silence = emu.next_audio_frame(48000);
+        //when
+        emu.st = 2;
+        let beep = emu.next_audio_frame(48000);
+        //then
+        assert_eq!(800, silence.len());
+        assert!(silence.iter().all(|&sample| sample == 0));
+        assert_eq!(800, beep.len());
+        assert!(beep.iter().all(|&sample| sample != 0));
+        assert!(beep.iter().any(|&sample| sample > 0) && 
+                beep.iter().any(|&sample| sample < 0));
+        assert_eq!(735, emu.next_audio_frame(44100).len());
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x60, 0x1e, 0xf0, 0x18, 0x12, 0x04]);
+        //when
+        emu.run_frame(3);
+        //then
+        assert_eq!(0x0204, emu.pc());
+        assert_eq!(0x1d, emu.st);
+        assert_eq!(1, emu.frame_count());
+    }
+
+    #[test]
+    fn test_run_to_frame() {
+        let mut emu = Emu::new();
+        //given
+        // Draws the hexadecimal digits side by side, one every 4 cycles.
+        emu.load_rom(vec![0x60, 0x00, 0x61, 0x00, 0x62, 0x00, 0xf2, 0x29, 
+                          0xd0, 0x15, 0x70, 0x05, 0x72, 0x01, 0x12, 0x06]);
+        //when
+        emu.run_to_frame(3, 4);
+        //then
+        assert_eq!(3, emu.frame_count());
+        assert_eq!(0x020e, emu.pc());
+        assert_eq!(0x02, emu.v[2]);
+        assert_eq!(0x112525ac4a75061d, emu.display_hash());
+        //when
+        emu.run_to_frame(2, 4);
+        //then
+        assert_eq!(3, emu.frame_count());
+    }
+
+    #[test]
+    fn test_run_until_draw() {
+        let rom = vec![0x60, 0x05, 0x61, 0x06, 0x62, 0x07, 0xa0, 0x00, 
+                       0xd0, 0x15, 0x12, 0x0a];
+        let mut emu = Emu::new();
+        emu.load_rom(rom.clone());
+        //when
+        let drawn = emu.run_until_draw(100);
+        //then
+        assert!(drawn);
+        assert!(emu.draw);
+        assert_eq!((0x020a, 0x07), (emu.pc(), emu.v[2]));
+        assert!(emu.run_until_draw(100));
+        assert_eq!(0x020a, emu.pc());
+        // Out of cycles before the draw.
+        let mut emu = Emu::new();
+        emu.load_rom(rom);
+        assert!(!emu.run_until_draw(4));
+        assert!(!emu.draw);
+        assert_eq!(0x0208, emu.pc());
+    }
+
+    #[test]
+    fn test_frames_run_one_at_a_time_match_continuous_run() {
+        let rom = include_bytes!("../../roms/brix.ch8").to_vec();
+        let mut stepped = Emu::new();
+        let mut continuous = Emu::new();
+        for emu in [&mut stepped, &mut continuous].iter_mut() {
+            emu.seed_rng(3);
+            emu.load_rom(rom.clone());
+        }
+        //given
+        stepped.run_to_frame(10, 8);
+        //when
+        for _ in 0..5 { stepped.run_frame(8); }
+        continuous.run_to_frame(15, 8);
+        //then
+        assert_eq!(continuous, stepped);
+    }
+
+    #[test]
+    fn test_display_hash_depends_on_pixels() {
+        let mut emu = Emu::new();
+        //given
+        let blank = emu.display_hash();
+        //when
+        emu.gfx[3][4] = true;
+        //then
+        assert!(blank != emu.display_hash());
+        emu.gfx[3][4] = false;
+        assert_eq!(blank, emu.display_hash());
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let mut emu = Emu::new();
+        assert_eq!(None, emu.bounding_box());
+        //given
+        // Draws the font glyph of 7, 4 pixels wide and 5 high, at (10, 3).
+        emu.load_rom(vec![0x60, 0x07, 0xf0, 0x29, 0x61, 0x0a, 0x62, 0x03, 
+                          0xd1, 0x25]);
+        //when
+        for _ in 0..5 { emu.execute_cycle(); }
+        //then
+        assert_eq!(Some((10, 3, 13, 7)), emu.bounding_box());
+        // Only the active region counts.
+        emu.gfx[100][40] = true;
+        assert_eq!(Some((10, 3, 13, 7)), emu.bounding_box());
+    }
+
+    #[test]
+    fn test_write_pbm() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0xa0, 0x00, 0xd0, 0x05]);
+        emu.execute_cycle();
+        emu.execute_cycle();
+        let mut pbm = Vec::new();
+        //when
+        emu.write_pbm(&mut pbm).unwrap();
+        //then
+        let header = b"P4\n64 32\n";
+        assert_eq!(&header[..], &pbm[..header.len()]);
+        assert_eq!(header.len() + 8 * 32, pbm.len());
+        // The glyph of 0 in the top left corner.
+        let rows: Vec<u8> = pbm[header.len()..].chunks(8)
+            .take(6).map(|row| row[0]).collect();
+        assert_eq!(vec![0xf0, 0x90, 0x90, 0x90, 0xf0, 0x00], rows);
+        assert!(pbm[header.len()..].chunks(8).all(|row| row[1..] == [0; 7]));
+    }
+
+    #[test]
+    fn test_plane_count_and_framebuffer_packed() {
+        let mut emu = Emu::new();
+        assert_eq!(1, emu.plane_count());
+        emu.xo_chip = true;
+        assert_eq!(1, emu.plane_count());
+        //given
+        emu.gfx[0][0] = true;
+        emu.gfx[9][1] = true;
+        emu.gfx[63][31] = true;
+        //when
+        let packed = emu.framebuffer_packed();
+        //then
+        assert_eq!(8 * 32, packed.len());
+        assert_eq!((0x80, 0x40, 0x01), (packed[0], packed[8 + 1], packed[255]));
+        assert_eq!(3, packed.iter().map(|b| b.count_ones()).sum::<u32>());
+    }
+
+    #[test]
+    fn test_write_pbm_in_super_mode() {
+        let mut emu = Emu::new();
+        //given
+        emu.mode = Mode::SUPER;
+        emu.gfx[127][63] = true;
+        let mut pbm = Vec::new();
+        //when
+        emu.write_pbm(&mut pbm).unwrap();
+        //then
+        assert!(pbm.starts_with(b"P4\n128 64\n"));
+        assert_eq!(Some(&0x01), pbm.last());
+    }
+
+    #[test]
+    fn test_execution_wraps_at_end_of_ram() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0ffe);
+        emu.ram[0x0ffe] = 0x60;
+        emu.ram[0x0fff] = 0x2a;
+        emu.ram[0x0000] = 0x61;
+        emu.ram[0x0001] = 0x3b;
+        //when
+        emu.execute_cycle();
+        emu.execute_cycle();
+        //then
+        assert_eq!(0x2a, emu.v[0]);
+        assert_eq!(0x3b, emu.v[1]);
+        assert_eq!(0x0002, emu.pc());
+    }
+
+    #[test]
+    fn test_ram_fill() {
+        let mut emu = EmuBuilder::new().ram_fill(0xff).build();
+        //when
+        emu.load_rom(vec![0x12, 0x00]);
+        //then
+        assert_eq!(0xff, emu.ram()[0x300]);
+        assert_eq!(0xff, emu.ram()[0x1ff]);
+        assert_eq!(&[0x12, 0x00, 0xff], &emu.ram()[0x200..0x203]);
+        // The fonts are where they always are.
+        assert_eq!(0xf0, emu.ram()[0x00]);
+        emu.reset();
+        assert_eq!(0xff, emu.ram()[0x300]);
+        assert_eq!(0x00, Emu::new().ram()[0x300]);
+    }
+
+    #[test]
+    fn test_extended_ram() {
+        let mut emu = EmuBuilder::new().ram_size(MAX_RAM_SIZE).build();
+        //given
+        emu.ram_idx = emu.addr(0xfff0);
+        emu.v[0x0] = 0xab;
+        emu.v[0x1] = 0xcd;
+        //when
+        emu.opcode = 0xf155;
+        emu.decode_and_execute_opcode();
+        emu.v[0x0] = 0;
+        emu.v[0x1] = 0;
+        emu.opcode = 0xf165;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(MAX_RAM_SIZE, emu.ram().len());
+        assert_eq!(&[0xab, 0xcd], &emu.ram()[0xfff0..0xfff2]);
+        assert_eq!((0xab, 0xcd), (emu.v[0x0], emu.v[0x1]));
+        // Execution wraps at the end of the larger ram, which reset keeps.
+        emu.pc = emu.addr(0xfffe);
+        emu.ram[0xfffe] = 0x60;
+        emu.ram[0xffff] = 0x2a;
+        emu.execute_cycle();
+        assert_eq!((0x2a, 0x0000), (emu.v[0x0], emu.pc()));
+        emu.reset();
+        assert_eq!(MAX_RAM_SIZE, emu.ram().len());
+    }
+
+    #[test]
+    fn test_seed() {
+        let random = |seed| {
+            let mut emu = EmuBuilder::new().seed(seed).build();
+            // Set v0 to a random byte, ten times over.
+            emu.load_rom([0xc0, 0xff].iter().cloned().cycle().take(20)
+                                     .collect());
+            (0..10).map(|_| {
+                emu.execute_cycle();
+                emu.v[0x0]
+            }).collect::<Vec<u8>>()
+        };
+        //then
+        assert_eq!(random(42), random(42));
+        assert!(random(42) != random(43));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unsupported ram size: 5000")]
+    fn test_ram_size_must_be_power_of_two() {
+        EmuBuilder::new().ram_size(5000);
+    }
+
+    #[test]
+    fn test_fetch_masks_pc_beyond_ram() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x1fff);
+        emu.ram[0x0fff] = 0x62;
+        emu.ram[0x0000] = 0x4c;
+        //when
+        emu.execute_cycle();
+        //then
+        assert_eq!(0x4c, emu.v[2]);
+        assert_eq!(0x0001, emu.pc());
+    }
+
+    #[test]
+    fn test_addr_wraps_to_12_bits() {
+        assert_eq!(0x0fff, Addr::new(0x1fff).get());
+        assert_eq!(0x0abc, Addr::new(0xfabc).get());
+        assert_eq!(0x0200, Addr::new(0x0200).get());
+        assert_eq!(0xfffe, Addr::within(0xfffe, 0xffff).get());
+    }
+
+    #[test]
+    fn test_jumps_wrap_to_12_bits() {
+        let mut emu = Emu::new();
+        //given
+        emu.v[0] = 0x02;
+        //when
+        emu.opcode = 0xbfff;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0001, emu.pc());
+        // A return address at the end of ram returns to its start.
+        emu.stack[0] = 0x0ffe;
+        emu.sp = 1;
+        emu.opcode = 0x00ee;
+        emu.decode_and_execute_opcode();
+        assert_eq!((0x0000, 0), (emu.pc(), emu.sp));
+    }
+
+    #[test]
+    fn test_load_store_wrap_at_end_of_ram() {
+        let mut emu = Emu::new();
+        //given
+        emu.ram_idx = Addr::new(0x0fff);
+        emu.v[0] = 0xab;
+        emu.v[1] = 0xcd;
+        //when
+        emu.opcode = 0xf155;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!((0xab, 0xcd), (emu.ram[0x0fff], emu.ram[0x0000]));
+    }
+
+    // Execute a control opcode of the 0 family on an emulator in the given 
+    // mode, with a single pixel lit at (4, 0) and a return address on the 
+    // stack.
+    fn execute_00(opcode: u16, mode: Mode) -> Emu {
+        let mut emu = Emu::new();
+        emu.mode = mode;
+        emu.pc = Addr::new(0x0210);
+        emu.stack[0] = 0x0300;
+        emu.sp = 1;
+        emu.gfx[4][0] = true;
+        emu.opcode = opcode;
+        emu.decode_and_execute_opcode();
+        emu
+    }
+
+    #[test]
+    fn test_0_family_low_bytes_are_told_apart() {
+        let emu = execute_00(0x00c3, Mode::SUPER);
+        assert!(emu.gfx[4][3] && !emu.gfx[4][0]);
+        assert_eq!(0x0212, emu.pc());
+        let emu = execute_00(0x00e0, Mode::SUPER);
+        assert!((0..GFX_W).all(|x| (0..GFX_H).all(|y| !emu.gfx[x][y])));
+        assert_eq!((0x0212, 1), (emu.pc(), emu.sp));
+        let emu = execute_00(0x00ee, Mode::SUPER);
+        assert_eq!((0x0302, 0), (emu.pc(), emu.sp));
+        let emu = execute_00(0x00fb, Mode::SUPER);
+        assert!(emu.gfx[8][0] && !emu.gfx[4][0]);
+        let emu = execute_00(0x00fc, Mode::SUPER);
+        assert!(emu.gfx[0][0] && !emu.gfx[4][0]);
+        let emu = execute_00(0x00fd, Mode::SUPER);
+        assert_eq!((0x0200, 0, false), (emu.pc(), emu.sp, emu.gfx[4][0]));
+        let emu = execute_00(0x00fe, Mode::SUPER);
+        assert_eq!((Mode::STANDARD, true), (emu.mode, emu.gfx[4][0]));
+        let emu = execute_00(0x00ff, Mode::STANDARD);
+        assert_eq!((Mode::SUPER, true), (emu.mode, emu.gfx[4][0]));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown opcode: 1e0")]
+    fn test_0nnn_sharing_low_byte_with_00e0_is_unknown() {
+        execute_00(0x01e0, Mode::STANDARD);
+    }
+
+    #[test]
+    fn test_unknown_opcode_is_logged() {
+        let mut emu = Emu::new();
+        //given
+        let logger = Arc::new(MemoryLogger::new());
+        emu.set_log(Log::new(Level::Warn, logger.clone()));
+        emu.set_on_error(ErrorPolicy::Halt);
+        emu.load_rom(vec![0x60, 0x05, 0x01, 0x23]);
+        //when
+        emu.execute_cycle();
+        emu.execute_cycle();
+        //then
+        assert_eq!(vec![(Level::Warn, 
+                         "Unknown opcode: 123 at 202".to_string())],
+                   logger.messages());
+    }
+
+    #[test]
+    fn test_trace_logs_each_instruction() {
+        let mut emu = Emu::new();
+        //given
+        let logger = Arc::new(MemoryLogger::new());
+        emu.set_log(Log::new(Level::Trace, logger.clone()));
+        emu.load_rom(vec![0x60, 0x05, 0x12, 0x00]);
+        //when
+        emu.execute_cycle();
+        emu.try_execute_cycle().unwrap();
+        emu.run_burst(2);
+        //then
+        let traced: Vec<_> = logger.messages().into_iter()
+            .filter(|&(level, _)| level == Level::Trace)
+            .map(|(_, message)| message).collect();
+        assert_eq!(vec![format!("200  6005  {}", disasm::disassemble(0x6005)),
+                        format!("202  1200  {}", disasm::disassemble(0x1200))],
+                   traced);
+    }
+
+    #[test]
+    pub fn test_opcode_00cn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000); 
+        emu.gfx[0][0] = true;
+        emu.gfx[1][0] = true;
+        //when
+        emu.opcode = 0x00c2;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(false, emu.gfx[0][0]);
+        assert_eq!(false, emu.gfx[1][0]);
+        assert_eq!(true, emu.gfx[0][2]);
+        assert_eq!(true, emu.gfx[1][2]);
+        assert_eq!(true, emu.draw);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_00e0() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000); 
+        emu.draw = false;
+        for x in 0..GFX_W { for y in 0..GFX_H { emu.gfx[x][y] = true; } }
+        //when
+        emu.opcode = 0x00e0;
+        emu.decode_and_execute_opcode();
+        //then
+        for x in 0..GFX_W { for y in 0..GFX_H { assert_eq!(false, emu.gfx[x][y]); } }
+        assert_eq!(true, emu.draw);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_00e0_clears_selected_planes() {
+        let mut emu = Emu::new();
+        //given
+        emu.set_plane_mask(0);
+        emu.gfx[3][4] = true;
+        //when
+        emu.opcode = 0x00e0;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(false, emu.gfx[3][4]);
+        //given
+        emu.xo_chip = true;
+        emu.gfx[3][4] = true;
+        //when
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(true, emu.gfx[3][4]);
+        assert_eq!(true, emu.draw);
+        emu.set_plane_mask(0b11);
+        emu.decode_and_execute_opcode();
+        assert_eq!(false, emu.gfx[3][4]);
+    }
+
+    #[test]
+    pub fn test_opcode_00fb() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000); 
+        emu.gfx[0][0] = true;
+        emu.gfx[1][0] = true;
+        //when
+        emu.opcode = 0x00fb;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(false, emu.gfx[0][0]);
+        assert_eq!(false, emu.gfx[1][0]);
+        assert_eq!(true, emu.gfx[4][0]);
+        assert_eq!(true, emu.gfx[5][0]);
+        assert_eq!(true, emu.draw);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_00fc() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000); 
+        emu.gfx[4][0] = true;
+        emu.gfx[5][0] = true;
+        //when
+        emu.opcode = 0x00fc;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(false, emu.gfx[4][0]);
+        assert_eq!(false, emu.gfx[5][0]);
+        assert_eq!(true, emu.gfx[0][0]);
+        assert_eq!(true, emu.gfx[1][0]);
+        assert_eq!(true, emu.draw);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_draw_collides_with_scrolled_pixels() {
+        let mut emu = Emu::new();
+        //given
+        // A 4 pixel wide bar at (10, 5), drawn and then scrolled right.
+        emu.load_rom(vec![0x60, 0x0a, 0x61, 0x05, 0xa2, 0x0e, 0xd0, 0x11,
+                          0x00, 0xfb, 0x70, 0x04, 0xd0, 0x11, 0xf0, 0x00]);
+        emu.mode = Mode::SUPER;
+        //when
+        for _ in 0..7 { emu.execute_cycle(); }
+        //then
+        // Drawing the bar where it was scrolled to erases it.
+        assert_eq!(0x01, emu.v[0x0f]);
+        assert!(emu.draw);
+        for x in 0..20 { assert_eq!(false, emu.gfx[x][5]); }
+    }
+
+    #[test]
+    pub fn test_scrolled_off_pixels_do_not_come_back() {
+        let mut emu = Emu::new();
+        //given
+        emu.gfx[SMALL_GFX_W-1][0] = true;
+        emu.gfx[0][SMALL_GFX_H-1] = true;
+        //when
+        emu.opcode = 0x00fb;
+        emu.decode_and_execute_opcode();
+        emu.opcode = 0x00fc;
+        emu.decode_and_execute_opcode();
+        emu.opcode = 0x00c4;
+        emu.decode_and_execute_opcode();
+        //then
+        for x in 0..GFX_W {
+            for y in 0..GFX_H { assert_eq!(false, emu.gfx[x][y]); }
+        }
+    }
+
+    #[test]
+    pub fn test_opcode_00ee() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0ccc); 
+        emu.stack[0] = 0x0aaa;
+        emu.stack[1] = 0x0bbb;
+        emu.sp = 0x01;
+        //when
+        emu.opcode = 0x00ee;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x00, emu.sp);
+        assert_eq!(0x0aaa+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_00fe() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0aaa); 
+        emu.mode = Mode::SUPER;
+        //when
+        emu.opcode = 0x00fe;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(Mode::STANDARD, emu.mode);
+        assert_eq!(0x0aaa+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_00ff() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0aaa); 
+        emu.mode = Mode::STANDARD;
+        //when
+        emu.opcode = 0x00ff;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(Mode::SUPER, emu.mode);
+        assert_eq!(0x0aaa+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_1nnn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0aaa); 
+        //when
+        emu.opcode = 0x1bcd;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0bcd, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_2nnn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000); 
+        //when
+        emu.opcode = 0x1234;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0234, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_3xnn_given_vx_equals_nn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        //when
+        emu.opcode = 0x3a23;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0000+4, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_3xnn_given_vx_not_equals_nn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        //when
+        emu.opcode = 0x3a24;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_4xnn_given_vx_equals_nn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        //when
+        emu.opcode = 0x4a23;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    pub fn test_opcode_4xnn_given_vx_not_equals_nn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        //when
+        emu.opcode = 0x4a24;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0000+4, emu.pc());
     }
     
-    // Fetch the opcode to which the program counter is pointing.
-    fn fetch_opcode(&mut self) {
-        let hbyte = self.ram[self.pc as usize];
-        let lbyte = self.ram[self.pc as usize + 1];
-        // Uses big-endiannes for multi byte data types.
-        self.opcode = (hbyte as u16) << 8 | lbyte as u16; 
+    #[test]
+    pub fn test_opcode_5xy0_given_vx_equals_vy() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        emu.v[0x0b] = 0x23;
+        //when
+        emu.opcode = 0x5ab0;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0000+4, emu.pc());
     }
-                
-    fn decode_and_execute_opcode(&mut self) {
-        match self.opcode & 0xf000 {
-            0x0000 => 
-                match self.opcode & 0x00f0 {
-                    0x00c0 => self.execute_opcode_00cn(),
-                    _ =>  match self.opcode & 0x00ff {
-                        0x00e0 => self.execute_opcode_00e0(),
-                        0x00ee => self.execute_opcode_00ee(),
-                        0x00fb => self.execute_opcode_00fb(),
-                        0x00fc => self.execute_opcode_00fc(),
-                        0x00fd => self.execute_opcode_00fd(),
-                        0x00fe => self.execute_opcode_00fe(),
-                        0x00ff => self.execute_opcode_00ff(),
-                        _ => self.unknown_opcode()
-                }, 
-            }, 
-            0x1000 => self.execute_opcode_1nnn(), 
-            0x2000 => self.execute_opcode_2nnn(), 
-            0x3000 => self.execute_opcode_3xnn(), 
-            0x4000 => self.execute_opcode_4xnn(), 
-            0x5000 => match self.opcode & 0x000f {
-                0x0000 => self.execute_opcode_5xy0(),   
-                _ => self.unknown_opcode()
-            }, 
-            0x6000 => self.execute_opcode_6xnn(), 
-            0x7000 => self.execute_opcode_7xnn(), 
-            0x8000 => match self.opcode & 0x000f {
-                0x0000 => self.execute_opcode_8xy0(),
-                0x0001 => self.execute_opcode_8xy1(),
-                0x0002 => self.execute_opcode_8xy2(),
-                0x0003 => self.execute_opcode_8xy3(),
-                0x0004 => self.execute_opcode_8xy4(),
-                0x0005 => self.execute_opcode_8xy5(),
-                0x0006 => self.execute_opcode_8xy6(),
-                0x0007 => self.execute_opcode_8xy7(),
-                0x000e => self.execute_opcode_8xye(),
-                _ => self.unknown_opcode()
-            }, 
-            0x9000 => self.execute_opcode_9xy0(), 
-            0xa000 => self.execute_opcode_annn(), 
-            0xb000 => self.execute_opcode_bnnn(), 
-            0xc000 => self.execute_opcode_cxnn(), 
-            0xd000 => self.execute_opcode_dxyn(), 
-            0xe000 => match self.opcode & 0x000f {
-                0x000E => self.execute_opcode_ex9e(),
-                0x0001 => self.execute_opcode_exa1(),
-                _ => self.unknown_opcode()
-            }, 
-            0xf000 => match self.opcode & 0x00ff {
-               0x0007 => self.execute_opcode_fx07(),
-               0x000a => self.execute_opcode_fx0a(),
-               0x0015 => self.execute_opcode_fx15(),
-               0x0018 => self.execute_opcode_fx18(),
-               0x001e => self.execute_opcode_fx1e(),
-               0x0029 => self.execute_opcode_fx29(),
-               0x0030 => self.execute_opcode_fx30(),
-               0x0033 => self.execute_opcode_fx33(),
-               0x0055 => self.execute_opcode_fx55(),
-               0x0065 => self.execute_opcode_fx65(),
-               0x0075 => self.execute_opcode_fx75(),
-               0x0085 => self.execute_opcode_fx85(),
-               _ => self.unknown_opcode()
-            },
-            _ => self.unknown_opcode()
-        }
+
+    #[test]
+    pub fn test_opcode_5xy0_given_vx_does_not_equal_vy() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        emu.v[0x0b] = 0x24;
+        //when
+        emu.opcode = 0x5ab0;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0000+2, emu.pc());
     }
     
-    fn unknown_opcode(&self) -> ! {
-        panic!(format!("Unknown opcode: {:x}", self.opcode));    
+    #[test]
+    fn test_opcode_6xnn() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        //when
+        emu.opcode = 0x6a24;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x0024, emu.v[0x0a]);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
-}
+    #[test]
+    fn test_opcode_7xnn_without_overflow() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x03;
+        //when
+        emu.opcode = 0x7afb;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0xfe, emu.v[0x0a]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
 
-#[cfg(test)]
-mod tests {
+    #[test]
+    fn test_opcode_7xnn_with_overflow() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x03;
+        //when
+        emu.opcode = 0x7aff;
+        emu.decode_and_execute_opcode();
+        //then
+        let wrap_mod = (0x0003u16 + 0x00ffu16) % (0x00ffu16 + 0x00001u16);
+        assert_eq!(wrap_mod, (emu.v[0x0a] as u16));
+        assert_eq!(0x0000+2, emu.pc());
+    }
+    
+    #[test]
+    fn test_opcode_8xy0() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        emu.v[0x0b] = 0x24;
+        //when
+        emu.opcode = 0x8ab0;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x24, emu.v[0x0a]);
+        assert_eq!(0x24, emu.v[0x0b]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+    
+    #[test]
+    fn test_opcode_8xy1() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        emu.v[0x0b] = 0x24;
+        //when
+        emu.opcode = 0x8ab1;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x23|0x24, emu.v[0x0a]);
+        assert_eq!(0x24, emu.v[0x0b]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+    
+    #[test]
+    fn test_opcode_8xy2() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        emu.v[0x0b] = 0x24;
+        //when
+        emu.opcode = 0x8ab2;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x23&0x24, emu.v[0x0a]);
+        assert_eq!(0x24, emu.v[0x0b]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
 
-    use super::Emu;
-    use super::{SMALL_GFX_H, SMALL_GFX_W};
-    use super::super::{Mode, GFX_H, GFX_W};
+    #[test]
+    fn test_opcode_8xy3() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.v[0x0a] = 0x23;
+        emu.v[0x0b] = 0x24;
+        //when
+        emu.opcode = 0x8ab3;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x23^0x24, emu.v[0x0a]);
+        assert_eq!(0x24, emu.v[0x0b]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
 
     #[test]
-    pub fn test_opcode_00cn() {
+    fn test_opcode_8xy1_to_8xy3_preserve_vf_by_default() {
+        for &opcode in &[0x8ab1, 0x8ab2, 0x8ab3] {
+            let mut emu = Emu::new();
+            //given
+            emu.v[0x0a] = 0x23;
+            emu.v[0x0b] = 0x24;
+            emu.v[0x0f] = 0x01;
+            //when
+            emu.opcode = opcode;
+            emu.decode_and_execute_opcode();
+            //then
+            assert_eq!(0x01, emu.v[0x0f]);
+        }
+    }
+
+    #[test]
+    fn test_opcode_8xy1_to_8xy3_with_logic_resets_vf_quirk() {
+        for &(opcode, result) in &[(0x8ab1, 0x23|0x24), (0x8ab2, 0x23&0x24),
+                                   (0x8ab3, 0x23^0x24)] {
+            let mut emu = Emu::new();
+            //given
+            emu.quirks.logic_resets_vf = true;
+            emu.v[0x0a] = 0x23;
+            emu.v[0x0b] = 0x24;
+            emu.v[0x0f] = 0x01;
+            //when
+            emu.opcode = opcode;
+            emu.decode_and_execute_opcode();
+            //then
+            assert_eq!(result, emu.v[0x0a]);
+            assert_eq!(0x00, emu.v[0x0f]);
+        }
+    }
+
+    #[test]
+    fn test_logic_resets_vf_quirk_applies_to_vf_as_target() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
-        emu.gfx[0][0] = true;
-        emu.gfx[1][0] = true;
+        emu.quirks.logic_resets_vf = true;
+        emu.v[0x0f] = 0x0f;
+        emu.v[0x01] = 0xf0;
         //when
-        emu.opcode = 0x00c2;
+        emu.opcode = 0x8f11;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(false, emu.gfx[0][0]);
-        assert_eq!(false, emu.gfx[1][0]);
-        assert_eq!(true, emu.gfx[0][2]);
-        assert_eq!(true, emu.gfx[1][2]);
-        assert_eq!(true, emu.draw);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x00, emu.v[0x0f]);
     }
 
     #[test]
-    pub fn test_opcode_00e0() {
+    fn test_quirk_presets() {
+        let vip = Quirks::cosmac_vip();
+        assert!(vip.logic_resets_vf && vip.shift_uses_vy && 
+                vip.load_store_increments_i && vip.display_wait);
+        assert!(!vip.jump_uses_vx);
+        let schip = Quirks::schip();
+        assert!(schip.jump_uses_vx);
+        assert!(!schip.logic_resets_vf && !schip.shift_uses_vy && 
+                !schip.load_store_increments_i && !schip.display_wait);
+        assert!(!vip.wrap_x && !vip.wrap_y && !schip.wrap_x && !schip.wrap_y);
+        let modern = Quirks::modern();
+        assert!(modern.load_store_increments_i && modern.wrap_x &&
+                modern.wrap_y);
+        assert!(!modern.logic_resets_vf && !modern.shift_uses_vy &&
+                !modern.jump_uses_vx && !modern.display_wait);
+    }
+
+    #[test]
+    fn test_shift_uses_vy_quirk() {
+        for &(opcode, result, flag) in &[(0x8126, 0x40, 1), (0x812e, 0x02, 1)] {
+            let mut emu = Emu::new();
+            //given
+            emu.quirks.shift_uses_vy = true;
+            emu.v[0x1] = 0x10;
+            emu.v[0x2] = 0x81;
+            //when
+            emu.opcode = opcode;
+            emu.decode_and_execute_opcode();
+            //then
+            assert_eq!((result, flag), (emu.v[0x1], emu.v[0xf]));
+        }
+    }
+
+    #[test]
+    fn test_load_store_increments_i_quirk() {
+        for &opcode in &[0xf255, 0xf265] {
+            let mut emu = Emu::new();
+            //given
+            emu.quirks.load_store_increments_i = true;
+            emu.ram_idx = Addr::new(0x300);
+            //when
+            emu.opcode = opcode;
+            emu.decode_and_execute_opcode();
+            //then
+            assert_eq!(0x303, emu.ram_idx());
+        }
+    }
+
+    #[test]
+    fn test_jump_uses_vx_quirk() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
-        emu.draw = false;
-        for x in 0..GFX_W { for y in 0..GFX_H { emu.gfx[x][y] = true; } }
+        emu.quirks.jump_uses_vx = true;
+        emu.v[0x0] = 0x01;
+        emu.v[0x3] = 0x10;
+        //when
+        emu.opcode = 0xb300;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x310, emu.pc());
+    }
+
+    #[test]
+    fn test_display_wait_quirk_draws_once_per_frame() {
+        let mut emu = Emu::new();
+        //given
+        emu.quirks.display_wait = true;
+        emu.load_rom(vec![0xd0, 0x05, 0x70, 0x08, 0x12, 0x00]);
+        //when
+        emu.run_frame(6);
+        //then
+        assert_eq!(0x08, emu.v[0x0]);
+        assert_eq!(0x200, emu.pc());
+        emu.run_frame(6);
+        assert_eq!(0x10, emu.v[0x0]);
+    }
+
+    // An emulator that has run for a while, with state in most fields.
+    fn busy_emu() -> Emu {
+        let mut emu = Emu::new();
+        emu.load_rom(vec![0x00, 0xff, 0x60, 0x05, 0xf0, 0x18, 0xa2, 0x00, 
+                          0xd0, 0x1a, 0x22, 0x0e, 0x12, 0x0c, 0x00, 0xee]);
+        emu.keys[0x3] = true;
+        emu.run_to_frame(3, 4);
+        emu
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let emu = busy_emu();
+        let mut restored = Emu::new();
+        //when
+        restored.load_state(&emu.save_state()).unwrap();
+        //then
+        assert_eq!(emu, restored);
+        assert!(Emu::new() != restored);
+    }
+
+    #[test]
+    fn test_save_and_load_state_from_path() {
+        let path = env::temp_dir().join("chip8_emu_state.sav");
+        let emu = busy_emu();
+        let mut restored = Emu::new();
         //when
-        emu.opcode = 0x00e0;
-        emu.decode_and_execute_opcode();
+        emu.save_state_to_path(&path).unwrap();
+        restored.load_state_from_path(&path).unwrap();
         //then
-        for x in 0..GFX_W { for y in 0..GFX_H { assert_eq!(false, emu.gfx[x][y]); } }
-        assert_eq!(true, emu.draw);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(emu, restored);
     }
 
     #[test]
-    pub fn test_opcode_00fb() {
+    fn test_load_state_rejects_invalid_data() {
         let mut emu = Emu::new();
-        //given
-        emu.pc = 0x0000; 
-        emu.gfx[0][0] = true;
-        emu.gfx[1][0] = true;
-        //when
-        emu.opcode = 0x00fb;
-        emu.decode_and_execute_opcode();
+        let mut state = busy_emu().save_state();
+        let len = state.len();
         //then
-        assert_eq!(false, emu.gfx[0][0]);
-        assert_eq!(false, emu.gfx[1][0]);
-        assert_eq!(true, emu.gfx[4][0]);
-        assert_eq!(true, emu.gfx[5][0]);
-        assert_eq!(true, emu.draw);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(Err(StateError::Truncated), 
+                   emu.load_state(&state[..len - 1]));
+        state[4] = 99;
+        assert_eq!(Err(StateError::UnsupportedVersion(99)), 
+                   emu.load_state(&state));
+        assert_eq!(Err(StateError::BadMagic), emu.load_state(b"PNG..."));
     }
 
     #[test]
-    pub fn test_opcode_00fc() {
-        let mut emu = Emu::new();
+    fn test_load_state_rejects_other_ram_size() {
+        let mut emu = EmuBuilder::new().ram_size(0x2000).build();
         //given
-        emu.pc = 0x0000; 
-        emu.gfx[4][0] = true;
-        emu.gfx[5][0] = true;
+        let state = busy_emu().save_state();
         //when
-        emu.opcode = 0x00fc;
-        emu.decode_and_execute_opcode();
+        let result = emu.load_state(&state);
         //then
-        assert_eq!(false, emu.gfx[4][0]);
-        assert_eq!(false, emu.gfx[5][0]);
-        assert_eq!(true, emu.gfx[0][0]);
-        assert_eq!(true, emu.gfx[1][0]);
-        assert_eq!(true, emu.draw);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(Err(StateError::Invalid("ram size")), result);
+        assert_eq!(0x2000, emu.ram().len());
     }
 
     #[test]
-    pub fn test_opcode_00ee() {
-        let mut emu = Emu::new();
+    fn test_load_state_leaves_emu_untouched_on_failure() {
+        let mut emu = busy_emu();
+        let before = emu.save_state();
         //given
-        emu.pc = 0x0ccc; 
-        emu.stack[0] = 0x0aaa;
-        emu.stack[1] = 0x0bbb;
-        emu.sp = 0x01;
+        let mut other = busy_emu();
+        other.run_frame(10);
+        let state = other.save_state();
+        // Cut off within the rom, after everything before it was read.
+        let truncated = &state[..state.len() - 12];
         //when
-        emu.opcode = 0x00ee;
-        emu.decode_and_execute_opcode();
+        let result = emu.load_state(truncated);
         //then
-        assert_eq!(0x00, emu.sp);
-        assert_eq!(0x0aaa+2, emu.pc);
+        assert_eq!(Err(StateError::Truncated), result);
+        assert_eq!(before, emu.save_state());
+        assert!(emu != other);
     }
 
     #[test]
-    pub fn test_opcode_00fe() {
+    fn test_load_state_from_path_maps_errors_to_invalid_data() {
+        let path = env::temp_dir().join("chip8_emu_state_invalid.sav");
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0aaa; 
-        emu.mode = Mode::SUPER;
+        let state = Emu::new().save_state();
+        File::create(&path).unwrap().write_all(&state[..10]).unwrap();
         //when
-        emu.opcode = 0x00fe;
-        emu.decode_and_execute_opcode();
+        let err = emu.load_state_from_path(&path).unwrap_err();
         //then
-        assert_eq!(Mode::STANDARD, emu.mode);
-        assert_eq!(0x0aaa+2, emu.pc);
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
     }
 
     #[test]
-    pub fn test_opcode_00ff() {
-        let mut emu = Emu::new();
-        //given
-        emu.pc = 0x0aaa; 
-        emu.mode = Mode::STANDARD;
-        //when
-        emu.opcode = 0x00ff;
-        emu.decode_and_execute_opcode();
+    fn test_supported_opcodes() {
+        let chip8 = supported_opcodes(Mode::STANDARD);
+        let schip = supported_opcodes(Mode::SUPER);
         //then
-        assert_eq!(Mode::SUPER, emu.mode);
-        assert_eq!(0x0aaa+2, emu.pc);
+        assert!(chip8.iter().all(|opcode| schip.contains(opcode)));
+        assert!(schip.len() > chip8.len());
+        for opcode in &["SCD nibble", "SCR", "EXIT"] {
+            assert!(schip.contains(opcode));
+            assert!(!chip8.contains(opcode));
+        }
+        assert!(chip8.contains(&"DRW Vx, Vy, nibble"));
     }
 
     #[test]
-    pub fn test_opcode_1nnn() {
+    fn test_took_self_modify() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0aaa; 
+        // Store v0 over the first instruction, then loop.
+        emu.load_rom(vec![0x60, 0x12, 0xa2, 0x00, 0xf0, 0x55, 0x12, 0x06]);
+        emu.track_self_modify(true);
+        emu.execute_cycle();
+        emu.execute_cycle();
+        assert!(!emu.took_self_modify());
         //when
-        emu.opcode = 0x1bcd;
-        emu.decode_and_execute_opcode();
+        emu.execute_cycle();
         //then
-        assert_eq!(0x0bcd, emu.pc);
+        assert!(emu.took_self_modify());
+        assert!(!emu.took_self_modify());
     }
 
     #[test]
-    pub fn test_opcode_2nnn() {
+    fn test_last_draw_collision_rows() {
+        // Draws a sprite over pixels that are on in rows 0 and 2 of it.
+        fn draw(emu: &mut Emu) {
+            emu.gfx[0][0] = true;
+            emu.gfx[1][2] = true;
+            emu.ram[0x300..0x304].copy_from_slice(&[0xc0, 0x80, 0xc0, 0x80]);
+            emu.ram_idx = Addr::new(0x300);
+            emu.opcode = 0xd004;
+            emu.decode_and_execute_opcode();
+        }
         let mut emu = Emu::new();
+        draw(&mut emu);
+        assert_eq!(0, emu.last_draw_collision_rows());
         //given
-        emu.pc = 0x0000; 
+        emu.track_collision_rows(true);
+        emu.gfx = [[false; GFX_H]; GFX_W];
         //when
-        emu.opcode = 0x1234;
-        emu.decode_and_execute_opcode();
+        draw(&mut emu);
         //then
-        assert_eq!(0x0234, emu.pc);
+        assert_eq!(0b101, emu.last_draw_collision_rows());
+        // Drawing it again collides on every row.
+        emu.opcode = 0xd004;
+        emu.decode_and_execute_opcode();
+        assert_eq!(0b1111, emu.last_draw_collision_rows());
+        // Tracking survives a reset.
+        emu.reset();
+        draw(&mut emu);
+        assert_eq!(0b101, emu.last_draw_collision_rows());
     }
 
     #[test]
-    pub fn test_opcode_3xnn_given_vx_equals_nn() {
+    fn test_debug_opcode() {
+        let rom = vec![0x60, 0x07, 0x61, 0x2a, 0xa3, 0x00, 0x00, 0xfa, 
+                       0x12, 0x08];
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
+        emu.load_rom(rom.clone());
+        emu.enable_debug_opcode(true);
         //when
-        emu.opcode = 0x3a23;
-        emu.decode_and_execute_opcode();
+        for _ in 0..5 { emu.execute_cycle(); }
         //then
-        assert_eq!(0x0000+4, emu.pc);
+        let events = emu.take_debug_events();
+        assert_eq!(1, events.len());
+        assert_eq!((0x206, 0x300), (events[0].pc, events[0].i));
+        assert_eq!(&[0x07, 0x2a, 0x00], &events[0].v[..3]);
+        assert_eq!(0x208, emu.pc());
+        assert!(emu.take_debug_events().is_empty());
+        // The trap stays enabled across a reset.
+        emu.reset();
+        for _ in 0..4 { emu.execute_cycle(); }
+        assert_eq!(1, emu.take_debug_events().len());
     }
 
     #[test]
-    pub fn test_opcode_3xnn_given_vx_not_equals_nn() {
+    fn test_debug_opcode_disabled_is_unknown() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
+        emu.load_rom(vec![0x00, 0xfa]);
+        emu.set_on_error(ErrorPolicy::Halt);
         //when
-        emu.opcode = 0x3a24;
-        emu.decode_and_execute_opcode();
+        emu.execute_cycle();
         //then
-        assert_eq!(0x0000+2, emu.pc);
+        assert!(emu.halted());
+        assert_eq!(Some(&CpuError::UnknownOpcode(0x00fa, 0x200)), 
+                   emu.last_error());
+        assert!(emu.take_debug_events().is_empty());
     }
 
     #[test]
-    pub fn test_opcode_4xnn_given_vx_equals_nn() {
+    fn test_write_log() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
+        emu.load_rom(vec![0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0xa3, 0x00,
+                          0xf2, 0x55]);
+        for _ in 0..4 { emu.execute_cycle(); }
+        assert!(emu.write_log().is_empty());
+        emu.enable_write_log();
         //when
-        emu.opcode = 0x4a23;
-        emu.decode_and_execute_opcode();
+        emu.execute_cycle();
         //then
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(&[(0x300, 0x11, 0x208), (0x301, 0x22, 0x208), 
+                     (0x302, 0x33, 0x208)], emu.write_log());
+        emu.enable_write_log();
+        assert!(emu.write_log().is_empty());
     }
 
     #[test]
-    pub fn test_opcode_4xnn_given_vx_not_equals_nn() {
+    fn test_writing_above_pc_is_not_self_modify() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
+        // Jump ahead and back, then store v0 over the code jumped to.
+        emu.load_rom(vec![0x12, 0x06, 0xa2, 0x06, 0xf0, 0x55, 0x60, 0x00,
+                          0x12, 0x02]);
+        emu.track_self_modify(true);
         //when
-        emu.opcode = 0x4a24;
-        emu.decode_and_execute_opcode();
+        for _ in 0..5 { emu.execute_cycle(); }
         //then
-        assert_eq!(0x0000+4, emu.pc);
+        assert_eq!(0x206, emu.pc());
+        assert!(!emu.took_self_modify());
     }
-    
+
     #[test]
-    pub fn test_opcode_5xy0_given_vx_equals_vy() {
+    fn test_writing_data_is_not_self_modify() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
-        emu.v[0x0b] = 0x23;
+        emu.load_rom(vec![0x60, 0x7b, 0xa3, 0x00, 0xf0, 0x33, 0xf0, 0x55]);
+        emu.track_self_modify(true);
         //when
-        emu.opcode = 0x5ab0;
-        emu.decode_and_execute_opcode();
+        for _ in 0..4 { emu.execute_cycle(); }
         //then
-        assert_eq!(0x0000+4, emu.pc);
+        assert!(!emu.took_self_modify());
     }
 
     #[test]
-    pub fn test_opcode_5xy0_given_vx_does_not_equal_vy() {
+    fn test_took_self_modify_without_tracking() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
-        emu.v[0x0b] = 0x24;
+        emu.load_rom(vec![0x60, 0x12, 0xa2, 0x00, 0xf0, 0x55]);
         //when
-        emu.opcode = 0x5ab0;
-        emu.decode_and_execute_opcode();
+        for _ in 0..3 { emu.execute_cycle(); }
         //then
-        assert_eq!(0x0000+2, emu.pc);
+        assert!(!emu.took_self_modify());
     }
-    
+
     #[test]
-    fn test_opcode_6xnn() {
+    fn test_quirks_survive_reset() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
+        emu.quirks.logic_resets_vf = true;
         //when
-        emu.opcode = 0x6a24;
-        emu.decode_and_execute_opcode();
+        emu.reset();
         //then
-        assert_eq!(0x0024, emu.v[0x0a]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert!(emu.quirks.logic_resets_vf);
     }
 
     #[test]
-    fn test_opcode_7xnn_without_overflow() {
-        let mut emu = Emu::new();
-        //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x03;
+    fn test_set_quirk_by_name() {
+        let mut quirks = Quirks::default();
         //when
-        emu.opcode = 0x7afb;
-        emu.decode_and_execute_opcode();
+        for name in Quirks::names() {
+            assert!(quirks.set(name, true));
+        }
         //then
-        assert_eq!(0xfe, emu.v[0x0a]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(Quirks { logic_resets_vf: true, shift_uses_vy: true, 
+                            load_store_increments_i: true, 
+                            jump_uses_vx: true, display_wait: true, 
+                            wrap_x: true, wrap_y: true }, quirks);
+        assert!(quirks.set("wrap_y", false));
+        assert_eq!(Some(false), quirks.get("wrap_y"));
+        assert_eq!(Some(true), quirks.get("wrap_x"));
+        assert!(!quirks.set("wrap_z", true));
+        assert_eq!(None, quirks.get("wrap_z"));
     }
 
     #[test]
-    fn test_opcode_7xnn_with_overflow() {
+    fn test_reset_with_rom_keeps_configuration() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x03;
+        emu.quirks.logic_resets_vf = true;
+        emu.mode = Mode::SUPER;
+        emu.load_rom(vec![0x60, 0x05, 0x61, 0x06, 0x12, 0x04]);
+        emu.run_frame(3);
         //when
-        emu.opcode = 0x7aff;
-        emu.decode_and_execute_opcode();
+        emu.reset_with_rom(vec![0x12, 0x00]).unwrap();
         //then
-        let wrap_mod = (0x0003u16 + 0x00ffu16) % (0x00ffu16 + 0x00001u16);
-        assert_eq!(wrap_mod, (emu.v[0x0a] as u16));
-        assert_eq!(0x0000+2, emu.pc);
+        assert!(emu.quirks.logic_resets_vf);
+        assert_eq!(Mode::SUPER, emu.mode);
+        assert_eq!([0; NUM_REGISTERS], emu.v);
+        assert_eq!((0x0200, 0), (emu.pc(), emu.frame_count()));
+        assert_eq!(&[0x12, 0x00, 0x00, 0x00], &emu.ram[0x200..0x204]);
+        assert_eq!(&[0x12, 0x00], emu.rom());
     }
-    
+
     #[test]
-    fn test_opcode_8xy0() {
+    fn test_reset_with_rom_too_large() {
         let mut emu = Emu::new();
-        //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
-        emu.v[0x0b] = 0x24;
+        emu.load_rom(vec![0x12, 0x00]);
         //when
-        emu.opcode = 0x8ab0;
-        emu.decode_and_execute_opcode();
+        let result = emu.reset_with_rom(vec![0; MAX_ROM_SIZE + 1]);
         //then
-        assert_eq!(0x24, emu.v[0x0a]);
-        assert_eq!(0x24, emu.v[0x0b]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(Err(LoadError::TooLarge(MAX_ROM_SIZE + 1)), result);
+        assert_eq!(&[0x12, 0x00], emu.rom());
     }
-    
+
     #[test]
-    fn test_opcode_8xy1() {
+    fn test_load_failure_is_logged() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
-        emu.v[0x0b] = 0x24;
+        let logger = Arc::new(MemoryLogger::new());
+        emu.set_log(Log::new(Level::Info, logger.clone()));
         //when
-        emu.opcode = 0x8ab1;
-        emu.decode_and_execute_opcode();
+        emu.load_rom(vec![0x12, 0x00]);
+        emu.reset_with_rom(vec![0; MAX_ROM_SIZE + 1]).unwrap_err();
         //then
-        assert_eq!(0x23|0x24, emu.v[0x0a]);
-        assert_eq!(0x24, emu.v[0x0b]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(vec![(Level::Info, "Loaded a rom of 2 bytes".to_string()),
+                        (Level::Warn, format!("Rom is too large: {} bytes, \
+                                               at most {} fit", 
+                                              MAX_ROM_SIZE + 1, 
+                                              MAX_ROM_SIZE))],
+                   logger.messages());
     }
-    
+
     #[test]
-    fn test_opcode_8xy2() {
+    fn test_select_bank() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
-        emu.v[0x0b] = 0x24;
+        emu.load_bank(0, &[0x12, 0x00]).unwrap();
+        emu.load_bank(1, &[0x60, 0x05, 0x12, 0x02]).unwrap();
         //when
-        emu.opcode = 0x8ab2;
-        emu.decode_and_execute_opcode();
+        emu.select_bank(1);
+        emu.run_frame(4);
         //then
-        assert_eq!(0x23&0x24, emu.v[0x0a]);
-        assert_eq!(0x24, emu.v[0x0b]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(&[0x60, 0x05, 0x12, 0x02], &emu.ram[0x200..0x204]);
+        assert_eq!(5, emu.v[0]);
+        //when
+        emu.select_bank(0);
+        //then
+        assert_eq!(&[0x12, 0x00, 0x00, 0x00], &emu.ram[0x200..0x204]);
+        assert_eq!((0, 0x0200), (emu.v[0], emu.pc()));
+        emu.select_bank(1);
+        assert_eq!(&[0x60, 0x05, 0x12, 0x02], &emu.ram[0x200..0x204]);
+        // An empty bank leaves the emulator alone.
+        emu.select_bank(2);
+        assert_eq!(&[0x60, 0x05, 0x12, 0x02], emu.rom());
     }
 
     #[test]
-    fn test_opcode_8xy3() {
+    fn test_load_bank_errors() {
         let mut emu = Emu::new();
-        //given
-        emu.pc = 0x0000;
-        emu.v[0x0a] = 0x23;
-        emu.v[0x0b] = 0x24;
         //when
-        emu.opcode = 0x8ab3;
-        emu.decode_and_execute_opcode();
+        let no_bank = emu.load_bank(NUM_BANKS, &[0x12, 0x00]);
+        let too_large = emu.load_bank(0, &vec![0; MAX_ROM_SIZE + 1]);
         //then
-        assert_eq!(0x23^0x24, emu.v[0x0a]);
-        assert_eq!(0x24, emu.v[0x0b]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(Err(LoadError::NoSuchBank(NUM_BANKS)), no_bank);
+        assert_eq!(Err(LoadError::TooLarge(MAX_ROM_SIZE + 1)), too_large);
+        emu.select_bank(0);
+        assert!(emu.rom().is_empty());
     }
     
     #[test]
     fn test_opcode_8xy4_without_carry() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0xf0;
         emu.v[0x0b] = 0x03;
         //when
@@ -1108,14 +4010,14 @@ mod tests {
         assert_eq!(0xf3, emu.v[0x0a]);
         assert_eq!(0x03, emu.v[0x0b]);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xy4_with_carry() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0xff;
         emu.v[0x0b] = 0x03;
         //when
@@ -1127,14 +4029,14 @@ mod tests {
         assert_eq!(0x02, emu.v[0x0a]);
         assert_eq!(0x03, emu.v[0x0b]);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
     
     #[test]
     fn test_opcode_8xy5_without_borrow() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x09;
         emu.v[0x0b] = 0x08;
         //when
@@ -1145,14 +4047,14 @@ mod tests {
         assert_eq!(0x01, emu.v[0x0a]);
         assert_eq!(0x08, emu.v[0x0b]);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xy5_with_borrow() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x08;
         emu.v[0x0b] = 0x09;
         //when
@@ -1162,52 +4064,52 @@ mod tests {
         assert_eq!(0xff, emu.v[0x0a]);
         assert_eq!(0x09, emu.v[0x0b]);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xy6_orig_not_used_least_significant_bit_not_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x07;
         emu.v[0x0b] = 0x04;
         //when
         emu.opcode = 0x8ab6;
-        emu.execute_opcode_8xy6_orig_not_used();
+        emu.execute_opcode_8xy6_vy();
         //then
         assert_eq!(0x02, 0x04 >> 1);
         assert_eq!(0x02, emu.v[0x0a]);
         assert_eq!(0x04, emu.v[0x0b]);
         assert_eq!(0x00, emu.v[0x0b] & 0x01);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xy6_orig_not_used_least_significant_bit_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x04;
         emu.v[0x0b] = 0x05;
         //when
         emu.opcode = 0x8ab6;
-        emu.execute_opcode_8xy6_orig_not_used();
+        emu.execute_opcode_8xy6_vy();
         //then
         assert_eq!(0x02, 0x05 >> 1);
         assert_eq!(0x02, emu.v[0x0a]);
         assert_eq!(0x05, emu.v[0x0b]);
         assert_eq!(0x01, emu.v[0x0b] & 0x01);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
     
     #[test]
     fn test_opcode_8xy6_least_significant_bit_not_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x04;
         emu.v[0x0b] = 0x07;
         //when
@@ -1219,14 +4121,14 @@ mod tests {
         assert_eq!(0x07, emu.v[0x0b]);
         assert_eq!(0x00, emu.v[0x0a] & 0x01);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xy6_least_significant_bit_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x05;
         emu.v[0x0b] = 0x04;
         //when
@@ -1238,14 +4140,14 @@ mod tests {
         assert_eq!(0x04, emu.v[0x0b]);
         assert_eq!(0x00, emu.v[0x0a] & 0x01);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
     
     #[test]
     fn test_opcode_8xy7_without_borrow() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x08;
         emu.v[0x0b] = 0x09;
         //when
@@ -1256,14 +4158,14 @@ mod tests {
         assert_eq!(0x01, emu.v[0x0a]);
         assert_eq!(0x09, emu.v[0x0b]);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xy7_with_borrow() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x09;
         emu.v[0x0b] = 0x08;
         //when
@@ -1273,14 +4175,14 @@ mod tests {
         assert_eq!(0xff, emu.v[0x0a]);
         assert_eq!(0x08, emu.v[0x0b]);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xye_most_significant_bit_not_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0b01111111_u8;
         emu.v[0x0b] = 0b11111111_u8;
         //when
@@ -1289,14 +4191,14 @@ mod tests {
         //then
         assert_eq!(0b11111110_u8, emu.v[0x0a]);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_8xye_most_significant_bit_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0b11111111_u8;
         emu.v[0x0b] = 0b01111111_u8;
         //when
@@ -1305,107 +4207,107 @@ mod tests {
         //then
         assert_eq!(0b11111110_u8, emu.v[0x0a]);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
    }
 
     #[test]
     fn test_opcode_8xye_orig_not_used_most_significant_bit_not_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0b11111111_u8;
         emu.v[0x0b] = 0b01111111_u8;
         //when
         emu.opcode = 0x8abe;
-        emu.execute_opcode_8xye_orig_not_used();
+        emu.execute_opcode_8xye_vy();
         //then
         assert_eq!(0b11111110_u8, emu.v[0x0a]);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
    }
 
     #[test]
     fn test_opcode_8xye_orig_not_used_most_significant_bit_set() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0b01111111_u8;
         emu.v[0x0b] = 0b11111111_u8;
         //when
         emu.opcode = 0x8abe;
-        emu.execute_opcode_8xye_orig_not_used();
+        emu.execute_opcode_8xye_vy();
         //then
         assert_eq!(0b11111110_u8, emu.v[0x0a]);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
    }
 
     #[test]
     fn test_opcode_9xy0_vx_does_not_match_vy() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x07;
         emu.v[0x0b] = 0x05;
         //when
         emu.opcode = 0x9ab0;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+4, emu.pc);
+        assert_eq!(0x0000+4, emu.pc());
     }
 
     #[test]
     fn test_opcode_9xy0_vx_matches_vy() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x0a] = 0x07;
         emu.v[0x0b] = 0x07;
         //when
         emu.opcode = 0x9ab0;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_annn() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0xacc;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0xacc);
         //when
         emu.opcode = 0xadef;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0def, emu.ram_idx);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0def, emu.ram_idx());
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_bnnn() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0] = 0x23;
         //when
         emu.opcode = 0xb345;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0368, emu.pc);
+        assert_eq!(0x0368, emu.pc());
     }
 
     #[test]
     fn test_opcode_dxyn_simple_draw() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
+        emu.pc = Addr::new(0x0000); 
         emu.draw = false;
         emu.v[1] = 0x0005;
         emu.v[2] = 0x0006;
-        emu.ram_idx = 0x222;
-        emu.ram[(emu.ram_idx+0) as usize] = 0b01010101 as u8;
-        emu.ram[(emu.ram_idx+1) as usize] = 0b11111111 as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[emu.ram_idx.index()+0] = 0b01010101 as u8;
+        emu.ram[emu.ram_idx.index()+1] = 0b11111111 as u8;
 
         //when
         emu.opcode = 0xd122;
@@ -1432,14 +4334,14 @@ mod tests {
         
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_dxyn_simple_undraw() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
+        emu.pc = Addr::new(0x0000); 
         emu.draw = false;
 
         emu.gfx[0x0005+0][0x006+0] = false;
@@ -1462,9 +4364,9 @@ mod tests {
 
         emu.v[1] = 0x0005;
         emu.v[2] = 0x0006;
-        emu.ram_idx = 0x222;
-        emu.ram[(emu.ram_idx+0) as usize] = 0b01010101 as u8;
-        emu.ram[(emu.ram_idx+1) as usize] = 0b11111111 as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[emu.ram_idx.index()+0] = 0b01010101 as u8;
+        emu.ram[emu.ram_idx.index()+1] = 0b11111111 as u8;
         
         //when
         emu.opcode = 0xd122;
@@ -1491,14 +4393,14 @@ mod tests {
         
         assert_eq!(false, emu.draw);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_dxyn_simple_partial_redraw() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
+        emu.pc = Addr::new(0x0000); 
         emu.draw = false;
 
         emu.gfx[0x0005+0][0x006+0] = false;
@@ -1521,9 +4423,9 @@ mod tests {
 
         emu.v[1] = 0x0005;
         emu.v[2] = 0x0006;
-        emu.ram_idx = 0x222;
-        emu.ram[(emu.ram_idx+0) as usize] = 0b11111111 as u8;
-        emu.ram[(emu.ram_idx+1) as usize] = 0b11110000 as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[emu.ram_idx.index()+0] = 0b11111111 as u8;
+        emu.ram[emu.ram_idx.index()+1] = 0b11110000 as u8;
         
         //when
         emu.opcode = 0xd122;
@@ -1550,20 +4452,20 @@ mod tests {
         
         assert_eq!(true, emu.draw);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_dxyn_overflow_width() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
+        emu.pc = Addr::new(0x0000); 
         emu.draw = false;
         emu.v[1] = (SMALL_GFX_W - 4) as u8;
         emu.v[2] = 0x0006 ;
-        emu.ram_idx = 0x222;
-        emu.ram[(emu.ram_idx+0) as usize] = 0b01010101 as u8;
-        emu.ram[(emu.ram_idx+1) as usize] = 0b11111111 as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[emu.ram_idx.index()+0] = 0b01010101 as u8;
+        emu.ram[emu.ram_idx.index()+1] = 0b11111111 as u8;
 
         //when
         emu.opcode = 0xd122;
@@ -1590,20 +4492,20 @@ mod tests {
         
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_dxyn_overflow_height() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000; 
+        emu.pc = Addr::new(0x0000); 
         emu.draw = false;
         emu.v[1] = 0x0005;
         emu.v[2] = (GFX_H - 1) as u8;
-        emu.ram_idx = 0x222;
-        emu.ram[(emu.ram_idx+0) as usize] = 0b01010101 as u8;
-        emu.ram[(emu.ram_idx+1) as usize] = 0b11111111 as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[emu.ram_idx.index()+0] = 0b01010101 as u8;
+        emu.ram[emu.ram_idx.index()+1] = 0b11111111 as u8;
 
         //when
         emu.opcode = 0xd122;
@@ -1630,16 +4532,68 @@ mod tests {
         
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
     
+    // Draw a 2x2 square at the bottom right corner of the display with the
+    // wrap quirks, returning the pixels lit.
+    fn draw_in_corner(wrap_x: bool, wrap_y: bool) -> Vec<(usize, usize)> {
+        let mut emu = Emu::new();
+        emu.quirks.wrap_x = wrap_x;
+        emu.quirks.wrap_y = wrap_y;
+        emu.v[1] = (SMALL_GFX_W - 1) as u8;
+        emu.v[2] = (SMALL_GFX_H - 1) as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[0x222] = 0b11000000;
+        emu.ram[0x223] = 0b11000000;
+        emu.opcode = 0xd122;
+        emu.decode_and_execute_opcode();
+        let mut lit = Vec::new();
+        for x in 0..SMALL_GFX_W {
+            for y in 0..SMALL_GFX_H {
+                if emu.gfx[x][y] { lit.push((x, y)); }
+            }
+        }
+        lit
+    }
+
+    #[test]
+    fn test_opcode_dxyn_wraps_or_clips_each_axis() {
+        let (right, bottom) = (SMALL_GFX_W - 1, SMALL_GFX_H - 1);
+        assert_eq!(vec![(0, 0), (0, bottom), (right, 0), (right, bottom)],
+                   draw_in_corner(true, true));
+        assert_eq!(vec![(0, bottom), (right, bottom)], 
+                   draw_in_corner(true, false));
+        assert_eq!(vec![(right, 0), (right, bottom)], 
+                   draw_in_corner(false, true));
+        assert_eq!(vec![(right, bottom)], draw_in_corner(false, false));
+        assert!(Quirks::default().wrap_x && Quirks::default().wrap_y);
+    }
+
+    #[test]
+    fn test_opcode_dxyn_start_wraps_when_clipping() {
+        let mut emu = Emu::new();
+        emu.quirks.wrap_x = false;
+        emu.quirks.wrap_y = false;
+        //given
+        emu.v[1] = (SMALL_GFX_W + 2) as u8;
+        emu.v[2] = (SMALL_GFX_H + 3) as u8;
+        emu.ram_idx = Addr::new(0x222);
+        emu.ram[0x222] = 0b10000000;
+        //when
+        emu.opcode = 0xd121;
+        emu.decode_and_execute_opcode();
+        //then
+        assert!(emu.gfx[2][3]);
+    }
+
     #[test]
     fn test_opcode_dxyn_draw_font_0() {
         let mut emu = Emu::new();
         //given
         let fchar = 0x0; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1651,7 +4605,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1659,8 +4613,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x1; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1672,7 +4626,7 @@ mod tests {
         assert_eq!(txt_to_byte(" ###"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1680,8 +4634,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x2; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1693,7 +4647,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1701,8 +4655,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x3; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1714,7 +4668,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1722,8 +4676,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x4; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1735,7 +4689,7 @@ mod tests {
         assert_eq!(txt_to_byte("   #"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1743,8 +4697,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x5; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1756,7 +4710,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1764,8 +4718,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x6; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1777,7 +4731,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1785,8 +4739,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x7; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1798,7 +4752,7 @@ mod tests {
         assert_eq!(txt_to_byte(" #  "), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1806,8 +4760,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x8; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1819,7 +4773,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1827,8 +4781,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0x9; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1840,7 +4794,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1848,8 +4802,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0xA; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1861,7 +4815,7 @@ mod tests {
         assert_eq!(txt_to_byte("#  #"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1869,8 +4823,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0xB; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1882,7 +4836,7 @@ mod tests {
         assert_eq!(txt_to_byte("### "), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
     
     #[test]
@@ -1890,8 +4844,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0xC; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1903,7 +4857,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1911,8 +4865,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0xD; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1924,7 +4878,7 @@ mod tests {
         assert_eq!(txt_to_byte("### "), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
@@ -1932,8 +4886,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0xE; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1945,7 +4899,7 @@ mod tests {
         assert_eq!(txt_to_byte("####"), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
     
     #[test]
@@ -1953,8 +4907,8 @@ mod tests {
         let mut emu = Emu::new();
         //given
         let fchar = 0xF; 
-        emu.ram_idx = 0x0000 + (fchar as u16) * 5; 
-        emu.pc = 0x0000;
+        emu.ram_idx = Addr::new(0x0000 + (fchar as u16) * 5); 
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xd005;
         emu.decode_and_execute_opcode();
@@ -1966,7 +4920,7 @@ mod tests {
         assert_eq!(txt_to_byte("#   "), booleans_to_byte(&emu.gfx, 0, 4));
         assert_eq!(true, emu.draw);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     fn txt_to_byte(txt: &str) -> u8 {
@@ -1990,63 +4944,63 @@ mod tests {
     fn test_opcode_ex9e_key_not_pressed() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[2] = 0x0a;
         emu.keys[0x0a] = false;
         //when
         emu.opcode = 0xe29e;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_ex9e_key_pressed() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[2] = 0x0a;
         emu.keys[0x0a] = true;
         //when
         emu.opcode = 0xe29e;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+4, emu.pc);
+        assert_eq!(0x0000+4, emu.pc());
     }
 
     #[test]
     fn test_opcode_exa1_key_not_pressed() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[2] = 0x0a;
         emu.keys[0x0a] = false;
         //when
         emu.opcode = 0xe2a1;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+4, emu.pc);
+        assert_eq!(0x0000+4, emu.pc());
     }
 
     #[test]
     fn test_opcode_exa1_key_pressed() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[2] = 0x0a;
         emu.keys[0x0a] = true;
         //when
         emu.opcode = 0xe2a1;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx07() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.dt = 0x9a;
         //when
         emu.opcode = 0xf207;
@@ -2054,40 +5008,138 @@ mod tests {
         //then
         assert_eq!(0x9a, emu.v[0x02]);
         assert_eq!(0x9a, emu.dt);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx0a_with_keypress() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.keys[0x0f] = true;
         //when
         emu.opcode = 0xf20a;
         emu.decode_and_execute_opcode();
         //then
         assert_eq!(0x0f, emu.v[0x02]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    fn test_opcode_fx0a_with_two_keypresses_stores_lowest() {
+        let mut emu = Emu::new();
+        //given
+        emu.pc = Addr::new(0x0000);
+        emu.keys[0x0c] = true;
+        emu.keys[0x03] = true;
+        //when
+        emu.opcode = 0xf20a;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!(0x03, emu.v[0x02]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    fn test_is_waiting_for_key() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x60, 0x01, 0xf3, 0x0a]);
+        assert!(!emu.is_waiting_for_key());
+        //when
+        emu.execute_cycle();
+        //then
+        assert!(emu.is_waiting_for_key());
+        emu.keys[0x9] = true;
+        assert!(!emu.is_waiting_for_key());
+    }
+
+    #[test]
+    fn test_is_idle() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x60, 0x02, 0xf0, 0x18, 0xf3, 0x0a, 0x12, 0x06]);
+        emu.execute_cycle();
+        assert!(!emu.is_idle());
+        //when
+        emu.execute_cycle();
+        emu.execute_cycle();
+        //then
+        // Waiting for a key, but the beep is still on.
+        assert!(emu.is_waiting_for_key() && !emu.is_idle());
+        emu.update_timers();
+        emu.update_timers();
+        assert!(emu.is_idle());
+        emu.keys[0x9] = true;
+        assert!(!emu.is_idle());
+        // Jumping to the jump can go on forever.
+        emu.execute_cycle();
+        assert_eq!(0x206, emu.pc());
+        assert!(emu.is_idle());
     }
 
     #[test]
     fn test_opcode_fx0a_without_keypress() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         //when
         emu.opcode = 0xf20a;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+0, emu.pc);
+        assert_eq!(0x0000+0, emu.pc());
+    }
+
+    #[test]
+    fn test_tap_key_releases_after_hold_cycles() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x12, 0x00]);
+        //when
+        emu.tap_key(0x7, 5);
+        for _ in 0..3 { emu.execute_cycle(); }
+        //then
+        assert!(emu.is_key_down(0x7));
+        for _ in 0..3 { emu.execute_cycle(); }
+        assert!(!emu.is_key_down(0x7));
+        assert!(!emu.is_key_down(0x6));
+    }
+
+    #[test]
+    fn test_input_script_presses_keys_at_cycles() {
+        let mut emu = Emu::new();
+        //given
+        emu.load_rom(vec![0x12, 0x00]);
+        //when
+        emu.load_input_script("0 down 1\n10 down 5\n25 up 5\n").unwrap();
+        //then
+        assert!(emu.is_key_down(0x1));
+        for _ in 0..9 { emu.execute_cycle(); }
+        assert!(!emu.is_key_down(0x5));
+        emu.execute_cycle();
+        assert!(emu.is_key_down(0x5));
+        emu.run_burst(14);
+        assert!(emu.is_key_down(0x5));
+        emu.try_execute_cycle().unwrap();
+        assert!(!emu.is_key_down(0x5));
+        assert!(emu.is_key_down(0x1));
+    }
+
+    #[test]
+    fn test_input_script_error_keeps_keys() {
+        let mut emu = Emu::new();
+        //when
+        let result = emu.load_input_script("0 down 1\n5 down x\n");
+        //then
+        assert_eq!(Err(ScriptError::InvalidKey(2, "x".to_string())), result);
+        assert!(!emu.is_key_down(0x1));
     }
 
     #[test]
     fn test_opcode_fx15() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x02] = 0x9a;
         //when
         emu.opcode = 0xf215;
@@ -2095,14 +5147,14 @@ mod tests {
         //then
         assert_eq!(0x9a, emu.v[0x02]);
         assert_eq!(0x9a, emu.dt);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx18() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0x02] = 0x9a;
         //when
         emu.opcode = 0xf218;
@@ -2110,33 +5162,33 @@ mod tests {
         //then
         assert_eq!(0x9a, emu.v[0x02]);
         assert_eq!(0x9a, emu.st);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx1e_without_overflow() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0x222;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0x222);
         emu.v[0x02] = 0xab;
         //when
         emu.opcode = 0xf21e;
         emu.decode_and_execute_opcode();
         //then
         assert_eq!(0x2cd, 0x222 + 0xab);
-        assert_eq!(0x2cd, emu.ram_idx);
+        assert_eq!(0x2cd, emu.ram_idx());
         assert_eq!(0xab, emu.v[0x02]);
         assert_eq!(0x00, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx1e_with_overflow() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0xfff;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0xfff);
         emu.v[0x02] = 0xab;
         //when
         emu.opcode = 0xf21e;
@@ -2144,50 +5196,50 @@ mod tests {
         //then
         let wrap_mod = (0xfff + 0xab) % (0xfff + 0x001);
         assert_eq!(0x0aa, wrap_mod);
-        assert_eq!(0x0aa, emu.ram_idx);
+        assert_eq!(0x0aa, emu.ram_idx());
         assert_eq!(0xab, emu.v[0x02]);
         assert_eq!(0x01, emu.v[0x0f]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx29() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0xfff;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0xfff);
         emu.v[0x03] = 0x0a;
         //when
         emu.opcode = 0xf329;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+(0x0a*5), emu.ram_idx);
+        assert_eq!(0x0000+(0x0a*5), emu.ram_idx());
         assert_eq!(0x0a, emu.v[0x03]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx30() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0xfff;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0xfff);
         emu.v[0x03] = 0x0a;
         //when
         emu.opcode = 0xf330;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0000+(0x0a*10), emu.ram_idx);
+        assert_eq!(0x0000+(0x0a*10), emu.ram_idx());
         assert_eq!(0x0a, emu.v[0x03]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx33() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0xbbb;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0xbbb);
         emu.v[0x02] = 0x7b;
         //when
         emu.opcode = 0xf233;
@@ -2195,18 +5247,41 @@ mod tests {
         //then
         assert_eq!(0x7b, 123);
         assert_eq!(0x7b, emu.v[0x02]);
-        assert_eq!(1, emu.ram[(emu.ram_idx+0) as usize]);
-        assert_eq!(2, emu.ram[(emu.ram_idx+1) as usize]);
-        assert_eq!(3, emu.ram[(emu.ram_idx+2) as usize]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(1, emu.ram[emu.ram_idx.index()+0]);
+        assert_eq!(2, emu.ram[emu.ram_idx.index()+1]);
+        assert_eq!(3, emu.ram[emu.ram_idx.index()+2]);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    #[test]
+    fn test_opcode_fx33_wraps_at_end_of_custom_ram() {
+        let mut emu = EmuBuilder::new().ram_size(0x2000).build();
+        //given
+        emu.ram_idx = emu.addr(0x1ffe);
+        emu.v[0x02] = 234;
+        //when
+        emu.opcode = 0xf233;
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!((2, 3, 4), (emu.ram[0x1ffe], emu.ram[0x1fff], emu.ram[0]));
+        // Beyond 4KB, the digits are not written to the wrapped 12-bit 
+        // address.
+        assert_eq!(0, emu.ram[0x0ffe]);
+        assert_eq!(0x1ffe, emu.ram_idx());
+        //when
+        emu.opcode = 0xf165;
+        emu.ram_idx = emu.addr(0x1fff);
+        emu.decode_and_execute_opcode();
+        //then
+        assert_eq!((3, 4), (emu.v[0x00], emu.v[0x01]));
     }
 
     #[test]
     fn test_opcode_fx55() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0x333;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0x333);
         emu.v[0x00] = 0x0a;
         emu.v[0x01] = 0x0b;
         emu.v[0x02] = 0x0c;
@@ -2214,21 +5289,21 @@ mod tests {
         emu.opcode = 0xf355;
         emu.decode_and_execute_opcode();
         //then
-        assert_eq!(0x0a, emu.ram[(emu.ram_idx+0) as usize]);
-        assert_eq!(0x0b, emu.ram[(emu.ram_idx+1) as usize]);
-        assert_eq!(0x0c, emu.ram[(emu.ram_idx+2) as usize]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0a, emu.ram[emu.ram_idx.index()+0]);
+        assert_eq!(0x0b, emu.ram[emu.ram_idx.index()+1]);
+        assert_eq!(0x0c, emu.ram[emu.ram_idx.index()+2]);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx65() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
-        emu.ram_idx = 0x333;
-        emu.ram[(emu.ram_idx + 0) as usize] = 0x0a;
-        emu.ram[(emu.ram_idx + 1) as usize] = 0x0b;
-        emu.ram[(emu.ram_idx + 2) as usize] = 0x0c;
+        emu.pc = Addr::new(0x0000);
+        emu.ram_idx = Addr::new(0x333);
+        emu.ram[emu.ram_idx.index() + 0] = 0x0a;
+        emu.ram[emu.ram_idx.index() + 1] = 0x0b;
+        emu.ram[emu.ram_idx.index() + 2] = 0x0c;
         //when
         emu.opcode = 0xf365;
         emu.decode_and_execute_opcode();
@@ -2236,14 +5311,14 @@ mod tests {
         assert_eq!(0x0a, emu.v[0]);
         assert_eq!(0x0b, emu.v[1]);
         assert_eq!(0x0c, emu.v[2]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx75() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0] = 0x03;
         emu.v[1] = 0x04;
         emu.v[2] = 0x05;
@@ -2265,14 +5340,14 @@ mod tests {
         assert_eq!(0x00, emu.super_mode_rpl_flags[5]);
         assert_eq!(0x00, emu.super_mode_rpl_flags[6]);
         assert_eq!(0x00, emu.super_mode_rpl_flags[7]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
     }
 
     #[test]
     fn test_opcode_fx75_safe_against_x_greater_than_7() {
         let mut emu = Emu::new();
         //given
-        emu.pc = 0x0000;
+        emu.pc = Addr::new(0x0000);
         emu.v[0] = 0x03;
         emu.v[1] = 0x04;
         emu.v[2] = 0x05;
@@ -2294,7 +5369,100 @@ mod tests {
         assert_eq!(0x08, emu.super_mode_rpl_flags[5]);
         assert_eq!(0x09, emu.super_mode_rpl_flags[6]);
         assert_eq!(0x0A, emu.super_mode_rpl_flags[7]);
-        assert_eq!(0x0000+2, emu.pc);
+        assert_eq!(0x0000+2, emu.pc());
+    }
+
+    // Load the rom, run it for the given number of cycles with random 
+    // numbers from the seed, and fingerprint the result.
+    fn run_and_fingerprint(rom: &[u8], cycles: usize, 
+                           seed: u64) -> MachineFingerprint {
+        let mut emu = Emu::new();
+        emu.seed_rng(seed);
+        emu.load_rom(rom.to_vec());
+        for _ in 0..cycles {
+            emu.execute_cycle();
+        }
+        emu.fingerprint()
+    }
+
+    #[test]
+    fn test_run_burst_matches_execute_cycle() {
+        let rom = include_bytes!("../../roms/brix.ch8").to_vec();
+        let mut burst = Emu::new();
+        let mut cycles = Emu::new();
+        for emu in [&mut burst, &mut cycles].iter_mut() {
+            emu.seed_rng(7);
+            emu.load_rom(rom.clone());
+        }
+        //when
+        burst.run_burst(100);
+        for _ in 0..100 { cycles.execute_cycle(); }
+        //then
+        assert_eq!(cycles, burst);
+        assert_eq!(cycles.fingerprint(), burst.fingerprint());
+    }
+
+    #[test]
+    fn test_read_sprite_is_cut_short_at_end_of_memory() {
+        let mut emu = Emu::new();
+        //given
+        emu.ram[0xfff] = 0x81;
+        //then
+        assert_eq!(vec![0x81], emu.read_sprite(0xfff, 5));
+        assert!(emu.read_sprite(0x1000, 5).is_empty());
+        assert_eq!(vec![0x20, 0x60], emu.read_sprite(5, 2));
+    }
+
+    #[test]
+    fn test_add_with_carry() {
+        assert_eq!((0xff, false), add_with_carry(0xfe, 0x01));
+        assert_eq!((0x00, true), add_with_carry(0xff, 0x01));
+        assert_eq!((0xfe, true), add_with_carry(0xff, 0xff));
+        assert_eq!((0x00, false), add_with_carry(0x00, 0x00));
+    }
+
+    #[test]
+    fn test_add16_overflow() {
+        assert_eq!((0x0fff, false), add16_overflow(0x0ffe, 0x0001, 0x0fff));
+        assert_eq!((0x0000, true), add16_overflow(0x0fff, 0x0001, 0x0fff));
+        assert_eq!((0x00fe, true), add16_overflow(0x0fff, 0x00ff, 0x0fff));
+        assert_eq!((0x00fe, true), add16_overflow(0xffff, 0x00ff, 0xffff));
+    }
+
+    #[test]
+    fn test_rom_info() {
+        let mut emu = Emu::new();
+        assert_eq!(0x00000000, emu.rom_info().crc32);
+        //when
+        emu.load_rom(include_bytes!("../../roms/brix.ch8").to_vec());
+        //then
+        assert_eq!(RomInfo { len: 280, crc32: 0xaaa44d0b, entry_point: 0x200 },
+                   emu.rom_info());
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_fingerprint_is_reproducible_with_the_same_seed() {
+        let rom = include_bytes!("../../roms/brix.ch8");
+        //when
+        let first = run_and_fingerprint(rom, 5000, 42);
+        let second = run_and_fingerprint(rom, 5000, 42);
+        //then
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_fingerprint_differs_with_another_seed_for_random_rom() {
+        // Fill v0 to v3 with random numbers and loop forever.
+        let rom = [0xc0, 0xff, 0xc1, 0xff, 0xc2, 0xff, 0xc3, 0xff, 
+                   0x12, 0x08];
+        //when
+        let first = run_and_fingerprint(&rom, 10, 1);
+        let second = run_and_fingerprint(&rom, 10, 2);
+        //then
+        assert!(first != second);
+        assert_eq!(first.pc, second.pc);
+        assert_eq!(first.ram_hash, second.ram_hash);
     }
 
 }
@@ -0,0 +1,122 @@
+use super::emu::FONT_MAP;
+
+// A small pixel font used to draw text on top of the gfx, e.g. for the
+// debugger panel. Like the CHIP-8 font, each glyph is 4 pixels wide and 5
+// pixels high, with each row stored in the high nibble of a byte. The
+// hexadecimal digits are taken straight from the CHIP-8 font.
+pub const GLYPH_W: usize = 4;
+pub const GLYPH_H: usize = 5;
+// Horizontal distance between the start of two consecutive glyphs.
+pub const ADVANCE: usize = GLYPH_W + 1;
+
+// Characters drawn as icons rather than letters.
+pub const SPEAKER: char = '\u{1f508}';
+
+const BLANK: [u8; GLYPH_H] = [0x00, 0x00, 0x00, 0x00, 0x00];
+
+// Return the glyph for the character. Lowercase letters are drawn as
+// uppercase, and characters without a glyph are drawn as blanks.
+pub fn glyph(c: char) -> [u8; GLYPH_H] {
+    let c = if c >= 'a' && c <= 'z' {
+        (c as u8 - b'a' + b'A') as char
+    } else {
+        c
+    };
+    if let Some(digit) = c.to_digit(16) {
+        let i = digit as usize * GLYPH_H;
+        let mut g = BLANK;
+        g.copy_from_slice(&FONT_MAP[i..i + GLYPH_H]);
+        return g;
+    }
+    match c {
+        'G' => [0xf0, 0x80, 0xb0, 0x90, 0xf0],
+        'H' => [0x90, 0x90, 0xf0, 0x90, 0x90],
+        'I' => [0xe0, 0x40, 0x40, 0x40, 0xe0],
+        'J' => [0x10, 0x10, 0x10, 0x90, 0x60],
+        'K' => [0x90, 0xa0, 0xc0, 0xa0, 0x90],
+        'L' => [0x80, 0x80, 0x80, 0x80, 0xf0],
+        'M' => [0x90, 0xf0, 0xf0, 0x90, 0x90],
+        'N' => [0x90, 0xd0, 0xb0, 0x90, 0x90],
+        'O' => [0x60, 0x90, 0x90, 0x90, 0x60],
+        'P' => [0xe0, 0x90, 0xe0, 0x80, 0x80],
+        'Q' => [0x60, 0x90, 0x90, 0xa0, 0x50],
+        'R' => [0xe0, 0x90, 0xe0, 0xa0, 0x90],
+        'S' => [0x70, 0x80, 0x60, 0x10, 0xe0],
+        'T' => [0xe0, 0x40, 0x40, 0x40, 0x40],
+        'U' => [0x90, 0x90, 0x90, 0x90, 0x60],
+        'V' => [0x90, 0x90, 0x90, 0xa0, 0x40],
+        'W' => [0x90, 0x90, 0xf0, 0xf0, 0x90],
+        'X' => [0x90, 0x90, 0x60, 0x90, 0x90],
+        'Y' => [0xa0, 0xa0, 0x40, 0x40, 0x40],
+        'Z' => [0xf0, 0x10, 0x60, 0x80, 0xf0],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x40],
+        ',' => [0x00, 0x00, 0x00, 0x40, 0x80],
+        ':' => [0x00, 0x40, 0x00, 0x40, 0x00],
+        '-' => [0x00, 0x00, 0xe0, 0x00, 0x00],
+        '+' => [0x00, 0x40, 0xe0, 0x40, 0x00],
+        '>' => [0x80, 0x40, 0x20, 0x40, 0x80],
+        '<' => [0x20, 0x40, 0x80, 0x40, 0x20],
+        '[' => [0xc0, 0x80, 0x80, 0x80, 0xc0],
+        ']' => [0x60, 0x20, 0x20, 0x20, 0x60],
+        '(' => [0x40, 0x80, 0x80, 0x80, 0x40],
+        ')' => [0x40, 0x20, 0x20, 0x20, 0x40],
+        '/' => [0x10, 0x20, 0x40, 0x80, 0x80],
+        '%' => [0x90, 0x10, 0x60, 0x80, 0x90],
+        '=' => [0x00, 0xf0, 0x00, 0xf0, 0x00],
+        '!' => [0x40, 0x40, 0x40, 0x00, 0x40],
+        '?' => [0xe0, 0x10, 0x60, 0x00, 0x40],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0xf0],
+        SPEAKER => [0x10, 0x30, 0xf0, 0x30, 0x10],
+        _ => BLANK,
+    }
+}
+
+// Return the coordinates of the pixels that are set when drawing the text,
+// relative to its top left corner.
+pub fn text_pixels(text: &str) -> Vec<(usize, usize)> {
+    let mut pixels = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let g = glyph(c);
+        for y in 0..GLYPH_H {
+            for x in 0..GLYPH_W {
+                if g[y] & (0b1000_0000 >> x) != 0 {
+                    pixels.push((i * ADVANCE + x, y));
+                }
+            }
+        }
+    }
+    pixels
+}
+
+// Return the width in pixels of the text when drawn.
+pub fn text_width(text: &str) -> usize {
+    text.chars().count() * ADVANCE
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{glyph, text_pixels, text_width, ADVANCE, SPEAKER};
+
+    #[test]
+    fn test_glyph_hex_digits_match_chip8_font() {
+        assert_eq!([0xf0, 0x90, 0xf0, 0x90, 0xf0], glyph('8'));
+        assert_eq!([0xf0, 0x80, 0xf0, 0x80, 0x80], glyph('f'));
+    }
+
+    #[test]
+    fn test_glyph_unknown_char_is_blank() {
+        assert_eq!([0x00; 5], glyph(' '));
+        assert_eq!([0x00; 5], glyph('~'));
+        assert!(glyph(SPEAKER) != [0x00; 5]);
+    }
+
+    #[test]
+    fn test_text_pixels() {
+        let pixels = text_pixels(" -");
+        assert_eq!(vec![(ADVANCE, 2), (ADVANCE + 1, 2), (ADVANCE + 2, 2)],
+                   pixels);
+        assert_eq!(2 * ADVANCE, text_width(" -"));
+    }
+
+}
@@ -0,0 +1,199 @@
+use super::{Mode, PROGRAM_START};
+use super::disasm;
+use super::emu;
+use super::rom;
+use super::romdb::RomDb;
+
+// What can be told about a rom without running it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+    pub size: usize,
+    // The FNV-1a hash, by which the rom database knows the rom.
+    pub hash: u64,
+    // The mode the rom was detected to be written for.
+    pub mode: Mode,
+    // The patterns of the opcodes found that only exist in SUPER mode or
+    // in XO-CHIP, such as 00cn, in the order first found.
+    pub super_opcodes: Vec<&'static str>,
+    pub xo_chip_opcodes: Vec<&'static str>,
+    // Whether the program calls machine code with 0nnn.
+    pub machine_calls: bool,
+    // The bytes of the instructions reached by following the program, and
+    // the bytes of the rom that are not, taken for data.
+    pub code_bytes: usize,
+    pub data_bytes: usize,
+    // The name of the rom's profile in the database, if it has one.
+    pub profile: Option<String>,
+}
+
+impl RomInfo {
+
+    // Find out about the rom. Opcodes are looked for across the whole rom,
+    // as the mode is detected, except machine calls, which are only looked
+    // for in the instructions reached: 0000 is common as padding.
+    pub fn inspect(rom: &[u8], db: &RomDb) -> Self {
+        let mut super_opcodes = Vec::new();
+        let mut xo_chip_opcodes = Vec::new();
+        for opcode in rom::words(rom) {
+            if let Some(pattern) = rom::super_opcode(opcode) {
+                add_pattern(&mut super_opcodes, pattern);
+            }
+            if let Some(pattern) = rom::xo_chip_opcode(opcode) {
+                add_pattern(&mut xo_chip_opcodes, pattern);
+            }
+        }
+        let starts = disasm::reachable(rom, PROGRAM_START as u16);
+        let mut code = vec![false; rom.len()];
+        let mut machine_calls = false;
+        for i in (0..rom.len()).filter(|&i| starts[i]) {
+            code[i] = true;
+            code[i + 1] = true;
+            let opcode = (rom[i] as u16) << 8 | rom[i + 1] as u16;
+            machine_calls |= is_machine_call(opcode);
+        }
+        let code_bytes = code.iter().filter(|&&code| code).count();
+        let profile = db.lookup(rom).map(|profile| {
+            profile.name.clone().unwrap_or_else(|| "unnamed".to_string())
+        });
+        RomInfo {
+            size: rom.len(),
+            hash: emu::fnv1a(rom),
+            mode: rom::detect_mode(rom),
+            super_opcodes: super_opcodes,
+            xo_chip_opcodes: xo_chip_opcodes,
+            machine_calls: machine_calls,
+            code_bytes: code_bytes,
+            data_bytes: rom.len() - code_bytes,
+            profile: profile,
+        }
+    }
+
+    // The name of the mode, as --mode takes it.
+    pub fn mode_name(&self) -> &'static str {
+        match self.mode {
+            Mode::STANDARD => "chip8",
+            Mode::SUPER => "schip",
+        }
+    }
+
+    // The information, a line each.
+    pub fn to_text(&self) -> String {
+        let list = |patterns: &[&str]| match patterns.len() {
+            0 => "none".to_string(),
+            _ => patterns.join(", "),
+        };
+        format!("Size:            {} bytes\n\
+                 Hash:            {:016x}\n\
+                 Mode:            {}\n\
+                 SCHIP opcodes:   {}\n\
+                 XO-CHIP opcodes: {}\n\
+                 Machine calls:   {}\n\
+                 Code:            {} bytes\n\
+                 Data:            {} bytes\n\
+                 Profile:         {}\n",
+                self.size, self.hash, self.mode_name(),
+                list(&self.super_opcodes), list(&self.xo_chip_opcodes),
+                if self.machine_calls {"yes"} else {"no"},
+                self.code_bytes, self.data_bytes,
+                self.profile.as_ref().map_or("none", |name| &name[..]))
+    }
+
+    // The information as a JSON object, on a single line.
+    pub fn to_json(&self) -> String {
+        let list = |patterns: &[&str]| {
+            let strings: Vec<String> = patterns.iter()
+                .map(|pattern| json_string(pattern)).collect();
+            format!("[{}]", strings.join(", "))
+        };
+        format!("{{\"size\": {}, \"hash\": \"{:016x}\", \"mode\": \"{}\", \
+                 \"schip_opcodes\": {}, \"xo_chip_opcodes\": {}, \
+                 \"machine_calls\": {}, \"code_bytes\": {}, \
+                 \"data_bytes\": {}, \"profile\": {}}}\n",
+                self.size, self.hash, self.mode_name(),
+                list(&self.super_opcodes), list(&self.xo_chip_opcodes),
+                self.machine_calls, self.code_bytes, self.data_bytes,
+                self.profile.as_ref().map_or("null".to_string(),
+                                             |name| json_string(name)))
+    }
+
+}
+
+fn add_pattern(patterns: &mut Vec<&'static str>, pattern: &'static str) {
+    if !patterns.contains(&pattern) {
+        patterns.push(pattern);
+    }
+}
+
+// Whether the opcode is a 0nnn call of machine code, rather than one of
+// the instructions that share its first nibble.
+fn is_machine_call(opcode: u16) -> bool {
+    disasm::disassemble(opcode).starts_with("SYS ") &&
+    rom::xo_chip_opcode(opcode).is_none()
+}
+
+// The text as a JSON string, quoted and escaped.
+fn json_string(text: &str) -> String {
+    let mut json = "\"".to_string();
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            _ if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{json_string, RomInfo};
+    use super::super::Mode;
+    use super::super::romdb::RomDb;
+
+    #[test]
+    fn test_inspect() {
+        //given
+        let rom = [0x00, 0xff,   // 200 HIGH
+                   0x03, 0x00,   // 202 SYS 0x300
+                   0x12, 0x04,   // 204 JP 0x204
+                   0xf2, 0x01,   // 206 plane 2, not reached
+                   0x00, 0x00];  // 208 data
+        let db = RomDb::parse(&format!("[rom.{:016x}]\nname = Demo\n",
+                                       super::super::emu::fnv1a(&rom)))
+            .unwrap();
+        //when
+        let info = RomInfo::inspect(&rom, &db);
+        //then
+        assert_eq!(10, info.size);
+        assert_eq!(Mode::SUPER, info.mode);
+        assert_eq!(vec!["00ff"], info.super_opcodes);
+        assert_eq!(vec!["fn01"], info.xo_chip_opcodes);
+        assert!(info.machine_calls);
+        assert_eq!((6, 4), (info.code_bytes, info.data_bytes));
+        assert_eq!(Some("Demo".to_string()), info.profile);
+        assert_eq!(None, RomInfo::inspect(&rom, &RomDb::new()).profile);
+    }
+
+    #[test]
+    fn test_to_text_and_json() {
+        //given
+        let info = RomInfo::inspect(&[0x12, 0x00, 0xf0, 0x90], &RomDb::new());
+        //then
+        assert!(info.to_text().contains("Mode:            chip8\n"));
+        assert!(info.to_text().contains("SCHIP opcodes:   none\n"));
+        assert!(info.to_text().contains("Code:            2 bytes\n"));
+        assert_eq!(format!("{{\"size\": 4, \"hash\": \"{:016x}\", \
+                            \"mode\": \"chip8\", \"schip_opcodes\": [], \
+                            \"xo_chip_opcodes\": [], \
+                            \"machine_calls\": false, \"code_bytes\": 2, \
+                            \"data_bytes\": 2, \"profile\": null}}\n",
+                           info.hash),
+                   info.to_json());
+        assert_eq!("\"a \\\"b\\\" \\\\ \\u000a\"",
+                   json_string("a \"b\" \\ \n"));
+    }
+
+}
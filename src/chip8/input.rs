@@ -0,0 +1,566 @@
+use super::config::{Config, ConfigError, Setting};
+use std::collections::VecDeque;
+use std::path::Path;
+
+// A change to the state of a key on the chip8 keypad. Every input device
+// is translated into these, so that the emulator only ever sees keys.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyEvent { Down(u8), Up(u8) }
+
+// Requests that are not keypad keys.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Command { Pause, Reset }
+
+// The buttons of a gamepad, named after their position on an Xbox style
+// controller.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Button {
+    A, B, X, Y,
+    Back, Start,
+    LeftShoulder, RightShoulder,
+    DPadUp, DPadDown, DPadLeft, DPadRight,
+}
+
+impl Button {
+
+    // The name by which the button is known in the configuration file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::A => "a",
+            Button::B => "b",
+            Button::X => "x",
+            Button::Y => "y",
+            Button::Back => "back",
+            Button::Start => "start",
+            Button::LeftShoulder => "left_shoulder",
+            Button::RightShoulder => "right_shoulder",
+            Button::DPadUp => "dpad_up",
+            Button::DPadDown => "dpad_down",
+            Button::DPadLeft => "dpad_left",
+            Button::DPadRight => "dpad_right",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Button> {
+        BUTTONS.iter().cloned().find(|button| button.name() == name)
+    }
+
+}
+
+const BUTTONS: [Button; 12] = [
+    Button::A, Button::B, Button::X, Button::Y,
+    Button::Back, Button::Start,
+    Button::LeftShoulder, Button::RightShoulder,
+    Button::DPadUp, Button::DPadDown, Button::DPadLeft, Button::DPadRight,
+];
+
+// The keypad keys gamepad buttons are mapped onto unless configured
+// otherwise. The d-pad follows the 2/4/6/8 arrows most games use. Start
+// and Back are not listed, they always pause and reset.
+pub const DEFAULT_GAMEPAD_MAPPING: [(Button, u8); 8] = [
+    (Button::DPadUp, 0x2),
+    (Button::DPadDown, 0x8),
+    (Button::DPadLeft, 0x4),
+    (Button::DPadRight, 0x6),
+    (Button::A, 0x5),
+    (Button::B, 0x6),
+    (Button::X, 0x4),
+    (Button::Y, 0xc),
+];
+
+// Maps gamepad buttons onto keypad keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GamepadMapping {
+    keys: Vec<(Button, u8)>,
+}
+
+impl GamepadMapping {
+
+    pub fn new() -> Self {
+        GamepadMapping { keys: DEFAULT_GAMEPAD_MAPPING.to_vec() }
+    }
+
+    // The mapping with the defaults overridden by the [gamepad] section of
+    // the configuration, for any rom.
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut mapping = GamepadMapping::new();
+        mapping.apply(config, "gamepad")?;
+        Ok(mapping)
+    }
+
+    // The mapping for the rom. The defaults are overridden by the
+    // [gamepad] section of the configuration, which in turn is overridden
+    // by the section named after the rom file, e.g. [gamepad.pong] for
+    // PONG.ch8:
+    //
+    //     [gamepad.pong]
+    //     dpad_up = 1
+    //     dpad_down = 4
+    pub fn for_rom(config: &Config, rom_path: &Path)
+                   -> Result<Self, ConfigError> {
+        let mut mapping = GamepadMapping::from_config(config)?;
+        if let Some(stem) = rom_path.file_stem() {
+            let section = format!("gamepad.{}",
+                                  stem.to_string_lossy().to_lowercase());
+            mapping.apply(config, &section)?;
+        }
+        Ok(mapping)
+    }
+
+    // Override the mapping with the settings in the configuration section.
+    // Each setting maps a button onto a hexadecimal key, e.g. `a = c`.
+    fn apply(&mut self, config: &Config, section: &str)
+             -> Result<(), ConfigError> {
+        for setting in config.section(section) {
+            let button = match Button::from_name(&setting.key) {
+                Some(Button::Start) | Some(Button::Back) =>
+                    return Err(setting.error("reserved for pause and reset")),
+                Some(button) => button,
+                None => return Err(setting.error("unknown gamepad button")),
+            };
+            self.set(button, parse_key(setting)?);
+        }
+        Ok(())
+    }
+
+    pub fn set(&mut self, button: Button, key: u8) {
+        self.keys.retain(|&(b, _)| b != button);
+        self.keys.push((button, key));
+    }
+
+    pub fn key(&self, button: Button) -> Option<u8> {
+        self.keys.iter().find(|&&(b, _)| b == button).map(|&(_, key)| key)
+    }
+
+    // The mapping as written in the [gamepad] section, for writing it out
+    // with config::set_settings.
+    pub fn to_config(&self) -> Vec<(&'static str, String)> {
+        self.keys.iter()
+            .map(|&(button, key)| (button.name(), format!("{:x}", key)))
+            .collect()
+    }
+
+}
+
+// The keypad keys keyboard keys are mapped onto unless configured 
+// otherwise: the left hand side of a QWERTY keyboard, laid out like the
+// keypad of the COSMAC VIP. Keys go by the names SDL gives them, in lower
+// case, which name where they are rather than what they say.
+pub const DEFAULT_KEYBOARD_MAPPING: [(&'static str, u8); 16] = [
+    ("1", 0x1), ("2", 0x2), ("3", 0x3), ("4", 0xc),
+    ("q", 0x4), ("w", 0x5), ("e", 0x6), ("r", 0xd),
+    ("a", 0x7), ("s", 0x8), ("d", 0x9), ("f", 0xe),
+    ("z", 0xa), ("x", 0x0), ("c", 0xb), ("v", 0xf),
+];
+
+// Maps keyboard keys onto keypad keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct KeyboardMapping {
+    keys: Vec<(String, u8)>,
+}
+
+impl KeyboardMapping {
+
+    pub fn new() -> Self {
+        KeyboardMapping { 
+            keys: DEFAULT_KEYBOARD_MAPPING.iter()
+                .map(|&(name, key)| (name.to_string(), key))
+                .collect()
+        }
+    }
+
+    // The mapping with the defaults overridden by the [keyboard] section 
+    // of the configuration. Each setting maps a key onto a hexadecimal 
+    // keypad key, adding to the keys already mapped onto it:
+    //
+    //     [keyboard]
+    //     up = 2
+    //     down = 8
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut mapping = KeyboardMapping::new();
+        for setting in config.section("keyboard") {
+            let key = parse_key(setting)?;
+            mapping.set(&setting.key.to_lowercase(), key);
+        }
+        Ok(mapping)
+    }
+
+    pub fn set(&mut self, name: &str, key: u8) {
+        self.keys.retain(|&(ref n, _)| n != name);
+        self.keys.push((name.to_string(), key));
+    }
+
+    // The keypad key the keyboard key with the name is mapped onto, if 
+    // any. Names are not case sensitive.
+    pub fn key(&self, name: &str) -> Option<u8> {
+        let name = name.to_lowercase();
+        self.keys.iter().find(|&&(ref n, _)| *n == name)
+                        .map(|&(_, key)| key)
+    }
+
+    // The mapping as written in the [keyboard] section, for writing it out
+    // with config::set_settings.
+    pub fn to_config(&self) -> Vec<(&str, String)> {
+        self.keys.iter()
+            .map(|&(ref name, key)| (&name[..], format!("{:x}", key)))
+            .collect()
+    }
+
+}
+
+// The keypad key a setting maps something onto, a single hexadecimal 
+// digit.
+fn parse_key(setting: &Setting) -> Result<u8, ConfigError> {
+    match u8::from_str_radix(&setting.value, 16) {
+        Ok(key) if setting.value.len() == 1 => Ok(key),
+        _ => Err(setting.error("expected a key from 0 to F")),
+    }
+}
+
+// The state of the keypad, fed by a queue of key events from all input
+// devices. A key stays down for as long as any device holds it, so that
+// e.g. releasing a keyboard key does not release the same key held on a
+// gamepad.
+pub struct Keypad {
+    queue: VecDeque<KeyEvent>,
+    held: [u8; 16],
+}
+
+impl Keypad {
+
+    pub fn new() -> Self {
+        Keypad { queue: VecDeque::new(), held: [0; 16] }
+    }
+
+    pub fn push(&mut self, event: KeyEvent) {
+        self.queue.push_back(event);
+    }
+
+    // Apply the queued events. Returns true if any key changed state.
+    pub fn drain(&mut self) -> bool {
+        let before = self.keys();
+        while let Some(event) = self.queue.pop_front() {
+            match event {
+                KeyEvent::Down(key) => self.held[key as usize] += 1,
+                KeyEvent::Up(key) => {
+                    let held = &mut self.held[key as usize];
+                    *held = held.saturating_sub(1);
+                },
+            }
+        }
+        before != self.keys()
+    }
+
+    // Release every key, e.g. when the window loses the focus and will not
+    // be told about keys being released.
+    pub fn release_all(&mut self) {
+        self.queue.clear();
+        for key in 0..16 {
+            for _ in 0..self.held[key] {
+                self.queue.push_back(KeyEvent::Up(key as u8));
+            }
+        }
+    }
+
+    // The keys that are down, as seen by the emulator.
+    pub fn keys(&self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for (key, &held) in keys.iter_mut().zip(self.held.iter()) {
+            *key = held > 0;
+        }
+        keys
+    }
+
+}
+
+// Translates the button events of all connected gamepads into key events
+// and commands.
+pub struct Gamepads {
+    mapping: GamepadMapping,
+    // The buttons that are down, along with the gamepad they belong to and
+    // the key they were pressed as.
+    held: Vec<(i32, Button, u8)>,
+}
+
+impl Gamepads {
+
+    pub fn new(mapping: GamepadMapping) -> Self {
+        Gamepads { mapping: mapping, held: Vec::new() }
+    }
+
+    // Switch to a new mapping, e.g. when another rom is loaded. Buttons
+    // that are down are released as the keys they were pressed as.
+    pub fn set_mapping(&mut self, mapping: GamepadMapping) {
+        self.mapping = mapping;
+    }
+
+    // Translate a button of the given gamepad being pressed or released.
+    // Keys are pushed onto the keypad's queue, while Start and Back return
+    // the command to carry out when pressed.
+    pub fn button(&mut self, which: i32, button: Button, pressed: bool,
+                  keypad: &mut Keypad) -> Option<Command> {
+        match (button, pressed) {
+            (Button::Start, true) => return Some(Command::Pause),
+            (Button::Back, true) => return Some(Command::Reset),
+            _ => {},
+        }
+        let held = self.held.iter()
+            .position(|&(w, b, _)| w == which && b == button);
+        match (held, pressed) {
+            (None, true) => if let Some(key) = self.mapping.key(button) {
+                self.held.push((which, button, key));
+                keypad.push(KeyEvent::Down(key));
+            },
+            (Some(i), false) => {
+                let (_, _, key) = self.held.remove(i);
+                keypad.push(KeyEvent::Up(key));
+            },
+            _ => {},
+        }
+        None
+    }
+
+    // Release everything held on every gamepad.
+    pub fn release_all(&mut self, keypad: &mut Keypad) {
+        for (_, _, key) in self.held.drain(..) {
+            keypad.push(KeyEvent::Up(key));
+        }
+    }
+
+    // Release everything held on a gamepad that was unplugged, since it
+    // will not report the buttons being released.
+    pub fn removed(&mut self, which: i32, keypad: &mut Keypad) {
+        let mut i = 0;
+        while i < self.held.len() {
+            if self.held[i].0 == which {
+                let (_, _, key) = self.held.remove(i);
+                keypad.push(KeyEvent::Up(key));
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Button, Command, GamepadMapping, Gamepads, KeyEvent, Keypad};
+    use super::KeyboardMapping;
+    use super::super::config::{self, Config, ConfigError};
+    use std::path::Path;
+
+    // The keys that are down, as a sorted list.
+    fn down(keypad: &Keypad) -> Vec<usize> {
+        (0..16).filter(|&key| keypad.keys()[key]).collect()
+    }
+
+    #[test]
+    fn test_default_mapping() {
+        let mapping = GamepadMapping::new();
+        assert_eq!(Some(0x2), mapping.key(Button::DPadUp));
+        assert_eq!(Some(0x5), mapping.key(Button::A));
+        assert_eq!(None, mapping.key(Button::Start));
+        assert_eq!(None, mapping.key(Button::LeftShoulder));
+    }
+
+    #[test]
+    fn test_rom_section_overrides_gamepad_section() {
+        //given
+        let config = Config::parse("[gamepad]\n\
+                                    a = 7\n\
+                                    dpad_up = 3\n\
+                                    [gamepad.pong]\n\
+                                    dpad_up = 1\n\
+                                    dpad_down = \"4\"\n").unwrap();
+        //when
+        let pong = GamepadMapping::for_rom(&config, Path::new("roms/PONG.ch8"))
+                                  .unwrap();
+        let brix = GamepadMapping::for_rom(&config, Path::new("brix.ch8"))
+                                  .unwrap();
+        //then
+        assert_eq!(Some(0x1), pong.key(Button::DPadUp));
+        assert_eq!(Some(0x4), pong.key(Button::DPadDown));
+        assert_eq!(Some(0x7), pong.key(Button::A));
+        assert_eq!(Some(0x3), brix.key(Button::DPadUp));
+        assert_eq!(Some(0x8), brix.key(Button::DPadDown));
+    }
+
+    #[test]
+    fn test_invalid_mapping_names_line_and_key() {
+        for &(text, key) in &[("[gamepad]\nturbo = 1", "turbo"),
+                              ("[gamepad]\nstart = 1", "start"),
+                              ("[gamepad]\na = 10", "a")] {
+            let config = Config::parse(text).unwrap();
+            match GamepadMapping::for_rom(&config, Path::new("pong.ch8")) {
+                Err(ConfigError::Setting(line, ref k, _)) => {
+                    assert_eq!(2, line);
+                    assert_eq!(key, k);
+                },
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_gamepad_mapping_round_trip() {
+        //given
+        let mut mapping = GamepadMapping::new();
+        mapping.set(Button::LeftShoulder, 0xf);
+        //when
+        let text = config::set_settings("", "gamepad", &mapping.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        let read = GamepadMapping::from_config(&config).unwrap();
+        assert_eq!(Some(0xf), read.key(Button::LeftShoulder));
+        assert_eq!(mapping.key(Button::A), read.key(Button::A));
+    }
+
+    #[test]
+    fn test_keyboard_mapping_from_config() {
+        assert_eq!(Some(0x0), KeyboardMapping::new().key("X"));
+        assert_eq!(None, KeyboardMapping::new().key("Up"));
+        //given
+        let config = Config::parse("[keyboard]\n\
+                                    up = 2\n\
+                                    x = b\n").unwrap();
+        //when
+        let mapping = KeyboardMapping::from_config(&config).unwrap();
+        //then
+        assert_eq!(Some(0x2), mapping.key("Up"));
+        assert_eq!(Some(0x2), mapping.key("2"));
+        assert_eq!(Some(0xb), mapping.key("x"));
+        let config = Config::parse("[keyboard]\nup = 10").unwrap();
+        match KeyboardMapping::from_config(&config) {
+            Err(ConfigError::Setting(line, ref key, _)) => {
+                assert_eq!(2, line);
+                assert_eq!("up", key);
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_keyboard_mapping_round_trip() {
+        //given
+        let mut mapping = KeyboardMapping::new();
+        mapping.set("left shift", 0x5);
+        //when
+        let text = config::set_settings("", "keyboard", &mapping.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(mapping, KeyboardMapping::from_config(&config).unwrap());
+    }
+
+    #[test]
+    fn test_buttons_translate_into_key_events() {
+        let mut keypad = Keypad::new();
+        let mut gamepads = Gamepads::new(GamepadMapping::new());
+        //when
+        assert_eq!(None, gamepads.button(0, Button::A, true, &mut keypad));
+        assert_eq!(None, gamepads.button(0, Button::DPadLeft, true,
+                                         &mut keypad));
+        //then
+        assert!(keypad.drain());
+        assert_eq!(vec![0x4, 0x5], down(&keypad));
+        //when
+        gamepads.button(0, Button::A, false, &mut keypad);
+        //then
+        assert!(keypad.drain());
+        assert_eq!(vec![0x4], down(&keypad));
+    }
+
+    #[test]
+    fn test_key_held_by_two_devices() {
+        let mut keypad = Keypad::new();
+        let mut gamepads = Gamepads::new(GamepadMapping::new());
+        //given
+        keypad.push(KeyEvent::Down(0x5));
+        gamepads.button(1, Button::A, true, &mut keypad);
+        keypad.drain();
+        //when
+        keypad.push(KeyEvent::Up(0x5));
+        //then
+        assert!(!keypad.drain());
+        assert_eq!(vec![0x5], down(&keypad));
+        gamepads.button(1, Button::A, false, &mut keypad);
+        assert!(keypad.drain());
+        assert!(down(&keypad).is_empty());
+    }
+
+    #[test]
+    fn test_start_pauses_and_back_resets() {
+        let mut keypad = Keypad::new();
+        let mut gamepads = Gamepads::new(GamepadMapping::new());
+        //then
+        assert_eq!(Some(Command::Pause),
+                   gamepads.button(0, Button::Start, true, &mut keypad));
+        assert_eq!(None, gamepads.button(0, Button::Start, false,
+                                         &mut keypad));
+        assert_eq!(Some(Command::Reset),
+                   gamepads.button(0, Button::Back, true, &mut keypad));
+        assert!(!keypad.drain());
+    }
+
+    #[test]
+    fn test_release_uses_key_pressed_before_remapping() {
+        let mut keypad = Keypad::new();
+        let mut gamepads = Gamepads::new(GamepadMapping::new());
+        //given
+        gamepads.button(0, Button::DPadUp, true, &mut keypad);
+        let mut mapping = GamepadMapping::new();
+        mapping.set(Button::DPadUp, 0x1);
+        gamepads.set_mapping(mapping);
+        //when
+        gamepads.button(0, Button::DPadUp, false, &mut keypad);
+        keypad.drain();
+        //then
+        assert!(down(&keypad).is_empty());
+    }
+
+    #[test]
+    fn test_unplugging_releases_held_buttons() {
+        let mut keypad = Keypad::new();
+        let mut gamepads = Gamepads::new(GamepadMapping::new());
+        //given
+        gamepads.button(0, Button::A, true, &mut keypad);
+        gamepads.button(1, Button::DPadUp, true, &mut keypad);
+        keypad.drain();
+        //when
+        gamepads.removed(0, &mut keypad);
+        keypad.drain();
+        //then
+        assert_eq!(vec![0x2], down(&keypad));
+        // A gamepad plugged in later may reuse the id.
+        gamepads.button(0, Button::A, true, &mut keypad);
+        keypad.drain();
+        assert_eq!(vec![0x2, 0x5], down(&keypad));
+    }
+
+    #[test]
+    fn test_release_all_clears_held_keys() {
+        let mut keypad = Keypad::new();
+        let mut gamepads = Gamepads::new(GamepadMapping::new());
+        //given
+        keypad.push(KeyEvent::Down(0x5));
+        keypad.push(KeyEvent::Down(0xa));
+        gamepads.button(0, Button::A, true, &mut keypad);
+        keypad.drain();
+        keypad.push(KeyEvent::Down(0x1));
+        //when
+        gamepads.release_all(&mut keypad);
+        keypad.release_all();
+        //then
+        assert!(keypad.drain());
+        assert!(down(&keypad).is_empty());
+        // Releasing the keys later on changes nothing.
+        keypad.push(KeyEvent::Up(0x5));
+        gamepads.button(0, Button::A, false, &mut keypad);
+        assert!(!keypad.drain());
+        gamepads.button(0, Button::A, true, &mut keypad);
+        keypad.drain();
+        assert_eq!(vec![0x5], down(&keypad));
+    }
+
+}
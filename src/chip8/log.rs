@@ -0,0 +1,208 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// How much is logged. Each level logs the messages of those before it too.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Off,
+    // Problems worked around, such as a rom that can not be loaded or an
+    // opcode that is not supported.
+    Warn,
+    // Roms loaded and the settings they run with.
+    Info,
+    // Decisions on pacing the emulator.
+    Debug,
+    // Every instruction executed, disassembled.
+    Trace,
+}
+
+impl Level {
+
+    // The name by which the level is known on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Off => "off",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        [Level::Off, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .iter().cloned().find(|level| level.name() == name)
+    }
+
+}
+
+// Where logged messages go.
+pub trait Logger {
+    fn log(&self, level: Level, message: &str);
+}
+
+// Writes each message on a line of its own, after its level.
+pub struct WriteLogger<W> {
+    out: Mutex<W>,
+}
+
+impl<W: Write> WriteLogger<W> {
+
+    pub fn new(out: W) -> Self {
+        WriteLogger { out: Mutex::new(out) }
+    }
+
+}
+
+impl<W: Write> Logger for WriteLogger<W> {
+    fn log(&self, level: Level, message: &str) {
+        let mut out = self.out.lock().unwrap();
+        // Logging is best effort, there is nowhere to report failing to.
+        let _ = writeln!(out, "[{}] {}", level.name(), message);
+    }
+}
+
+// Keeps the messages logged, for tests to check.
+pub struct MemoryLogger {
+    messages: Mutex<Vec<(Level, String)>>,
+}
+
+impl MemoryLogger {
+
+    pub fn new() -> Self {
+        MemoryLogger { messages: Mutex::new(Vec::new()) }
+    }
+
+    // The messages logged so far, in order.
+    pub fn messages(&self) -> Vec<(Level, String)> {
+        self.messages.lock().unwrap().clone()
+    }
+
+}
+
+impl Logger for MemoryLogger {
+    fn log(&self, level: Level, message: &str) {
+        self.messages.lock().unwrap().push((level, message.to_string()));
+    }
+}
+
+// A logger, along with the level up to which messages are passed on to
+// it. Clones share the logger, so that the emulator thread and the ui can
+// log to the same file.
+//
+// Messages that are costly to put together should only be formatted once
+// enabled says they will be logged, which is a single comparison:
+//
+//     if log.enabled(Level::Trace) {
+//         log.trace(&format!("{:03x}  {}", pc, instruction));
+//     }
+#[derive(Clone)]
+pub struct Log {
+    level: Level,
+    logger: Arc<Logger + Send + Sync>,
+}
+
+impl Log {
+
+    pub fn new(level: Level, logger: Arc<Logger + Send + Sync>) -> Self {
+        Log { level: level, logger: logger }
+    }
+
+    // Log nothing.
+    pub fn off() -> Self {
+        Log::new(Level::Off, Arc::new(WriteLogger::new(io::sink())))
+    }
+
+    // Log to the file, created afresh, or to standard error without one.
+    pub fn open(level: Level, path: Option<&Path>) -> io::Result<Self> {
+        let logger: Arc<Logger + Send + Sync> = match path {
+            Some(path) => Arc::new(WriteLogger::new(File::create(path)?)),
+            None => Arc::new(WriteLogger::new(io::stderr())),
+        };
+        Ok(Log::new(level, logger))
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    // Whether messages of the level are logged.
+    #[inline]
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level && level != Level::Off
+    }
+
+    pub fn log(&self, level: Level, message: &str) {
+        if self.enabled(level) {
+            self.logger.log(level, message);
+        }
+    }
+
+    pub fn warn(&self, message: &str) {
+        self.log(Level::Warn, message);
+    }
+
+    pub fn info(&self, message: &str) {
+        self.log(Level::Info, message);
+    }
+
+    pub fn debug(&self, message: &str) {
+        self.log(Level::Debug, message);
+    }
+
+    pub fn trace(&self, message: &str) {
+        self.log(Level::Trace, message);
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{Level, Log, MemoryLogger, WriteLogger};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_level_names() {
+        assert_eq!(Some(Level::Debug), Level::from_name("debug"));
+        assert_eq!(Some(Level::Off), Level::from_name("off"));
+        assert_eq!(None, Level::from_name("verbose"));
+        assert_eq!("trace", Level::Trace.name());
+    }
+
+    #[test]
+    fn test_log_up_to_level() {
+        //given
+        let logger = Arc::new(MemoryLogger::new());
+        let log = Log::new(Level::Info, logger.clone());
+        //when
+        log.warn("rom too large");
+        log.info("loaded");
+        log.debug("skipped 2 frames");
+        log.trace("200  6005  LD V0, 05");
+        log.log(Level::Off, "never");
+        //then
+        assert_eq!(vec![(Level::Warn, "rom too large".to_string()),
+                        (Level::Info, "loaded".to_string())],
+                   logger.messages());
+        assert!(log.enabled(Level::Info));
+        assert!(!log.enabled(Level::Trace));
+        assert!(!Log::off().enabled(Level::Warn));
+    }
+
+    #[test]
+    fn test_write_logger() {
+        //given
+        let logger = WriteLogger::new(Vec::new());
+        //when
+        super::Logger::log(&logger, Level::Warn, "unknown opcode");
+        super::Logger::log(&logger, Level::Info, "loaded");
+        //then
+        let out = logger.out.into_inner().unwrap();
+        assert_eq!("[warn] unknown opcode\n[info] loaded\n",
+                   String::from_utf8(out).unwrap());
+    }
+
+}
@@ -1,29 +1,1610 @@
+use super::clock::{Clock, SystemClock};
+use super::config::{Config, ConfigError};
+use super::emu::{Emu, ErrorPolicy};
+use super::log::Log;
+use std::cmp;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
 use time::Duration;
-use time::SteadyTime;
 
+// The most emulation frames run for a single presented frame. After a 
+// longer stall, e.g. while the window is being dragged, the missed time is
+// dropped rather than caught up on.
+pub const MAX_FRAMES_PER_PRESENT: u32 = 4;
+// The most ticks a metronome catches up on after falling behind, unless 
+// configured otherwise. Matches the frames run for a single present.
+pub const DEFAULT_MAX_CATCH_UP: u32 = MAX_FRAMES_PER_PRESENT - 1;
+pub const MAX_CATCH_UP: u32 = 60;
+// Instructions executed per second unless configured otherwise, and the 
+// range they can be set to. Changed in steps while running.
+pub const DEFAULT_CYCLES_PER_SECOND: u32 = 500;
+pub const MIN_CYCLES_PER_SECOND: u32 = 100;
+pub const MAX_CYCLES_PER_SECOND: u32 = 20000;
+pub const CYCLES_PER_SECOND_STEP: u32 = 100;
+// The rate at which the timers count down, which is also the frame rate,
+// unless configured otherwise.
+pub const DEFAULT_TIMER_HZ: u32 = 60;
+pub const MAX_TIMER_HZ: u32 = 1000;
+// The slowest and fastest speeds, as multiples of the normal speed.
+pub const MIN_SPEED: f32 = 0.25;
+pub const MAX_SPEED: f32 = 16.0;
+
+// The longest the emulator sleeps while idle before looking around, e.g. to
+// notice the ui going away, in milliseconds.
+pub const IDLE_WAKE_MS: u64 = 100;
+
+// The most frames that can be run ahead of the frames really run.
+pub const MAX_RUN_AHEAD: u32 = 2;
+// Speeds are kept in hundredths of the normal speed, so that the frames
+// run at e.g. 1.5x add up exactly.
+const SPEED_UNITS: u32 = 100;
+
+// Calls back at a steady rate, as often as it is polled for a tick. Times 
+// are those of its clock, which is the system clock unless told otherwise.
 pub struct Metronome {
+    clock: Arc<Clock + Send + Sync>,
     freq: Duration,
-    ticked_at: SteadyTime,
+    // Each tick calls back this many hundredths of a time, to run faster 
+    // than normal without needing ticks closer together than the caller 
+    // can poll. The hundredths left over are owed to the next tick.
+    speed: u32,
+    owed: u32,
+    // When the last tick was due. Ticks are due a whole number of periods
+    // apart, so that lateness in polling does not add up.
+    ticked_at: Duration,
+    // How many ticks to catch up on after falling behind, e.g. when the 
+    // host stalls. Beyond that the backlog is dropped and counted here.
+    max_catch_up: u32,
+    skipped: u64,
+    // Set while paused, to the time at which the pause started.
+    paused_at: Option<Duration>,
+    // The time spent in pauses that have ended.
+    paused_for: Duration,
 }
 
 impl Metronome {
-    
+
     pub fn new(hz: i64) -> Metronome {
+        Metronome::with_clock(hz, Arc::new(SystemClock::new()))
+    }
+
+    pub fn with_clock(hz: i64, clock: Arc<Clock + Send + Sync>) 
+                      -> Metronome {
         let freq = Metronome::hz_to_duration(hz);
-        Metronome { freq: freq, ticked_at: SteadyTime::now() }
+        let now = from_std(clock.now());
+        Metronome { clock: clock, freq: freq, speed: SPEED_UNITS, owed: 0, 
+                    ticked_at: now, 
+                    max_catch_up: DEFAULT_MAX_CATCH_UP, skipped: 0, 
+                    paused_at: None,
+                    paused_for: Duration::zero() }
+    }
+
+    // The time of the clock.
+    pub fn now(&self) -> Duration {
+        from_std(self.clock.now())
     }
 
-    pub fn on_tick<F>(&mut self, mut f: F) 
+    pub fn on_tick<F>(&mut self, f: F)
         where F: FnMut() -> () {
-        let now = SteadyTime::now();
-        if now - self.ticked_at >= self.freq {
+        let now = self.now();
+        self.on_tick_at(now, f);
+    }
+
+    // Call f if a tick is due at the given time, once for each multiple of
+    // the normal speed. Below normal speed, some ticks call f not at all.
+    // Never ticks while paused. Ticks missed since the last call are caught
+    // up on, unless there are more than max_catch_up of them: then they 
+    // are skipped, so that e.g. waking from sleep does not fast forward.
+    pub fn on_tick_at<F>(&mut self, now: Duration, mut f: F)
+        where F: FnMut() -> () {
+        if self.paused_at.is_some() {
+            return;
+        }
+        let due = (now - self.ticked_at).num_nanoseconds()
+                                        .unwrap_or(i64::max_value()) / 
+                  self.freq.num_nanoseconds().unwrap();
+        if due < 1 {
+            return;
+        }
+        let ticks = if due > 1 + self.max_catch_up as i64 {
+            self.skipped += due as u64 - 1;
             self.ticked_at = now;
-            f();
-        } 
+            1
+        } else {
+            self.ticked_at = self.ticked_at + self.freq * due as i32;
+            due as u32
+        };
+        for _ in 0..self.at_speed(ticks) { f(); }
+    }
+
+    // Catch up on at most this many missed ticks.
+    pub fn set_max_catch_up(&mut self, ticks: u32) {
+        self.max_catch_up = ticks;
+    }
+
+    // The number of ticks skipped since the last call, for reporting.
+    pub fn take_skipped(&mut self) -> u64 {
+        mem::replace(&mut self.skipped, 0)
+    }
+
+    // Run at a multiple of the normal speed, from 0.25 to 16 times. The
+    // ticks stay as far apart, so changing speed neither stalls nor 
+    // catches up.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = (clamp_speed(speed) * SPEED_UNITS as f32).round() as u32;
+    }
+
+    // The speed actually run at, after clamping and rounding.
+    pub fn speed(&self) -> f32 {
+        self.speed as f32 / SPEED_UNITS as f32
+    }
+
+    // The number of frames to run at the current speed in place of the
+    // given number at normal speed, carrying any part of a frame over to 
+    // the next call.
+    pub fn at_speed(&mut self, frames: u32) -> u32 {
+        self.owed += frames * self.speed;
+        let frames = self.owed / SPEED_UNITS;
+        self.owed %= SPEED_UNITS;
+        frames
+    }
+
+    // The time left until the next tick is due, or zero if it is overdue.
+    pub fn until_tick(&self) -> Duration {
+        let due = self.ticked_at + self.freq - self.now();
+        if due < Duration::zero() { Duration::zero() } else { due }
+    }
+
+    // Sleep until the next tick is due, rather than polling for it. Does 
+    // not sleep while paused, since no tick is due then.
+    pub fn sleep_until_tick(&self) {
+        if self.paused_at.is_none() {
+            self.clock.sleep(to_std(self.until_tick()));
+        }
+    }
+
+    // Stop ticking until resumed.
+    pub fn pause(&mut self) {
+        let now = self.now();
+        self.pause_at(now);
+    }
+
+    pub fn pause_at(&mut self, now: Duration) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(now);
+        }
+    }
+
+    // Continue ticking where the pause left off. The time spent paused is
+    // excluded, so the next tick is due no sooner than it would have been
+    // had the pause never happened, and no backlog of ticks builds up.
+    pub fn resume(&mut self) {
+        let now = self.now();
+        self.resume_at(now);
+    }
+
+    pub fn resume_at(&mut self, now: Duration) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.ticked_at = self.ticked_at + (now - paused_at);
+            self.paused_for = self.paused_for + (now - paused_at);
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    // The total time spent paused, not counting a pause still going on.
+    pub fn paused_for(&self) -> Duration {
+        self.paused_for
     }
 
     fn hz_to_duration(hz: i64) -> Duration {
-        Duration::nanoseconds(10i64.pow(9) / hz) 
-    } 
-    
+        Duration::nanoseconds(10i64.pow(9) / hz)
+    }
+
+}
+
+// Stops the frames while the program idles, e.g. on fx0a waiting for a 
+// key, so that the emulator can sleep until the ui has news rather than 
+// run frames that change nothing. The frames start again as soon as the 
+// program stops idling. As with a pause, the time idled is not made up 
+// for. A pause by the user takes precedence.
+pub struct IdleGate {
+    idling: bool,
+}
+
+impl IdleGate {
+
+    pub fn new() -> Self {
+        IdleGate { idling: false }
+    }
+
+    // Stop or start the frames as the program idles or not. Returns true 
+    // while idling.
+    pub fn update(&mut self, idle: bool, paused: bool, 
+                  frame_rate: &mut Metronome) -> bool {
+        if paused {
+            // The metronome is paused for the user, and stays so.
+            self.idling = false;
+        } else if idle && !self.idling {
+            frame_rate.pause();
+            self.idling = true;
+        } else if !idle && self.idling {
+            frame_rate.resume();
+            self.idling = false;
+        }
+        self.idling
+    }
+
+}
+
+// With vsync on, presenting a frame waits for the display, so emulation is
+// paced by the time between presents rather than by a metronome. Returns 
+// the number of frames at the given rate that are due after a present
+// taking interval, along with the time left over to carry into the next
+// present. Rounding down and carrying the rest means that e.g. a 144Hz 
+// display runs 60 frames every 144 presents, and that a missed present 
+// is made up for by running two frames the next time.
+pub fn frames_per_present(interval: Duration, hz: i64, 
+                          carry: Duration) -> (u32, Duration) {
+    let period = Metronome::hz_to_duration(hz);
+    let owed = carry + interval;
+    let frames = owed.num_nanoseconds().unwrap_or(i64::max_value()) / 
+                 period.num_nanoseconds().unwrap();
+    if frames > MAX_FRAMES_PER_PRESENT as i64 {
+        (MAX_FRAMES_PER_PRESENT, Duration::zero())
+    } else {
+        (frames as u32, owed - period * frames as i32)
+    }
+}
+
+// Keeps the time carried from one present to the next.
+pub struct FramePacer {
+    hz: i64,
+    carry: Duration,
+}
+
+impl FramePacer {
+
+    pub fn new(hz: i64) -> Self {
+        FramePacer { hz: hz, carry: Duration::zero() }
+    }
+
+    // The number of frames to run for a present that took interval.
+    pub fn frames(&mut self, interval: Duration) -> u32 {
+        let (frames, carry) = frames_per_present(interval, self.hz, 
+                                                 self.carry);
+        self.carry = carry;
+        frames
+    }
+
+}
+
+// Spreads the instructions of a second evenly over its frames, of which 
+// there is one per timer tick, as the number of cycles to hand to 
+// run_frame. When they do not divide evenly, the part of a cycle left over
+// is carried into the next frame, so that e.g. 500 instructions per second
+// at 60Hz run 8 or 9 per frame.
+pub struct CycleBudget {
+    cycles_per_second: u32,
+    timer_hz: u32,
+    // The cycles carried over, in 1/timer_hz of a cycle.
+    carry: u32,
+}
+
+impl CycleBudget {
+
+    pub fn new(cycles_per_second: u32, timer_hz: u32) -> Self {
+        CycleBudget { cycles_per_second: cycles_per_second, 
+                      timer_hz: timer_hz.max(1), carry: 0 }
+    }
+
+    pub fn cycles_per_second(&self) -> u32 {
+        self.cycles_per_second
+    }
+
+    pub fn timer_hz(&self) -> u32 {
+        self.timer_hz
+    }
+
+    // Run faster or slower from the next frame on. Only the part of a 
+    // cycle carried over is kept, so that changing speed neither bursts
+    // nor stalls.
+    pub fn set_cycles_per_second(&mut self, cycles_per_second: u32) {
+        self.cycles_per_second = cycles_per_second;
+    }
+
+    // The number of cycles to run in the next frame.
+    pub fn next_frame(&mut self) -> usize {
+        let owed = self.carry + self.cycles_per_second;
+        self.carry = owed % self.timer_hz;
+        (owed / self.timer_hz) as usize
+    }
+
+}
+
+// Convert between the durations of the clock and those kept here.
+fn from_std(duration: StdDuration) -> Duration {
+    Duration::seconds(duration.as_secs() as i64) + 
+    Duration::nanoseconds(duration.subsec_nanos() as i64)
+}
+
+fn to_std(duration: Duration) -> StdDuration {
+    let nanos = duration.num_nanoseconds().unwrap_or(0).max(0) as u64;
+    StdDuration::new(nanos / 1_000_000_000, (nanos % 1_000_000_000) as u32)
+}
+
+// Keep a speed within the range that can be run at.
+pub fn clamp_speed(speed: f32) -> f32 {
+    speed.max(MIN_SPEED).min(MAX_SPEED)
+}
+
+// Step the instructions per second up or down, within their range.
+pub fn step_cycles_per_second(cycles_per_second: u32, up: bool) -> u32 {
+    if up {
+        (cycles_per_second + CYCLES_PER_SECOND_STEP).min(MAX_CYCLES_PER_SECOND)
+    } else {
+        cycles_per_second.saturating_sub(CYCLES_PER_SECOND_STEP)
+                         .max(MIN_CYCLES_PER_SECOND)
+    }
+}
+
+// How fast the emulator runs.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClockSettings {
+    // Instructions executed per second.
+    pub cycles_per_second: u32,
+    // How many times a second the timers count down.
+    pub timer_hz: u32,
+    // How many frames to catch up on after falling behind.
+    pub max_catch_up: u32,
+    // How many frames to run ahead to hide input latency, 0 for none.
+    pub run_ahead: u32,
+}
+
+impl ClockSettings {
+
+    pub fn new() -> Self {
+        ClockSettings { cycles_per_second: DEFAULT_CYCLES_PER_SECOND,
+                        timer_hz: DEFAULT_TIMER_HZ, 
+                        max_catch_up: DEFAULT_MAX_CATCH_UP,
+                        run_ahead: 0 }
+    }
+
+    // The settings in the [clock] section of the configuration:
+    //
+    //     [clock]
+    //     cycles_per_second = 700
+    //     timer_hz = 60
+    //     max_catch_up = 3
+    //     run_ahead = 1
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut settings = ClockSettings::new();
+        for setting in config.section("clock") {
+            match &setting.key[..] {
+                "cycles_per_second" => {
+                    settings.cycles_per_second = setting.parse()?;
+                    if !is_valid_cycles_per_second(
+                            settings.cycles_per_second) {
+                        return Err(setting.error(
+                            "expected 100 to 20000 instructions per second"));
+                    }
+                },
+                "timer_hz" => {
+                    settings.timer_hz = setting.parse()?;
+                    if settings.timer_hz < 1 || 
+                       settings.timer_hz > MAX_TIMER_HZ {
+                        return Err(setting.error("expected 1 to 1000Hz"));
+                    }
+                },
+                "max_catch_up" => {
+                    settings.max_catch_up = setting.parse()?;
+                    if settings.max_catch_up > MAX_CATCH_UP {
+                        return Err(setting.error(
+                            "expected 0 to 60 frames"));
+                    }
+                },
+                "run_ahead" => {
+                    settings.run_ahead = setting.parse()?;
+                    if settings.run_ahead > MAX_RUN_AHEAD {
+                        return Err(setting.error("expected 0 to 2 frames"));
+                    }
+                },
+                _ => return Err(setting.error("unknown clock setting")),
+            }
+        }
+        Ok(settings)
+    }
+
+    // The settings as written in the [clock] section, for writing them 
+    // out with config::set_settings.
+    pub fn to_config(&self) -> Vec<(&'static str, String)> {
+        vec![("cycles_per_second", self.cycles_per_second.to_string()),
+             ("timer_hz", self.timer_hz.to_string()),
+             ("max_catch_up", self.max_catch_up.to_string()),
+             ("run_ahead", self.run_ahead.to_string())]
+    }
+
+}
+
+// Runs the emulator a frame or two ahead of the frames it has really run, 
+// so that what is shown reacts to input sooner. The emulator itself stays 
+// authoritative: it runs every frame once, with the keys actually held, 
+// and its timers are what is heard. What is shown comes from a copy of it
+// run ahead as though the keys stay as they are, and the copy is thrown 
+// away once shown, which rolls back whatever it guessed wrong.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RunAhead {
+    frames: u32,
+}
+
+impl RunAhead {
+
+    pub fn new(frames: u32) -> Self {
+        RunAhead { frames: frames.min(MAX_RUN_AHEAD) }
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    // The emulator to show once emu has run its frames: a copy run the 
+    // frames ahead with the given cycles each, or None when not running
+    // ahead. A fault while running ahead halts the copy rather than 
+    // panicking, as the real run may never get there. Nor is anything the
+    // copy runs logged.
+    pub fn ahead(&self, emu: &Emu, cycles: usize) -> Option<Emu> {
+        if self.frames == 0 {
+            return None;
+        }
+        let mut ahead = emu.clone();
+        ahead.set_on_error(ErrorPolicy::Halt);
+        ahead.set_log(Log::off());
+        for _ in 0..self.frames {
+            ahead.run_frame(cycles);
+        }
+        Some(ahead)
+    }
+
+}
+
+pub fn is_valid_cycles_per_second(cycles_per_second: u32) -> bool {
+    cycles_per_second >= MIN_CYCLES_PER_SECOND && 
+    cycles_per_second <= MAX_CYCLES_PER_SECOND
+}
+
+// Number of frames kept for the timing graph: two seconds at 60 frames per
+// second.
+pub const TIMING_FRAMES: usize = 120;
+
+// Where the time of a frame went.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Phase { Emulate, Present, Sleep }
+
+// The time spent in each phase during a single frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameTiming {
+    pub emulate: Duration,
+    pub present: Duration,
+    pub sleep: Duration,
+}
+
+impl FrameTiming {
+
+    pub fn new() -> Self {
+        FrameTiming { emulate: Duration::zero(), present: Duration::zero(),
+                      sleep: Duration::zero() }
+    }
+
+}
+
+// Keeps the timings of the most recent frames, dropping the oldest once 
+// full, for diagnosing stutter. Time is added to the frame in progress 
+// until it ends.
+pub struct FrameTimings {
+    samples: VecDeque<FrameTiming>,
+    capacity: usize,
+    current: FrameTiming,
+}
+
+impl FrameTimings {
+
+    pub fn new(capacity: usize) -> Self {
+        FrameTimings { samples: VecDeque::with_capacity(capacity),
+                       capacity: capacity, current: FrameTiming::new() }
+    }
+
+    // Count time spent in a phase towards the frame in progress.
+    pub fn add(&mut self, phase: Phase, time: Duration) {
+        let total = match phase {
+            Phase::Emulate => &mut self.current.emulate,
+            Phase::Present => &mut self.current.present,
+            Phase::Sleep => &mut self.current.sleep,
+        };
+        *total = *total + time;
+    }
+
+    // Keep the frame in progress and start timing the next one.
+    pub fn end_frame(&mut self) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(self.current);
+        self.current = FrameTiming::new();
+    }
+
+    // The timings of the frames kept, oldest first.
+    pub fn samples(&self) -> Vec<FrameTiming> {
+        self.samples.iter().cloned().collect()
+    }
+
+}
+
+// The timing actually achieved over the last second, for the timing graph
+// and for bug reports.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MetroStats {
+    // The average and the longest time from one frame to the next, in
+    // milliseconds.
+    pub avg_frame_ms: f32,
+    pub worst_frame_ms: f32,
+    // Instructions executed, and frames run, which is how often the timers
+    // counted down.
+    pub cycles_per_second: u32,
+    pub timer_hz: u32,
+    // Since starting, the frames that took longer to emulate than a frame 
+    // lasts, and the times the backlog of frames was dropped.
+    pub over_budget_frames: u64,
+    pub dropped_backlogs: u64,
+    // How late sleeps wake up lately, in milliseconds, as measured by the
+    // FrameLimiter.
+    pub oversleep_ms: f32,
+}
+
+impl MetroStats {
+
+    // The stats on a single line, e.g. "60HZ 500IPS 16.7/18.0MS 0 SLOW 0 
+    // DROPPED 0.1MS LATE".
+    pub fn summary(&self) -> String {
+        format!("{}HZ {}IPS {:.1}/{:.1}MS {} SLOW {} DROPPED {:.1}MS LATE", 
+                self.timer_hz, self.cycles_per_second, self.avg_frame_ms, 
+                self.worst_frame_ms, self.over_budget_frames, 
+                self.dropped_backlogs, self.oversleep_ms)
+    }
+
+}
+
+// A frame run, as kept for a second by FrameStats.
+struct FrameRecord {
+    at: Duration,
+    // The time since the frame before, if there was one.
+    interval: Option<Duration>,
+    cycles: usize,
+}
+
+// Measures the MetroStats of the frames run, keeping those of the last 
+// second.
+pub struct FrameStats {
+    clock: Arc<Clock + Send + Sync>,
+    // How long a frame lasts.
+    period: Duration,
+    frames: VecDeque<FrameRecord>,
+    last_frame_at: Option<Duration>,
+    stats: MetroStats,
+}
+
+impl FrameStats {
+
+    pub fn new(hz: i64) -> Self {
+        FrameStats::with_clock(hz, Arc::new(SystemClock::new()))
+    }
+
+    pub fn with_clock(hz: i64, clock: Arc<Clock + Send + Sync>) -> Self {
+        FrameStats { clock: clock, period: Metronome::hz_to_duration(hz),
+                     frames: VecDeque::new(), last_frame_at: None, 
+                     stats: MetroStats::default() }
+    }
+
+    // Count a frame that ran the cycles and took busy to emulate.
+    pub fn frame(&mut self, cycles: usize, busy: Duration) {
+        let now = from_std(self.clock.now());
+        let second_ago = now - Duration::seconds(1);
+        while self.frames.front().map_or(false, |f| f.at <= second_ago) {
+            self.frames.pop_front();
+        }
+        let interval = self.last_frame_at.map(|at| now - at);
+        self.frames.push_back(FrameRecord { at: now, interval: interval, 
+                                            cycles: cycles });
+        self.last_frame_at = Some(now);
+        if busy > self.period {
+            self.stats.over_budget_frames += 1;
+        }
+        let intervals: Vec<f32> = self.frames.iter()
+            .filter_map(|f| f.interval)
+            .map(|interval| interval.num_microseconds().unwrap_or(0) as f32 
+                            / 1000.0)
+            .collect();
+        self.stats.avg_frame_ms = if intervals.is_empty() {
+            0.0
+        } else {
+            intervals.iter().sum::<f32>() / intervals.len() as f32
+        };
+        self.stats.worst_frame_ms = intervals.iter().cloned()
+                                             .fold(0.0, f32::max);
+        self.stats.cycles_per_second = 
+            self.frames.iter().map(|f| f.cycles).sum::<usize>() as u32;
+        self.stats.timer_hz = self.frames.len() as u32;
+    }
+
+    // Count a backlog of frames that was dropped rather than caught up on.
+    pub fn dropped_backlog(&mut self) {
+        self.stats.dropped_backlogs += 1;
+    }
+
+    // Note how late sleeps wake up, as measured by a FrameLimiter.
+    pub fn set_oversleep(&mut self, oversleep: Duration) {
+        self.stats.oversleep_ms = 
+            oversleep.num_microseconds().unwrap_or(0) as f32 / 1000.0;
+    }
+
+    pub fn stats(&self) -> MetroStats {
+        self.stats
+    }
+
+}
+
+// The most a FrameLimiter spins for, however late sleeps wake up, in 
+// microseconds.
+pub const MAX_SPIN_MARGIN_US: i64 = 4000;
+
+// How late sleeps are assumed to wake up until measured, in microseconds.
+const INITIAL_OVERSLEEP_US: i64 = 1000;
+
+// Waits precisely, for pacing frames. A sleep can wake up late by as much 
+// as a tick of the scheduler of the OS, which shows up as jitter. Instead 
+// the limiter sleeps until a margin before the time is up and spins for 
+// the rest. The margin follows how late sleeps have woken up lately, with
+// half again to spare, so that where sleeps are precise little time is 
+// spent spinning.
+pub struct FrameLimiter {
+    clock: Arc<Clock + Send + Sync>,
+    // A moving average of how late sleeps woke up.
+    oversleep: Duration,
+}
+
+impl FrameLimiter {
+
+    pub fn new() -> Self {
+        FrameLimiter::with_clock(Arc::new(SystemClock::new()))
+    }
+
+    pub fn with_clock(clock: Arc<Clock + Send + Sync>) -> Self {
+        FrameLimiter { clock: clock, 
+                       oversleep: Duration::microseconds(INITIAL_OVERSLEEP_US) }
+    }
+
+    // How late sleeps wake up lately.
+    pub fn oversleep(&self) -> Duration {
+        self.oversleep
+    }
+
+    // How long before the time is up to stop sleeping and start spinning.
+    pub fn margin(&self) -> Duration {
+        cmp::min(self.oversleep * 3 / 2, 
+                 Duration::microseconds(MAX_SPIN_MARGIN_US))
+    }
+
+    // Wait for the duration to pass, sleeping for most of it.
+    pub fn wait(&mut self, duration: Duration) {
+        let started = from_std(self.clock.now());
+        let sleep = duration - self.margin();
+        if sleep > Duration::zero() {
+            self.clock.sleep(to_std(sleep));
+            let slept = from_std(self.clock.now()) - started;
+            let late = cmp::max(slept - sleep, Duration::zero());
+            self.oversleep = self.oversleep + (late - self.oversleep) / 8;
+        }
+        while from_std(self.clock.now()) - started < duration {
+            self.clock.spin();
+        }
+    }
+
+}
+
+// Keeps a loop running at a target rate by sleeping away what is left of
+// each turn, for frontends that run frames in a loop of their own rather
+// than through emu_exec.
+pub struct Throttle {
+    clock: Arc<Clock + Send + Sync>,
+    metro: Metronome,
+    fps: u32,
+    // Without sleeping, the loop runs as fast as it can while keeping 
+    // count of the turns, e.g. in tests.
+    sleeping: bool,
+}
+
+impl Throttle {
+
+    pub fn new() -> Self {
+        Throttle::with_clock(Arc::new(SystemClock::new()))
+    }
+
+    pub fn with_clock(clock: Arc<Clock + Send + Sync>) -> Self {
+        let metro = Metronome::with_clock(DEFAULT_TIMER_HZ as i64, 
+                                          clock.clone());
+        Throttle { clock: clock, metro: metro, fps: DEFAULT_TIMER_HZ,
+                   sleeping: true }
+    }
+
+    pub fn set_sleeping(&mut self, sleeping: bool) {
+        self.sleeping = sleeping;
+    }
+
+    // How long the current turn has left, which is zero once it has taken
+    // longer than a turn should.
+    pub fn sleep_for(&self) -> Duration {
+        self.metro.until_tick()
+    }
+
+    // Sleep until the next turn of the loop is due at the rate. Turns are
+    // due a whole number of periods apart, so that a slow turn is made up
+    // for by sleeping less after the next. A change of rate starts over
+    // from the current time.
+    pub fn throttle(&mut self, target_fps: u32) {
+        let fps = cmp::max(target_fps, 1);
+        if fps != self.fps {
+            self.metro = Metronome::with_clock(fps as i64, 
+                                               self.clock.clone());
+            self.fps = fps;
+        }
+        if self.sleeping {
+            self.clock.sleep(to_std(self.sleep_for()));
+        }
+        self.metro.on_tick(|| ());
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{frames_per_present, FramePacer, FrameTimings, Metronome};
+    use super::{from_std, FrameLimiter, FrameStats, MetroStats};
+    use super::MAX_SPIN_MARGIN_US;
+    use super::{step_cycles_per_second, ClockSettings, CycleBudget, Phase};
+    use super::{IdleGate, RunAhead, MAX_FRAMES_PER_PRESENT, MAX_RUN_AHEAD};
+    use super::Throttle;
+    use super::super::config::{self, Config};
+    use super::super::emu::Emu;
+    use super::super::clock::{Clock, MockClock, MOCK_SPIN};
+    use super::super::log::{Level, Log, MemoryLogger};
+    use std::sync::Arc;
+    use std::time::Duration as StdDuration;
+    use time::Duration;
+
+    // Offer the metronome a tick every millisecond from start (inclusive)
+    // to end (exclusive), returning the number of ticks.
+    fn count_ticks(metro: &mut Metronome, base: Duration, start: i64,
+                   end: i64) -> usize {
+        let mut ticks = 0;
+        for ms in start..end {
+            metro.on_tick_at(base + Duration::milliseconds(ms), || ticks += 1);
+        }
+        ticks
+    }
+
+    #[test]
+    fn test_on_tick_at() {
+        let mut metro = Metronome::new(100);
+        let base = metro.ticked_at;
+        //then
+        assert_eq!(10, count_ticks(&mut metro, base, 0, 101));
+    }
+
+    #[test]
+    fn test_no_ticks_while_paused() {
+        let mut metro = Metronome::new(100);
+        let base = metro.ticked_at;
+        //when
+        metro.pause_at(base);
+        //then
+        assert_eq!(0, count_ticks(&mut metro, base, 0, 1000));
+    }
+
+    #[test]
+    fn test_resume_schedules_no_backlog() {
+        let mut metro = Metronome::new(100);
+        let base = metro.ticked_at;
+        //given
+        assert_eq!(1, count_ticks(&mut metro, base, 0, 15));
+        metro.pause_at(base + Duration::milliseconds(15));
+        //when
+        metro.resume_at(base + Duration::milliseconds(5015));
+        //then
+        // The tick that was due 5ms into the pause is now due 5ms after
+        // resuming.
+        assert_eq!(0, count_ticks(&mut metro, base, 5015, 5020));
+        assert_eq!(1, count_ticks(&mut metro, base, 5020, 5021));
+        assert_eq!(10, count_ticks(&mut metro, base, 5021, 5121));
+    }
+
+    // Run frames at 500 instructions per second between start and end ms,
+    // returning the cycles run.
+    fn run_cycles(frame_rate: &mut Metronome, budget: &mut CycleBudget,
+                  base: Duration, start: i64, end: i64) -> usize {
+        let mut cycles = 0;
+        for ms in start..end {
+            frame_rate.on_tick_at(base + Duration::milliseconds(ms), || {
+                cycles += budget.next_frame();
+            });
+        }
+        cycles
+    }
+
+    #[test]
+    fn test_pause_schedules_no_catch_up_cycles() {
+        let mut frame_rate = Metronome::new(100);
+        let mut budget = CycleBudget::new(500, 100);
+        let base = frame_rate.ticked_at;
+        //given
+        assert_eq!(500, run_cycles(&mut frame_rate, &mut budget, base, 
+                                   1, 1001));
+        //when
+        frame_rate.pause_at(base + Duration::milliseconds(1000));
+        assert!(frame_rate.is_paused());
+        assert_eq!(0, run_cycles(&mut frame_rate, &mut budget, base, 
+                                 1000, 6000));
+        frame_rate.resume_at(base + Duration::milliseconds(6000));
+        //then
+        assert!(!frame_rate.is_paused());
+        assert_eq!(Duration::seconds(5), frame_rate.paused_for());
+        // The first frame after resuming is due a frame later, as it would
+        // have been without the pause, and then pacing carries on as 
+        // before.
+        assert_eq!(0, run_cycles(&mut frame_rate, &mut budget, base, 
+                                 6000, 6010));
+        assert_eq!(5, run_cycles(&mut frame_rate, &mut budget, base, 
+                                 6010, 6011));
+        assert_eq!(500, run_cycles(&mut frame_rate, &mut budget, base, 
+                                   6011, 7011));
+    }
+
+    #[test]
+    fn test_speed_change_while_paused() {
+        let mut frame_rate = Metronome::new(100);
+        let mut budget = CycleBudget::new(500, 100);
+        let base = frame_rate.ticked_at;
+        frame_rate.pause_at(base);
+        //when
+        frame_rate.set_speed(2.0);
+        budget.set_cycles_per_second(1000);
+        assert_eq!(0, run_cycles(&mut frame_rate, &mut budget, base, 
+                                 0, 3000));
+        frame_rate.resume_at(base + Duration::milliseconds(3000));
+        //then
+        assert_eq!(2000, run_cycles(&mut frame_rate, &mut budget, base, 
+                                    3001, 4001));
+        assert_eq!(Duration::seconds(3), frame_rate.paused_for());
+    }
+
+    // Poll a 60Hz metronome every millisecond for the ranges of ms, 
+    // returning the ticks.
+    fn poll_60hz(metro: &mut Metronome, base: Duration, 
+                 ranges: &[(i64, i64)]) -> usize {
+        ranges.iter().map(|&(start, end)| count_ticks(metro, base, start, end))
+                     .sum()
+    }
+
+    #[test]
+    fn test_short_hiccup_is_caught_up_on() {
+        let mut metro = Metronome::new(60);
+        let base = metro.ticked_at;
+        //when
+        // Nothing polls the metronome for 40ms, two and a half frames.
+        let ticks = poll_60hz(&mut metro, base, &[(1, 500), (540, 1001)]);
+        //then
+        assert_eq!(60, ticks);
+        assert_eq!(0, metro.take_skipped());
+    }
+
+    #[test]
+    fn test_long_gap_drops_backlog() {
+        let mut metro = Metronome::new(60);
+        let base = metro.ticked_at;
+        //given
+        assert_eq!(30, poll_60hz(&mut metro, base, &[(1, 501)]));
+        //when
+        // E.g. the host sleeps for ten seconds.
+        let ticks = poll_60hz(&mut metro, base, &[(10500, 11001)]);
+        //then
+        // A single tick runs after the gap, and pacing carries on from 
+        // there.
+        assert_eq!(1 + 30, ticks);
+        assert_eq!(599, metro.take_skipped());
+        assert_eq!(0, metro.take_skipped());
+    }
+
+    #[test]
+    fn test_catch_up_is_bounded() {
+        let mut metro = Metronome::new(100);
+        let base = metro.ticked_at;
+        metro.set_max_catch_up(1);
+        //when
+        let caught_up = count_ticks(&mut metro, base, 20, 21);
+        //then
+        assert_eq!((2, 0), (caught_up, metro.take_skipped()));
+        let skipped = count_ticks(&mut metro, base, 50, 51);
+        assert_eq!((1, 2), (skipped, metro.take_skipped()));
+    }
+
+    fn mock_metronome(hz: i64) -> (Arc<MockClock>, Metronome) {
+        let clock = Arc::new(MockClock::new());
+        (clock.clone(), Metronome::with_clock(hz, clock))
+    }
+
+    // Sleep until each tick and run it, for the given number of ticks.
+    fn sleep_and_tick(metro: &mut Metronome, ticks: usize) -> usize {
+        let mut ran = 0;
+        for _ in 0..ticks {
+            metro.sleep_until_tick();
+            metro.on_tick(|| ran += 1);
+        }
+        ran
+    }
+
+    #[test]
+    fn test_paced_by_mock_clock() {
+        let (clock, mut metro) = mock_metronome(60);
+        //when
+        let ticks = sleep_and_tick(&mut metro, 60);
+        //then
+        let frame = StdDuration::new(0, 16666666);
+        assert_eq!(60, ticks);
+        assert_eq!(vec![frame; 60], clock.sleeps());
+        assert_eq!(frame * 60, clock.now());
+    }
+
+    #[test]
+    fn test_mock_clock_sleeps_only_what_is_left_of_a_tick() {
+        let (clock, mut metro) = mock_metronome(100);
+        //given
+        clock.advance(StdDuration::from_millis(4));
+        //when
+        metro.sleep_until_tick();
+        //then
+        assert_eq!(vec![StdDuration::from_millis(6)], clock.sleeps());
+        assert_eq!(1, sleep_and_tick(&mut metro, 1));
+        // A tick that is overdue does not sleep at all.
+        clock.advance(StdDuration::from_millis(25));
+        assert_eq!(Duration::zero(), metro.until_tick());
+        assert_eq!(2, sleep_and_tick(&mut metro, 1));
+    }
+
+    #[test]
+    fn test_mock_clock_hiccup_and_gap() {
+        let (clock, mut metro) = mock_metronome(60);
+        assert_eq!(10, sleep_and_tick(&mut metro, 10));
+        //when
+        clock.advance(StdDuration::from_millis(40));
+        //then
+        // The 40ms hiccup is caught up on, and the next tick is due at the
+        // time it would have been without it.
+        assert_eq!(2, sleep_and_tick(&mut metro, 1));
+        assert_eq!(0, metro.take_skipped());
+        assert_eq!(Duration::nanoseconds(16666666 * 13), 
+                   metro.now() + metro.until_tick());
+        // A ten second gap is not.
+        clock.advance(StdDuration::from_secs(10));
+        assert_eq!(1, sleep_and_tick(&mut metro, 1));
+        assert!(metro.take_skipped() > 500);
+        assert_eq!(10, sleep_and_tick(&mut metro, 10));
+    }
+
+    #[test]
+    fn test_mock_clock_pause() {
+        let (clock, mut metro) = mock_metronome(100);
+        assert_eq!(1, sleep_and_tick(&mut metro, 1));
+        //when
+        metro.pause();
+        clock.advance(StdDuration::from_secs(5));
+        metro.sleep_until_tick();
+        metro.resume();
+        //then
+        assert_eq!(1, clock.sleeps().len());
+        assert_eq!(Duration::seconds(5), metro.paused_for());
+        assert_eq!(Duration::milliseconds(10), metro.until_tick());
+        assert_eq!(100, sleep_and_tick(&mut metro, 100));
+        assert_eq!(0, metro.take_skipped());
+    }
+
+    #[test]
+    fn test_frame_stats_over_a_second() {
+        let clock = Arc::new(MockClock::new());
+        let mut stats = FrameStats::with_clock(60, clock.clone());
+        let busy = Duration::milliseconds(2);
+        //when
+        // 50 frames 16ms apart, then one after a 50ms stall that took 20ms
+        // to emulate, and 9 more.
+        for _ in 0..50 {
+            clock.advance(StdDuration::from_millis(16));
+            stats.frame(8, busy);
+        }
+        clock.advance(StdDuration::from_millis(50));
+        stats.frame(8, Duration::milliseconds(20));
+        for _ in 0..9 {
+            clock.advance(StdDuration::from_millis(16));
+            stats.frame(9, busy);
+        }
+        //then
+        let total_ms = 49.0 * 16.0 + 50.0 + 9.0 * 16.0;
+        assert_eq!(MetroStats { avg_frame_ms: total_ms / 59.0, 
+                                worst_frame_ms: 50.0, 
+                                cycles_per_second: 51 * 8 + 9 * 9, 
+                                timer_hz: 60, over_budget_frames: 1, 
+                                dropped_backlogs: 0, oversleep_ms: 0.0 }, 
+                   stats.stats());
+    }
+
+    #[test]
+    fn test_frame_stats_forget_frames_after_a_second() {
+        let clock = Arc::new(MockClock::new());
+        let mut stats = FrameStats::with_clock(60, clock.clone());
+        stats.frame(8, Duration::zero());
+        //when
+        clock.advance(StdDuration::from_millis(1500));
+        stats.frame(8, Duration::zero());
+        stats.dropped_backlog();
+        stats.set_oversleep(Duration::microseconds(1300));
+        //then
+        // The stall is remembered until the frame after it is a second old.
+        let achieved = stats.stats();
+        assert_eq!((1, 8), (achieved.timer_hz, achieved.cycles_per_second));
+        assert_eq!(1, achieved.dropped_backlogs);
+        assert_eq!("1HZ 8IPS 1500.0/1500.0MS 0 SLOW 1 DROPPED 1.3MS LATE", 
+                   achieved.summary());
+        for &ms in &[500, 600] {
+            clock.advance(StdDuration::from_millis(ms));
+            stats.frame(8, Duration::zero());
+        }
+        assert_eq!((2, 600.0), (stats.stats().timer_hz, 
+                                stats.stats().worst_frame_ms));
+    }
+
+    // Wait out a second of frames at 60Hz with sleeps that wake up late by
+    // the given time, returning how late each frame ended.
+    fn limit_frames(limiter: &mut FrameLimiter, clock: &MockClock, 
+                    late: StdDuration) -> Vec<Duration> {
+        let frame = Duration::nanoseconds(16_666_667);
+        clock.oversleep(&vec![late; 60]);
+        (0..60).map(|_| {
+            let started = from_std(clock.now());
+            limiter.wait(frame);
+            from_std(clock.now()) - started - frame
+        }).collect()
+    }
+
+    #[test]
+    fn test_frame_limiter_margin_converges_on_oversleep() {
+        let clock = Arc::new(MockClock::new());
+        let mut limiter = FrameLimiter::with_clock(clock.clone());
+        //when
+        let late = limit_frames(&mut limiter, &clock, 
+                                StdDuration::from_millis(2));
+        //then
+        let oversleep = limiter.oversleep().num_microseconds().unwrap();
+        assert!(oversleep > 1990 && oversleep <= 2000);
+        let margin = limiter.margin().num_microseconds().unwrap();
+        assert!(margin > 2985 && margin <= 3000);
+        // Once the margin has grown past the oversleep, frames end on time,
+        // give or take a spin.
+        let spin = Duration::microseconds(MOCK_SPIN as i64);
+        assert!(late[..4].iter().any(|&late| late > spin));
+        assert!(late[10..].iter().all(|&late| late < spin));
+        // Sleeping stops the margin short of the end of the frame.
+        let sleep = clock.sleeps()[59];
+        assert!(sleep > StdDuration::new(0, 13_600_000) && 
+                sleep < StdDuration::new(0, 13_700_000));
+    }
+
+    #[test]
+    fn test_frame_limiter_margin_shrinks_with_precise_sleeps() {
+        let clock = Arc::new(MockClock::new());
+        let mut limiter = FrameLimiter::with_clock(clock.clone());
+        //when
+        let late = limit_frames(&mut limiter, &clock, StdDuration::new(0, 0));
+        //then
+        assert!(limiter.margin() < Duration::microseconds(10));
+        let spin = Duration::microseconds(MOCK_SPIN as i64);
+        assert!(late.iter().all(|&late| late < spin));
+    }
+
+    #[test]
+    fn test_frame_limiter_spins_for_a_bounded_time() {
+        let clock = Arc::new(MockClock::new());
+        let mut limiter = FrameLimiter::with_clock(clock.clone());
+        //when
+        limit_frames(&mut limiter, &clock, StdDuration::from_millis(10));
+        //then
+        assert_eq!(Duration::microseconds(MAX_SPIN_MARGIN_US), 
+                   limiter.margin());
+        // A wait shorter than the margin only spins.
+        let sleeps = clock.sleeps().len();
+        limiter.wait(Duration::milliseconds(1));
+        assert_eq!(sleeps, clock.sleeps().len());
+    }
+
+    #[test]
+    fn test_throttle_sleeps_only_after_fast_frames() {
+        let clock = Arc::new(MockClock::new());
+        let mut throttle = Throttle::with_clock(clock.clone());
+        throttle.throttle(50);
+        //when
+        clock.advance(StdDuration::from_millis(5));
+        //then
+        assert_eq!(Duration::milliseconds(15), throttle.sleep_for());
+        throttle.throttle(50);
+        assert_eq!(StdDuration::from_millis(15), clock.sleeps()[1]);
+        //when
+        clock.advance(StdDuration::from_millis(30));
+        //then
+        assert_eq!(Duration::zero(), throttle.sleep_for());
+        throttle.throttle(50);
+        assert_eq!(StdDuration::new(0, 0), clock.sleeps()[2]);
+        // The slow frame is made up for after the next one.
+        clock.advance(StdDuration::from_millis(5));
+        assert_eq!(Duration::milliseconds(5), throttle.sleep_for());
+    }
+
+    #[test]
+    fn test_throttle_without_sleeping() {
+        let clock = Arc::new(MockClock::new());
+        let mut throttle = Throttle::with_clock(clock.clone());
+        //given
+        throttle.set_sleeping(false);
+        //when
+        for _ in 0..10 {
+            throttle.throttle(60);
+        }
+        //then
+        assert!(clock.sleeps().is_empty());
+        assert_eq!(Duration::nanoseconds(16_666_666), throttle.sleep_for());
+    }
+
+    #[test]
+    fn test_pause_mid_beep_preserves_sound_timer() {
+        let mut emu = Emu::new();
+        let mut timers = Metronome::new(60);
+        let base = timers.ticked_at;
+        //given
+        emu.load_rom(vec![0x60, 0x1e, 0xf0, 0x18]);
+        emu.execute_cycle();
+        emu.execute_cycle();
+        for ms in 0..100 {
+            let now = base + Duration::milliseconds(ms);
+            timers.on_tick_at(now, || emu.update_timers());
+        }
+        let st = emu.sound_timer();
+        assert!(emu.beeping());
+        //when
+        timers.pause_at(base + Duration::milliseconds(100));
+        for ms in 100..5000 {
+            let now = base + Duration::milliseconds(ms);
+            timers.on_tick_at(now, || emu.update_timers());
+        }
+        timers.resume_at(base + Duration::milliseconds(5000));
+        //then
+        assert_eq!(st, emu.sound_timer());
+        for ms in 5000..5017 {
+            let now = base + Duration::milliseconds(ms);
+            timers.on_tick_at(now, || emu.update_timers());
+        }
+        assert_eq!(st - 1, emu.sound_timer());
+    }
+
+    #[test]
+    fn test_speed_keeps_emulated_time_in_step() {
+        let mut clock_rate = Metronome::new(500);
+        let mut timers = Metronome::new(100);
+        let base = clock_rate.ticked_at;
+        timers.ticked_at = base;
+        let mut emu = Emu::new();
+        emu.load_rom(vec![0x12, 0x00]);
+        let (mut cycles, mut ticks) = (0, 0);
+        //when
+        clock_rate.set_speed(8.0);
+        timers.set_speed(8.0);
+        for ms in 0..1001 {
+            let now = base + Duration::milliseconds(ms);
+            clock_rate.on_tick_at(now, || {
+                emu.execute_cycle();
+                cycles += 1;
+            });
+            timers.on_tick_at(now, || ticks += 1);
+        }
+        //then
+        // A second of wall clock time runs eight seconds of emulated time,
+        // with as many cycles per timer tick as at normal speed.
+        assert_eq!((4000, 800), (cycles, ticks));
+    }
+
+    #[test]
+    fn test_back_to_normal_speed_without_stall() {
+        let mut metro = Metronome::new(100);
+        let base = metro.ticked_at;
+        metro.set_speed(8.0);
+        //given
+        assert_eq!(80, count_ticks(&mut metro, base, 0, 105));
+        //when
+        metro.set_speed(1.0);
+        //then
+        assert_eq!(0, count_ticks(&mut metro, base, 105, 110));
+        assert_eq!(1, count_ticks(&mut metro, base, 110, 111));
+        assert_eq!(1.0, metro.speed());
+    }
+
+    // Run the frames of a CycleBudget at 500 instructions per second for 
+    // 600 ticks of a 60Hz metronome, returning the frames and cycles run.
+    fn run_600_ticks_at(speed: f32) -> (u32, usize) {
+        let mut frame_rate = Metronome::new(60);
+        let mut budget = CycleBudget::new(500, 60);
+        let base = frame_rate.ticked_at;
+        let (mut frames, mut cycles) = (0, 0);
+        frame_rate.set_speed(speed);
+        for tick in 1..601 {
+            let now = base + frame_rate.freq * tick;
+            frame_rate.on_tick_at(now, || {
+                frames += 1;
+                cycles += budget.next_frame();
+            });
+        }
+        (frames, cycles)
+    }
+
+    #[test]
+    fn test_fractional_speeds_add_up_exactly() {
+        // 600 frames are ten seconds, or 5000 cycles, at normal speed.
+        assert_eq!((600, 5000), run_600_ticks_at(1.0));
+        assert_eq!((900, 7500), run_600_ticks_at(1.5));
+        assert_eq!((150, 1250), run_600_ticks_at(0.25));
+        assert_eq!((9600, 80000), run_600_ticks_at(16.0));
+    }
+
+    #[test]
+    fn test_speed_is_clamped() {
+        let mut metro = Metronome::new(60);
+        //when
+        metro.set_speed(0.1);
+        //then
+        assert_eq!(0.25, metro.speed());
+        metro.set_speed(100.0);
+        assert_eq!(16.0, metro.speed());
+        metro.set_speed(1.5);
+        assert_eq!(1.5, metro.speed());
+    }
+
+    #[test]
+    fn test_at_speed_carries_part_frames() {
+        let mut metro = Metronome::new(60);
+        metro.set_speed(0.25);
+        //when
+        let frames: Vec<u32> = (0..8).map(|_| metro.at_speed(1)).collect();
+        //then
+        assert_eq!(vec![0, 0, 0, 1, 0, 0, 0, 1], frames);
+        metro.set_speed(1.5);
+        assert_eq!(3, metro.at_speed(2));
+    }
+
+    // Run the timer metronome of the budget for a simulated second, 
+    // returning the frames run and the cycles handed to them.
+    fn schedule_second(budget: &mut CycleBudget) -> (usize, usize) {
+        let mut timers = Metronome::new(budget.timer_hz() as i64);
+        let base = timers.ticked_at;
+        let (mut frames, mut cycles) = (0, 0);
+        for ms in 1..1001 {
+            timers.on_tick_at(base + Duration::milliseconds(ms), || {
+                frames += 1;
+                cycles += budget.next_frame();
+            });
+        }
+        (frames, cycles)
+    }
+
+    #[test]
+    fn test_cycles_scheduled_per_second() {
+        for &(cycles_per_second, timer_hz) in 
+                &[(500, 60), (700, 60), (1000, 60), (1000, 100), (600, 50)] {
+            let mut budget = CycleBudget::new(cycles_per_second, timer_hz);
+            //when
+            let (frames, cycles) = schedule_second(&mut budget);
+            //then
+            assert_eq!(timer_hz as usize, frames);
+            assert_eq!(cycles_per_second as usize, cycles);
+        }
+    }
+
+    #[test]
+    fn test_cycle_budget_carries_fractions() {
+        let mut budget = CycleBudget::new(500, 60);
+        //when
+        let frames: Vec<usize> = (0..60).map(|_| budget.next_frame())
+                                        .collect();
+        //then
+        assert_eq!(500, frames.iter().sum::<usize>());
+        assert!(frames.iter().all(|&n| n == 8 || n == 9));
+    }
+
+    #[test]
+    fn test_changing_cycles_per_second_neither_bursts_nor_stalls() {
+        let mut budget = CycleBudget::new(500, 60);
+        for _ in 0..7 { budget.next_frame(); }
+        //when
+        budget.set_cycles_per_second(1000);
+        let faster: Vec<usize> = (0..3).map(|_| budget.next_frame())
+                                       .collect();
+        budget.set_cycles_per_second(100);
+        let slower: Vec<usize> = (0..3).map(|_| budget.next_frame())
+                                       .collect();
+        //then
+        assert!(faster.iter().all(|&n| n == 16 || n == 17));
+        assert!(slower.iter().all(|&n| n == 1 || n == 2));
+    }
+
+    #[test]
+    fn test_step_cycles_per_second() {
+        assert_eq!(800, step_cycles_per_second(700, true));
+        assert_eq!(600, step_cycles_per_second(700, false));
+        assert_eq!(100, step_cycles_per_second(100, false));
+        assert_eq!(20000, step_cycles_per_second(20000, true));
+    }
+
+    #[test]
+    fn test_clock_settings_from_config() {
+        assert_eq!(ClockSettings { cycles_per_second: 500, timer_hz: 60, 
+                                   max_catch_up: 3, run_ahead: 0 },
+                   ClockSettings::new());
+        //given
+        let config = Config::parse("[clock]\n\
+                                    cycles_per_second = 1200\n\
+                                    timer_hz = 50\n\
+                                    max_catch_up = 0\n\
+                                    run_ahead = 2\n").unwrap();
+        //when
+        let settings = ClockSettings::from_config(&config).unwrap();
+        //then
+        assert_eq!(ClockSettings { cycles_per_second: 1200, timer_hz: 50,
+                                   max_catch_up: 0, run_ahead: 2 },
+                   settings);
+        for bad in &["cycles_per_second = 50", "timer_hz = 0", "hz = 60",
+                     "max_catch_up = 61", "run_ahead = 3"] {
+            let config = Config::parse(&format!("[clock]\n{}", bad)).unwrap();
+            assert!(ClockSettings::from_config(&config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_clock_settings_round_trip() {
+        //given
+        let settings = ClockSettings { cycles_per_second: 1200, timer_hz: 50,
+                                       max_catch_up: 0, run_ahead: 2 };
+        //when
+        let text = config::set_settings("", "clock", &settings.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(settings, ClockSettings::from_config(&config).unwrap());
+    }
+
+    // Run the frames due at 60Hz over the given time, as the emulator 
+    // thread does, returning the number of frames run.
+    fn run_idling(emu: &mut Emu, gate: &mut IdleGate, metro: &mut Metronome,
+                  clock: &MockClock, ms: u64) -> usize {
+        let mut frames = 0;
+        for _ in 0..ms {
+            if !gate.update(emu.is_idle(), false, metro) {
+                metro.on_tick(|| {
+                    emu.run_frame(8);
+                    frames += 1;
+                });
+            }
+            clock.advance(StdDuration::from_millis(1));
+        }
+        frames
+    }
+
+    #[test]
+    fn test_no_frames_while_idle() {
+        let clock = Arc::new(MockClock::new());
+        let mut metro = Metronome::with_clock(60, clock.clone());
+        let mut gate = IdleGate::new();
+        let mut emu = Emu::new();
+        //given
+        // Waits for a key, then counts in v1 forever.
+        emu.load_rom(vec![0xf0, 0x0a, 0x71, 0x01, 0x12, 0x02]);
+        //when
+        let frames = run_idling(&mut emu, &mut gate, &mut metro, &clock, 
+                                1000);
+        //then
+        assert_eq!(0, frames);
+        assert!(metro.is_paused());
+        //when
+        emu.keys[0x3] = true;
+        let frames = run_idling(&mut emu, &mut gate, &mut metro, &clock, 18);
+        //then
+        // Frames start again within a frame of the key.
+        assert_eq!(1, frames);
+        assert!(!metro.is_paused() && emu.register(0x1) > 0);
+    }
+
+    #[test]
+    fn test_idle_gate_leaves_pause_alone() {
+        let clock = Arc::new(MockClock::new());
+        let mut metro = Metronome::with_clock(60, clock.clone());
+        let mut gate = IdleGate::new();
+        assert!(gate.update(true, false, &mut metro));
+        //when
+        metro.pause();
+        let idling = gate.update(false, true, &mut metro);
+        //then
+        assert!(!idling && metro.is_paused());
+        metro.resume();
+        assert!(!gate.update(false, false, &mut metro));
+        assert!(!metro.is_paused());
+    }
+
+    // Waits for key 5, then sets the delay timer and waits for it to run 
+    // out, like a game that reacts to input a frame later. Then draws the
+    // digit 5 and starts the sound timer.
+    const LATE_REACTION_ROM: [u8; 24] = [
+        0x65, 0x05, // 200 LD V5, 5
+        0xe5, 0x9e, // 202 wait: SKP V5
+        0x12, 0x02, // 204 JP wait
+        0x61, 0x01, // 206 LD V1, 1
+        0xf1, 0x15, // 208 LD DT, V1
+        0xf1, 0x07, // 20a delay: LD V1, DT
+        0x31, 0x00, // 20c SE V1, 0
+        0x12, 0x0a, // 20e JP delay
+        0xf5, 0x29, // 210 LD F, V5
+        0xd0, 0x05, // 212 DRW V0, V0, 5
+        0xf5, 0x18, // 214 LD ST, V5
+        0x12, 0x16, // 216 done: JP done
+    ];
+
+    // Run the rom with key 5 pressed from the given frame on, returning 
+    // for each frame whether what is shown has been drawn on, and whether
+    // the beep heard is on.
+    fn run_scripted(run_ahead: RunAhead, pressed_at: usize) 
+                    -> Vec<(bool, bool)> {
+        let mut emu = Emu::new();
+        emu.load_rom(LATE_REACTION_ROM.to_vec());
+        (0..8).map(|frame| {
+            emu.keys[5] = frame >= pressed_at;
+            emu.run_frame(10);
+            let shown = run_ahead.ahead(&emu, 10);
+            let drawn = shown.as_ref().unwrap_or(&emu).display_hash() != 
+                        Emu::new().display_hash();
+            (drawn, emu.beeping())
+        }).collect()
+    }
+
+    #[test]
+    fn test_run_ahead_shows_input_a_frame_earlier() {
+        //when
+        let without = run_scripted(RunAhead::new(0), 3);
+        let with = run_scripted(RunAhead::new(1), 3);
+        //then
+        let shown = |frames: &[(bool, bool)]| 
+            frames.iter().position(|&(drawn, _)| drawn);
+        assert_eq!(Some(4), shown(&without));
+        assert_eq!(Some(3), shown(&with));
+        // The beep is heard from the frames really run, at the same time 
+        // either way.
+        let heard = |frames: &[(bool, bool)]| 
+            frames.iter().map(|&(_, beeping)| beeping).collect::<Vec<_>>();
+        assert_eq!(heard(&without), heard(&with));
+        assert_eq!(Some(4), heard(&with).iter().position(|&on| on));
+    }
+
+    #[test]
+    fn test_run_ahead_leaves_emulator_alone() {
+        let mut emu = Emu::new();
+        emu.load_rom(LATE_REACTION_ROM.to_vec());
+        emu.keys[5] = true;
+        emu.run_frame(10);
+        let logger = Arc::new(MemoryLogger::new());
+        emu.set_log(Log::new(Level::Trace, logger.clone()));
+        //when
+        let ahead = RunAhead::new(2).ahead(&emu, 10).unwrap();
+        //then
+        assert_eq!((1, 3), (emu.frame_count(), ahead.frame_count()));
+        assert!(!emu.beeping() && ahead.beeping());
+        assert!(logger.messages().is_empty());
+        assert_eq!(MAX_RUN_AHEAD, RunAhead::new(5).frames());
+        assert!(RunAhead::new(0).ahead(&emu, 10).is_none());
+    }
+
+    // Present at the refresh rate of a display for a second, returning 
+    // the number of frames run per present.
+    fn pace(display_hz: i64) -> Vec<u32> {
+        let mut pacer = FramePacer::new(60);
+        let interval = Duration::nanoseconds(10i64.pow(9) / display_hz);
+        (0..display_hz).map(|_| pacer.frames(interval)).collect()
+    }
+
+    #[test]
+    fn test_frames_per_present_at_common_refresh_rates() {
+        for &hz in &[60, 75, 120, 144] {
+            let frames = pace(hz);
+            //then
+            // Rounding of the intervals may cost a single frame.
+            let total: u32 = frames.iter().sum();
+            assert!(total == 60 || total == 59, "{}Hz ran {}", hz, total);
+            assert!(frames.iter().all(|&n| n <= 1));
+        }
+        assert!(pace(60).iter().all(|&n| n == 1));
+        assert_eq!(&[0, 1, 0, 1], &pace(120)[..4]);
+    }
+
+    #[test]
+    fn test_missed_present_runs_extra_frame() {
+        let frame = Duration::nanoseconds(16_666_667);
+        //when
+        let (frames, carry) = frames_per_present(frame * 2, 60, 
+                                                 Duration::zero());
+        //then
+        assert_eq!(2, frames);
+        assert!(carry < Duration::milliseconds(1));
+    }
+
+    #[test]
+    fn test_long_stall_is_not_caught_up_on() {
+        //when
+        let (frames, carry) = frames_per_present(Duration::seconds(2), 60,
+                                                 Duration::zero());
+        //then
+        assert_eq!(MAX_FRAMES_PER_PRESENT, frames);
+        assert_eq!(Duration::zero(), carry);
+    }
+
+    #[test]
+    fn test_frame_timings_add_up_per_frame() {
+        let mut timings = FrameTimings::new(2);
+        let ms = Duration::milliseconds;
+        //when
+        timings.add(Phase::Emulate, ms(3));
+        timings.add(Phase::Emulate, ms(2));
+        timings.add(Phase::Sleep, ms(10));
+        timings.end_frame();
+        timings.add(Phase::Present, ms(1));
+        timings.end_frame();
+        //then
+        let samples = timings.samples();
+        assert_eq!(2, samples.len());
+        assert_eq!((ms(5), ms(0), ms(10)), 
+                   (samples[0].emulate, samples[0].present, 
+                    samples[0].sleep));
+        assert_eq!((ms(0), ms(1), ms(0)), 
+                   (samples[1].emulate, samples[1].present, 
+                    samples[1].sleep));
+    }
+
+    #[test]
+    fn test_frame_timings_drop_oldest_frame() {
+        let mut timings = FrameTimings::new(2);
+        //when
+        for i in 0..3 {
+            timings.add(Phase::Sleep, Duration::milliseconds(i));
+            timings.end_frame();
+        }
+        //then
+        let sleeps: Vec<Duration> = timings.samples().iter()
+            .map(|sample| sample.sleep).collect();
+        assert_eq!(vec![Duration::milliseconds(1), Duration::milliseconds(2)],
+                   sleeps);
+    }
+
 }
@@ -4,7 +4,36 @@ pub const GFX_H: usize = 64;
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Mode { STANDARD, SUPER }
 
+pub use self::emu::{MAX_ROM_SIZE,NUM_REGISTERS,PROGRAM_START,RAM_SIZE,STACK_SIZE};
+
+pub mod asm;
+pub mod builtin;
+pub mod cli;
+pub mod clock;
+pub mod compare;
+pub mod config;
+pub mod debugger;
+pub mod demo;
+pub mod disasm;
+pub mod display;
+pub mod effects;
 pub mod emu;
+pub mod font;
+pub mod info;
+pub mod input;
+pub mod log;
 pub mod metro;
+pub mod options;
+pub mod overlay;
+pub mod picker;
+pub mod quicksave;
+pub mod recent;
+pub mod rewind;
+pub mod rom;
+pub mod romdb;
+pub mod runner;
+pub mod script;
+pub mod title;
 pub mod ui;
 pub mod wav;
+pub mod window;
@@ -0,0 +1,714 @@
+use super::compare;
+use super::config::{Config, ConfigError};
+use super::demo::DEFAULT_DEMO_SECS;
+use super::effects::{self, Effects};
+use super::emu::{Emu, Quirks};
+use super::log::Level;
+use super::metro::{self, ClockSettings};
+use super::wav::{self, AudioSettings, Waveform};
+use super::window::{self, WindowSettings};
+use std::fmt;
+use std::str::FromStr;
+
+pub const USAGE: &'static str =
+    "Usage: chip8 [OPTIONS] [PATH_TO_ROM]\n       \
+     chip8 [OPTIONS] --demo DIR [--demo-secs N]\n       \
+     chip8 [OPTIONS] --compare QUIRKS,QUIRKS PATH_TO_ROM\n       \
+     chip8 [OPTIONS] --headless --frames N PATH_TO_ROM\n       \
+     chip8 disasm PATH_TO_ROM [--start ADDRESS] [--raw]\n       \
+     chip8 asm SOURCE -o PATH_TO_ROM [--force] [--listing]\n       \
+     chip8 info PATH_TO_ROM [--json]\n\n\
+     Options:\n  \
+     --mode chip8|schip|xochip\n  \
+     --quirk NAME=on|off         may be repeated\n  \
+     --seed N\n  \
+     --speed INSTRUCTIONS_PER_SECOND\n  \
+     --turbo MULTIPLIER\n  \
+     --run-ahead FRAMES\n  \
+     --vsync on|off\n  \
+     --scale N\n  \
+     --fullscreen\n  \
+     --palette FOREGROUND,BACKGROUND   colours such as ffb000,000000\n  \
+     --waveform square|sine|triangle|sawtooth\n  \
+     --sample-rate HZ\n  \
+     --audio-buffer SAMPLES\n  \
+     --record-audio OUT.wav\n  \
+     --config PATH\n  \
+     --write-config              print the settings in effect and exit\n  \
+     --no-rom-db                 run every rom without its profile\n  \
+     --log-level off|warn|info|debug|trace\n  \
+     --log-file PATH             log there instead of to standard error";
+
+// The platforms a rom can be written for, each with its own quirks.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Platform { Chip8, Schip, XoChip }
+
+impl Platform {
+
+    // The name by which the platform is known on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Schip => "schip",
+            Platform::XoChip => "xochip",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Platform> {
+        [Platform::Chip8, Platform::Schip, Platform::XoChip].iter().cloned()
+            .find(|platform| platform.name() == name)
+    }
+
+    // The quirks roms written for the platform expect.
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Chip8 => Quirks::cosmac_vip(),
+            Platform::Schip => Quirks::schip(),
+            Platform::XoChip => Quirks::modern(),
+        }
+    }
+
+}
+
+// The quirks to run roms with, and the platform they were picked for.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct QuirkSettings {
+    pub platform: Option<Platform>,
+    pub quirks: Quirks,
+}
+
+impl QuirkSettings {
+
+    pub fn new() -> Self {
+        QuirkSettings { platform: None, quirks: Quirks::default() }
+    }
+
+    // The settings in the [quirks] section of the configuration. The mode
+    // picks the quirks of its platform, which the settings after it can 
+    // switch on or off one by one:
+    //
+    //     [quirks]
+    //     mode = chip8
+    //     display_wait = false
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut settings = QuirkSettings::new();
+        for setting in config.section("quirks") {
+            match &setting.key[..] {
+                "mode" => settings.set_platform(
+                    Platform::from_name(&setting.value).ok_or_else(|| {
+                        setting.error("expected chip8, schip or xochip")
+                    })?),
+                name => {
+                    let on = setting.parse()?;
+                    if !settings.quirks.set(name, on) {
+                        return Err(setting.error("unknown quirk"));
+                    }
+                },
+            }
+        }
+        Ok(settings)
+    }
+
+    // The settings as written in the [quirks] section, for writing them 
+    // out with config::set_settings. Every quirk is listed, after the 
+    // mode.
+    pub fn to_config(&self) -> Vec<(&'static str, String)> {
+        let mode = self.platform.map(|platform| {
+            ("mode", platform.name().to_string())
+        });
+        let quirks = Quirks::names().iter().map(|&name| {
+            (name, self.quirks.get(name).unwrap().to_string())
+        });
+        mode.into_iter().chain(quirks).collect()
+    }
+
+    // Run roms written for the platform, with its quirks.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = Some(platform);
+        self.quirks = platform.quirks();
+    }
+
+    // Give the emulator the quirks, and the XO-CHIP behaviour if that is 
+    // the platform.
+    pub fn apply(&self, emu: &mut Emu) {
+        emu.quirks = self.quirks;
+        emu.xo_chip = self.platform == Some(Platform::XoChip);
+    }
+
+}
+
+// Reasons the command line can not be used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionsError {
+    // An option that does not exist, or a second rom.
+    Unexpected(String),
+    // An option given without its value. Holds the option.
+    MissingValue(String),
+    // An option given a value it does not take. Holds the option and the
+    // value.
+    InvalidValue(String, String),
+    // Two options that can not be used together.
+    Conflict(&'static str, &'static str),
+    // An option used without another one it needs.
+    Requires(&'static str, &'static str),
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OptionsError::Unexpected(ref arg) =>
+                write!(f, "Unexpected argument {}", arg),
+            OptionsError::MissingValue(ref option) =>
+                write!(f, "{} needs a value", option),
+            OptionsError::InvalidValue(ref option, ref value) =>
+                write!(f, "Invalid value for {}: {}", option, value),
+            OptionsError::Conflict(a, b) =>
+                write!(f, "{} can not be used with {}", a, b),
+            OptionsError::Requires(a, b) =>
+                write!(f, "{} needs {}", a, b),
+        }
+    }
+}
+
+// What was asked for on the command line. Settings that were not given
+// are None, leaving them to the configuration.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub rom: Option<String>,
+    pub demo: Option<String>,
+    pub demo_secs: u64,
+    pub compare: Option<(Quirks, Quirks)>,
+    pub platform: Option<Platform>,
+    // Quirks switched on or off by name, in the order given, on top of
+    // those of the platform.
+    pub quirks: Vec<(String, bool)>,
+    pub seed: Option<u64>,
+    pub speed: Option<u32>,
+    pub turbo: Option<f32>,
+    pub run_ahead: Option<u32>,
+    pub vsync: Option<bool>,
+    pub scale: Option<u32>,
+    pub fullscreen: bool,
+    // The colours of pixels that are on and off.
+    pub palette: Option<((u8, u8, u8), (u8, u8, u8))>,
+    pub waveform: Option<Waveform>,
+    pub sample_rate: Option<usize>,
+    pub audio_buffer: Option<usize>,
+    pub record_audio: Option<String>,
+    // Run the number of frames without a window, then print the display.
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub config: Option<String>,
+    // Print the settings in effect as a configuration file, and exit.
+    pub write_config: bool,
+    // Leave out the profiles of the rom database.
+    pub no_rom_db: bool,
+    // How much is logged, and the file it goes to rather than standard
+    // error.
+    pub log_level: Level,
+    pub log_file: Option<String>,
+}
+
+impl Options {
+
+    pub fn new() -> Self {
+        Options { rom: None, demo: None, demo_secs: DEFAULT_DEMO_SECS,
+                  compare: None, platform: None, quirks: Vec::new(),
+                  seed: None, speed: None, turbo: None, run_ahead: None,
+                  vsync: None, scale: None, fullscreen: false,
+                  palette: None, waveform: None, sample_rate: None,
+                  audio_buffer: None, record_audio: None, headless: false,
+                  frames: None, config: None, write_config: false,
+                  no_rom_db: false, log_level: Level::Warn, 
+                  log_file: None }
+    }
+
+    // Apply the settings given on the command line on top of those read 
+    // from the configuration, since they take precedence, as the 
+    // configuration does over the defaults.
+    pub fn apply_to_clock(&self, clock: &mut ClockSettings) {
+        clock.cycles_per_second = 
+            self.speed.unwrap_or(clock.cycles_per_second);
+        clock.run_ahead = self.run_ahead.unwrap_or(clock.run_ahead);
+    }
+
+    pub fn apply_to_window(&self, window: &mut WindowSettings) {
+        window.turbo = self.turbo.unwrap_or(window.turbo);
+        window.vsync = self.vsync.unwrap_or(window.vsync);
+        window.scale = self.scale.unwrap_or(window.scale);
+    }
+
+    pub fn apply_to_effects(&self, effects: &mut Effects) {
+        if let Some((fg, bg)) = self.palette {
+            effects.fg = fg;
+            effects.bg = bg;
+        }
+    }
+
+    pub fn apply_to_audio(&self, audio: &mut AudioSettings) {
+        audio.waveform = self.waveform.unwrap_or(audio.waveform);
+        audio.sample_rate = self.sample_rate.unwrap_or(audio.sample_rate);
+        audio.buffer_samples = 
+            self.audio_buffer.unwrap_or(audio.buffer_samples);
+    }
+
+    // A mode replaces the quirks of the configuration with those of its
+    // platform, before the quirks given by name are switched.
+    pub fn apply_to_quirks(&self, settings: &mut QuirkSettings) {
+        if let Some(platform) = self.platform {
+            settings.set_platform(platform);
+        }
+        for &(ref name, on) in &self.quirks {
+            settings.quirks.set(name, on);
+        }
+    }
+
+}
+
+// Parse the command line, without the name of the program.
+pub fn parse_args<I>(args: I) -> Result<Options, OptionsError>
+    where I: IntoIterator<Item = String> {
+    let mut args = args.into_iter();
+    let mut options = Options::new();
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--mode" => options.platform =
+                Some(parse_with(&arg, args.next(), Platform::from_name)?),
+            "--quirk" => options.quirks.push(
+                parse_with(&arg, args.next(), parse_quirk)?),
+            "--seed" => options.seed =
+                Some(parse_with(&arg, args.next(), parse)?),
+            "--speed" => options.speed = Some(parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| {
+                    metro::is_valid_cycles_per_second(n)
+                }))?),
+            "--turbo" => options.turbo = Some(parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| {
+                    n >= 1.0 && n <= metro::MAX_SPEED
+                }))?),
+            "--run-ahead" => options.run_ahead = Some(parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| {
+                    n <= metro::MAX_RUN_AHEAD
+                }))?),
+            "--vsync" => options.vsync =
+                Some(parse_with(&arg, args.next(), parse_on_off)?),
+            "--scale" => options.scale = Some(parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| {
+                    n >= 1 && n <= window::MAX_SCALE
+                }))?),
+            "--fullscreen" => options.fullscreen = true,
+            "--palette" => options.palette =
+                Some(parse_with(&arg, args.next(), parse_palette)?),
+            "--waveform" => options.waveform =
+                Some(parse_with(&arg, args.next(), Waveform::from_name)?),
+            "--sample-rate" => options.sample_rate = Some(parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| {
+                    wav::is_valid_sample_rate(n)
+                }))?),
+            "--audio-buffer" => options.audio_buffer = Some(parse_with(
+                &arg, args.next(), |n| parse(n).map(wav::clamp_buffer_samples)
+            )?),
+            "--record-audio" => options.record_audio =
+                Some(parse_with(&arg, args.next(), |path| Some(path.into()))?),
+            "--demo" => options.demo =
+                Some(parse_with(&arg, args.next(), |dir| Some(dir.into()))?),
+            "--demo-secs" => options.demo_secs = parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| n >= 1))?,
+            "--compare" => options.compare = Some(parse_with(
+                &arg, args.next(), compare::parse_quirk_pair)?),
+            "--headless" => options.headless = true,
+            "--frames" => options.frames = Some(parse_with(
+                &arg, args.next(), |n| parse_if(n, |n| n >= 1))?),
+            "--config" => options.config =
+                Some(parse_with(&arg, args.next(), |path| Some(path.into()))?),
+            "--write-config" => options.write_config = true,
+            "--no-rom-db" => options.no_rom_db = true,
+            "--log-level" => options.log_level =
+                parse_with(&arg, args.next(), Level::from_name)?,
+            "--log-file" => options.log_file =
+                Some(parse_with(&arg, args.next(), |path| Some(path.into()))?),
+            _ if options.rom.is_none() && !arg.starts_with("--") =>
+                options.rom = Some(arg),
+            _ => return Err(OptionsError::Unexpected(arg)),
+        }
+    }
+    check_combination(&options)?;
+    Ok(options)
+}
+
+// Options that only make sense together, or not at all together.
+fn check_combination(options: &Options) -> Result<(), OptionsError> {
+    let given = |option: &'static str| match option {
+        "--demo" => options.demo.is_some(),
+        "--compare" => options.compare.is_some(),
+        "--mode" => options.platform.is_some(),
+        "--quirk" => !options.quirks.is_empty(),
+        "--fullscreen" => options.fullscreen,
+        "--record-audio" => options.record_audio.is_some(),
+        "--headless" => options.headless,
+        "--frames" => options.frames.is_some(),
+        "PATH_TO_ROM" => options.rom.is_some(),
+        _ => false,
+    };
+    let conflicts = [("--demo", "PATH_TO_ROM"), ("--demo", "--compare"),
+                     ("--compare", "--mode"), ("--compare", "--quirk"),
+                     ("--headless", "--fullscreen"),
+                     ("--headless", "--demo"), ("--headless", "--compare"),
+                     ("--headless", "--record-audio")];
+    for &(a, b) in &conflicts {
+        if given(a) && given(b) {
+            return Err(OptionsError::Conflict(a, b));
+        }
+    }
+    let requirements = [("--compare", "PATH_TO_ROM"),
+                        ("--headless", "PATH_TO_ROM"),
+                        ("--headless", "--frames"),
+                        ("--frames", "--headless")];
+    for &(a, b) in &requirements {
+        if given(a) && !given(b) {
+            return Err(OptionsError::Requires(a, b));
+        }
+    }
+    Ok(())
+}
+
+// The value of the option, parsed by the function.
+fn parse_with<T, F>(option: &str, value: Option<String>, f: F)
+                    -> Result<T, OptionsError>
+    where F: Fn(&str) -> Option<T> {
+    match value {
+        Some(value) => f(&value).ok_or_else(|| {
+            OptionsError::InvalidValue(option.into(), value.clone())
+        }),
+        None => Err(OptionsError::MissingValue(option.into())),
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Option<T> {
+    value.parse().ok()
+}
+
+// The value parsed, if it is one the option allows.
+fn parse_if<T: FromStr + Copy, F>(value: &str, valid: F) -> Option<T>
+    where F: Fn(T) -> bool {
+    parse(value).and_then(|n| if valid(n) {Some(n)} else {None})
+}
+
+fn parse_on_off(value: &str) -> Option<bool> {
+    match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+// A quirk switched by name, as in wrap_x=off.
+fn parse_quirk(value: &str) -> Option<(String, bool)> {
+    let mut parts = value.splitn(2, '=');
+    let name = parts.next().unwrap_or_default().trim();
+    let on = parts.next().and_then(|on| parse_on_off(on.trim()));
+    match on {
+        Some(on) if Quirks::default().set(name, on) =>
+            Some((name.into(), on)),
+        _ => None,
+    }
+}
+
+// The colours of pixels that are on and off, separated by a comma.
+fn parse_palette(value: &str) -> Option<((u8, u8, u8), (u8, u8, u8))> {
+    let colors: Vec<_> = value.split(',').map(effects::parse_color)
+                              .collect();
+    if colors.len() != 2 {
+        return None;
+    }
+    match (colors[0], colors[1]) {
+        (Some(fg), Some(bg)) => Some((fg, bg)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{parse_args, Options, OptionsError, Platform, QuirkSettings};
+    use super::super::config::{self, Config};
+    use super::super::effects::Effects;
+    use super::super::emu::{Emu, Quirks};
+    use super::super::log::Level;
+    use super::super::metro::ClockSettings;
+    use super::super::wav::{AudioSettings, Waveform};
+    use super::super::window::WindowSettings;
+
+    fn parse(args: &str) -> Result<Options, OptionsError> {
+        parse_args(args.split_whitespace().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_no_args() {
+        assert_eq!(Options::new(), parse("").unwrap());
+    }
+
+    #[test]
+    fn test_rom() {
+        assert_eq!(Some("pong.ch8".to_string()),
+                   parse("pong.ch8").unwrap().rom);
+        assert_eq!(Err(OptionsError::Unexpected("tetris.ch8".into())),
+                   parse("pong.ch8 tetris.ch8"));
+    }
+
+    #[test]
+    fn test_unknown_option() {
+        assert_eq!(Err(OptionsError::Unexpected("--colour".into())),
+                   parse("--colour red pong.ch8"));
+    }
+
+    #[test]
+    fn test_missing_value() {
+        assert_eq!(Err(OptionsError::MissingValue("--speed".into())),
+                   parse("--speed"));
+    }
+
+    #[test]
+    fn test_mode() {
+        assert_eq!(Some(Platform::Schip),
+                   parse("--mode schip").unwrap().platform);
+        assert_eq!(Some(Platform::XoChip),
+                   parse("--mode xochip").unwrap().platform);
+        assert_eq!(Err(OptionsError::InvalidValue("--mode".into(),
+                                                  "superchip".into())),
+                   parse("--mode superchip"));
+    }
+
+    #[test]
+    fn test_quirk() {
+        //when
+        let options = parse("--mode chip8 --quirk display_wait=off \
+                             --quirk wrap_x=off").unwrap();
+        //then
+        let mut settings = QuirkSettings::new();
+        options.apply_to_quirks(&mut settings);
+        assert_eq!(Some(Platform::Chip8), settings.platform);
+        assert_eq!(Quirks { display_wait: false, wrap_x: false,
+                            ..Quirks::cosmac_vip() }, settings.quirks);
+        assert!(parse("--quirk wrap_z=on").is_err());
+        assert!(parse("--quirk wrap_x=yes").is_err());
+        assert!(parse("--quirk wrap_x").is_err());
+    }
+
+    #[test]
+    fn test_without_mode_quirks_are_switched_on_top_of_the_current_ones() {
+        //given
+        let options = parse("--quirk jump_uses_vx=on").unwrap();
+        let mut settings = QuirkSettings::new();
+        settings.set_platform(Platform::Chip8);
+        //when
+        options.apply_to_quirks(&mut settings);
+        //then
+        assert_eq!(Quirks { jump_uses_vx: true, ..Quirks::cosmac_vip() },
+                   settings.quirks);
+    }
+
+    #[test]
+    fn test_speed() {
+        assert_eq!(Some(1000), parse("--speed 1000").unwrap().speed);
+        assert!(parse("--speed 0").is_err());
+        assert!(parse("--speed fast").is_err());
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(Some(8), parse("--scale 8").unwrap().scale);
+        assert!(parse("--scale 0").is_err());
+        assert!(parse("--scale 17").is_err());
+    }
+
+    #[test]
+    fn test_palette() {
+        assert_eq!(Some(((0xff, 0xb0, 0x00), (0, 0, 0))),
+                   parse("--palette ffb000,#000000").unwrap().palette);
+        assert!(parse("--palette ffb000").is_err());
+        assert!(parse("--palette ffb000,000000,ffffff").is_err());
+        assert!(parse("--palette amber,black").is_err());
+    }
+
+    #[test]
+    fn test_fullscreen() {
+        assert!(parse("--fullscreen").unwrap().fullscreen);
+        assert!(!parse("").unwrap().fullscreen);
+    }
+
+    #[test]
+    fn test_headless() {
+        //when
+        let options = parse("--headless --frames 60 pong.ch8").unwrap();
+        //then
+        assert!(options.headless);
+        assert_eq!(Some(60), options.frames);
+        assert_eq!(Err(OptionsError::Requires("--headless", "--frames")),
+                   parse("--headless pong.ch8"));
+        assert_eq!(Err(OptionsError::Requires("--frames", "--headless")),
+                   parse("--frames 60 pong.ch8"));
+        assert_eq!(Err(OptionsError::Requires("--headless", "PATH_TO_ROM")),
+                   parse("--headless --frames 60"));
+        assert!(parse("--headless --frames 0 pong.ch8").is_err());
+    }
+
+    #[test]
+    fn test_headless_conflicts_with_fullscreen() {
+        assert_eq!(Err(OptionsError::Conflict("--headless", "--fullscreen")),
+                   parse("--headless --fullscreen --frames 60 pong.ch8"));
+    }
+
+    #[test]
+    fn test_seed() {
+        assert_eq!(Some(42), parse("--seed 42").unwrap().seed);
+        assert!(parse("--seed -1").is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        assert_eq!(Some("test.toml".to_string()),
+                   parse("--config test.toml").unwrap().config);
+        assert!(parse("--config").is_err());
+    }
+
+    #[test]
+    fn test_existing_options() {
+        //when
+        let options = parse("--vsync on --waveform sine --sample-rate 48000 \
+                             --turbo 4 --run-ahead 1").unwrap();
+        //then
+        assert_eq!(Some(true), options.vsync);
+        assert_eq!(Some(Waveform::Sine), options.waveform);
+        assert_eq!(Some(48000), options.sample_rate);
+        assert_eq!(Some(4.0), options.turbo);
+        assert_eq!(Some(1), options.run_ahead);
+        assert!(parse("--vsync maybe").is_err());
+        assert!(parse("--run-ahead 3").is_err());
+    }
+
+    #[test]
+    fn test_demo_and_compare() {
+        assert_eq!(Some("roms".to_string()),
+                   parse("--demo roms --demo-secs 5").unwrap().demo);
+        assert_eq!(Err(OptionsError::Conflict("--demo", "PATH_TO_ROM")),
+                   parse("--demo roms pong.ch8"));
+        assert_eq!(Err(OptionsError::Requires("--compare", "PATH_TO_ROM")),
+                   parse("--compare default,vip"));
+        assert_eq!(Err(OptionsError::Conflict("--compare", "--mode")),
+                   parse("--compare default,vip --mode schip pong.ch8"));
+    }
+
+    #[test]
+    fn test_quirk_settings_from_config() {
+        //given
+        let config = Config::parse("[quirks]\n\
+                                    mode = xochip\n\
+                                    wrap_y = false\n").unwrap();
+        //when
+        let settings = QuirkSettings::from_config(&config).unwrap();
+        //then
+        assert_eq!(Some(Platform::XoChip), settings.platform);
+        assert_eq!(Quirks { wrap_y: false, ..Quirks::modern() },
+                   settings.quirks);
+        let mut emu = Emu::new();
+        settings.apply(&mut emu);
+        assert!(emu.xo_chip);
+        assert_eq!(settings.quirks, emu.quirks);
+        for bad in &["mode = superchip", "wrap_z = true", "wrap_y = maybe"] {
+            let config = Config::parse(&format!("[quirks]\n{}", bad))
+                .unwrap();
+            assert!(QuirkSettings::from_config(&config).is_err());
+        }
+    }
+
+    #[test]
+    fn test_quirk_settings_round_trip() {
+        //given
+        let mut settings = QuirkSettings::new();
+        settings.set_platform(Platform::Schip);
+        settings.quirks.display_wait = true;
+        //when
+        let text = config::set_settings("", "quirks", &settings.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(settings, QuirkSettings::from_config(&config).unwrap());
+        assert_eq!(QuirkSettings::new(), QuirkSettings::from_config(
+            &Config::parse(&config::set_settings(
+                "", "quirks", &QuirkSettings::new().to_config())).unwrap())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_command_line_takes_precedence_over_configuration() {
+        //given
+        let config = Config::parse("[clock]\n\
+                                    cycles_per_second = 1000\n\
+                                    run_ahead = 1\n\
+                                    [window]\n\
+                                    scale = 4\n\
+                                    vsync = true\n\
+                                    [audio]\n\
+                                    waveform = sine\n\
+                                    [quirks]\n\
+                                    mode = schip\n\
+                                    wrap_x = false\n").unwrap();
+        let options = parse("--speed 2000 --scale 8 --palette 000000,ffffff \
+                             --quirk wrap_y=off").unwrap();
+        let mut clock = ClockSettings::from_config(&config).unwrap();
+        let mut window = WindowSettings::from_config(&config).unwrap();
+        let mut effects = Effects::from_config(&config).unwrap();
+        let mut audio = AudioSettings::from_config(&config).unwrap();
+        let mut quirks = QuirkSettings::from_config(&config).unwrap();
+        //when
+        options.apply_to_clock(&mut clock);
+        options.apply_to_window(&mut window);
+        options.apply_to_effects(&mut effects);
+        options.apply_to_audio(&mut audio);
+        options.apply_to_quirks(&mut quirks);
+        //then
+        assert_eq!((2000, 1), (clock.cycles_per_second, clock.run_ahead));
+        assert_eq!((8, true), (window.scale, window.vsync));
+        assert_eq!(8.0, window.turbo);
+        assert_eq!(((0, 0, 0), (0xff, 0xff, 0xff)), (effects.fg, effects.bg));
+        assert_eq!(Waveform::Sine, audio.waveform);
+        assert_eq!(Some(Platform::Schip), quirks.platform);
+        assert_eq!(Quirks { wrap_x: false, wrap_y: false, ..Quirks::schip() },
+                   quirks.quirks);
+    }
+
+    #[test]
+    fn test_mode_replaces_quirks_of_configuration() {
+        //given
+        let config = Config::parse("[quirks]\nwrap_x = false").unwrap();
+        let mut quirks = QuirkSettings::from_config(&config).unwrap();
+        //when
+        parse("--mode chip8").unwrap().apply_to_quirks(&mut quirks);
+        //then
+        assert_eq!(Quirks::cosmac_vip(), quirks.quirks);
+    }
+
+    #[test]
+    fn test_write_config() {
+        assert!(parse("--write-config").unwrap().write_config);
+        assert!(!parse("").unwrap().write_config);
+    }
+
+    #[test]
+    fn test_no_rom_db() {
+        assert!(parse("--no-rom-db game.ch8").unwrap().no_rom_db);
+        assert!(!parse("game.ch8").unwrap().no_rom_db);
+    }
+
+    #[test]
+    fn test_log_options() {
+        let options = parse("--log-level trace --log-file chip8.log").unwrap();
+        assert_eq!(Level::Trace, options.log_level);
+        assert_eq!(Some("chip8.log".to_string()), options.log_file);
+        assert_eq!(Level::Warn, parse("").unwrap().log_level);
+        assert_eq!(Err(OptionsError::InvalidValue("--log-level".to_string(),
+                                                  "loud".to_string())),
+                   parse("--log-level loud"));
+    }
+
+}
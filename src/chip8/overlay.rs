@@ -0,0 +1,285 @@
+// Indicators drawn on top of the gfx, kept free of rendering so that they
+// can be tested.
+
+use super::metro::{FrameTiming, TIMING_FRAMES};
+use time::Duration;
+
+// The keys of the hexadecimal keypad as laid out on the COSMAC VIP, row
+// by row.
+pub const KEYPAD_LAYOUT: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xc],
+                                         [0x4, 0x5, 0x6, 0xd],
+                                         [0x7, 0x8, 0x9, 0xe],
+                                         [0xa, 0x0, 0xb, 0xf]];
+
+// A key of the keypad overlay, at a column and row of the layout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeypadCell {
+    pub key: u8,
+    pub col: usize,
+    pub row: usize,
+    // Whether the emulator sees the key as held down.
+    pub pressed: bool,
+}
+
+impl KeypadCell {
+
+    // The hexadecimal digit drawn on the key.
+    pub fn label(&self) -> char {
+        ::std::char::from_digit(self.key as u32, 16).unwrap()
+    }
+
+}
+
+// The cells of the keypad overlay for the key states, which are those the 
+// emulator sees, row by row.
+pub fn keypad_cells(keys: &[bool; 16]) -> Vec<KeypadCell> {
+    let mut cells = Vec::with_capacity(16);
+    for (row, keys_of_row) in KEYPAD_LAYOUT.iter().enumerate() {
+        for (col, &key) in keys_of_row.iter().enumerate() {
+            cells.push(KeypadCell { key: key, col: col, row: row, 
+                                    pressed: keys[key as usize] });
+        }
+    }
+    cells
+}
+
+// The keypad as text, a row of the layout per line, with the keys held 
+// down in brackets, e.g. "[1] 2  3  C " with only 1 held. For debugging 
+// input alongside a dump of the screen.
+pub fn keypad_text(keys: &[bool; 16]) -> String {
+    let mut text = String::new();
+    for cell in keypad_cells(keys) {
+        let label = cell.label().to_uppercase();
+        if cell.pressed {
+            text.push_str(&format!("[{}]", label));
+        } else {
+            text.push_str(&format!(" {} ", label));
+        }
+        if cell.col == 3 {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+// The fewest frames the sound indicator stays visible for, so that even a
+// beep lasting a single timer tick can be seen.
+pub const MIN_SOUND_FRAMES: u32 = 6;
+
+// Decides when to show the speaker in the corner of the window, for those
+// playing without sound. Fed the beep starting and stopping, and asked 
+// once per frame whether the speaker is visible.
+pub struct SoundIndicator {
+    beeping: bool,
+    // Frames for which the speaker stays visible regardless.
+    hold: u32,
+}
+
+impl SoundIndicator {
+
+    pub fn new() -> Self {
+        SoundIndicator { beeping: false, hold: 0 }
+    }
+
+    pub fn beep(&mut self, on: bool) {
+        if on && !self.beeping {
+            self.hold = MIN_SOUND_FRAMES;
+        }
+        self.beeping = on;
+    }
+
+    // Whether the speaker is visible for the next frame.
+    pub fn frame(&mut self) -> bool {
+        let visible = self.beeping || self.hold > 0;
+        self.hold = self.hold.saturating_sub(1);
+        visible
+    }
+
+}
+
+// The time a frame may take at 60 frames per second. The timing graph
+// spans twice that, so that the budget is marked halfway up.
+pub const FRAME_BUDGET_NS: i64 = 1_000_000_000 / 60;
+
+// A bar of the timing graph. The heights, in pixels, are those of its 
+// parts stacked from the bottom up: emulating, presenting, then sleeping.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GraphBar {
+    pub x: usize,
+    pub emulate: usize,
+    pub present: usize,
+    pub sleep: usize,
+}
+
+// The timing graph laid out in a strip along the bottom of the window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimingGraph {
+    pub bars: Vec<GraphBar>,
+    pub bar_w: usize,
+    // Height of the line marking the frame budget, from the bottom.
+    pub budget_h: usize,
+}
+
+// Lay out the timings of the frames, oldest first, as bars scale pixels
+// wide in a strip height pixels high. The newest frame is on the right of
+// room for TIMING_FRAMES bars. Frames over twice the budget are cut off at
+// the top of the strip.
+pub fn timing_graph(samples: &[FrameTiming], scale: usize, 
+                    height: usize) -> TimingGraph {
+    let span = 2 * FRAME_BUDGET_NS;
+    let to_px = |time: Duration| {
+        let ns = time.num_nanoseconds().unwrap_or(i64::max_value()).max(0);
+        (ns.min(span) as u64 * height as u64 / span as u64) as usize
+    };
+    let skip = samples.len().saturating_sub(TIMING_FRAMES);
+    let first_x = TIMING_FRAMES - (samples.len() - skip);
+    let bars = samples[skip..].iter().enumerate().map(|(i, sample)| {
+        // Rounding the tops of the parts, rather than their heights, keeps
+        // the bar as high as the whole frame.
+        let emulate = to_px(sample.emulate);
+        let present = to_px(sample.emulate + sample.present);
+        let sleep = to_px(sample.emulate + sample.present + sample.sleep);
+        GraphBar { x: (first_x + i) * scale, emulate: emulate, 
+                   present: present - emulate, sleep: sleep - present }
+    }).collect();
+    TimingGraph { bars: bars, bar_w: scale, 
+                  budget_h: to_px(Duration::nanoseconds(FRAME_BUDGET_NS)) }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{keypad_cells, keypad_text, KeypadCell, SoundIndicator};
+    use super::MIN_SOUND_FRAMES;
+    use super::{timing_graph, GraphBar};
+    use super::super::metro::{FrameTiming, TIMING_FRAMES};
+    use time::Duration;
+
+    #[test]
+    fn test_keypad_layout() {
+        //when
+        let cells = keypad_cells(&[false; 16]);
+        //then
+        let labels: String = cells.iter().map(|cell| cell.label()).collect();
+        assert_eq!("123c456d789ea0bf", labels);
+        assert_eq!(KeypadCell { key: 0x0, col: 1, row: 3, pressed: false },
+                   cells[13]);
+        assert!(cells.iter().all(|cell| !cell.pressed));
+    }
+
+    #[test]
+    fn test_keypad_highlights_pressed_keys() {
+        let mut keys = [false; 16];
+        //given
+        keys[0x0] = true;
+        keys[0xc] = true;
+        //when
+        let pressed: Vec<(usize, usize)> = keypad_cells(&keys).iter()
+            .filter(|cell| cell.pressed)
+            .map(|cell| (cell.col, cell.row))
+            .collect();
+        //then
+        assert_eq!(vec![(3, 0), (1, 3)], pressed);
+    }
+
+    #[test]
+    fn test_keypad_text_brackets_held_keys() {
+        let mut keys = [false; 16];
+        //given
+        keys[0x1] = true;
+        keys[0x5] = true;
+        keys[0xa] = true;
+        //when
+        let text = keypad_text(&keys);
+        //then
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(vec!["[1] 2  3  C ", " 4 [5] 6  D ", " 7  8  9  E ",
+                        "[A] 0  B  F "], rows);
+    }
+
+    // Feed the indicator a beep event, if any, before each frame.
+    fn run(events: &[Option<bool>]) -> Vec<bool> {
+        let mut indicator = SoundIndicator::new();
+        events.iter().map(|event| {
+            if let Some(on) = *event { indicator.beep(on); }
+            indicator.frame()
+        }).collect()
+    }
+
+    #[test]
+    fn test_short_beep_is_visible_for_minimum_frames() {
+        //when
+        let visible = run(&[None, Some(true), Some(false), None, None, None,
+                            None, None, None]);
+        //then
+        assert_eq!(vec![false, true, true, true, true, true, true, false,
+                        false], visible);
+        assert_eq!(MIN_SOUND_FRAMES as usize,
+                   visible.iter().filter(|&&v| v).count());
+    }
+
+    #[test]
+    fn test_long_beep_is_visible_until_it_stops() {
+        let mut events = vec![Some(true)];
+        events.extend(vec![None; 9]);
+        events.push(Some(false));
+        events.push(None);
+        //when
+        let visible = run(&events);
+        //then
+        assert!(visible[..10].iter().all(|&v| v));
+        assert_eq!(&[false, false], &visible[10..]);
+    }
+
+    #[test]
+    fn test_repeated_start_does_not_extend_hold() {
+        let visible = run(&[Some(true), Some(true), Some(false), None, None,
+                            None, None]);
+        assert_eq!(vec![true, true, true, true, true, true, false], visible);
+    }
+
+    fn timing(emulate_ms: i64, present_ms: i64, sleep_ms: i64) -> FrameTiming {
+        FrameTiming { emulate: Duration::milliseconds(emulate_ms),
+                      present: Duration::milliseconds(present_ms),
+                      sleep: Duration::milliseconds(sleep_ms) }
+    }
+
+    #[test]
+    fn test_timing_graph_scales_bars() {
+        // 100 pixels span 33.3ms, so a pixel is a third of a millisecond.
+        let samples = [timing(3, 6, 7), timing(0, 0, 0)];
+        //when
+        let graph = timing_graph(&samples, 2, 100);
+        //then
+        assert_eq!(2, graph.bar_w);
+        assert_eq!(50, graph.budget_h);
+        assert_eq!(vec![GraphBar { x: 236, emulate: 9, present: 18, 
+                                   sleep: 21 },
+                        GraphBar { x: 238, emulate: 0, present: 0, 
+                                   sleep: 0 }],
+                   graph.bars);
+    }
+
+    #[test]
+    fn test_timing_graph_cuts_off_slow_frames() {
+        //when
+        let graph = timing_graph(&[timing(20, 20, 20)], 1, 100);
+        //then
+        let bar = graph.bars[0];
+        assert_eq!((60, 40, 0), (bar.emulate, bar.present, bar.sleep));
+    }
+
+    #[test]
+    fn test_timing_graph_keeps_newest_frames() {
+        let mut samples = vec![timing(1, 0, 0); TIMING_FRAMES];
+        samples.push(timing(2, 0, 0));
+        //when
+        let graph = timing_graph(&samples, 3, 30);
+        //then
+        assert_eq!(TIMING_FRAMES, graph.bars.len());
+        assert_eq!(0, graph.bars[0].x);
+        let last = graph.bars[TIMING_FRAMES - 1];
+        assert_eq!(((TIMING_FRAMES - 1) * 3, 1), (last.x, last.emulate));
+    }
+
+}
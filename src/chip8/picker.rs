@@ -0,0 +1,146 @@
+use super::recent::RecentRoms;
+use std::path::{Path, PathBuf};
+
+// Number of recent roms that can be launched with the number keys.
+pub const NUM_SHORTCUTS: usize = 9;
+
+// An entry of the rom picker.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PickerItem {
+    pub path: PathBuf,
+    // Number key that launches the rom, if any.
+    pub shortcut: Option<usize>,
+    // Roms that no longer exist are listed, greyed out, but can not be
+    // launched.
+    pub available: bool,
+}
+
+impl PickerItem {
+
+    // The line shown for the entry, e.g. "1 PONG.ch8".
+    pub fn label(&self) -> String {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        match self.shortcut {
+            Some(n) => format!("{} {}", n, name),
+            None => format!("  {}", name),
+        }
+    }
+
+}
+
+// Lists roms to choose from: the recently loaded roms at the top, followed
+// by the other roms in the directory of the running rom.
+pub struct Picker {
+    items: Vec<PickerItem>,
+    selected: usize,
+}
+
+impl Picker {
+
+    pub fn new(recent: &RecentRoms, others: &[PathBuf]) -> Self {
+        let mut items: Vec<PickerItem> = recent.roms().into_iter()
+            .enumerate()
+            .map(|(i, rom)| PickerItem {
+                path: rom.path,
+                shortcut: if i < NUM_SHORTCUTS {Some(i + 1)} else {None},
+                available: rom.exists,
+            })
+            .collect();
+        for path in others {
+            let known = items.iter().any(|item| same_file(&item.path, path));
+            if !known {
+                items.push(PickerItem { path: path.clone(), shortcut: None,
+                                        available: true });
+            }
+        }
+        let selected = items.iter().position(|item| item.available)
+                                   .unwrap_or(0);
+        Picker { items: items, selected: selected }
+    }
+
+    pub fn items(&self) -> &[PickerItem] {
+        &self.items
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // Move the selection up (negative) or down, stopping at either end.
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.items.is_empty() { return; }
+        let last = self.items.len() as isize - 1;
+        let selected = (self.selected as isize + delta).max(0).min(last);
+        self.selected = selected as usize;
+    }
+
+    // The rom to launch for the selected entry.
+    pub fn choose_selected(&self) -> Option<&Path> {
+        match self.items.get(self.selected) {
+            Some(item) if item.available => Some(&*item.path),
+            _ => None,
+        }
+    }
+
+    // The rom to launch for the number key.
+    pub fn choose_shortcut(&self, n: usize) -> Option<&Path> {
+        self.items.iter()
+            .find(|item| item.shortcut == Some(n) && item.available)
+            .map(|item| &*item.path)
+    }
+
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b || (a.exists() && a.canonicalize().ok() == b.canonicalize().ok())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::Picker;
+    use super::super::recent::RecentRoms;
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_recent_roms_come_first_with_shortcuts() {
+        let dir = env::temp_dir().join("chip8_picker");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let pong = dir.join("pong.ch8");
+        let brix = dir.join("brix.ch8");
+        File::create(&pong).unwrap();
+        File::create(&brix).unwrap();
+        let mut recent = RecentRoms::new();
+        //given
+        recent.add(Path::new("/no/such/tank.ch8"));
+        recent.add(&pong);
+        //when
+        let picker = Picker::new(&recent, &[brix.clone(), pong.clone()]);
+        //then
+        let labels: Vec<String> = picker.items().iter()
+            .map(|item| item.label()).collect();
+        assert_eq!(vec!["1 pong.ch8", "2 tank.ch8", "  brix.ch8"], labels);
+        assert!(!picker.items()[1].available);
+        assert!(picker.choose_shortcut(1).unwrap().ends_with("pong.ch8"));
+        assert_eq!(None, picker.choose_shortcut(2));
+        assert_eq!(None, picker.choose_shortcut(3));
+    }
+
+    #[test]
+    fn test_move_selection() {
+        let others = [PathBuf::from("/a.ch8"), PathBuf::from("/b.ch8")];
+        let mut picker = Picker::new(&RecentRoms::new(), &others);
+        assert_eq!(0, picker.selected());
+        //when
+        picker.move_selection(5);
+        //then
+        assert_eq!(1, picker.selected());
+        assert_eq!(Some(Path::new("/b.ch8")), picker.choose_selected());
+        picker.move_selection(-5);
+        assert_eq!(0, picker.selected());
+    }
+
+}
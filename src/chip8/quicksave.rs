@@ -0,0 +1,252 @@
+use super::config;
+use super::emu::{self, Emu, StateError};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Number of quicksave slots per rom.
+pub const NUM_SLOTS: usize = 4;
+
+// Reasons why a quicksave slot can not be saved or loaded.
+#[derive(Debug)]
+pub enum QuickSaveError {
+    // Saving or loading would make the recording being made unreplayable.
+    Recording,
+    // Nothing was saved in the slot.
+    Empty(usize),
+    // The slot holds a save state of another rom.
+    Mismatch(usize),
+    // The slot file could not be read or written.
+    Io(usize, io::Error),
+    // The slot file is not a valid save state.
+    State(usize, StateError),
+}
+
+impl fmt::Display for QuickSaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            QuickSaveError::Recording =>
+                write!(f, "Quicksaves are disabled while recording"),
+            QuickSaveError::Empty(slot) => write!(f, "Slot {} is empty", slot),
+            QuickSaveError::Mismatch(slot) =>
+                write!(f, "Slot {} was saved for another rom", slot),
+            QuickSaveError::Io(slot, ref err) =>
+                write!(f, "Slot {}: {}", slot, err),
+            QuickSaveError::State(slot, ref err) =>
+                write!(f, "Slot {}: {}", slot, err),
+        }
+    }
+}
+
+// The file of a quicksave slot, numbered from 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slot {
+    pub number: usize,
+    pub path: PathBuf,
+}
+
+impl Slot {
+
+    pub fn save(&self, emu: &Emu) -> Result<(), QuickSaveError> {
+        let io_err = |err| QuickSaveError::Io(self.number, err);
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(&io_err)?;
+        }
+        emu.save_state_to_path(&self.path).map_err(&io_err)
+    }
+
+    // Restore the state saved in the slot. The emulator is left as it was
+    // if the slot is empty, invalid or holds the state of another rom.
+    pub fn load(&self, emu: &mut Emu) -> Result<(), QuickSaveError> {
+        let mut state = Vec::new();
+        match File::open(&self.path) {
+            Ok(mut file) => file.read_to_end(&mut state).map(|_| ()),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound =>
+                return Err(QuickSaveError::Empty(self.number)),
+            Err(err) => Err(err),
+        }.map_err(|err| QuickSaveError::Io(self.number, err))?;
+        let mut saved = Emu::new();
+        saved.load_state(&state)
+             .map_err(|err| QuickSaveError::State(self.number, err))?;
+        if saved.rom() != emu.rom() {
+            return Err(QuickSaveError::Mismatch(self.number));
+        }
+        emu.load_state(&state)
+           .map_err(|err| QuickSaveError::State(self.number, err))
+    }
+
+}
+
+// The file of the slot for the rom with the given hash, e.g. 
+// "0123456789abcdef-1.state".
+pub fn slot_path(dir: &Path, rom_hash: u64, slot: usize) -> PathBuf {
+    dir.join(format!("{:016x}-{}.state", rom_hash, slot))
+}
+
+// Keeps track of the selected quicksave slot of the running rom.
+pub struct QuickSaves {
+    dir: PathBuf,
+    rom_hash: u64,
+    slot: usize,
+    recording: bool,
+}
+
+impl QuickSaves {
+
+    pub fn new(dir: PathBuf, rom: &[u8]) -> Self {
+        QuickSaves { dir: dir, rom_hash: emu::fnv1a(rom), slot: 1,
+                     recording: false }
+    }
+
+    // Where the slots are kept unless told otherwise: next to the 
+    // configuration file, or in the current directory if there is none.
+    pub fn default_dir() -> PathBuf {
+        config::config_dir().unwrap_or_else(|| PathBuf::from("."))
+                            .join("saves")
+    }
+
+    // Switch to the slots of another rom, starting at the first one.
+    pub fn set_rom(&mut self, rom: &[u8]) {
+        self.rom_hash = emu::fnv1a(rom);
+        self.slot = 1;
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    // Select the next slot, going round from the last slot to the first.
+    pub fn next_slot(&mut self) -> usize {
+        self.slot = self.slot % NUM_SLOTS + 1;
+        self.slot
+    }
+
+    // Select the previous slot, going round from the first slot to the 
+    // last.
+    pub fn previous_slot(&mut self) -> usize {
+        self.slot = (self.slot + NUM_SLOTS - 2) % NUM_SLOTS + 1;
+        self.slot
+    }
+
+    // Quicksaves can not be used while a recording is being made, since 
+    // replaying it would not restore the same state.
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording = recording;
+    }
+
+    // The selected slot, or the reason it can not be used right now.
+    pub fn current(&self) -> Result<Slot, QuickSaveError> {
+        if self.recording {
+            return Err(QuickSaveError::Recording);
+        }
+        Ok(Slot { number: self.slot, 
+                  path: slot_path(&self.dir, self.rom_hash, self.slot) })
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{slot_path, QuickSaveError, QuickSaves, NUM_SLOTS};
+    use super::super::emu::Emu;
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // Create an empty scratch directory for the test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8_quicksave_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn emu_with_rom(rom: Vec<u8>) -> Emu {
+        let mut emu = Emu::new();
+        emu.load_rom(rom);
+        emu
+    }
+
+    #[test]
+    fn test_slot_path() {
+        let dir = Path::new("/saves");
+        assert_eq!(Path::new("/saves/00000000000000ab-3.state"),
+                   slot_path(dir, 0xab, 3));
+        let pong = QuickSaves::new(dir.to_path_buf(), &[0x12, 0x00]);
+        let brix = QuickSaves::new(dir.to_path_buf(), &[0x12, 0x02]);
+        assert!(pong.current().unwrap().path != 
+                brix.current().unwrap().path);
+    }
+
+    #[test]
+    fn test_slots_go_round() {
+        let mut saves = QuickSaves::new(PathBuf::from("/saves"), &[]);
+        assert_eq!(1, saves.slot());
+        //when
+        let slots: Vec<usize> = (0..NUM_SLOTS).map(|_| saves.next_slot())
+                                              .collect();
+        //then
+        assert_eq!(vec![2, 3, 4, 1], slots);
+        assert_eq!(4, saves.previous_slot());
+        assert_eq!(3, saves.previous_slot());
+        assert!(saves.current().unwrap().path.ends_with(
+            format!("{:016x}-3.state", 0xcbf29ce484222325u64)));
+        saves.set_rom(&[0x00]);
+        assert_eq!(1, saves.slot());
+    }
+
+    #[test]
+    fn test_slots_can_not_be_used_while_recording() {
+        let mut saves = QuickSaves::new(PathBuf::from("/saves"), &[]);
+        //when
+        saves.set_recording(true);
+        //then
+        match saves.current() {
+            Err(QuickSaveError::Recording) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        saves.set_recording(false);
+        assert_eq!(1, saves.current().unwrap().number);
+    }
+
+    #[test]
+    fn test_save_and_load_slot() {
+        let rom = vec![0x60, 0x05, 0x12, 0x02];
+        let saves = QuickSaves::new(scratch_dir("roundtrip"), &rom);
+        let slot = saves.current().unwrap();
+        let mut emu = emu_with_rom(rom.clone());
+        //given
+        emu.run_frame(3);
+        slot.save(&emu).unwrap();
+        let saved = emu_with_rom(rom);
+        let mut restored = emu_with_rom(vec![0x60, 0x05, 0x12, 0x02]);
+        //when
+        slot.load(&mut restored).unwrap();
+        //then
+        assert_eq!(emu, restored);
+        assert!(saved != restored);
+    }
+
+    #[test]
+    fn test_load_missing_or_mismatched_slot() {
+        let dir = scratch_dir("mismatch");
+        let pong = emu_with_rom(vec![0x12, 0x00]);
+        let mut brix = emu_with_rom(vec![0x12, 0x02]);
+        let slot = QuickSaves::new(dir, brix.rom()).current().unwrap();
+        //when
+        match slot.load(&mut brix) {
+            //then
+            Err(QuickSaveError::Empty(1)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        // e.g. a slot file copied over from another rom.
+        slot.save(&pong).unwrap();
+        match slot.load(&mut brix) {
+            Err(QuickSaveError::Mismatch(1)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(&[0x12, 0x02], brix.rom());
+    }
+
+}
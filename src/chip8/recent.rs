@@ -0,0 +1,193 @@
+use super::config;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Number of roms remembered.
+pub const MAX_RECENT: usize = 10;
+// The first line of the file, naming the format and its version.
+const HEADER: &'static str = "chip8-recent 1";
+
+// A remembered rom, along with whether its file still exists.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecentRom {
+    pub path: PathBuf,
+    pub exists: bool,
+}
+
+// The roms loaded most recently, most recent first. Stored in a file with
+// a header line followed by one path per line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecentRoms {
+    paths: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+
+    pub fn new() -> Self {
+        RecentRoms { paths: Vec::new() }
+    }
+
+    // Where the list is kept, next to the configuration file.
+    pub fn default_path() -> Option<PathBuf> {
+        config::config_dir().map(|dir| dir.join("recent.txt"))
+    }
+
+    // Read the list from file. A missing file, or one written in another
+    // format, gives an empty list.
+    pub fn load(path: &Path) -> Self {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .ok()
+            .and_then(|_| RecentRoms::parse(&text))
+            .unwrap_or_else(RecentRoms::new)
+    }
+
+    // Write the list to file, leaving out roms that no longer exist.
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        self.prune(|rom| rom.exists());
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        File::create(path)?.write_all(self.to_text().as_bytes())
+    }
+
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next().map(|line| line.trim()) != Some(HEADER) {
+            return None;
+        }
+        let mut recent = RecentRoms::new();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            if recent.paths.len() < MAX_RECENT {
+                recent.paths.push(PathBuf::from(line));
+            }
+        }
+        Some(recent)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+        for path in &self.paths {
+            text.push_str(&format!("{}\n", path.display()));
+        }
+        text
+    }
+
+    // Remember the rom as the most recent one. A rom that was already
+    // remembered moves to the front, and the oldest rom is forgotten once
+    // there are too many.
+    pub fn add(&mut self, path: &Path) {
+        let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        self.paths.retain(|p| *p != path);
+        self.paths.insert(0, path);
+        self.paths.truncate(MAX_RECENT);
+    }
+
+    // Forget the roms for which keep returns false.
+    pub fn prune<F>(&mut self, keep: F) where F: Fn(&Path) -> bool {
+        self.paths.retain(|path| keep(path));
+    }
+
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    // The remembered roms, most recent first, noting which still exist.
+    pub fn roms(&self) -> Vec<RecentRom> {
+        self.paths.iter()
+            .map(|path| RecentRom { path: path.clone(), exists: path.exists() })
+            .collect()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{RecentRoms, MAX_RECENT};
+    use std::env;
+    use std::fs::{self, File};
+    use std::path::{Path, PathBuf};
+
+    fn paths(recent: &RecentRoms) -> Vec<&str> {
+        recent.paths().iter().map(|path| path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_add_puts_most_recent_first_without_duplicates() {
+        let mut recent = RecentRoms::new();
+        //when
+        recent.add(Path::new("/no/such/pong.ch8"));
+        recent.add(Path::new("/no/such/brix.ch8"));
+        recent.add(Path::new("/no/such/pong.ch8"));
+        //then
+        assert_eq!(vec!["/no/such/pong.ch8", "/no/such/brix.ch8"],
+                   paths(&recent));
+    }
+
+    #[test]
+    fn test_add_forgets_oldest() {
+        let mut recent = RecentRoms::new();
+        //when
+        for i in 0..MAX_RECENT + 2 {
+            recent.add(&PathBuf::from(format!("/no/such/{}.ch8", i)));
+        }
+        //then
+        assert_eq!(MAX_RECENT, recent.paths().len());
+        assert_eq!("/no/such/11.ch8", paths(&recent)[0]);
+        assert_eq!("/no/such/2.ch8", paths(&recent)[MAX_RECENT - 1]);
+    }
+
+    #[test]
+    fn test_prune() {
+        let mut recent = RecentRoms::new();
+        //given
+        recent.add(Path::new("/no/such/pong.ch8"));
+        recent.add(Path::new("/no/such/brix.ch8"));
+        //when
+        recent.prune(|path| path.ends_with("pong.ch8"));
+        //then
+        assert_eq!(vec!["/no/such/pong.ch8"], paths(&recent));
+    }
+
+    #[test]
+    fn test_text_format() {
+        let mut recent = RecentRoms::new();
+        //given
+        recent.add(Path::new("/no/such/pong.ch8"));
+        recent.add(Path::new("/no/such/brix.ch8"));
+        //when
+        let text = recent.to_text();
+        //then
+        assert_eq!("chip8-recent 1\n/no/such/brix.ch8\n/no/such/pong.ch8\n",
+                   text);
+        assert_eq!(Some(recent), RecentRoms::parse(&text));
+        assert_eq!(None, RecentRoms::parse("chip8-recent 2\n/a.ch8\n"));
+        assert_eq!(None, RecentRoms::parse(""));
+    }
+
+    #[test]
+    fn test_save_prunes_missing_roms() {
+        let dir = env::temp_dir().join("chip8_recent_save");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let pong = dir.join("pong.ch8");
+        File::create(&pong).unwrap();
+        let mut recent = RecentRoms::new();
+        //given
+        recent.add(&pong);
+        recent.add(&dir.join("missing.ch8"));
+        let roms = recent.roms();
+        assert!(!roms[0].exists);
+        assert!(roms[1].exists);
+        //when
+        recent.save(&dir.join("recent.txt")).unwrap();
+        //then
+        let loaded = RecentRoms::load(&dir.join("recent.txt"));
+        assert_eq!(1, loaded.paths().len());
+        assert!(loaded.paths()[0].ends_with("pong.ch8"));
+    }
+
+}
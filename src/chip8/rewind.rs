@@ -0,0 +1,208 @@
+use std::collections::VecDeque;
+
+// Number of frames kept for rewinding: ten seconds at 60 frames per second.
+pub const REWIND_FRAMES: usize = 600;
+
+// Where the save states to rewind through come from.
+pub trait History {
+    // Take the most recent state, if any are left.
+    fn pop(&mut self) -> Option<Vec<u8>>;
+    // Start or stop keeping the states of new frames.
+    fn set_capturing(&mut self, capturing: bool);
+}
+
+// Keeps the save states of the most recent frames, dropping the oldest once
+// full.
+pub struct RewindBuffer {
+    states: VecDeque<Vec<u8>>,
+    capacity: usize,
+    capturing: bool,
+}
+
+impl RewindBuffer {
+
+    pub fn new(capacity: usize) -> Self {
+        RewindBuffer { states: VecDeque::with_capacity(capacity),
+                       capacity: capacity, capturing: true }
+    }
+
+    // Keep the state of a frame. Ignored while not capturing.
+    pub fn push(&mut self, state: Vec<u8>) {
+        if !self.capturing || self.capacity == 0 {
+            return;
+        }
+        if self.states.len() == self.capacity {
+            self.states.pop_front();
+        }
+        self.states.push_back(state);
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    // Forget every state, e.g. when another rom is loaded.
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+}
+
+impl History for RewindBuffer {
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        self.states.pop_back()
+    }
+
+    fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+    }
+
+}
+
+// What the ui does with a presented frame.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RewindFrame {
+    // Not rewinding, emulation carries on.
+    Run,
+    // Restore the state, one frame further back.
+    Restore(Vec<u8>),
+    // Rewinding, but there is nothing further back. The oldest state
+    // stays on screen until the key is released.
+    Exhausted,
+}
+
+// Runs the game backwards while the rewind key is held. Capturing stops
+// while rewinding, so that rewinding itself can not be rewound, and picks
+// up again from wherever rewinding stopped.
+pub struct Rewinder {
+    rewinding: bool,
+}
+
+impl Rewinder {
+
+    pub fn new() -> Self {
+        Rewinder { rewinding: false }
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    // The rewind key was pressed (true) or released. Returns true if this
+    // started or stopped rewinding; repeated presses change nothing.
+    pub fn key<H: History>(&mut self, history: &mut H, held: bool) -> bool {
+        if held == self.rewinding {
+            return false;
+        }
+        self.rewinding = held;
+        history.set_capturing(!held);
+        true
+    }
+
+    // Called once per presented frame.
+    pub fn frame<H: History>(&mut self, history: &mut H) -> RewindFrame {
+        if !self.rewinding {
+            return RewindFrame::Run;
+        }
+        match history.pop() {
+            Some(state) => RewindFrame::Restore(state),
+            None => RewindFrame::Exhausted,
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{History, RewindBuffer, RewindFrame, Rewinder};
+
+    // Hands out numbered states and records when capturing changes.
+    struct FakeHistory {
+        states: Vec<Vec<u8>>,
+        capturing: Vec<bool>,
+    }
+
+    impl History for FakeHistory {
+        fn pop(&mut self) -> Option<Vec<u8>> {
+            self.states.pop()
+        }
+        fn set_capturing(&mut self, capturing: bool) {
+            self.capturing.push(capturing);
+        }
+    }
+
+    #[test]
+    fn test_buffer_drops_oldest_state() {
+        let mut buffer = RewindBuffer::new(2);
+        //when
+        buffer.push(vec![1]);
+        buffer.push(vec![2]);
+        buffer.push(vec![3]);
+        //then
+        assert_eq!(2, buffer.len());
+        assert_eq!(Some(vec![3]), buffer.pop());
+        assert_eq!(Some(vec![2]), buffer.pop());
+        assert_eq!(None, buffer.pop());
+    }
+
+    #[test]
+    fn test_buffer_ignores_states_while_not_capturing() {
+        let mut buffer = RewindBuffer::new(4);
+        buffer.push(vec![1]);
+        //when
+        buffer.set_capturing(false);
+        buffer.push(vec![2]);
+        //then
+        assert_eq!(1, buffer.len());
+        buffer.set_capturing(true);
+        buffer.push(vec![3]);
+        assert_eq!(Some(vec![3]), buffer.pop());
+    }
+
+    #[test]
+    fn test_rewind_key_sequence() {
+        let mut history = FakeHistory { states: vec![vec![1], vec![2]],
+                                        capturing: Vec::new() };
+        let mut rewinder = Rewinder::new();
+        assert_eq!(RewindFrame::Run, rewinder.frame(&mut history));
+        //when
+        let started = rewinder.key(&mut history, true);
+        let repeated = rewinder.key(&mut history, true);
+        let frames: Vec<RewindFrame> = (0..3)
+            .map(|_| rewinder.frame(&mut history))
+            .collect();
+        let stopped = rewinder.key(&mut history, false);
+        //then
+        assert_eq!((true, false, true), (started, repeated, stopped));
+        assert_eq!(vec![RewindFrame::Restore(vec![2]),
+                        RewindFrame::Restore(vec![1]),
+                        RewindFrame::Exhausted], frames);
+        assert_eq!(vec![false, true], history.capturing);
+        assert!(!rewinder.is_rewinding());
+        assert_eq!(RewindFrame::Run, rewinder.frame(&mut history));
+    }
+
+    #[test]
+    fn test_release_resumes_from_where_rewinding_stopped() {
+        let mut buffer = RewindBuffer::new(8);
+        for i in 0..4 { buffer.push(vec![i]); }
+        let mut rewinder = Rewinder::new();
+        //given
+        rewinder.key(&mut buffer, true);
+        rewinder.frame(&mut buffer);
+        buffer.push(vec![9]);
+        //when
+        rewinder.key(&mut buffer, false);
+        buffer.push(vec![4]);
+        //then
+        assert_eq!(Some(vec![4]), buffer.pop());
+        assert_eq!(Some(vec![2]), buffer.pop());
+    }
+
+}
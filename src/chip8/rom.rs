@@ -0,0 +1,284 @@
+use super::{MAX_ROM_SIZE,Mode};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// Reasons why a file can not be loaded as a rom.
+#[derive(Debug)]
+pub enum RomError {
+    // The file could not be read.
+    Io(PathBuf, io::Error),
+    // The file is empty.
+    Empty(PathBuf),
+    // The file does not fit into memory. Holds the size of the file.
+    TooLarge(PathBuf, usize),
+    // A directory that does not contain any files.
+    NoRoms(PathBuf),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RomError::Io(ref path, ref err) =>
+                write!(f, "Can not read {}: {}", path.display(), err),
+            RomError::Empty(ref path) =>
+                write!(f, "{} is empty", path.display()),
+            RomError::TooLarge(ref path, size) =>
+                write!(f, "{} is too large ({} > {} bytes)", path.display(),
+                       size, MAX_ROM_SIZE),
+            RomError::NoRoms(ref path) =>
+                write!(f, "No roms found in {}", path.display()),
+        }
+    }
+}
+
+// A rom read from file, ready to be loaded into the emulator.
+#[derive(Debug)]
+pub struct RomFile {
+    pub path: PathBuf,
+    pub rom: Vec<u8>,
+    // Number of other candidate files that were not loaded.
+    pub ignored: usize,
+}
+
+// Read the rom at the given path, making sure it fits into memory.
+pub fn read_rom(path: &Path) -> Result<RomFile, RomError> {
+    let mut rom = Vec::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_end(&mut rom))
+        .map_err(|err| RomError::Io(path.to_path_buf(), err))?;
+    if rom.is_empty() {
+        return Err(RomError::Empty(path.to_path_buf()));
+    }
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(RomError::TooLarge(path.to_path_buf(), rom.len()));
+    }
+    Ok(RomFile { path: path.to_path_buf(), rom: rom, ignored: 0 })
+}
+
+// Load the first valid rom from the given paths, for instance the files
+// dropped onto the window. Directories are expanded into the files they
+// contain, in alphabetical order. Returns the first error encountered if
+// none of the candidates is a valid rom.
+pub fn read_first_rom(paths: &[PathBuf]) -> Result<RomFile, RomError> {
+    let mut candidates = Vec::new();
+    let mut first_err = None;
+    for path in paths {
+        if path.is_dir() {
+            match list_files(path) {
+                Ok(ref files) if files.is_empty() => {
+                    first_err = first_err.or(
+                        Some(RomError::NoRoms(path.clone())));
+                },
+                Ok(files) => candidates.extend(files),
+                Err(err) => {
+                    first_err = first_err.or(
+                        Some(RomError::Io(path.clone(), err)));
+                },
+            }
+        } else {
+            candidates.push(path.clone());
+        }
+    }
+    let num_candidates = candidates.len();
+    for path in candidates {
+        match read_rom(&path) {
+            Ok(mut rom_file) => {
+                rom_file.ignored = num_candidates - 1;
+                return Ok(rom_file);
+            },
+            Err(err) => first_err = first_err.or(Some(err)),
+        }
+    }
+    Err(first_err.unwrap_or(RomError::NoRoms(PathBuf::new())))
+}
+
+// List the files in the directory, sorted by name.
+pub fn list_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() { files.push(path); }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Guess the mode a rom was written for by looking for opcodes that only
+// exist in SUPER mode. Code and data are not told apart, so this is only
+// a heuristic.
+pub fn detect_mode(rom: &[u8]) -> Mode {
+    let is_super = words(rom).any(|opcode| super_opcode(opcode).is_some());
+    if is_super {Mode::SUPER} else {Mode::STANDARD}
+}
+
+// The words of the rom at even offsets, where the instructions of a rom
+// that is not self modifying start.
+pub fn words<'a>(rom: &'a [u8]) -> Box<Iterator<Item = u16> + 'a> {
+    Box::new(rom.chunks(2).filter(|word| word.len() == 2)
+                .map(|word| (word[0] as u16) << 8 | word[1] as u16))
+}
+
+// The pattern of the opcode, such as 00cn, if it only exists in SUPER
+// mode.
+pub fn super_opcode(opcode: u16) -> Option<&'static str> {
+    match opcode {
+        0x00fb => Some("00fb"),
+        0x00fc => Some("00fc"),
+        0x00fd => Some("00fd"),
+        0x00fe => Some("00fe"),
+        0x00ff => Some("00ff"),
+        _ if opcode & 0xfff0 == 0x00c0 => Some("00cn"),
+        _ if opcode & 0xf0ff == 0xf030 => Some("fx30"),
+        _ if opcode & 0xf0ff == 0xf075 => Some("fx75"),
+        _ if opcode & 0xf0ff == 0xf085 => Some("fx85"),
+        _ => None,
+    }
+}
+
+// The pattern of the opcode, such as 5xy2, if it only exists in XO-CHIP.
+pub fn xo_chip_opcode(opcode: u16) -> Option<&'static str> {
+    match opcode {
+        0xf000 => Some("f000"),
+        0xf002 => Some("f002"),
+        _ if opcode & 0xfff0 == 0x00d0 => Some("00dn"),
+        _ if opcode & 0xf00f == 0x5002 => Some("5xy2"),
+        _ if opcode & 0xf00f == 0x5003 => Some("5xy3"),
+        _ if opcode & 0xf0ff == 0xf001 => Some("fn01"),
+        _ if opcode & 0xf0ff == 0xf03a => Some("fx3a"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{detect_mode, read_first_rom, read_rom, RomError};
+    use super::{super_opcode, xo_chip_opcode};
+    use super::super::{MAX_ROM_SIZE, Mode};
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    // Create an empty scratch directory for the test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("chip8_rom_{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(path: &PathBuf, bytes: &[u8]) {
+        File::create(path).unwrap().write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_read_rom() {
+        let dir = scratch_dir("read");
+        let path = dir.join("pong.ch8");
+        //given
+        write_file(&path, &[0x00, 0xe0, 0x12, 0x00]);
+        //when
+        let rom_file = read_rom(&path).unwrap();
+        //then
+        assert_eq!(vec![0x00, 0xe0, 0x12, 0x00], rom_file.rom);
+        assert_eq!(0, rom_file.ignored);
+    }
+
+    #[test]
+    fn test_read_rom_too_large() {
+        let dir = scratch_dir("too_large");
+        let path = dir.join("huge.ch8");
+        //given
+        write_file(&path, &vec![0; MAX_ROM_SIZE + 1]);
+        //when
+        match read_rom(&path) {
+            //then
+            Err(RomError::TooLarge(_, size)) =>
+                assert_eq!(MAX_ROM_SIZE + 1, size),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_rom_missing_file() {
+        let dir = scratch_dir("missing");
+        //when
+        match read_rom(&dir.join("missing.ch8")) {
+            //then
+            Err(RomError::Io(..)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_read_first_rom_skips_invalid_and_counts_ignored() {
+        let dir = scratch_dir("first");
+        let empty = dir.join("empty.ch8");
+        let pong = dir.join("pong.ch8");
+        let brix = dir.join("brix.ch8");
+        //given
+        write_file(&empty, &[]);
+        write_file(&pong, &[0x12, 0x00]);
+        write_file(&brix, &[0x12, 0x02]);
+        //when
+        let rom_file = read_first_rom(&[empty, pong.clone(), brix]).unwrap();
+        //then
+        assert_eq!(pong, rom_file.path);
+        assert_eq!(2, rom_file.ignored);
+    }
+
+    #[test]
+    fn test_read_first_rom_from_directory() {
+        let dir = scratch_dir("dir");
+        //given
+        write_file(&dir.join("b.sch8"), &[0x00, 0xff]);
+        write_file(&dir.join("a.huge"), &vec![0; MAX_ROM_SIZE + 1]);
+        write_file(&dir.join("c.ch8"), &[0x12, 0x00]);
+        //when
+        let rom_file = read_first_rom(&[dir.clone()]).unwrap();
+        //then
+        assert_eq!(dir.join("b.sch8"), rom_file.path);
+        assert_eq!(vec![0x00, 0xff], rom_file.rom);
+        assert_eq!(2, rom_file.ignored);
+    }
+
+    #[test]
+    fn test_read_first_rom_reports_first_error() {
+        let dir = scratch_dir("error");
+        //given
+        write_file(&dir.join("empty.ch8"), &[]);
+        //when
+        match read_first_rom(&[dir.join("empty.ch8"), dir.join("missing")]) {
+            //then
+            Err(RomError::Empty(path)) =>
+                assert_eq!(dir.join("empty.ch8"), path),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_detect_mode() {
+        assert_eq!(Mode::STANDARD, detect_mode(&[0x00, 0xe0, 0xa2, 0x00]));
+        assert_eq!(Mode::SUPER, detect_mode(&[0x00, 0xe0, 0x00, 0xff]));
+        assert_eq!(Mode::SUPER, detect_mode(&[0x00, 0xc4]));
+        assert_eq!(Mode::SUPER, detect_mode(&[0xf3, 0x75]));
+        // Odd offsets are not considered.
+        assert_eq!(Mode::STANDARD, detect_mode(&[0x12, 0x00, 0xff, 0x00]));
+    }
+
+    #[test]
+    fn test_opcode_patterns() {
+        assert_eq!(Some("00cn"), super_opcode(0x00c4));
+        assert_eq!(Some("fx85"), super_opcode(0xf385));
+        assert_eq!(None, super_opcode(0x00e0));
+        assert_eq!(Some("5xy2"), xo_chip_opcode(0x5122));
+        assert_eq!(Some("fn01"), xo_chip_opcode(0xf201));
+        assert_eq!(Some("f000"), xo_chip_opcode(0xf000));
+        assert_eq!(None, xo_chip_opcode(0x5120));
+        assert_eq!(None, xo_chip_opcode(0x00fe));
+    }
+
+}
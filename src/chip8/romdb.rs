@@ -0,0 +1,482 @@
+use super::config::{self, Config, ConfigError, Setting};
+use super::effects;
+use super::emu;
+use super::log::Log;
+use super::metro;
+use super::options::{Platform, QuirkSettings};
+use std::path::{Path, PathBuf};
+
+// Profiles for well known roms that need a platform, quirks or a speed of
+// their own, built into the program. The user's database adds to them.
+pub const STARTER: &'static str = "\
+# Profiles of roms, keyed by the 64 bit FNV-1a hash of the rom.
+
+[rom.0daf9351419594b1]
+name = BLINKY
+mode = schip
+cycles_per_second = 1000
+controls = \"3/6 up and down, 7/8 left and right\"
+
+[rom.618a84f06fe32861]
+name = SPACE INVADERS
+shift_uses_vy = false
+foreground = 33ff66
+controls = \"4/6 move, 5 fires\"
+
+[rom.04eb2109dc29b1ab]
+name = TETRIS
+controls = \"5/6 move, 4 rotates, 7 drops\"
+
+[rom.9495733f60624ee6]
+name = PONG
+controls = \"1/4 move the paddle\"
+
+[rom.c86e8ff63fce668c]
+name = BRIX
+controls = \"4/6 move the paddle\"
+
+[rom.3e2c2d43b296b74c]
+name = TANK
+controls = \"2/4/6/8 move, 5 fires\"
+
+[rom.8d8a02fa3a2ed293]
+name = UFO
+controls = \"4/5/6 fire left, up and right\"
+
+[rom.b7e1d74b387bede6]
+name = WIPEOFF
+controls = \"4/6 move the paddle\"
+
+[rom.ef3f1bedfcbf05a8]
+name = ANT
+mode = schip
+cycles_per_second = 1000
+
+[rom.afcb28153ef5b24e]
+name = CAR
+mode = schip
+cycles_per_second = 1000
+
+[rom.b52b8fba47b34bd7]
+name = JOUST
+mode = schip
+cycles_per_second = 1000
+
+[rom.4fd85d6299b65798]
+name = SUPER WORM
+mode = schip
+cycles_per_second = 1000
+
+[rom.0103be25a6ec4d2f]
+name = SUPER ASTRO DODGE
+mode = schip
+cycles_per_second = 1000
+";
+
+// The settings a profile can override, as they are for roms without one.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RomSettings {
+    pub quirks: QuirkSettings,
+    pub cycles_per_second: u32,
+    pub fg: (u8, u8, u8),
+    pub bg: (u8, u8, u8),
+}
+
+// What a particular rom needs to run as intended. Settings that are not
+// given are None, leaving them as configured.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomProfile {
+    pub name: Option<String>,
+    pub platform: Option<Platform>,
+    // Quirks switched on or off by name, on top of those of the platform.
+    pub quirks: Vec<(String, bool)>,
+    pub cycles_per_second: Option<u32>,
+    pub fg: Option<(u8, u8, u8)>,
+    pub bg: Option<(u8, u8, u8)>,
+    // Which keys do what, shown when the rom is loaded.
+    pub controls: Option<String>,
+}
+
+impl RomProfile {
+
+    pub fn new() -> Self {
+        RomProfile { name: None, platform: None, quirks: Vec::new(),
+                     cycles_per_second: None, fg: None, bg: None,
+                     controls: None }
+    }
+
+    fn set(&mut self, setting: &Setting) -> Result<(), ConfigError> {
+        match &setting.key[..] {
+            "name" => self.name = Some(setting.value.clone()),
+            "mode" => self.platform = Some(
+                Platform::from_name(&setting.value).ok_or_else(|| {
+                    setting.error("expected chip8, schip or xochip")
+                })?),
+            "cycles_per_second" => {
+                let cycles_per_second = setting.parse()?;
+                if !metro::is_valid_cycles_per_second(cycles_per_second) {
+                    return Err(setting.error(
+                        "expected 100 to 20000 instructions per second"));
+                }
+                self.cycles_per_second = Some(cycles_per_second);
+            },
+            "foreground" => self.fg = Some(parse_color(setting)?),
+            "background" => self.bg = Some(parse_color(setting)?),
+            "controls" => self.controls = Some(setting.value.clone()),
+            name => {
+                if QuirkSettings::new().quirks.get(name).is_none() {
+                    return Err(setting.error("unknown setting"));
+                }
+                self.quirks.push((name.to_string(), setting.parse()?));
+            },
+        }
+        Ok(())
+    }
+
+    // Take the settings the other profile of the same rom gives, keeping
+    // those it leaves out. Its quirks apply after these.
+    pub fn merge(&mut self, other: &RomProfile) {
+        if other.name.is_some() {
+            self.name = other.name.clone();
+        }
+        if other.platform.is_some() {
+            // The quirks of a platform replace any set before.
+            self.platform = other.platform;
+            self.quirks.clear();
+        }
+        self.quirks.extend(other.quirks.iter().cloned());
+        self.cycles_per_second =
+            other.cycles_per_second.or(self.cycles_per_second);
+        self.fg = other.fg.or(self.fg);
+        self.bg = other.bg.or(self.bg);
+        if other.controls.is_some() {
+            self.controls = other.controls.clone();
+        }
+    }
+
+    // Override the settings the profile gives.
+    pub fn apply(&self, settings: &mut RomSettings) {
+        if let Some(platform) = self.platform {
+            settings.quirks.set_platform(platform);
+        }
+        for &(ref name, on) in &self.quirks {
+            settings.quirks.quirks.set(name, on);
+        }
+        if let Some(cycles_per_second) = self.cycles_per_second {
+            settings.cycles_per_second = cycles_per_second;
+        }
+        settings.fg = self.fg.unwrap_or(settings.fg);
+        settings.bg = self.bg.unwrap_or(settings.bg);
+    }
+
+    // The note shown when the profile is applied, e.g. "Applied profile:
+    // BLINKY (mode schip, 1000 instructions per second) - 3/6 up and down".
+    pub fn note(&self) -> String {
+        let mut changes = Vec::new();
+        if let Some(platform) = self.platform {
+            changes.push(format!("mode {}", platform.name()));
+        }
+        for &(ref name, on) in &self.quirks {
+            changes.push(format!("{} {}", name, if on {"on"} else {"off"}));
+        }
+        if let Some(cycles_per_second) = self.cycles_per_second {
+            changes.push(format!("{} instructions per second",
+                                 cycles_per_second));
+        }
+        if self.fg.is_some() || self.bg.is_some() {
+            changes.push("palette".to_string());
+        }
+        let name = self.name.as_ref().map_or("unnamed", |name| &name[..]);
+        let mut note = format!("Applied profile: {}", name);
+        if !changes.is_empty() {
+            note.push_str(&format!(" ({})", changes.join(", ")));
+        }
+        if let Some(ref controls) = self.controls {
+            note.push_str(&format!(" - {}", controls));
+        }
+        note
+    }
+
+}
+
+fn parse_color(setting: &Setting) -> Result<(u8, u8, u8), ConfigError> {
+    effects::parse_color(&setting.value)
+        .ok_or_else(|| setting.error("expected a colour such as ffb000"))
+}
+
+// Profiles of roms, found by the hash of the rom rather than its file name
+// so that they follow a rom wherever it is kept. Written in the format of
+// the configuration, with a section per rom:
+//
+//     [rom.0daf9351419594b1]
+//     name = BLINKY
+//     mode = schip
+//     shift_uses_vy = false
+//     cycles_per_second = 1000
+//     foreground = ffb000
+//     background = 000000
+//     controls = "3/6 up and down, 7/8 left and right"
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomDb {
+    profiles: Vec<(u64, RomProfile)>,
+}
+
+impl RomDb {
+
+    // A database without profiles, leaving every rom as configured.
+    pub fn new() -> Self {
+        RomDb { profiles: Vec::new() }
+    }
+
+    // The profiles built into the program.
+    pub fn builtin() -> Self {
+        RomDb::parse(STARTER).unwrap()
+    }
+
+    // Where the user's database lives, next to the configuration file.
+    pub fn default_path() -> Option<PathBuf> {
+        config::config_dir().map(|dir| dir.join("romdb.toml"))
+    }
+
+    // The profiles built in, extended by those of the user's database. A
+    // database that can not be read is warned about and left out.
+    pub fn load_default(log: &Log) -> Self {
+        let mut db = RomDb::builtin();
+        if let Some(path) = RomDb::default_path() {
+            match RomDb::load(&path) {
+                Ok(user) => db.extend(&user),
+                Err(err) => log.warn(&format!("{}: {}", path.display(), err)),
+            }
+        }
+        db
+    }
+
+    // Read the database from file. A missing file gives an empty one.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        RomDb::from_config(&Config::load(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ConfigError> {
+        RomDb::from_config(&Config::parse(text)?)
+    }
+
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut db = RomDb::new();
+        for section in config.sections() {
+            if section.name.is_empty() {
+                match section.settings.first() {
+                    Some(setting) => return Err(
+                        setting.error("expected a [rom.HASH] section first")),
+                    None => continue,
+                }
+            }
+            let hash = parse_key(&section.name).ok_or_else(|| {
+                ConfigError::Setting(section.line, section.name.clone(),
+                                     "expected [rom.HASH]".to_string())
+            })?;
+            let mut profile = RomProfile::new();
+            for setting in &section.settings {
+                profile.set(setting)?;
+            }
+            db.add(hash, &profile);
+        }
+        Ok(db)
+    }
+
+    // Add the profiles of the other database. Where both have a profile
+    // for the same rom, the settings of the other one take precedence.
+    pub fn extend(&mut self, other: &RomDb) {
+        for &(hash, ref profile) in &other.profiles {
+            self.add(hash, profile);
+        }
+    }
+
+    fn add(&mut self, hash: u64, profile: &RomProfile) {
+        match self.profiles.iter().position(|&(h, _)| h == hash) {
+            Some(i) => self.profiles[i].1.merge(profile),
+            None => self.profiles.push((hash, profile.clone())),
+        }
+    }
+
+    // The profile of the rom, if there is one.
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomProfile> {
+        let hash = emu::fnv1a(rom);
+        self.profiles.iter().find(|&&(h, _)| h == hash)
+            .map(|&(_, ref profile)| profile)
+    }
+
+    // The settings to run the rom with: those given, with those of its
+    // profile on top. Also gives the profile, if there is one.
+    pub fn settings_for(&self, rom: &[u8], mut settings: RomSettings)
+                        -> (RomSettings, Option<&RomProfile>) {
+        let profile = self.lookup(rom);
+        if let Some(profile) = profile {
+            profile.apply(&mut settings);
+        }
+        (settings, profile)
+    }
+
+}
+
+// The hash of the rom a section is for, from a name such as
+// rom.0daf9351419594b1.
+fn parse_key(name: &str) -> Option<u64> {
+    if !name.starts_with("rom.") || name.len() != 20 {
+        return None;
+    }
+    u64::from_str_radix(&name[4..], 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{RomDb, RomProfile, RomSettings};
+    use super::super::config::ConfigError;
+    use super::super::emu::Quirks;
+    use super::super::options::{Platform, QuirkSettings};
+
+    const BLINKY: &'static [u8] = include_bytes!("../../roms/blinky.sch8");
+    const TETRIS: &'static [u8] = include_bytes!("../../roms/tetris.ch8");
+
+    fn defaults() -> RomSettings {
+        RomSettings { quirks: QuirkSettings::new(), cycles_per_second: 500,
+                      fg: (0xff, 0xff, 0xff), bg: (0, 0, 0) }
+    }
+
+    #[test]
+    fn test_parse_profile() {
+        //when
+        let db = RomDb::parse("[rom.0daf9351419594b1]\n\
+                               name = BLINKY\n\
+                               mode = schip\n\
+                               shift_uses_vy = true\n\
+                               cycles_per_second = 1000\n\
+                               foreground = ffb000\n\
+                               background = 000000\n\
+                               controls = \"3/6 up and down\"\n").unwrap();
+        //then
+        let profile = db.lookup(BLINKY).unwrap();
+        assert_eq!(Some("BLINKY".to_string()), profile.name);
+        assert_eq!(Some(Platform::Schip), profile.platform);
+        assert_eq!(vec![("shift_uses_vy".to_string(), true)],
+                   profile.quirks);
+        assert_eq!(Some(1000), profile.cycles_per_second);
+        assert_eq!(Some((0xff, 0xb0, 0)), profile.fg);
+        assert_eq!(Some((0, 0, 0)), profile.bg);
+        assert_eq!(Some("3/6 up and down".to_string()), profile.controls);
+        assert!(db.lookup(TETRIS).is_none());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let line_of = |text| match RomDb::parse(text) {
+            Err(ConfigError::Setting(line, _, _)) => line,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(1, line_of("[blinky]\nname = BLINKY\n"));
+        assert_eq!(2, line_of("\n[rom.0daf935141959zzz]\n"));
+        assert_eq!(1, line_of("name = BLINKY\n"));
+        assert_eq!(2, line_of("[rom.0daf9351419594b1]\nmode = vip\n"));
+        assert_eq!(2, line_of("[rom.0daf9351419594b1]\nspeed = 2\n"));
+        assert_eq!(3, line_of("[rom.0daf9351419594b1]\n\n\
+                               cycles_per_second = 5\n"));
+        assert_eq!(2, line_of("[rom.0daf9351419594b1]\nforeground = red\n"));
+        assert_eq!(2, line_of("[rom.0daf9351419594b1]\nwrap_x = maybe\n"));
+    }
+
+    #[test]
+    fn test_builtin_profiles() {
+        //given
+        let db = RomDb::builtin();
+        //then
+        let blinky = db.lookup(BLINKY).unwrap();
+        assert_eq!(Some("BLINKY".to_string()), blinky.name);
+        assert_eq!(Some(Platform::Schip), blinky.platform);
+        assert_eq!(Some("TETRIS".to_string()),
+                   db.lookup(TETRIS).unwrap().name);
+        assert!(db.lookup(include_bytes!("../../roms/merlin.ch8")).is_none());
+    }
+
+    #[test]
+    fn test_extend_overrides_settings_given() {
+        //given
+        let mut db = RomDb::builtin();
+        let user = RomDb::parse("[rom.0daf9351419594b1]\n\
+                                 cycles_per_second = 700\n\
+                                 display_wait = true\n\
+                                 [rom.04eb2109dc29b1ab]\n\
+                                 mode = chip8\n\
+                                 [rom.0000000000000001]\n\
+                                 name = MINE\n").unwrap();
+        //when
+        db.extend(&user);
+        //then
+        let blinky = db.lookup(BLINKY).unwrap();
+        assert_eq!(Some("BLINKY".to_string()), blinky.name);
+        assert_eq!(Some(Platform::Schip), blinky.platform);
+        assert_eq!(Some(700), blinky.cycles_per_second);
+        assert_eq!(vec![("display_wait".to_string(), true)], blinky.quirks);
+        let tetris = db.lookup(TETRIS).unwrap();
+        assert_eq!(Some(Platform::Chip8), tetris.platform);
+        assert!(tetris.controls.is_some());
+        assert_eq!(14, db.profiles.len());
+    }
+
+    #[test]
+    fn test_merge_platform_replaces_quirks() {
+        //given
+        let mut profile = RomProfile::new();
+        profile.quirks.push(("wrap_x".to_string(), false));
+        let mut other = RomProfile::new();
+        other.platform = Some(Platform::Chip8);
+        other.quirks.push(("display_wait".to_string(), false));
+        //when
+        profile.merge(&other);
+        //then
+        assert_eq!(Some(Platform::Chip8), profile.platform);
+        assert_eq!(vec![("display_wait".to_string(), false)],
+                   profile.quirks);
+    }
+
+    #[test]
+    fn test_settings_for() {
+        //given
+        let db = RomDb::parse("[rom.0daf9351419594b1]\n\
+                               mode = schip\n\
+                               shift_uses_vy = true\n\
+                               cycles_per_second = 1000\n\
+                               background = 102030\n").unwrap();
+        //when
+        let (settings, profile) = db.settings_for(BLINKY, defaults());
+        //then
+        assert!(profile.is_some());
+        assert_eq!(Some(Platform::Schip), settings.quirks.platform);
+        let mut quirks = Quirks::schip();
+        quirks.shift_uses_vy = true;
+        assert_eq!(quirks, settings.quirks.quirks);
+        assert_eq!(1000, settings.cycles_per_second);
+        assert_eq!((0xff, 0xff, 0xff), settings.fg);
+        assert_eq!((0x10, 0x20, 0x30), settings.bg);
+        assert_eq!((defaults(), None), db.settings_for(TETRIS, defaults()));
+        assert_eq!((defaults(), None),
+                   RomDb::new().settings_for(BLINKY, defaults()));
+    }
+
+    #[test]
+    fn test_note() {
+        //given
+        let mut profile = RomProfile::new();
+        //then
+        assert_eq!("Applied profile: unnamed", profile.note());
+        profile.name = Some("BLINKY".to_string());
+        profile.platform = Some(Platform::Schip);
+        profile.quirks.push(("shift_uses_vy".to_string(), true));
+        profile.cycles_per_second = Some(1000);
+        profile.fg = Some((0, 0, 0));
+        profile.controls = Some("3/6 up and down".to_string());
+        assert_eq!("Applied profile: BLINKY (mode schip, shift_uses_vy on, \
+                    1000 instructions per second, palette) - 3/6 up and down",
+                   profile.note());
+    }
+
+}
@@ -0,0 +1,626 @@
+use super::{GFX_H, GFX_W, Mode};
+use super::builtin;
+use super::compare::Lockstep;
+use super::debugger::DebugSnapshot;
+use super::emu::{Emu, ErrorPolicy};
+use super::log::Log;
+use super::metro::{ClockSettings, CycleBudget, FrameLimiter, FrameStats};
+use super::metro::{self, IdleGate, MetroStats, Metronome, RunAhead};
+use super::options::QuirkSettings;
+use super::quicksave::Slot;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, SendError, Sender};
+use std::thread;
+use std::time::Duration as StdDuration;
+use time::{Duration, SteadyTime};
+
+// Messages that get passed from the ui to the emulator.
+pub enum UiToEmuMsg { AdvanceFrame, CyclesPerSecond(u32), Debug(bool), 
+                      Keys([bool; 16]), Load(Vec<u8>, QuirkSettings), 
+                      LoadState(Slot), Paused(bool), Quit, Reset, 
+                      Restore(Vec<u8>), RunFrames(u32), SaveState(Slot), 
+                      Speed(f32), Step }
+
+// Messages that get passed from the emulator to the ui. What is drawn is
+// published to a FrameMailbox instead. When comparing quirks, that is the
+// frame of the first emulator and Compared carries that of the second. 
+// Beeping carries the frame the beep started or stopped at, and Keys the
+// keys held down as the emulator sees them.
+pub enum EmuToUiMsg { Beeping(u64, bool), Compared([[bool; GFX_H]; GFX_W]),
+                      Debug(DebugSnapshot), Diverged(u64),
+                      Frame(Vec<u8>, Duration, MetroStats), Halted(bool),
+                      Keys([bool; 16]), Message(String), QuitAck, 
+                      Started(&'static str, Vec<u8>) }
+
+// A frame drawn by the emulator, as published for the ui to show.
+#[derive(Clone)]
+pub struct PublishedFrame {
+    // The number of frames the emulator had run.
+    pub frame: u64,
+    pub mode: Mode,
+    pub gfx: [[bool; GFX_H]; GFX_W],
+}
+
+// Hands the frames drawn by the emulator thread over to the ui. Only the 
+// latest frame is kept: publishing replaces a frame the ui has yet to 
+// take, so that a ui falling behind skips stale frames rather than 
+// showing each of them late, and the emulator never waits for the ui.
+// The beep goes over the channel instead, as a change of it must not be
+// skipped.
+#[derive(Clone)]
+pub struct FrameMailbox {
+    latest: Arc<Mutex<Option<PublishedFrame>>>,
+}
+
+impl FrameMailbox {
+
+    pub fn new() -> Self {
+        FrameMailbox { latest: Arc::new(Mutex::new(None)) }
+    }
+
+    pub fn publish(&self, frame: PublishedFrame) {
+        *self.latest.lock().unwrap() = Some(frame);
+    }
+
+    // The frame published last, unless it was taken already.
+    pub fn take(&self) -> Option<PublishedFrame> {
+        self.latest.lock().unwrap().take()
+    }
+
+}
+
+// The emulator thread's end of the link to the ui: messages go over the 
+// channel, and what is drawn to the mailbox.
+pub struct EmuSender {
+    tx: Sender<EmuToUiMsg>,
+    frames: FrameMailbox,
+}
+
+impl EmuSender {
+
+    pub fn new(tx: Sender<EmuToUiMsg>, frames: FrameMailbox) -> Self {
+        EmuSender { tx: tx, frames: frames }
+    }
+
+    pub fn send(&self, msg: EmuToUiMsg) -> Result<(), SendError<EmuToUiMsg>> {
+        self.tx.send(msg)
+    }
+
+    // Publish the display of the emulator.
+    pub fn publish(&self, emu: &Emu) {
+        self.frames.publish(PublishedFrame { frame: emu.frame_count(), 
+                                             mode: emu.mode, gfx: emu.gfx });
+    }
+
+}
+
+// Drives the emulator. Communicates with the user interface by exchanging
+// messages across a two way channel, and publishes what it draws to a
+// FrameMailbox.
+//
+// Assigned its own thread. 
+//
+// The emulator runs a frame per timer tick, with the instructions of a 
+// second spread evenly over its frames. With vsync, runs the frames the ui
+// asks for instead of keeping time itself. While the program idles, e.g. 
+// waiting for a key, no frames run and the thread sleeps until the ui has
+// news. A program that faults halts, rather than taking the thread down 
+// with it, until it is reset or replaced.
+pub fn emu_exec(mut emu: Emu, tx: EmuSender, rx: Receiver<UiToEmuMsg>, 
+                vsync: bool, clock: ClockSettings) {
+    let mut frame_rate = Metronome::new(clock.timer_hz as i64);
+    frame_rate.set_max_catch_up(clock.max_catch_up);
+    let mut budget = CycleBudget::new(clock.cycles_per_second, 
+                                      clock.timer_hz);
+    let mut stats = FrameStats::new(clock.timer_hz as i64);
+    let run_ahead = RunAhead::new(clock.run_ahead);
+    let mut limiter = FrameLimiter::new();
+    let mut idle_gate = IdleGate::new();
+    let mut idle = false;
+    let mut paused = false;
+    let mut beeping = false;
+    let mut halted = false;
+    let mut keys = [false; 16];
+    let mut debugging = false;
+    let mut frames_due = 0;
+    emu.set_on_error(ErrorPolicy::Halt);
+    'emu_exec_loop: loop {
+        if process_ui_events(&mut emu, &tx, &rx, &mut paused, 
+                             &mut debugging, &mut frame_rate, &mut budget,
+                             &mut frames_due, idle) {
+            break 'emu_exec_loop;
+        }
+        let was_idle = idle;
+        idle = idle_gate.update(emu.is_idle(), paused, &mut frame_rate);
+        if idle != was_idle {
+            emu.log().debug(if idle {"Idling until a key is pressed"} 
+                            else {"Running frames again"});
+        }
+        if idle {
+            frames_due = 0;
+        } else if !vsync {
+            frame_rate.on_tick(|| frames_due += 1);
+            if signal_skipped_frames(&mut frame_rate, &tx, emu.log()) {
+                stats.dropped_backlog();
+            }
+        }
+        run_frames(&mut emu, &tx, &paused, &debugging, &mut beeping,
+                   &mut frames_due, &mut budget, &mut stats, &run_ahead);
+        start_menu_choice(&mut emu, &tx);
+        signal_halt_change(&emu, &tx, &mut halted);
+        signal_key_change(&emu, &tx, &mut keys);
+        if vsync || paused {
+            // Short sleep to free up cpu cycles
+            thread::sleep_ms(1);    
+        } else if !idle {
+            // Wait for the next frame precisely, so that frames are evenly
+            // spaced.
+            limiter.wait(frame_rate.until_tick());
+            stats.set_oversleep(limiter.oversleep());
+        }
+    }
+}
+
+// Handle every UI event waiting. Returns true if Quit signal received from 
+// UI. While idle, waits for an event rather than polling.
+fn process_ui_events(emu: &mut Emu, tx: &EmuSender,  
+                     rx: &Receiver<UiToEmuMsg>, paused: &mut bool, 
+                     debugging: &mut bool, frame_rate: &mut Metronome, 
+                     budget: &mut CycleBudget, frames_due: &mut u32, 
+                     idle: bool) -> bool {
+    let mut received = if idle {
+        rx.recv_timeout(StdDuration::from_millis(metro::IDLE_WAKE_MS)).ok()
+    } else {
+        rx.try_recv().ok()
+    };
+    while let Some(ui_to_emu_msg) = received {
+        match ui_to_emu_msg {
+            // New key press states.
+            UiToEmuMsg::Keys(new_keys) => emu.keys = new_keys,
+            // Reset everything.
+            UiToEmuMsg::Reset => emu.reset(),
+            // Replace the running rom, running it with the quirks.
+            UiToEmuMsg::Load(rom, quirks) => {
+                *emu = fresh_emu(emu);
+                quirks.apply(emu);
+                emu.load_rom(rom);
+                tx.publish(emu);
+            },
+            // Pause or unpause. Execution and timers stop, and the time
+            // spent paused is not made up for after unpausing.
+            UiToEmuMsg::Paused(p) => {
+                *paused = p;
+                if p { frame_rate.pause(); } else { frame_rate.resume(); }
+            },
+            // Start or stop reporting state to the debugger panel.
+            UiToEmuMsg::Debug(on) => {
+                *debugging = on;
+                if on { signal_debug_event(emu, tx); }
+            },
+            // Save to or load from a quicksave slot. A slot that can 
+            // not be loaded leaves the running program alone.
+            UiToEmuMsg::SaveState(slot) => {
+                let msg = match slot.save(emu) {
+                    Ok(_) => format!("Saved slot {}", slot.number),
+                    Err(err) => err.to_string(),
+                };
+                tx.send(EmuToUiMsg::Message(msg)).unwrap();
+            },
+            UiToEmuMsg::LoadState(slot) => {
+                let msg = match slot.load(emu) {
+                    Ok(_) => {
+                        tx.publish(emu);
+                        format!("Loaded slot {}", slot.number)
+                    },
+                    Err(err) => err.to_string(),
+                };
+                tx.send(EmuToUiMsg::Message(msg)).unwrap();
+                if *debugging { signal_debug_event(emu, tx); }
+            },
+            // Go back to a frame kept for rewinding.
+            UiToEmuMsg::Restore(frame) => {
+                if emu.load_state(&frame).is_ok() {
+                    tx.publish(emu);
+                    emu.draw = false;
+                }
+                if *debugging { signal_debug_event(emu, tx); }
+            },
+            // Frames presented with vsync, to be caught up on. Turbo 
+            // runs several frames for each, slow motion part of one.
+            UiToEmuMsg::RunFrames(n) => 
+                *frames_due += frame_rate.at_speed(n),
+            // Run faster or back at normal speed. Instructions and 
+            // timers speed up alike, so that games play the same.
+            UiToEmuMsg::Speed(speed) => frame_rate.set_speed(speed),
+            // Run more or fewer instructions per frame from the next 
+            // frame on.
+            UiToEmuMsg::CyclesPerSecond(cycles_per_second) => 
+                budget.set_cycles_per_second(cycles_per_second),
+            // Run a single frame while paused, as it would have run
+            // unpaused.
+            UiToEmuMsg::AdvanceFrame => if *paused {
+                emu.run_frame(budget.next_frame());
+                tx.publish(emu);
+                emu.draw = false;
+                if *debugging { signal_debug_event(emu, tx); }
+            },
+            // Execute a single instruction while paused.
+            UiToEmuMsg::Step => if *paused {
+                emu.execute_cycle();
+                if emu.draw {
+                    tx.publish(emu);
+                    emu.draw = false;
+                }
+                if *debugging { signal_debug_event(emu, tx); }
+            },
+            // Acknowledge quit and shut down gracefully.
+            UiToEmuMsg::Quit => {
+                tx.send(EmuToUiMsg::QuitAck).unwrap();
+                return true;
+            }, 
+        }
+        received = rx.try_recv().ok();
+    }
+    false
+}
+
+// A new emulator to replace the running one, logging to the same log and
+// halting on faults.
+fn fresh_emu(emu: &Emu) -> Emu {
+    let mut fresh = Emu::new();
+    fresh.set_log(emu.log().clone());
+    fresh.set_on_error(ErrorPolicy::Halt);
+    fresh
+}
+
+// Replace the built in menu with the rom picked from it, if any.
+fn start_menu_choice(emu: &mut Emu, tx: &EmuSender) {
+    if let Some(choice) = builtin::menu_choice(emu) {
+        *emu = fresh_emu(emu);
+        emu.load_rom(choice.rom.to_vec());
+        tx.publish(emu);
+        tx.send(EmuToUiMsg::Started(choice.name, choice.rom.to_vec()))
+          .unwrap();
+    }
+}
+
+// Drives the two emulators of a quirk comparison in place of emu_exec, 
+// running them a frame at a time in lockstep. Keys, pausing, resetting, 
+// turbo and frame advance apply to both; other requests are ignored.
+//
+// Assigned its own thread.
+pub fn compare_exec(mut lockstep: Lockstep, tx: EmuSender, 
+                    rx: Receiver<UiToEmuMsg>, vsync: bool, 
+                    clock: ClockSettings, log: Log) {
+    lockstep.set_log(&log);
+    let mut frame_rate = Metronome::new(clock.timer_hz as i64);
+    frame_rate.set_max_catch_up(clock.max_catch_up);
+    let mut paused = false;
+    let mut beeping = false;
+    let mut keys = [false; 16];
+    let mut frames_due = 0;
+    loop {
+        match rx.try_recv() {
+            Ok(UiToEmuMsg::Keys(keys)) => lockstep.set_keys(keys),
+            Ok(UiToEmuMsg::Reset) => {
+                lockstep.reset();
+                run_compared_frames(&mut lockstep, &tx, &mut beeping, 0);
+            },
+            Ok(UiToEmuMsg::Paused(p)) => {
+                paused = p;
+                if p { frame_rate.pause(); } else { frame_rate.resume(); }
+            },
+            Ok(UiToEmuMsg::Speed(speed)) => frame_rate.set_speed(speed),
+            Ok(UiToEmuMsg::RunFrames(n)) => 
+                frames_due += frame_rate.at_speed(n),
+            Ok(UiToEmuMsg::AdvanceFrame) => if paused {
+                run_compared_frames(&mut lockstep, &tx, &mut beeping, 1);
+            },
+            Ok(UiToEmuMsg::Quit) => {
+                tx.send(EmuToUiMsg::QuitAck).unwrap();
+                return;
+            },
+            _ => {},
+        }
+        if vsync {
+            if !paused {
+                run_compared_frames(&mut lockstep, &tx, &mut beeping, 
+                                    frames_due);
+            }
+            frames_due = 0;
+        } else {
+            let mut due = 0;
+            frame_rate.on_tick(|| due += 1);
+            signal_skipped_frames(&mut frame_rate, &tx, &log);
+            if due > 0 {
+                run_compared_frames(&mut lockstep, &tx, &mut beeping, due);
+            }
+        }
+        signal_key_change(lockstep.emus().0, &tx, &mut keys);
+        // Short sleep to free up cpu cycles
+        thread::sleep_ms(1);    
+    }
+}
+
+// Run frames of both compared emulators and send the ui what they drew, 
+// along with the frame at which they first drew something different. 
+// The beep is that of the first emulator.
+fn run_compared_frames(lockstep: &mut Lockstep, tx: &EmuSender, 
+                       beeping: &mut bool, frames: u32) {
+    for _ in 0..frames {
+        if lockstep.run_frame() {
+            let frame = lockstep.diverged_at().unwrap();
+            tx.send(EmuToUiMsg::Diverged(frame)).unwrap();
+        }
+    }
+    let (a, b) = lockstep.emus();
+    tx.publish(a);
+    tx.send(EmuToUiMsg::Compared(b.gfx)).unwrap();
+    signal_beep_change(a, tx, beeping);
+}
+
+// Run the frames that are due, each with the cycles the budget has for it.
+// The timers count down once per frame. Frames due while paused are 
+// dropped. The timing achieved is passed on with the state. When running
+// ahead, what is drawn comes from the frames run ahead, but the beep and 
+// everything else from the frames really run. Every frame run ahead is
+// shown, drawn or not, so that a wrong guess is replaced by the next one.
+fn run_frames(emu: &mut Emu, tx: &EmuSender, paused: &bool,
+              debugging: &bool, beeping: &mut bool, frames_due: &mut u32,
+              budget: &mut CycleBudget, stats: &mut FrameStats,
+              run_ahead: &RunAhead) {
+    if *frames_due == 0 || *paused {
+        *frames_due = 0;
+        return;
+    }
+    let started = SteadyTime::now();
+    let mut cycles = 0;
+    for _ in 0..*frames_due {
+        let frame_started = SteadyTime::now();
+        cycles = budget.next_frame();
+        emu.run_frame(cycles);
+        stats.frame(cycles, SteadyTime::now() - frame_started);
+    }
+    *frames_due = 0;
+    let ahead = if *debugging { None } else { run_ahead.ahead(emu, cycles) };
+    if emu.draw || ahead.is_some() {
+        tx.publish(ahead.as_ref().unwrap_or(emu));
+        emu.draw = false;
+    }
+    signal_beep_change(emu, tx, beeping);
+    if *debugging { signal_debug_event(emu, tx); }
+    let busy = SteadyTime::now() - started;
+    tx.send(EmuToUiMsg::Frame(emu.save_state(), busy, stats.stats()))
+      .unwrap();
+}
+
+// Tell the user when the emulator fell too far behind to catch up, e.g. 
+// after the computer slept, and skipped frames instead. Returns true if it
+// did.
+fn signal_skipped_frames(frame_rate: &mut Metronome, tx: &EmuSender, 
+                         log: &Log) -> bool {
+    let skipped = frame_rate.take_skipped();
+    if skipped > 0 {
+        let msg = format!("Skipped {} frames", skipped);
+        log.debug(&msg);
+        tx.send(EmuToUiMsg::Message(msg)).unwrap();
+    }
+    skipped > 0
+}
+
+// Signal the ui if the program halted, or runs again after a reset.
+fn signal_halt_change(emu: &Emu, tx: &EmuSender, halted: &mut bool) {
+    if *halted != emu.halted() {
+        *halted ^= true;
+        tx.send(EmuToUiMsg::Halted(*halted)).unwrap();
+    }
+}
+
+// Signal the ui if the keys held down changed, whether by the ui or by 
+// keys tapped running out.
+fn signal_key_change(emu: &Emu, tx: &EmuSender, keys: &mut [bool; 16]) {
+    if *keys != emu.keys {
+        *keys = emu.keys;
+        tx.send(EmuToUiMsg::Keys(*keys)).unwrap();
+    }
+}
+
+// Signal the ui if the beep state changed.
+fn signal_beep_change(emu: &Emu, tx: &EmuSender, beeping: &mut bool) {
+    if *beeping != emu.beeping() {
+        *beeping ^= true; 
+        tx.send(EmuToUiMsg::Beeping(emu.frame_count(), *beeping)).unwrap();
+    }
+}
+
+// Signal the ui with the state shown by the debugger panel.
+fn signal_debug_event(emu: &Emu, tx: &EmuSender) {
+    tx.send(EmuToUiMsg::Debug(DebugSnapshot::capture(emu))).unwrap();
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use super::{emu_exec, run_frames, EmuSender, EmuToUiMsg, FrameMailbox};
+    use super::{PublishedFrame, UiToEmuMsg};
+    use super::super::Mode;
+    use super::super::builtin::KEYPAD_TEST;
+    use super::super::emu::Emu;
+    use super::super::metro::{ClockSettings, CycleBudget, FrameStats};
+    use super::super::metro::RunAhead;
+    use super::super::options::QuirkSettings;
+    use std::sync::mpsc::{self, Receiver};
+    use std::thread;
+
+    fn frame(frame: u64) -> PublishedFrame {
+        let emu = Emu::new();
+        PublishedFrame { frame: frame, mode: Mode::STANDARD, gfx: emu.gfx }
+    }
+
+    // Wait for the emulator to finish running the frames it was asked to.
+    fn wait_for_frames(rx: &Receiver<EmuToUiMsg>) {
+        loop {
+            if let EmuToUiMsg::Frame(..) = rx.recv().unwrap() {
+                return;
+            }
+        }
+    }
+
+    // Wait for the emulator to report that it halted or runs again.
+    fn wait_for_halted(rx: &Receiver<EmuToUiMsg>) -> bool {
+        loop {
+            if let EmuToUiMsg::Halted(halted) = rx.recv().unwrap() {
+                return halted;
+            }
+        }
+    }
+
+    #[test]
+    fn test_mailbox_keeps_only_the_latest_frame() {
+        let frames = FrameMailbox::new();
+        assert!(frames.take().is_none());
+        //when
+        frames.clone().publish(frame(1));
+        frames.publish(frame(2));
+        //then
+        assert_eq!(Some(2), frames.take().map(|frame| frame.frame));
+        assert!(frames.take().is_none());
+    }
+
+    #[test]
+    fn test_emu_thread_runs_scripted_commands() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let frames = FrameMailbox::new();
+        let sender = EmuSender::new(tx2, frames.clone());
+        let mut emu = Emu::new();
+        emu.load_rom(KEYPAD_TEST.to_vec());
+        // With vsync, the emulator only runs the frames it is asked to.
+        let emu_thread = thread::spawn(move || {
+            emu_exec(emu, sender, rx1, true, ClockSettings::new());
+        });
+        let mut keys = [false; 16];
+        keys[0xa] = true;
+        let mut expected = Emu::new();
+        expected.load_rom(KEYPAD_TEST.to_vec());
+        let mut budget = CycleBudget::new(500, 60);
+        for _ in 0..2 { expected.run_frame(budget.next_frame()); }
+        expected.keys = keys;
+        for _ in 0..3 { expected.run_frame(budget.next_frame()); }
+        //when
+        tx1.send(UiToEmuMsg::RunFrames(2)).unwrap();
+        wait_for_frames(&rx2);
+        tx1.send(UiToEmuMsg::Keys(keys)).unwrap();
+        tx1.send(UiToEmuMsg::RunFrames(3)).unwrap();
+        wait_for_frames(&rx2);
+        //then
+        // The blank screen drawn in the first two frames was never taken,
+        // and is replaced by the key drawn in the next three.
+        let shown = frames.take().unwrap();
+        assert_eq!(5, shown.frame);
+        let mut shown_emu = Emu::new();
+        shown_emu.gfx = shown.gfx;
+        assert_eq!(expected.display_hash(), shown_emu.display_hash());
+        assert!(shown_emu.display_hash() != Emu::new().display_hash());
+        assert!(frames.take().is_none());
+        // Quitting is acknowledged and ends the thread.
+        tx1.send(UiToEmuMsg::Quit).unwrap();
+        loop {
+            if let EmuToUiMsg::QuitAck = rx2.recv().unwrap() {
+                break;
+            }
+        }
+        emu_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_emu_thread_halts_a_faulting_program() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let sender = EmuSender::new(tx2, FrameMailbox::new());
+        let mut emu = Emu::new();
+        //given
+        // An unknown opcode.
+        emu.load_rom(vec![0x51, 0x21]);
+        let emu_thread = thread::spawn(move || {
+            emu_exec(emu, sender, rx1, true, ClockSettings::new());
+        });
+        //when
+        tx1.send(UiToEmuMsg::RunFrames(1)).unwrap();
+        //then
+        assert!(wait_for_halted(&rx2));
+        // Runs again once a working rom replaces it.
+        tx1.send(UiToEmuMsg::Load(KEYPAD_TEST.to_vec(), 
+                                  QuirkSettings::new())).unwrap();
+        assert!(!wait_for_halted(&rx2));
+        tx1.send(UiToEmuMsg::Quit).unwrap();
+        emu_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_emu_thread_reports_the_keys_it_sees() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let sender = EmuSender::new(tx2, FrameMailbox::new());
+        let mut emu = Emu::new();
+        emu.load_rom(KEYPAD_TEST.to_vec());
+        let emu_thread = thread::spawn(move || {
+            emu_exec(emu, sender, rx1, true, ClockSettings::new());
+        });
+        let mut keys = [false; 16];
+        keys[0x5] = true;
+        //when
+        tx1.send(UiToEmuMsg::Keys(keys)).unwrap();
+        //then
+        loop {
+            if let EmuToUiMsg::Keys(seen) = rx2.recv().unwrap() {
+                assert_eq!(keys, seen);
+                break;
+            }
+        }
+        tx1.send(UiToEmuMsg::Quit).unwrap();
+        emu_thread.join().unwrap();
+    }
+
+    #[test]
+    fn test_wrong_guess_of_run_ahead_does_not_stick() {
+        // Once a frame, toggles the digit 0 in the top left corner while 
+        // key 0 is held down.
+        let rom = [0x61, 0x01,   // 200 LD V1, 1
+                   0xf1, 0x15,   // 202 LD DT, V1
+                   0xf1, 0x07,   // 204 LD V1, DT
+                   0x31, 0x00,   // 206 SE V1, 0
+                   0x12, 0x04,   // 208 JP 0x204
+                   0xe0, 0x9e,   // 20a SKP V0
+                   0x12, 0x00,   // 20c JP 0x200
+                   0xd2, 0x25,   // 20e DRW V2, V2, 5
+                   0x12, 0x00];  // 210 JP 0x200
+        let (tx, _rx) = mpsc::channel();
+        let frames = FrameMailbox::new();
+        let sender = EmuSender::new(tx, frames.clone());
+        let mut emu = Emu::new();
+        emu.load_rom(rom.to_vec());
+        let mut budget = CycleBudget::new(600, 60);
+        let mut stats = FrameStats::new(60);
+        let run_ahead = RunAhead::new(1);
+        let mut run = |emu: &mut Emu| {
+            run_frames(emu, &sender, &false, &false, &mut false, &mut 1, 
+                       &mut budget, &mut stats, &run_ahead);
+        };
+        run(&mut emu);
+        //given
+        // The digit is drawn, and the frame run ahead guesses that the key
+        // stays held down, which clears it again.
+        emu.keys[0] = true;
+        run(&mut emu);
+        assert!(frames.take().unwrap().gfx != emu.gfx);
+        //when
+        // The key is let go, so that nothing is drawn.
+        emu.keys[0] = false;
+        run(&mut emu);
+        //then
+        assert!(frames.take().unwrap().gfx == emu.gfx);
+    }
+
+}
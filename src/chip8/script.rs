@@ -0,0 +1,166 @@
+use std::error::Error;
+use std::fmt;
+
+// Reasons why an input script can not be used.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptError {
+    // A line that is not `CYCLE down|up KEY`. Holds the line number.
+    Syntax(usize),
+    // A key other than 0 to f. Holds the line number and the key.
+    InvalidKey(usize, String),
+    // An event for an earlier cycle than the event before it. Holds the
+    // line number.
+    OutOfOrder(usize),
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScriptError::Syntax(line) =>
+                write!(f, "Script line {}: expected CYCLE down|up KEY", line),
+            ScriptError::InvalidKey(line, ref key) =>
+                write!(f, "Script line {}: no key {}, expected 0 to f",
+                       line, key),
+            ScriptError::OutOfOrder(line) =>
+                write!(f, "Script line {}: cycle before the one above", line),
+        }
+    }
+}
+
+impl Error for ScriptError {
+    fn description(&self) -> &str {
+        "invalid input script"
+    }
+}
+
+// A key pressed or released once a number of cycles have executed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScriptEvent {
+    pub cycle: u64,
+    pub key: u8,
+    pub down: bool,
+}
+
+// Key presses written out as text, one per line, to replay a game the
+// same way every time, e.g. to share how to reproduce a problem:
+//
+//     # Hold 5 from cycle 10 up to cycle 25.
+//     10 down 5
+//     25 up 5
+//
+// Cycles count from when the script is loaded, and keys are the hex
+// digits of the keypad. Lines after a `#` are comments.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputScript {
+    events: Vec<ScriptEvent>,
+    // The first event yet to be played, and the cycles executed so far.
+    next: usize,
+    cycle: u64,
+}
+
+impl InputScript {
+
+    pub fn parse(text: &str) -> Result<Self, ScriptError> {
+        let mut events: Vec<ScriptEvent> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 3 {
+                return Err(ScriptError::Syntax(line_no));
+            }
+            let cycle = words[0].parse()
+                .map_err(|_| ScriptError::Syntax(line_no))?;
+            let down = match words[1] {
+                "down" => true,
+                "up" => false,
+                _ => return Err(ScriptError::Syntax(line_no)),
+            };
+            let key = match u8::from_str_radix(words[2], 16) {
+                Ok(key) if key < 16 && words[2].len() == 1 => key,
+                _ => return Err(ScriptError::InvalidKey(
+                    line_no, words[2].to_string())),
+            };
+            if events.last().map_or(false, |event| event.cycle > cycle) {
+                return Err(ScriptError::OutOfOrder(line_no));
+            }
+            events.push(ScriptEvent { cycle: cycle, key: key, down: down });
+        }
+        Ok(InputScript { events: events, next: 0, cycle: 0 })
+    }
+
+    // Count a cycle executed.
+    pub fn advance(&mut self) {
+        self.cycle += 1;
+    }
+
+    // The events that are due by the cycles executed so far, and were not
+    // played yet. They count as played once returned.
+    pub fn due(&mut self) -> &[ScriptEvent] {
+        let start = self.next;
+        while self.next < self.events.len() &&
+              self.events[self.next].cycle <= self.cycle {
+            self.next += 1;
+        }
+        &self.events[start..self.next]
+    }
+
+    // Whether every event has been played.
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{InputScript, ScriptError, ScriptEvent};
+
+    #[test]
+    fn test_parse() {
+        //when
+        let mut script = InputScript::parse("# fire\n\
+                                             0 down a\n\
+                                             \n\
+                                             10 down 5  # hold\n\
+                                             10 up A\n\
+                                             25 up 5\n").unwrap();
+        //then
+        assert_eq!(vec![ScriptEvent { cycle: 0, key: 0xa, down: true }],
+                   script.due());
+        for _ in 0..10 {
+            assert!(script.due().is_empty());
+            script.advance();
+        }
+        assert_eq!(vec![ScriptEvent { cycle: 10, key: 5, down: true },
+                        ScriptEvent { cycle: 10, key: 0xa, down: false }],
+                   script.due());
+        for _ in 0..20 {
+            script.advance();
+        }
+        assert!(!script.is_finished());
+        assert_eq!(vec![ScriptEvent { cycle: 25, key: 5, down: false }],
+                   script.due());
+        assert!(script.is_finished());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text| InputScript::parse(text).unwrap_err();
+        assert_eq!(ScriptError::Syntax(2), error("1 down 1\n1 down\n"));
+        assert_eq!(ScriptError::Syntax(1), error("x down 1\n"));
+        assert_eq!(ScriptError::Syntax(1), error("-1 down 1\n"));
+        assert_eq!(ScriptError::Syntax(1), error("1 press 1\n"));
+        assert_eq!(ScriptError::InvalidKey(1, "g".to_string()),
+                   error("1 down g\n"));
+        assert_eq!(ScriptError::InvalidKey(1, "10".to_string()),
+                   error("1 down 10\n"));
+        assert_eq!(ScriptError::OutOfOrder(3),
+                   error("5 down 1\n5 up 1\n4 down 2\n"));
+    }
+
+}
@@ -0,0 +1,150 @@
+use super::Mode;
+
+// What the window title reflects about the emulator.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TitleState {
+    // File name of the running rom.
+    pub rom_name: Option<String>,
+    pub mode: Mode,
+    // The size of a pixel of the mode, in pixels of the display.
+    pub scale: Option<u32>,
+    // Emulation speed relative to normal.
+    pub speed: f32,
+    pub paused: bool,
+    pub rewinding: bool,
+    pub recording: bool,
+    // The emulator has stopped and will not continue without a reset.
+    pub halted: bool,
+    // When comparing quirks, the first frame at which the two emulators
+    // drew something different.
+    pub diverged_at: Option<u64>,
+}
+
+impl TitleState {
+
+    pub fn new() -> Self {
+        TitleState {
+            rom_name: None,
+            mode: Mode::STANDARD,
+            scale: None,
+            speed: 1.0,
+            paused: false,
+            rewinding: false,
+            recording: false,
+            halted: false,
+            diverged_at: None,
+        }
+    }
+
+}
+
+// The window title for the state, e.g. "chip8 — PONG.ch8 [SCHIP] 1.0x",
+// or "chip8 — PONG.ch8 [SCHIP 8x] 1.0x" once the scale is known, followed
+// by each of "(paused)", "(rewinding)", "(recording)" and "(HALTED)" that
+// applies, and by e.g. "(diverged at frame 42)" when comparing quirks.
+pub fn title_for(state: &TitleState) -> String {
+    let mut title = "chip8".to_string();
+    if let Some(ref rom_name) = state.rom_name {
+        let mode = match state.mode {
+            Mode::STANDARD => "CHIP-8",
+            Mode::SUPER => "SCHIP",
+        };
+        let scale = match state.scale {
+            Some(scale) => format!(" {}x", scale),
+            None => String::new(),
+        };
+        title.push_str(&format!(" \u{2014} {} [{}{}] {:.1}x", rom_name, mode,
+                                scale, state.speed));
+    }
+    let flags = [(state.paused, "(paused)"),
+                 (state.rewinding, "(rewinding)"),
+                 (state.recording, "(recording)"),
+                 (state.halted, "(HALTED)")];
+    for &(on, flag) in &flags {
+        if on {
+            title.push(' ');
+            title.push_str(flag);
+        }
+    }
+    if let Some(frame) = state.diverged_at {
+        title.push_str(&format!(" (diverged at frame {})", frame));
+    }
+    title
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{title_for, TitleState};
+    use super::super::Mode;
+
+    fn pong() -> TitleState {
+        TitleState { rom_name: Some("PONG.ch8".to_string()),
+                     ..TitleState::new() }
+    }
+
+    #[test]
+    fn test_title_without_rom() {
+        assert_eq!("chip8", title_for(&TitleState::new()));
+    }
+
+    #[test]
+    fn test_title_for_mode_and_speed() {
+        let mut state = pong();
+        assert_eq!("chip8 \u{2014} PONG.ch8 [CHIP-8] 1.0x", title_for(&state));
+        //when
+        state.mode = Mode::SUPER;
+        state.speed = 2.5;
+        //then
+        assert_eq!("chip8 \u{2014} PONG.ch8 [SCHIP] 2.5x", title_for(&state));
+        state.scale = Some(8);
+        assert_eq!("chip8 \u{2014} PONG.ch8 [SCHIP 8x] 2.5x", 
+                   title_for(&state));
+    }
+
+    #[test]
+    fn test_title_for_each_state() {
+        let base = "chip8 \u{2014} PONG.ch8 [CHIP-8] 1.0x";
+        let cases: [(fn(&mut TitleState), &str); 4] = [
+            (|s| s.paused = true, " (paused)"),
+            (|s| s.rewinding = true, " (rewinding)"),
+            (|s| s.recording = true, " (recording)"),
+            (|s| s.halted = true, " (HALTED)"),
+        ];
+        for &(set, suffix) in &cases {
+            let mut state = pong();
+            set(&mut state);
+            assert_eq!(format!("{}{}", base, suffix), title_for(&state));
+        }
+    }
+
+    #[test]
+    fn test_title_for_combined_states() {
+        let mut state = pong();
+        //given
+        state.paused = true;
+        state.recording = true;
+        state.halted = true;
+        //then
+        assert_eq!("chip8 \u{2014} PONG.ch8 [CHIP-8] 1.0x \
+                    (paused) (recording) (HALTED)", title_for(&state));
+        //when
+        state.rewinding = true;
+        //then
+        assert_eq!("chip8 \u{2014} PONG.ch8 [CHIP-8] 1.0x \
+                    (paused) (rewinding) (recording) (HALTED)",
+                   title_for(&state));
+    }
+
+    #[test]
+    fn test_title_for_divergence() {
+        let mut state = pong();
+        //when
+        state.paused = true;
+        state.diverged_at = Some(42);
+        //then
+        assert_eq!("chip8 \u{2014} PONG.ch8 [CHIP-8] 1.0x \
+                    (paused) (diverged at frame 42)", title_for(&state));
+    }
+
+}
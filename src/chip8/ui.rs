@@ -1,138 +1,850 @@
 use sdl2;
 use sdl2::audio::{AudioCallback,AudioDevice,AudioSpecDesired};
+use sdl2::controller::{self,GameController};
 use sdl2::event::Event;
-use sdl2::pixels::Color::RGB;
-use sdl2::rect::Rect;
-use sdl2::render::Renderer;
+use sdl2::pixels::Color;
+use sdl2::pixels::Color::{RGB,RGBA};
+use sdl2::rect::{Point,Rect};
+use sdl2::render::{BlendMode,Renderer};
+use sdl2::video::FullscreenType;
 use sdl2::keyboard::Scancode;
-use sdl2::Sdl;
-use super::{GFX_H,GFX_W,Mode,wav};
+use sdl2::{GameControllerSubsystem,Sdl};
+use super::{GFX_H,GFX_W,Mode,display,font,wav};
+use super::effects::{self,Effects,Flicker,FrameBlend,Phosphor};
+use super::input::{Button,KeyboardMapping};
+use super::metro::{FrameTiming,MetroStats,Metronome,TIMING_FRAMES};
+use super::overlay::{self,SoundIndicator};
+use super::picker::PickerItem;
+use super::title::{self,TitleState};
+use super::wav::AudioSink;
+use super::window;
+use std::sync::Arc;
+use time::{Duration, SteadyTime};
 
-const SCALE: usize = 8;
+// Size of a single font pixel when drawing text on top of the gfx.
+const TEXT_SCALE: usize = 2;
+// Vertical distance between two lines of text, in font pixels.
+const LINE_H: usize = font::GLYPH_H + 2;
+// Width of the debugger panel, in characters.
+const PANEL_CHARS: usize = 26;
+// Size of a single font pixel when drawing the PAUSED banner.
+const BANNER_SCALE: usize = 8;
+// How long a message stays on screen.
+const MESSAGE_SECS: i64 = 3;
+// Messages longer than this are cut short to fit the window.
+const MESSAGE_MAX_CHARS: usize = 100;
 
-pub struct BeepCallback;
-
-impl BeepCallback {
-    fn new() -> Self {
-        BeepCallback
-    }
+// Plays the beep for as long as the audio device is open, pulling samples
+// from the source.
+pub struct BeepCallback {
+    source: wav::SampleSource,
 }
 
 impl AudioCallback for BeepCallback {
     type Channel = u8;
 
     fn callback(&mut self, out: &mut [u8]) {
-        assert!(out.len() == wav::PLAYBACK_BUFFER.len());
-        for i in 0..wav::PLAYBACK_BUFFER.len() {
-            out[i] = wav::PLAYBACK_BUFFER[i];
-        }
+        self.source.fill_u8(out);
     }    
 
 }
 
+// The open audio device, along with the controls of the source it plays,
+// so that the beep can be changed without locking out the callback.
+pub struct SdlAudio {
+    // Plays for as long as it is kept.
+    _device: AudioDevice<BeepCallback>,
+    controls: Arc<wav::SoundControls>,
+}
+
+impl AudioSink for SdlAudio {
+
+    fn controls(&self) -> &wav::SoundControls {
+        &self.controls
+    }
+
+}
+
 pub struct Ui {
     sdl_ctx: Sdl,
     renderer: Renderer<'static>,
-    audio: AudioDevice<BeepCallback>,
+    audio: SdlAudio,
+    controller_subsystem: GameControllerSubsystem,
+    // The gamepads that are plugged in.
+    controllers: Vec<GameController>,
+    // The last frame received from the emulator, kept so that overlays
+    // can be redrawn while the emulator is not producing frames.
+    mode: Mode,
+    gfx: [[bool; GFX_H]; GFX_W],
+    // When comparing quirks, the last frame of the second emulator, drawn
+    // to the right of the first.
+    compared: Option<[[bool; GFX_H]; GFX_W]>,
+    // Lines of the debugger panel, shown when present.
+    debug_panel: Option<Vec<String>>,
+    // The keys held down as the emulator sees them, shown on the keypad 
+    // overlay when present.
+    keypad: Option<[bool; 16]>,
+    // Entries of the rom picker and the selected entry, shown when present.
+    picker: Option<(Vec<PickerItem>, usize)>,
+    // The timings of the most recent frames, graphed when present along 
+    // with the timing achieved.
+    timing_graph: Option<(Vec<FrameTiming>, MetroStats)>,
+    // Time spent drawing since it was last taken, for the timing graph.
+    draw_time: Duration,
+    // Whether the emulator wants to beep, regardless of whether audio is
+    // currently silenced by a pause or by rewinding.
+    beeping: bool,
+    paused: bool,
+    rewinding: bool,
+    // Shows a speaker while the emulator beeps, whether or not it is heard.
+    // The speaker is crossed out while muted.
+    sound_indicator: SoundIndicator,
+    muted: bool,
+    sound_visible: bool,
+    sound_rate: Metronome,
+    // A short message shown along the bottom of the window until it 
+    // expires.
+    message: Option<(String, SteadyTime)>,
+    // What the window title currently shows.
+    title: TitleState,
+    // The rate at which the emulator runs frames at normal speed.
+    timer_hz: u32,
+    // Post-processing of the frames before they are shown.
+    effects: Effects,
+    phosphor: Phosphor,
+    blend: FrameBlend,
+    // The rate at which the phosphor fades.
+    phosphor_rate: Metronome,
+    // With vsync, the window is redrawn once per refresh of the display by
+    // present_frame, rather than whenever something changes.
+    vsync: bool,
+    presented_at: SteadyTime,
+    // The size of the inside of the window, in pixels.
+    size: (usize, usize),
 }
 
 impl Ui {
     
-    pub fn new() -> Self {
+    // Open a window showing the CHIP-8 resolution at the scale, and an 
+    // audio device as the settings ask.
+    pub fn new(vsync: bool, scale: u32, audio: &wav::AudioSettings) -> Self {
         let sdl_ctx = sdl2::init().unwrap();
         let video_subsystem = sdl_ctx.video().unwrap();
-        let window = video_subsystem.window("chip8", 
-                                     (GFX_W * SCALE) as u32, 
-                                     (GFX_H * SCALE) as u32)
+        // SDL sizes the inside of the window, leaving out decorations.
+        let (w, h) = window::window_size(scale, 
+                                         window::resolution(Mode::STANDARD),
+                                         (0, 0));
+        let window = video_subsystem.window("chip8", w, h)
                                     .position_centered()
                                     .build()
                                     .unwrap();
 
-        let renderer = window.renderer().build().unwrap(); 
+        let renderer = if vsync {
+            window.renderer().present_vsync().build().unwrap()
+        } else {
+            window.renderer().build().unwrap()
+        };
         
         let audio_subsystem = sdl_ctx.audio().unwrap();
         let audio_spec = AudioSpecDesired {
-            freq: Some(wav::SAMPLE_RATE_HZ as i32),
+            freq: Some(audio.sample_rate as i32),
             channels: Some(wav::CHANNELS as u8),
-            samples: Some(wav::SAMPLES as u16)
+            samples: Some(audio.buffer_samples as u16)
         };
     
-        let audio = audio_subsystem.open_playback(None, audio_spec, |_| {
-            BeepCallback::new()
+        // The device may not play at the sample rate asked for, so the beep
+        // is rendered at the one it plays at.
+        let mut controls = None;
+        let device = audio_subsystem.open_playback(None, audio_spec, |spec| {
+            let source = wav::SampleSource::new(audio.waveform,
+                                                spec.freq as usize);
+            controls = Some(source.controls());
+            BeepCallback { source: source }
         }).unwrap();
+        device.resume();
+        let audio = SdlAudio { _device: device, controls: controls.unwrap() };
+
+        // Gamepads that are already plugged in are reported as added once
+        // events are polled.
+        let controller_subsystem = sdl_ctx.game_controller().unwrap();
 
-        Ui { sdl_ctx: sdl_ctx, renderer: renderer, audio: audio } 
+        Ui { 
+            sdl_ctx: sdl_ctx, 
+            renderer: renderer, 
+            audio: audio,
+            controller_subsystem: controller_subsystem,
+            controllers: Vec::new(),
+            mode: Mode::STANDARD,
+            gfx: [[false; GFX_H]; GFX_W],
+            compared: None,
+            debug_panel: None,
+            keypad: None,
+            picker: None,
+            timing_graph: None,
+            draw_time: Duration::zero(),
+            beeping: false,
+            paused: false,
+            rewinding: false,
+            sound_indicator: SoundIndicator::new(),
+            muted: false,
+            sound_visible: false,
+            sound_rate: Metronome::new(60),
+            message: None,
+            title: TitleState { scale: Some(scale), ..TitleState::new() },
+            timer_hz: wav::FRAME_HZ as u32,
+            effects: Effects::new(),
+            phosphor: Phosphor::new(),
+            blend: FrameBlend::new(),
+            phosphor_rate: Metronome::new(60),
+            vsync: vsync,
+            presented_at: SteadyTime::now(),
+            size: (w as usize, h as usize),
+        } 
     }
 
-    pub fn beep(&self, on: bool) {
-        match on {
-            true => self.audio.resume(),
-            false => self.audio.pause()
+    // The emulator started or stopped beeping at the frame. The beep is 
+    // timed by the frames, so that it lasts as long as they do.
+    pub fn beep(&mut self, frame: u64, on: bool) {
+        self.beeping = on;
+        self.sound_indicator.beep(on);
+        if !self.paused && !self.rewinding {
+            self.audio.beep_at(frame, on);
         }
     }
 
-    pub fn refresh_gfx(&mut self, mode: Mode, gfx: &[[bool; GFX_H]; GFX_W]) {
-        let bg = RGB(0x1c, 0x28, 0x41);
-        let fg = RGB(0xff, 0xff, 0xff);
-        let projection_factor = match mode { 
-            //
-            // For STANDARD mode, the 64x32 gfx subscreen will be projected 
-            // to fit the entire viewable area. The excess between 64x32 and
-            // 128x64 will be projected offscreen. 
-            // +-----------------------+-----------------------+
-            // |                       |                       |
-            // |         64x32         |                       |
-            // |                       |                       |
-            // +-----------------------+    drawn offscreen    |
-            // |                                               |
-            // |                                               |
-            // |                                               |
-            // +-----------------------------------------------+ (128x64)
-            Mode::STANDARD => SCALE * 2, 
-            Mode::SUPER => SCALE, 
+    // While paused, the last frame stays on screen with a PAUSED banner 
+    // and the beep is silenced. The beep continues on resume if the 
+    // emulator still wants to beep.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.title.paused = paused;
+        self.update_audio();
+        self.update_title();
+        self.present();
+    }
+
+    // The beep is silenced while the game runs backwards.
+    pub fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+        self.title.rewinding = rewinding;
+        self.update_audio();
+        self.update_title();
+    }
+
+    // Whether the beep is being recorded, as shown in the window title.
+    pub fn set_recording(&mut self, recording: bool) {
+        self.title.recording = recording;
+        self.update_title();
+    }
+
+    // Whether the program halted on a fault, as shown in the window title.
+    pub fn set_halted(&mut self, halted: bool) {
+        self.title.halted = halted;
+        self.update_title();
+    }
+
+    // The speed relative to normal is shown in the window title. The beep
+    // keeps its pitch at any speed, while its length follows the frames.
+    pub fn set_speed(&mut self, speed: f32) {
+        self.title.speed = speed;
+        self.audio.set_speed(speed * self.timer_hz as f32 / 
+                             wav::FRAME_HZ as f32);
+        self.update_title();
+    }
+
+    // Beeps are timed in frames, so the audio needs to know how many there
+    // are per second when the timers do not count down at 60Hz.
+    pub fn set_timer_hz(&mut self, hz: u32) {
+        self.timer_hz = hz;
+        let speed = self.title.speed;
+        self.set_speed(speed);
+    }
+
+    // Resize the window to show the resolution of the current mode at the
+    // scale. The window keeps its size when the mode changes later on.
+    pub fn set_scale(&mut self, scale: u32) {
+        let (w, h) = window::window_size(scale, window::resolution(self.mode),
+                                         (0, 0));
+        let w = w * self.displays();
+        if let Some(window) = self.renderer.window_mut() {
+            window.set_size(w, h);
+        }
+        self.size = (w as usize, h as usize);
+        // When fullscreen, the window is drawn at this size and stretched
+        // over the screen.
+        let _ = self.renderer.set_logical_size(w, h);
+        self.title.scale = Some(scale);
+        self.update_title();
+        self.present();
+    }
+
+    // Fill the screen with the window, or go back to a window of the size
+    // it had. The window keeps its proportions, with bars where those of 
+    // the screen differ.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let (w, h) = (self.size.0 as u32, self.size.1 as u32);
+        let fullscreen = if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
         };
+        if let Some(window) = self.renderer.window_mut() {
+            let _ = window.set_fullscreen(fullscreen);
+        }
+        let _ = self.renderer.set_logical_size(w, h);
+        self.present();
+    }
+
+    // The size of the window as a multiple of the CHIP-8 resolution, which
+    // does not change with the mode.
+    pub fn window_scale(&self) -> u32 {
+        window::scale_for(self.inner_size(), 
+                          window::resolution(Mode::STANDARD))
+    }
+
+    // The size of a pixel of the current mode.
+    fn pixel_size(&self) -> usize {
+        window::scale_for(self.inner_size(), 
+                          window::resolution(self.mode)) as usize
+    }
+
+    // The size of the inside of the window taken up by a single display.
+    fn inner_size(&self) -> (u32, u32) {
+        (self.size.0 as u32 / self.displays(), self.size.1 as u32)
+    }
+
+    // The number of displays side by side in the window.
+    fn displays(&self) -> u32 {
+        if self.compared.is_some() {2} else {1}
+    }
+
+    // Show a second display to the right of the first, for comparing 
+    // quirks, or go back to a single display. The window widens or narrows
+    // to keep the scale.
+    pub fn set_comparing(&mut self, comparing: bool) {
+        let scale = self.window_scale();
+        self.compared = if comparing {
+            Some([[false; GFX_H]; GFX_W])
+        } else {
+            None
+        };
+        self.set_scale(scale);
+    }
+
+    // Show the new frame of the second emulator when comparing quirks.
+    pub fn refresh_compared(&mut self, gfx: &[[bool; GFX_H]; GFX_W]) {
+        if self.compared.is_some() {
+            self.compared = Some(*gfx);
+            self.present();
+        }
+    }
+
+    // Show the frame at which the compared emulators first drew something
+    // different in the window title.
+    pub fn set_diverged_at(&mut self, frame: Option<u64>) {
+        self.title.diverged_at = frame;
+        self.update_title();
+    }
+
+    // Show the name of the running rom in the window title.
+    pub fn set_rom_name(&mut self, rom_name: &str) {
+        self.title.rom_name = Some(rom_name.to_string());
+        self.update_title();
+    }
+
+    fn update_title(&mut self) {
+        let title = title::title_for(&self.title);
+        if let Some(window) = self.renderer.window_mut() {
+            window.set_title(&title);
+        }
+    }
+
+    // Show a message for a few seconds.
+    pub fn show_message(&mut self, text: &str) {
+        let text: String = text.chars().take(MESSAGE_MAX_CHARS).collect();
+        let expires_at = SteadyTime::now() + Duration::seconds(MESSAGE_SECS);
+        self.message = Some((text, expires_at));
+        self.present();
+    }
+
+    pub fn effects(&self) -> Effects {
+        self.effects
+    }
+
+    pub fn set_effects(&mut self, effects: Effects) {
+        if effects.flicker != self.effects.flicker {
+            self.phosphor.clear();
+            self.blend.clear();
+        }
+        self.effects = effects;
+        self.present();
+    }
+
+    // Play the beep as the waveform from now on.
+    pub fn set_waveform(&mut self, waveform: wav::Waveform) {
+        self.audio.set_waveform(waveform);
+    }
+
+    // Play a square wave beep high for the duty cycle of each period.
+    pub fn set_duty_cycle(&mut self, duty_cycle: wav::DutyCycle) {
+        self.audio.set_duty_cycle(duty_cycle);
+    }
+
+    // Silence the beep, or let it be heard again.
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        self.audio.set_muted(muted);
+        self.present();
+    }
+
+    // Set the volume of the beep, as a percentage.
+    pub fn set_volume(&mut self, volume: u8) {
+        self.audio.set_volume(volume);
+    }
+
+    // Redraw if an overlay has expired or the phosphor has faded some 
+    // more. Meant to be called regularly, since the emulator does not 
+    // produce new frames while paused or when nothing changes.
+    pub fn update(&mut self) {
+        let mut redraw = false;
+        let expired = match self.message {
+            Some((_, expires_at)) => SteadyTime::now() >= expires_at,
+            None => false,
+        };
+        if expired {
+            self.message = None;
+            redraw = true;
+        }
+        if self.effects.flicker == Flicker::Phosphor && !self.paused {
+            let phosphor = &mut self.phosphor;
+            let gfx = &self.gfx;
+            let decay = self.effects.decay;
+            self.phosphor_rate.on_tick(|| {
+                redraw |= phosphor.fading(gfx);
+                phosphor.update(gfx, decay);
+            });
+        }
+        let indicator = &mut self.sound_indicator;
+        let visible = &mut self.sound_visible;
+        self.sound_rate.on_tick(|| {
+            let was_visible = *visible;
+            *visible = indicator.frame();
+            redraw |= *visible != was_visible;
+        });
+        if redraw {
+            self.present();
+        }
+    }
+
+    fn update_audio(&mut self) {
+        let on = self.beeping && !self.paused && !self.rewinding;
+        self.audio.beep(on);
+    }
+
+    // Show the new frame.
+    pub fn refresh_gfx(&mut self, mode: Mode, gfx: &[[bool; GFX_H]; GFX_W]) {
+        self.mode = mode;
+        self.gfx = *gfx;
+        if self.effects.flicker == Flicker::Blend {
+            self.blend.update(gfx);
+        }
+        if self.title.mode != mode {
+            self.title.mode = mode;
+            self.title.scale = Some(self.pixel_size() as u32);
+            self.update_title();
+        }
+        self.present();
+    }
+
+    // The frame shown, scaled up by a whole factor with the given colours,
+    // as width, height and RGBA bytes, for frontends that blit it 
+    // themselves.
+    pub fn scaled_rgba(&self, scale: usize, on: [u8; 4], 
+                       off: [u8; 4]) -> (usize, usize, Vec<u8>) {
+        display::scaled_rgba(self.mode, &self.gfx, scale, on, off)
+    }
+
+    // Show the debugger panel with the given lines, or hide it if None.
+    pub fn show_debug_panel(&mut self, lines: Option<Vec<String>>) {
+        self.debug_panel = lines;
+        self.present();
+    }
+
+    // Show the keypad overlay with the given keys held down, or hide it if 
+    // None.
+    pub fn show_keypad(&mut self, keys: Option<[bool; 16]>) {
+        self.keypad = keys;
+        self.present();
+    }
+
+    // The keypad overlay as text, with the keys held down highlighted, or
+    // with none held while the overlay is hidden.
+    pub fn render_keypad(&self) -> String {
+        overlay::keypad_text(&self.keypad.unwrap_or([false; 16]))
+    }
+
+    // Show the rom picker with the given entries, or hide it if None.
+    pub fn show_picker(&mut self, picker: Option<(Vec<PickerItem>, usize)>) {
+        self.picker = picker;
+        self.present();
+    }
+
+    // Graph the timings of the frames, oldest first, with a summary of the
+    // timing achieved, or hide the graph if None.
+    pub fn show_timing_graph(&mut self, 
+                             graph: Option<(Vec<FrameTiming>, MetroStats)>) {
+        self.timing_graph = graph;
+        self.present();
+    }
+
+    // The time spent drawing since the last call. With vsync, this includes
+    // waiting for the display.
+    pub fn take_draw_time(&mut self) -> Duration {
+        ::std::mem::replace(&mut self.draw_time, Duration::zero())
+    }
+
+    // Draw the last frame along with any overlays.
+    fn present(&mut self) {
+        if !self.vsync {
+            self.draw_frame();
+        }
+    }
+
+    // Draw the last frame and wait for the display to show it. Returns the
+    // time since the previous call, by which emulation is paced. Only 
+    // meant to be used with vsync.
+    pub fn present_frame(&mut self) -> Duration {
+        self.draw_frame();
+        let now = SteadyTime::now();
+        let interval = now - self.presented_at;
+        self.presented_at = now;
+        interval
+    }
+
+    fn draw_frame(&mut self) {
+        let started = SteadyTime::now();
+        self.draw_gfx();
+        if self.paused {
+            self.draw_banner("PAUSED");
+        }
+        if let Some(lines) = self.debug_panel.take() {
+            self.draw_debug_panel(&lines);
+            self.debug_panel = Some(lines);
+        }
+        if let Some(keys) = self.keypad {
+            self.draw_keypad(&keys);
+        }
+        if let Some((items, selected)) = self.picker.take() {
+            self.draw_picker(&items, selected);
+            self.picker = Some((items, selected));
+        }
+        if let Some((samples, stats)) = self.timing_graph.take() {
+            self.draw_timing_graph(&samples, &stats);
+            self.timing_graph = Some((samples, stats));
+        }
+        if let Some((text, expires_at)) = self.message.take() {
+            self.draw_message(&text);
+            self.message = Some((text, expires_at));
+        }
+        if self.sound_visible {
+            self.draw_sound_indicator();
+        }
+        self.renderer.present();
+        self.draw_time = self.draw_time + (SteadyTime::now() - started);
+    }
+
+    fn draw_gfx(&mut self) {
+        //
+        // For STANDARD mode, the 64x32 gfx subscreen will be projected 
+        // to fit the entire viewable area. The excess between 64x32 and
+        // 128x64 will be projected offscreen. 
+        // +-----------------------+-----------------------+
+        // |                       |                       |
+        // |         64x32         |                       |
+        // |                       |                       |
+        // +-----------------------+    drawn offscreen    |
+        // |                                               |
+        // |                                               |
+        // |                                               |
+        // +-----------------------------------------------+ (128x64)
+        let projection_factor = self.pixel_size();
         for x in 0..GFX_W {
             for y in 0..GFX_H {
-                let pix_on = gfx[x][y];
-                let color = if pix_on {fg} else {bg};
-                let rx = (x * projection_factor) as i32;
-                let ry = (y * projection_factor) as i32;
-                let rw = projection_factor as u32;
-                let rh = projection_factor as u32;
-                let rect = Rect::new(rx, ry, rw, rh).unwrap().unwrap();
-                self.renderer.set_draw_color(color);
-                self.renderer.fill_rect(rect);
+                let level = match self.effects.flicker {
+                    _ if self.gfx[x][y] => 1.0,
+                    Flicker::Off => 0.0,
+                    Flicker::Phosphor => self.phosphor.intensity(x, y),
+                    Flicker::Blend => if self.blend.lit(x, y) {1.0} else {0.0},
+                };
+                self.draw_pixel(x * projection_factor, y * projection_factor,
+                                projection_factor, level);
             }
         }
-        self.renderer.present();
+        // The second display covers whatever of the first was projected 
+        // past its edge. Flicker reduction only applies to the first.
+        if let Some(gfx) = self.compared {
+            let x0 = self.inner_size().0 as usize;
+            for x in 0..GFX_W {
+                for y in 0..GFX_H {
+                    self.draw_pixel(x0 + x * projection_factor, 
+                                    y * projection_factor, projection_factor,
+                                    if gfx[x][y] {1.0} else {0.0});
+                }
+            }
+        }
+        if self.effects.scanlines {
+            self.draw_scanlines();
+        }
     } 
+
+    // Fill a square of the window for a pixel lit to the level, from 0 for
+    // off up to 1 for on.
+    fn draw_pixel(&mut self, x: usize, y: usize, size: usize, level: f32) {
+        let (fg, bg) = (self.effects.fg, self.effects.bg);
+        let color = RGB(effects::mix(bg.0, fg.0, level),
+                        effects::mix(bg.1, fg.1, level),
+                        effects::mix(bg.2, fg.2, level));
+        let rect = Rect::new(x as i32, y as i32, size as u32, size as u32)
+                        .unwrap().unwrap();
+        self.renderer.set_draw_color(color);
+        self.renderer.fill_rect(rect);
+    }
+
+    // Darken every other row of the window.
+    fn draw_scanlines(&mut self) {
+        let w = self.size.0 as i32;
+        self.renderer.set_blend_mode(BlendMode::Blend);
+        self.renderer.set_draw_color(RGBA(0, 0, 0, effects::SCANLINE_SHADE));
+        for row in 0..self.size.1 / 2 {
+            let y = (row * 2 + 1) as i32;
+            self.renderer.draw_line(Point::new(0, y), Point::new(w, y));
+        }
+        self.renderer.set_blend_mode(BlendMode::None);
+    }
+
+    // Draw the debugger panel along the right edge of the window. The line
+    // of the current instruction is highlighted.
+    fn draw_debug_panel(&mut self, lines: &[String]) {
+        let bg = RGB(0x0a, 0x10, 0x1c);
+        let fg = RGB(0xc0, 0xc8, 0xd8);
+        let highlight = RGB(0xff, 0xd0, 0x40);
+        // At the smallest scales the panel covers the whole window.
+        let panel_w = ((PANEL_CHARS * font::ADVANCE + 2) * TEXT_SCALE)
+                      .min(self.size.0);
+        let panel_h = (lines.len() * LINE_H + 2) * TEXT_SCALE;
+        let panel_x = self.size.0 - panel_w;
+        let rect = Rect::new(panel_x as i32, 0, panel_w as u32, 
+                             panel_h as u32).unwrap().unwrap();
+        self.renderer.set_draw_color(bg);
+        self.renderer.fill_rect(rect);
+        for (i, line) in lines.iter().enumerate() {
+            let color = if line.starts_with('>') {highlight} else {fg};
+            let x = panel_x + 2 * TEXT_SCALE;
+            let y = (i * LINE_H + 2) * TEXT_SCALE;
+            self.draw_text(line, x, y, color);
+        }
+    }
+
+    // Draw the rom picker over the whole window, scrolled to keep the
+    // selected entry in view. Roms that no longer exist are greyed out.
+    fn draw_picker(&mut self, items: &[PickerItem], selected: usize) {
+        let bg = RGB(0x0a, 0x10, 0x1c);
+        let fg = RGB(0xc0, 0xc8, 0xd8);
+        let grey = RGB(0x50, 0x58, 0x68);
+        let highlight = RGB(0xff, 0xd0, 0x40);
+        let rect = Rect::new(0, 0, self.size.0 as u32, 
+                             self.size.1 as u32).unwrap().unwrap();
+        self.renderer.set_draw_color(bg);
+        self.renderer.fill_rect(rect);
+        self.draw_text("OPEN ROM  1-9 RECENT  ESC CLOSE", 2 * TEXT_SCALE, 
+                       2 * TEXT_SCALE, highlight);
+        let rows = ((self.size.1 / TEXT_SCALE).saturating_sub(2) / LINE_H)
+                   .saturating_sub(1);
+        let first = (selected + 1).saturating_sub(rows);
+        for (row, (i, item)) in items.iter().enumerate()
+                                     .skip(first).take(rows).enumerate() {
+            let color = if !item.available {grey} 
+                        else if i == selected {highlight} 
+                        else {fg};
+            let marker = if i == selected {'>'} else {' '};
+            let text = format!("{}{}", marker, item.label());
+            let y = ((row + 1) * LINE_H + 2) * TEXT_SCALE;
+            self.draw_text(&text, 2 * TEXT_SCALE, y, color);
+        }
+    }
+
+    // Draw the text in large letters across the middle of the window.
+    fn draw_banner(&mut self, text: &str) {
+        let bg = RGB(0x0a, 0x10, 0x1c);
+        let fg = RGB(0xff, 0xd0, 0x40);
+        let text_w = font::text_width(text) * BANNER_SCALE;
+        let text_h = font::GLYPH_H * BANNER_SCALE;
+        // Text wider than the window is cut off on the right.
+        let x = self.size.0.saturating_sub(text_w) / 2;
+        let y = self.size.1.saturating_sub(text_h) / 2;
+        let rect = Rect::new(0, y as i32 - (BANNER_SCALE * 2) as i32, 
+                             self.size.0 as u32, 
+                             (text_h + BANNER_SCALE * 4) as u32)
+                        .unwrap().unwrap();
+        self.renderer.set_draw_color(bg);
+        self.renderer.fill_rect(rect);
+        self.draw_scaled_text(text, x, y, BANNER_SCALE, fg);
+    }
+
+    // Draw the message along the bottom left of the window.
+    fn draw_message(&mut self, text: &str) {
+        let bg = RGB(0x0a, 0x10, 0x1c);
+        let fg = RGB(0xff, 0xff, 0xff);
+        let w = (font::text_width(text) + 3) * TEXT_SCALE;
+        let h = (font::GLYPH_H + 4) * TEXT_SCALE;
+        let y = self.size.1.saturating_sub(h);
+        let rect = Rect::new(0, y as i32, w as u32, h as u32)
+                        .unwrap().unwrap();
+        self.renderer.set_draw_color(bg);
+        self.renderer.fill_rect(rect);
+        self.draw_text(text, 2 * TEXT_SCALE, y + 2 * TEXT_SCALE, fg);
+    }
+
+    // Draw the keypad in the bottom right corner of the window, with the 
+    // keys held down highlighted.
+    fn draw_keypad(&mut self, keys: &[bool; 16]) {
+        let bg = RGB(0x0a, 0x10, 0x1c);
+        let fg = RGB(0xc0, 0xc8, 0xd8);
+        let highlight = RGB(0xff, 0xd0, 0x40);
+        // Each key is a square with a gap to the next one.
+        let cell = (font::GLYPH_H + 3) * TEXT_SCALE;
+        let size = (cell - TEXT_SCALE) as u32;
+        let x0 = self.size.0.saturating_sub(4 * cell + TEXT_SCALE);
+        let y0 = self.size.1.saturating_sub(4 * cell + TEXT_SCALE);
+        for key in overlay::keypad_cells(keys) {
+            let x = x0 + key.col * cell;
+            let y = y0 + key.row * cell;
+            let rect = Rect::new(x as i32, y as i32, size, size)
+                            .unwrap().unwrap();
+            self.renderer.set_draw_color(if key.pressed {highlight} else {bg});
+            self.renderer.fill_rect(rect);
+            let label = key.label().to_string();
+            self.draw_text(&label, x + 2 * TEXT_SCALE, y + TEXT_SCALE, 
+                           if key.pressed {bg} else {fg});
+        }
+    }
+
+    // Draw the timing graph in a strip along the bottom of the window, with
+    // a line marking the time a frame may take, and the stats on top.
+    fn draw_timing_graph(&mut self, samples: &[FrameTiming], 
+                         stats: &MetroStats) {
+        let bg = RGB(0x0a, 0x10, 0x1c);
+        let emulate = RGB(0x40, 0xc0, 0x60);
+        let present = RGB(0x40, 0x80, 0xff);
+        let sleep = RGB(0x50, 0x58, 0x68);
+        let budget = RGB(0xff, 0x40, 0x40);
+        let height = self.size.1 / 4;
+        let scale = (self.size.0 / TIMING_FRAMES).max(1);
+        let graph = overlay::timing_graph(samples, scale, height);
+        let bottom = self.size.1;
+        let strip = Rect::new(0, (bottom - height) as i32, 
+                              (TIMING_FRAMES * scale) as u32, height as u32)
+                         .unwrap().unwrap();
+        self.renderer.set_draw_color(bg);
+        self.renderer.fill_rect(strip);
+        for bar in graph.bars {
+            let mut y = bottom;
+            for &(h, color) in &[(bar.emulate, emulate), 
+                                 (bar.present, present), 
+                                 (bar.sleep, sleep)] {
+                y -= h;
+                if let Ok(Some(rect)) = Rect::new(bar.x as i32, y as i32, 
+                                                  graph.bar_w as u32, 
+                                                  h as u32) {
+                    self.renderer.set_draw_color(color);
+                    self.renderer.fill_rect(rect);
+                }
+            }
+        }
+        let y = (bottom - graph.budget_h) as i32;
+        self.renderer.set_draw_color(budget);
+        self.renderer.draw_line(Point::new(0, y), 
+                                Point::new((TIMING_FRAMES * scale) as i32, y));
+        let fg = RGB(0xc0, 0xc8, 0xd8);
+        self.draw_text(&stats.summary(), 2 * TEXT_SCALE, 
+                       bottom - height + 2 * TEXT_SCALE, fg);
+    }
+
+    // Draw the speaker in the top left corner of the window.
+    fn draw_sound_indicator(&mut self) {
+        let fg = RGB(0xff, 0xd0, 0x40);
+        let speaker = font::SPEAKER.to_string();
+        self.draw_text(&speaker, 2 * TEXT_SCALE, 2 * TEXT_SCALE, fg);
+        // Crossed out while muted.
+        if self.muted {
+            let cross = RGB(0xff, 0x40, 0x40);
+            self.draw_text("X", 2 * TEXT_SCALE, 2 * TEXT_SCALE, cross);
+        }
+    }
+
+    // Draw the text with its top left corner at window position (x, y).
+    fn draw_text(&mut self, text: &str, x: usize, y: usize, color: Color) {
+        self.draw_scaled_text(text, x, y, TEXT_SCALE, color);
+    }
+
+    fn draw_scaled_text(&mut self, text: &str, x: usize, y: usize, 
+                        scale: usize, color: Color) {
+        self.renderer.set_draw_color(color);
+        for (px, py) in font::text_pixels(text) {
+            let rect = Rect::new((x + px * scale) as i32, 
+                                 (y + py * scale) as i32, 
+                                 scale as u32, 
+                                 scale as u32).unwrap().unwrap();
+            self.renderer.fill_rect(rect);
+        }
+    }
     
     pub fn poll_event(&self) -> Option<Event> {
         let mut event_pump = self.sdl_ctx.event_pump().unwrap();
         return event_pump.poll_event();
     }
 
-    pub fn get_updated_keys(&self) -> [bool; 16] {
-        let event_pump = self.sdl_ctx.event_pump().unwrap();
-        let keyboard_state = event_pump.keyboard_state();
-        let mut keys = [false; 16];
-        keys[0x0] = keyboard_state.is_scancode_pressed(Scancode::X);
-        keys[0x1] = keyboard_state.is_scancode_pressed(Scancode::Num1);
-        keys[0x2] = keyboard_state.is_scancode_pressed(Scancode::Num2);
-        keys[0x3] = keyboard_state.is_scancode_pressed(Scancode::Num3);
-        keys[0x4] = keyboard_state.is_scancode_pressed(Scancode::Q);
-        keys[0x5] = keyboard_state.is_scancode_pressed(Scancode::W);
-        keys[0x6] = keyboard_state.is_scancode_pressed(Scancode::E);
-        keys[0x7] = keyboard_state.is_scancode_pressed(Scancode::A);
-        keys[0x8] = keyboard_state.is_scancode_pressed(Scancode::S);
-        keys[0x9] = keyboard_state.is_scancode_pressed(Scancode::D);
-        keys[0xA] = keyboard_state.is_scancode_pressed(Scancode::Z);
-        keys[0xB] = keyboard_state.is_scancode_pressed(Scancode::C);
-        keys[0xC] = keyboard_state.is_scancode_pressed(Scancode::Num4);
-        keys[0xD] = keyboard_state.is_scancode_pressed(Scancode::R);
-        keys[0xE] = keyboard_state.is_scancode_pressed(Scancode::F);
-        keys[0xF] = keyboard_state.is_scancode_pressed(Scancode::V);
-        keys
+    // Start receiving events from the gamepad that was plugged in at the
+    // given device index. Joysticks that SDL does not know how to map as 
+    // gamepads are ignored.
+    pub fn open_controller(&mut self, index: i32) {
+        let index = index as u32;
+        if self.controller_subsystem.is_game_controller(index) {
+            if let Ok(controller) = self.controller_subsystem.open(index) {
+                self.controllers.push(controller);
+            }
+        }
     }
 
+    // Let go of the gamepads that have been unplugged.
+    pub fn close_detached_controllers(&mut self) {
+        self.controllers.retain(|controller| controller.attached());
+    }
+
+}
+
+// The keypad key a keyboard key is mapped onto, if any.
+pub fn keypad_key(mapping: &KeyboardMapping, scancode: Scancode) 
+                  -> Option<u8> {
+    mapping.key(scancode.name())
+}
+
+// The gamepad button as known to the input mapping, if it is one that can
+// be mapped.
+pub fn gamepad_button(button: controller::Button) -> Option<Button> {
+    match button {
+        controller::Button::A => Some(Button::A),
+        controller::Button::B => Some(Button::B),
+        controller::Button::X => Some(Button::X),
+        controller::Button::Y => Some(Button::Y),
+        controller::Button::Back => Some(Button::Back),
+        controller::Button::Start => Some(Button::Start),
+        controller::Button::LeftShoulder => Some(Button::LeftShoulder),
+        controller::Button::RightShoulder => Some(Button::RightShoulder),
+        controller::Button::DPadUp => Some(Button::DPadUp),
+        controller::Button::DPadDown => Some(Button::DPadDown),
+        controller::Button::DPadLeft => Some(Button::DPadLeft),
+        controller::Button::DPadRight => Some(Button::DPadRight),
+        _ => None,
+    }
 }
@@ -1,51 +1,1519 @@
+use super::config::{Config, ConfigError};
+use super::emu::BEEP_FREQUENCY_HZ;
+use std::f32::consts::PI;
+use std::fs::File;
+use std::i16;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
 pub const SAMPLE_RATE_HZ: usize = 44100;
 pub const SAMPLES: usize = 288; 
 pub const CHANNELS: usize = 1;
+/// The sample rates that can be chosen, in Hz.
+pub const MIN_SAMPLE_RATE_HZ: usize = 8000;
+pub const MAX_SAMPLE_RATE_HZ: usize = 96000;
+/// The sizes of audio buffer that can be chosen, in samples. Smaller 
+/// buffers make for less latency, at the risk of gaps in the sound.
+pub const MIN_BUFFER_SAMPLES: usize = 64;
+pub const MAX_BUFFER_SAMPLES: usize = 8192;
+
+/// The peak amplitude of every waveform, around the silence of 0x80 in 
+/// unsigned 8 bit samples. Waveforms of the same peak amplitude do not 
+/// sound equally loud: a square wave sounds loudest, a sine or triangle 
+/// wave noticeably softer, and a sawtooth in between.
+pub const AMPLITUDE: f32 = 100.0;
+/// The sample of silence.
+pub const SILENCE: u8 = 0x80;
+/// The volume is a percentage, changed in steps of this many percent.
+pub const MAX_VOLUME: u8 = 100;
+pub const VOLUME_STEP: u8 = 10;
+/// How long a change of volume takes to ramp up or down. Changing the 
+/// gain from one sample to the next would be heard as a click.
+pub const GAIN_RAMP_MS: usize = 5;
+/// How long a beep takes to fade in when it starts, and to fade out when it
+/// stops. Starting or stopping at full amplitude would be heard as a pop.
+pub const ATTACK_MS: usize = 2;
+pub const RELEASE_MS: usize = 5;
+/// The emulator runs, and the beep is recorded, a 60Hz frame at a time.
+pub const FRAME_HZ: usize = 60;
+/// How many beep changes can wait to be played at once. More than that 
+/// and the source gives up on timing them, following the latest one.
+pub const BEEP_EVENTS: usize = 64;
+/// Beep changes further apart than this many frames, or out of order, 
+/// are played as they come rather than timed from the one before, as the
+/// emulator was reset or restored in between.
+pub const MAX_BEEP_GAP_FRAMES: usize = FRAME_HZ * 60;
+/// The size of the header of a WAV file, up to the samples.
+const WAV_HEADER_LEN: u32 = 44;
+
+const WAVEFORMS: [Waveform; 4] = [Waveform::Square, Waveform::Sine, 
+                                  Waveform::Triangle, Waveform::Sawtooth];
+const DUTY_CYCLES: [DutyCycle; 4] = [DutyCycle::Eighth, DutyCycle::Quarter,
+                                     DutyCycle::Half, 
+                                     DutyCycle::ThreeQuarters];
+
+/// The shapes of wave the beep can be played with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+
+    /// The name by which the waveform is known in the configuration file
+    /// and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        WAVEFORMS.iter().cloned().find(|waveform| waveform.name() == name)
+    }
+
+    fn index(self) -> usize {
+        WAVEFORMS.iter().position(|&waveform| waveform == self).unwrap()
+    }
+
+    /// The level of the wave at a phase from 0 up to 1, from -1 to 1. All
+    /// but the sawtooth start at 0 and rise first, like a sine.
+    pub fn level(self, phase: f32) -> f32 {
+        match self {
+            Waveform::Square => if phase < 0.5 {1.0} else {-1.0},
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Triangle => if phase < 0.25 {
+                4.0 * phase
+            } else if phase < 0.75 {
+                2.0 - 4.0 * phase
+            } else {
+                4.0 * phase - 4.0
+            },
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+
+}
+
+/// How much of each period a square wave is high for. The narrower the 
+/// pulse, the thinner the beep sounds, as on some of the classic machines.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DutyCycle {
+    Eighth,
+    Quarter,
+    Half,
+    ThreeQuarters,
+}
+
+impl DutyCycle {
+
+    /// The name by which the duty cycle is known in the configuration 
+    /// file: its percentage.
+    pub fn name(self) -> &'static str {
+        match self {
+            DutyCycle::Eighth => "12.5",
+            DutyCycle::Quarter => "25",
+            DutyCycle::Half => "50",
+            DutyCycle::ThreeQuarters => "75",
+        }
+    }
+
+    /// The duty cycle of a percentage, with or without a % sign.
+    pub fn from_name(name: &str) -> Option<DutyCycle> {
+        let name = name.trim_right_matches('%');
+        DUTY_CYCLES.iter().cloned().find(|duty| duty.name() == name)
+    }
+
+    fn index(self) -> usize {
+        DUTY_CYCLES.iter().position(|&duty| duty == self).unwrap()
+    }
+
+    /// The fraction of a period the square wave is high for.
+    pub fn fraction(self) -> f32 {
+        match self {
+            DutyCycle::Eighth => 0.125,
+            DutyCycle::Quarter => 0.25,
+            DutyCycle::Half => 0.5,
+            DutyCycle::ThreeQuarters => 0.75,
+        }
+    }
+
+    /// The level of a square wave of this duty cycle at a phase from 0 up 
+    /// to 1.
+    pub fn square_level(self, phase: f32) -> f32 {
+        if phase < self.fraction() {1.0} else {-1.0}
+    }
+
+}
+
+/// Generates the unsigned 8 bit samples of a beep of a waveform, forever.
+pub struct Tone {
+    waveform: Waveform,
+    /// Applies to the square wave only.
+    duty_cycle: DutyCycle,
+    /// How far into a period the next sample is, from 0 up to 1.
+    phase: f32,
+    /// How far a period advances per sample.
+    step: f32,
+}
+
+impl Tone {
+
+    pub fn new(waveform: Waveform, frequency: f32, sample_rate: usize) -> Self {
+        Tone { waveform: waveform, duty_cycle: DutyCycle::Half, phase: 0.0, 
+               step: frequency / sample_rate as f32 }
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// Carries on from the same phase, so that changing the duty cycle 
+    /// mid-beep does not click.
+    pub fn set_duty_cycle(&mut self, duty_cycle: DutyCycle) {
+        self.duty_cycle = duty_cycle;
+    }
+
+    /// Start the next sample this far into a period, from 0 up to 1, e.g. 
+    /// to carry on from where a tone generated earlier left off.
+    pub fn set_phase(&mut self, phase: f32) {
+        self.phase = phase.max(0.0) % 1.0;
+    }
+
+}
+
+impl Tone {
+
+    /// The next sample, from -1 to 1 of full scale. Peaks at AMPLITUDE, as
+    /// the unsigned 8 bit samples do.
+    pub fn next_level(&mut self) -> f32 {
+        let level = match self.waveform {
+            Waveform::Square => self.duty_cycle.square_level(self.phase),
+            waveform => waveform.level(self.phase),
+        };
+        self.phase += self.step;
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+        }
+        level * AMPLITUDE / SILENCE as f32
+    }
+
+}
+
+impl Iterator for Tone {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        Some(sample_u8(self.next_level()))
+    }
+
+}
+
+/// Convert a sample from -1 to 1 of full scale to an unsigned 8 bit one.
+pub fn sample_u8(level: f32) -> u8 {
+    let sample = (SILENCE as f32 * (1.0 + level)).round();
+    sample.max(0.0).min(255.0) as u8
+}
+
+/// The volume a step up or down from the volume, kept from 0 to 
+/// MAX_VOLUME.
+pub fn step_volume(volume: u8, up: bool) -> u8 {
+    if up {
+        volume.saturating_add(VOLUME_STEP).min(MAX_VOLUME)
+    } else {
+        volume.saturating_sub(VOLUME_STEP)
+    }
+}
+
+/// The gain multiplier of a volume.
+pub fn volume_gain(volume: u8) -> f32 {
+    volume.min(MAX_VOLUME) as f32 / MAX_VOLUME as f32
+}
+
+/// Scales unsigned 8 bit samples around silence by a gain that ramps 
+/// towards its target one sample at a time.
+pub struct Gain {
+    current: f32,
+    target: f32,
+    /// How much the gain may change per sample.
+    step: f32,
+}
+
+impl Gain {
+
+    /// A gain that takes ramp_samples samples to ramp all the way from 0 
+    /// to 1.
+    pub fn new(gain: f32, ramp_samples: usize) -> Self {
+        Gain { current: gain, target: gain, 
+               step: 1.0 / ramp_samples.max(1) as f32 }
+    }
+
+    pub fn set_target(&mut self, gain: f32) {
+        self.target = gain;
+    }
+
+    pub fn current(&self) -> f32 {
+        self.current
+    }
+
+    /// Scale the sample by the gain, then ramp the gain a step closer to 
+    /// its target.
+    pub fn apply(&mut self, sample: u8) -> u8 {
+        let level = sample as f32 - SILENCE as f32;
+        (SILENCE as f32 + level * self.next_gain()).round() as u8
+    }
+
+    /// The gain for the next sample, ramping a step closer to the target.
+    pub fn next_gain(&mut self) -> f32 {
+        let gain = self.current;
+        if self.current < self.target {
+            self.current = (self.current + self.step).min(self.target);
+        } else {
+            self.current = (self.current - self.step).max(self.target);
+        }
+        gain
+    }
+
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Stage {
+    Silent,
+    Attack,
+    Sustain,
+    Release,
+}
+
+/// Fades a beep in when it starts and out when it stops. A beep that stops
+/// before it has faded in fully still fades in before fading out, so that 
+/// even the shortest beep is heard as a short blip.
+pub struct Envelope {
+    stage: Stage,
+    /// The gain applied to the next sample, from 0 to 1.
+    level: f32,
+    /// Set when the beep stopped during the attack.
+    stopping: bool,
+    /// How much the level changes per sample during the attack and release.
+    attack_step: f32,
+    release_step: f32,
+}
+
+impl Envelope {
+
+    pub fn new(sample_rate: usize) -> Self {
+        let step = |ms: usize| 1000.0 / (sample_rate * ms).max(1) as f32;
+        Envelope { stage: Stage::Silent, level: 0.0, stopping: false,
+                   attack_step: step(ATTACK_MS), 
+                   release_step: step(RELEASE_MS) }
+    }
+
+    /// The beep starts. Fades in from wherever a release has got to.
+    pub fn start(&mut self) {
+        self.stopping = false;
+        if self.stage == Stage::Silent || self.stage == Stage::Release {
+            self.stage = Stage::Attack;
+        }
+    }
+
+    /// The beep stops, fading out once it has faded in.
+    pub fn stop(&mut self) {
+        self.stopping = true;
+        if self.stage == Stage::Sustain {
+            self.stage = Stage::Release;
+        }
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.stage == Stage::Silent
+    }
+
+    /// Scale the sample by the level of the envelope around silence, then 
+    /// move on by a sample.
+    pub fn apply(&mut self, sample: u8) -> u8 {
+        let level = sample as f32 - SILENCE as f32;
+        (SILENCE as f32 + level * self.next_gain()).round() as u8
+    }
+
+    /// The level of the envelope for the next sample, moving on by a 
+    /// sample.
+    pub fn next_gain(&mut self) -> f32 {
+        let gain = self.level;
+        match self.stage {
+            Stage::Attack => {
+                self.level = (self.level + self.attack_step).min(1.0);
+                if self.level == 1.0 {
+                    self.stage = if self.stopping {Stage::Release} 
+                                 else {Stage::Sustain};
+                }
+            },
+            Stage::Release => {
+                self.level = (self.level - self.release_step).max(0.0);
+                if self.level == 0.0 {
+                    self.stage = Stage::Silent;
+                }
+            },
+            Stage::Silent | Stage::Sustain => {},
+        }
+        gain
+    }
+
+}
+
+/// A change of beep, as the emulator made it. 
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct BeepEvent {
+    on: bool,
+    /// The emulated frame the beep changed at, if the change is to be 
+    /// timed from the one before rather than played at once.
+    frame: Option<usize>,
+}
+
+impl BeepEvent {
+
+    fn encode(self) -> usize {
+        let frame = self.frame.map(|frame| frame << 2 | 0b10).unwrap_or(0);
+        frame | self.on as usize
+    }
+
+    fn decode(bits: usize) -> Self {
+        BeepEvent { on: bits & 0b01 != 0,
+                    frame: if bits & 0b10 != 0 {Some(bits >> 2)} else {None} }
+    }
+
+}
+
+/// The beep changes on their way from the ui to the source: a ring that 
+/// one thread pushes to and another pops from, without either waiting.
+struct BeepEvents {
+    slots: Vec<AtomicUsize>,
+    /// The number of events ever popped and pushed. 
+    head: AtomicUsize,
+    tail: AtomicUsize,
+    /// Where the latest untimed event was pushed. Those before it are 
+    /// skipped, as it overrides them.
+    latest_untimed: AtomicUsize,
+    /// Set when an event did not fit.
+    overflowed: AtomicBool,
+}
+
+impl BeepEvents {
+
+    fn new() -> Self {
+        BeepEvents { slots: (0..BEEP_EVENTS).map(|_| AtomicUsize::new(0))
+                                            .collect(),
+                     head: AtomicUsize::new(0), tail: AtomicUsize::new(0),
+                     latest_untimed: AtomicUsize::new(0),
+                     overflowed: AtomicBool::new(false) }
+    }
+
+    fn push(&self, event: BeepEvent) {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == 
+           BEEP_EVENTS {
+            self.overflowed.store(true, Ordering::Release);
+            return;
+        }
+        self.slots[tail % BEEP_EVENTS].store(event.encode(), 
+                                             Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        if event.frame.is_none() {
+            self.latest_untimed.store(tail, Ordering::Release);
+        }
+    }
+
+    fn peek(&self) -> Option<BeepEvent> {
+        let latest_untimed = self.latest_untimed.load(Ordering::Acquire);
+        let mut head = self.head.load(Ordering::Relaxed);
+        let behind = latest_untimed.wrapping_sub(head);
+        if behind > 0 && behind < BEEP_EVENTS {
+            head = latest_untimed;
+            self.head.store(head, Ordering::Release);
+        }
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let bits = self.slots[head % BEEP_EVENTS].load(Ordering::Relaxed);
+        Some(BeepEvent::decode(bits))
+    }
+
+    fn pop(&self) {
+        let head = self.head.load(Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Throw away the events waiting, if some were lost. Returns whether 
+    /// any were.
+    fn clear_if_overflowed(&self) -> bool {
+        if !self.overflowed.swap(false, Ordering::Acquire) {
+            return false;
+        }
+        self.head.store(self.tail.load(Ordering::Acquire), Ordering::Release);
+        true
+    }
+
+}
+
+/// The state of the sound, as set by the ui from what the emulator says, 
+/// and picked up by a SampleSource as it fills buffers. Shared through 
+/// atomics, so that neither side ever waits for the other. Beeps should 
+/// be started and stopped from one thread only.
+pub struct SoundControls {
+    /// The latest beep asked for, timed or not.
+    beeping: AtomicBool,
+    events: BeepEvents,
+    muted: AtomicBool,
+    /// A percentage, from 0 to MAX_VOLUME.
+    volume: AtomicUsize,
+    /// The position of the waveform in WAVEFORMS, and of the duty cycle in
+    /// DUTY_CYCLES.
+    waveform: AtomicUsize,
+    duty_cycle: AtomicUsize,
+    /// The speed of emulation relative to normal, as the bits of an f32.
+    speed: AtomicUsize,
+}
+
+impl SoundControls {
+
+    pub fn new(waveform: Waveform) -> Self {
+        SoundControls { beeping: AtomicBool::new(false),
+                        events: BeepEvents::new(),
+                        muted: AtomicBool::new(false),
+                        volume: AtomicUsize::new(MAX_VOLUME as usize),
+                        waveform: AtomicUsize::new(waveform.index()),
+                        duty_cycle: AtomicUsize::new(
+                            DutyCycle::Half.index()),
+                        speed: AtomicUsize::new(1.0f32.to_bits() as usize) }
+    }
+
+    /// The beep starts or stops with the next sample, whatever the 
+    /// emulator does meanwhile, e.g. when pausing.
+    pub fn beep(&self, on: bool) {
+        self.change_beep(BeepEvent { on: on, frame: None });
+    }
+
+    /// The beep starts or stops as the emulator did at the frame. It plays 
+    /// for as long as the frames since the previous change take at the 
+    /// current speed, however the change is delayed on its way, so that 
+    /// beeps keep their length in emulated time.
+    pub fn beep_at(&self, frame: u64, on: bool) {
+        self.change_beep(BeepEvent { on: on, frame: Some(frame as usize) });
+    }
+
+    fn change_beep(&self, event: BeepEvent) {
+        self.beeping.store(event.on, Ordering::Relaxed);
+        self.events.push(event);
+    }
+
+    /// Set how fast the emulator runs relative to normal. Beeps keep their
+    /// pitch, and their length scales with the frames they last.
+    pub fn set_speed(&self, speed: f32) {
+        self.speed.store(speed.to_bits() as usize, Ordering::Relaxed);
+    }
+
+    pub fn set_waveform(&self, waveform: Waveform) {
+        self.waveform.store(waveform.index(), Ordering::Relaxed);
+    }
+
+    pub fn set_duty_cycle(&self, duty_cycle: DutyCycle) {
+        self.duty_cycle.store(duty_cycle.index(), Ordering::Relaxed);
+    }
+
+    /// Set the volume as a percentage, which the source ramps to.
+    pub fn set_volume(&self, volume: u8) {
+        self.volume.store(volume as usize, Ordering::Relaxed);
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+}
+
+/// Renders the beep, for whatever plays it to pull samples from as it needs
+/// them. Beeps start and stop as the controls say, fading in and out, at 
+/// the volume set. Filling a buffer neither allocates nor locks, so it can
+/// be done from a real-time audio callback.
+pub struct SampleSource {
+    controls: Arc<SoundControls>,
+    sample_rate: usize,
+    tone: Tone,
+    envelope: Envelope,
+    gain: Gain,
+    /// Silences the samples, while beeps go on starting and stopping 
+    /// unheard. Unmuting in the middle of a beep carries on with it.
+    muted: bool,
+    /// The frame of the last timed beep change played, and how many 
+    /// emulated frames have been rendered since.
+    played_frame: Option<usize>,
+    frames_since: f64,
+    /// How many emulated frames each sample lasts at the current speed.
+    frames_per_sample: f64,
+}
+
+impl SampleSource {
+
+    pub fn new(waveform: Waveform, sample_rate: usize) -> Self {
+        SampleSource { 
+            controls: Arc::new(SoundControls::new(waveform)),
+            sample_rate: sample_rate,
+            tone: Tone::new(waveform, BEEP_FREQUENCY_HZ, sample_rate),
+            envelope: Envelope::new(sample_rate),
+            gain: Gain::new(1.0, sample_rate * GAIN_RAMP_MS / 1000),
+            muted: false,
+            played_frame: None,
+            frames_since: 0.0,
+            frames_per_sample: FRAME_HZ as f64 / sample_rate as f64,
+        }
+    }
+
+    /// The controls of the source, for sharing with whoever drives the 
+    /// sound while the source is being filled elsewhere.
+    pub fn controls(&self) -> Arc<SoundControls> {
+        self.controls.clone()
+    }
+
+    pub fn beep(&self, on: bool) {
+        self.controls.beep(on);
+    }
+
+    pub fn beep_at(&self, frame: u64, on: bool) {
+        self.controls.beep_at(frame, on);
+    }
+
+    pub fn set_speed(&self, speed: f32) {
+        self.controls.set_speed(speed);
+    }
+
+    pub fn set_waveform(&self, waveform: Waveform) {
+        self.controls.set_waveform(waveform);
+    }
+
+    pub fn set_duty_cycle(&self, duty_cycle: DutyCycle) {
+        self.controls.set_duty_cycle(duty_cycle);
+    }
+
+    pub fn set_volume(&self, volume: u8) {
+        self.controls.set_volume(volume);
+    }
+
+    pub fn set_muted(&self, muted: bool) {
+        self.controls.set_muted(muted);
+    }
+
+    /// Render the next samples, from -1 to 1 of full scale.
+    pub fn fill(&mut self, out: &mut [f32]) {
+        self.apply_controls();
+        for sample in out.iter_mut() {
+            *sample = self.next_sample();
+        }
+    }
+
+    /// Render the next samples as unsigned 8 bit ones.
+    pub fn fill_u8(&mut self, out: &mut [u8]) {
+        self.apply_controls();
+        for sample in out.iter_mut() {
+            *sample = sample_u8(self.next_sample());
+        }
+    }
+
+    fn apply_controls(&mut self) {
+        let controls = &self.controls;
+        if controls.events.clear_if_overflowed() {
+            self.played_frame = None;
+            if controls.beeping.load(Ordering::Relaxed) {
+                self.envelope.start();
+            } else {
+                self.envelope.stop();
+            }
+        }
+        let speed = f32::from_bits(controls.speed.load(Ordering::Relaxed) 
+                                   as u32);
+        self.frames_per_sample = speed as f64 * FRAME_HZ as f64 / 
+                                 self.sample_rate as f64;
+        let waveform = controls.waveform.load(Ordering::Relaxed);
+        self.tone.set_waveform(WAVEFORMS[waveform]);
+        let duty_cycle = controls.duty_cycle.load(Ordering::Relaxed);
+        self.tone.set_duty_cycle(DUTY_CYCLES[duty_cycle]);
+        let volume = controls.volume.load(Ordering::Relaxed);
+        self.gain.set_target(volume_gain(volume as u8));
+        self.muted = controls.muted.load(Ordering::Relaxed);
+    }
+
+    /// Play the beep changes that are due by the next sample. A timed 
+    /// change is due once the frames since the one before it have been 
+    /// rendered. What is left over of the last sample counts towards the 
+    /// next change, so that beeps keep their length however the samples 
+    /// fall, but a change that came late does not bring the next forward.
+    fn play_beep_events(&mut self) {
+        while let Some(event) = self.controls.events.peek() {
+            let gap = match (event.frame, self.played_frame) {
+                (Some(frame), Some(played)) => frame.checked_sub(played)
+                    .and_then(|gap| if gap <= MAX_BEEP_GAP_FRAMES {Some(gap)}
+                                    else {None}),
+                _ => None,
+            };
+            match gap {
+                Some(gap) if self.frames_since < gap as f64 => return,
+                Some(gap) => {
+                    self.frames_since = (self.frames_since - gap as f64)
+                        .min(self.frames_per_sample);
+                },
+                None => self.frames_since = 0.0,
+            }
+            self.played_frame = event.frame;
+            if event.on {
+                self.envelope.start();
+            } else {
+                self.envelope.stop();
+            }
+            self.controls.events.pop();
+        }
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        self.play_beep_events();
+        self.frames_since += self.frames_per_sample;
+        let level = self.tone.next_level() * self.envelope.next_gain() * 
+                    self.gain.next_gain();
+        if self.muted {0.0} else {level}
+    }
+
+}
+
+/// Something that plays the beep by pulling samples from a SampleSource, 
+/// such as an audio device. Embedders implement it for their own mixer, 
+/// handing out the controls of the source they fill from.
+pub trait AudioSink {
+
+    fn controls(&self) -> &SoundControls;
+
+    fn beep(&self, on: bool) {
+        self.controls().beep(on);
+    }
+
+    fn beep_at(&self, frame: u64, on: bool) {
+        self.controls().beep_at(frame, on);
+    }
+
+    fn set_speed(&self, speed: f32) {
+        self.controls().set_speed(speed);
+    }
+
+    fn set_waveform(&self, waveform: Waveform) {
+        self.controls().set_waveform(waveform);
+    }
+
+    fn set_duty_cycle(&self, duty_cycle: DutyCycle) {
+        self.controls().set_duty_cycle(duty_cycle);
+    }
+
+    fn set_volume(&self, volume: u8) {
+        self.controls().set_volume(volume);
+    }
+
+    fn set_muted(&self, muted: bool) {
+        self.controls().set_muted(muted);
+    }
+
+}
+
+/// Whether the sample rate can be chosen.
+pub fn is_valid_sample_rate(sample_rate: usize) -> bool {
+    sample_rate >= MIN_SAMPLE_RATE_HZ && sample_rate <= MAX_SAMPLE_RATE_HZ
+}
+
+/// The size of audio buffer nearest to the one asked for, from 
+/// MIN_BUFFER_SAMPLES to MAX_BUFFER_SAMPLES.
+pub fn clamp_buffer_samples(samples: usize) -> usize {
+    samples.max(MIN_BUFFER_SAMPLES).min(MAX_BUFFER_SAMPLES)
+}
+
+/// How the beep sounds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AudioSettings {
+    pub waveform: Waveform,
+    pub duty_cycle: DutyCycle,
+    /// A percentage, from 0 to MAX_VOLUME.
+    pub volume: u8,
+    /// Silences the beep, apart from in recordings.
+    pub muted: bool,
+    pub sample_rate: usize,
+    /// The size of the audio buffer in samples, as clamped by 
+    /// clamp_buffer_samples.
+    pub buffer_samples: usize,
+}
+
+impl AudioSettings {
+
+    pub fn new() -> Self {
+        AudioSettings { waveform: Waveform::Square, 
+                        duty_cycle: DutyCycle::Half, volume: MAX_VOLUME,
+                        muted: false, sample_rate: SAMPLE_RATE_HZ, 
+                        buffer_samples: SAMPLES }
+    }
+
+    /// The settings in the [audio] section of the configuration:
+    ///
+    /// ```text
+    /// [audio]
+    /// waveform = sine
+    /// duty_cycle = 25
+    /// volume = 80
+    /// muted = false
+    /// sample_rate = 48000
+    /// buffer_samples = 512
+    /// ```
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut settings = AudioSettings::new();
+        for setting in config.section("audio") {
+            match &setting.key[..] {
+                "waveform" => settings.waveform = 
+                    Waveform::from_name(&setting.value).ok_or_else(|| {
+                        setting.error(
+                            "expected square, sine, triangle or sawtooth")
+                    })?,
+                "duty_cycle" => settings.duty_cycle = 
+                    DutyCycle::from_name(&setting.value).ok_or_else(|| {
+                        setting.error("expected 12.5, 25, 50 or 75")
+                    })?,
+                "volume" => settings.volume = match setting.parse()? {
+                    volume if volume <= MAX_VOLUME => volume,
+                    _ => return Err(setting.error("expected 0 to 100")),
+                },
+                "muted" => settings.muted = setting.parse()?,
+                "sample_rate" => settings.sample_rate = match setting.parse()? {
+                    rate if is_valid_sample_rate(rate) => rate,
+                    _ => return Err(setting.error("expected 8000 to 96000")),
+                },
+                "buffer_samples" => settings.buffer_samples = 
+                    clamp_buffer_samples(setting.parse()?),
+                _ => return Err(setting.error("unknown audio setting")),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// The settings as written in the [audio] section, for writing them 
+    /// out with config::set_settings.
+    pub fn to_config(&self) -> Vec<(&'static str, String)> {
+        vec![("waveform", self.waveform.name().to_string()),
+             ("duty_cycle", self.duty_cycle.name().to_string()),
+             ("volume", self.volume.to_string()),
+             ("muted", self.muted.to_string()),
+             ("sample_rate", self.sample_rate.to_string()),
+             ("buffer_samples", self.buffer_samples.to_string())]
+    }
+
+}
+
+/// Convert an unsigned 8 bit sample to a signed 16 bit one.
+pub fn sample_i16(sample: u8) -> i16 {
+    (sample as i16 - SILENCE as i16) << 8
+}
+
+/// Writes mono 16 bit samples to a RIFF/WAVE file. The lengths in the 
+/// header are only known once all samples have been written, so they are
+/// patched by finalize.
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    /// The number of bytes of samples written so far.
+    data_len: u32,
+}
+
+impl WavWriter<BufWriter<File>> {
+
+    pub fn create<P: AsRef<Path>>(path: P, 
+                                  sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+
+    /// Start writing to out, beginning with a header of empty lengths.
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(WAV_HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        push_u32_le(&mut header, WAV_HEADER_LEN - 8);
+        header.extend_from_slice(b"WAVEfmt ");
+        // The format chunk: uncompressed PCM, mono, 16 bits per sample.
+        push_u32_le(&mut header, 16);
+        push_u16_le(&mut header, 1);
+        push_u16_le(&mut header, 1);
+        push_u32_le(&mut header, sample_rate);
+        push_u32_le(&mut header, sample_rate * 2);
+        push_u16_le(&mut header, 2);
+        push_u16_le(&mut header, 16);
+        header.extend_from_slice(b"data");
+        push_u32_le(&mut header, 0);
+        out.write_all(&header)?;
+        Ok(WavWriter { out: out, data_len: 0 })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(samples.len() * 2);
+        for &sample in samples {
+            push_u16_le(&mut bytes, sample as u16);
+        }
+        self.out.write_all(&bytes)?;
+        self.data_len += bytes.len() as u32;
+        Ok(())
+    }
+
+    /// Patch the lengths in the header, and hand back what was written to.
+    pub fn finalize(mut self) -> io::Result<W> {
+        let mut riff_len = Vec::new();
+        push_u32_le(&mut riff_len, WAV_HEADER_LEN - 8 + self.data_len);
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&riff_len)?;
+        let mut data_len = Vec::new();
+        push_u32_le(&mut data_len, self.data_len);
+        self.out.seek(SeekFrom::Start(WAV_HEADER_LEN as u64 - 4))?;
+        self.out.write_all(&data_len)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+}
+
+/// Records the beep to a WAV file, a frame at a time, as it would be 
+/// played: faded in and out, and silent in between beeps.
+pub struct BeepRecorder {
+    writer: WavWriter<BufWriter<File>>,
+    source: SampleSource,
+    sample_rate: usize,
+    /// The frames and samples recorded so far. Frames need not last a whole
+    /// number of samples, so this keeps the recording in step.
+    frames: usize,
+    samples: usize,
+}
+
+impl BeepRecorder {
+
+    pub fn create<P: AsRef<Path>>(path: P, waveform: Waveform, 
+                                  sample_rate: usize) -> io::Result<Self> {
+        Ok(BeepRecorder { 
+            writer: WavWriter::create(path, sample_rate as u32)?,
+            source: SampleSource::new(waveform, sample_rate),
+            sample_rate: sample_rate,
+            frames: 0,
+            samples: 0,
+        })
+    }
+
+    /// The beep starts or stops at the start of the next frame.
+    pub fn beep(&mut self, on: bool) {
+        self.source.beep(on);
+    }
+
+    pub fn set_duty_cycle(&mut self, duty_cycle: DutyCycle) {
+        self.source.set_duty_cycle(duty_cycle);
+    }
+
+    pub fn record_frame(&mut self) -> io::Result<()> {
+        self.frames += 1;
+        let due = self.frames * self.sample_rate / FRAME_HZ - self.samples;
+        self.samples += due;
+        let mut levels = vec![0.0; due];
+        self.source.fill(&mut levels);
+        let samples: Vec<i16> = levels.iter()
+            .map(|&level| (level * i16::MAX as f32).round() as i16)
+            .collect();
+        self.writer.write_samples(&samples)
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finalize().map(|_| ())
+    }
+
+}
+
+// Append a 16-bit value in little endian order, as WAV files use.
+fn push_u16_le(bytes: &mut Vec<u8>, value: u16) {
+    bytes.push(value as u8);
+    bytes.push((value >> 8) as u8);
+}
+
+fn push_u32_le(bytes: &mut Vec<u8>, value: u32) {
+    push_u16_le(bytes, value as u16);
+    push_u16_le(bytes, (value >> 16) as u16);
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{clamp_buffer_samples, step_volume, volume_gain};
+    use super::{AudioSettings, BeepRecorder, DutyCycle, Envelope, Gain};
+    use super::SampleSource;
+    use super::{Tone, WavWriter, Waveform, AMPLITUDE, SILENCE};
+    use super::super::config::{self, Config};
+    use std::env;
+    use std::fs::File;
+    use std::io::{Cursor, Read};
+
+    // A period of the waveform, at a frequency that makes for 8 samples 
+    // per period.
+    fn period(waveform: Waveform) -> Vec<u8> {
+        Tone::new(waveform, 100.0, 800).take(8).collect()
+    }
+
+    #[test]
+    fn test_square_period() {
+        assert_eq!(vec![228, 228, 228, 228, 28, 28, 28, 28],
+                   period(Waveform::Square));
+    }
+
+    #[test]
+    fn test_sine_period() {
+        assert_eq!(vec![128, 199, 228, 199, 128, 57, 28, 57],
+                   period(Waveform::Sine));
+    }
+
+    #[test]
+    fn test_triangle_period() {
+        assert_eq!(vec![128, 178, 228, 178, 128, 78, 28, 78],
+                   period(Waveform::Triangle));
+    }
+
+    #[test]
+    fn test_sawtooth_period() {
+        assert_eq!(vec![28, 53, 78, 103, 128, 153, 178, 203],
+                   period(Waveform::Sawtooth));
+    }
+
+    // The average number of samples from one rise through silence to the 
+    // next, over a second of a sine wave.
+    fn zero_crossing_period(frequency: f32, sample_rate: usize) -> f32 {
+        let samples: Vec<u8> = Tone::new(Waveform::Sine, frequency, 
+                                         sample_rate).take(sample_rate)
+                                                     .collect();
+        let rises: Vec<usize> = samples.windows(2).enumerate()
+            .filter(|&(_, pair)| pair[0] < SILENCE && pair[1] >= SILENCE)
+            .map(|(i, _)| i).collect();
+        (rises[rises.len() - 1] - rises[0]) as f32 / (rises.len() - 1) as f32
+    }
+
+    #[test]
+    fn test_tone_pitch_at_any_sample_rate() {
+        for &sample_rate in &[22050, 44100, 48000] {
+            //when
+            let period = zero_crossing_period(440.0, sample_rate);
+            //then
+            let expected = sample_rate as f32 / 440.0;
+            assert!((period - expected).abs() < 0.01 * expected,
+                    "{} samples at {}Hz", period, sample_rate);
+        }
+    }
+
+    #[test]
+    fn test_tone_repeats_periods() {
+        let samples: Vec<u8> = Tone::new(Waveform::Sine, 100.0, 800)
+            .take(24).collect();
+        assert_eq!(&samples[..8], &samples[8..16]);
+        assert_eq!(&samples[..8], &samples[16..]);
+    }
+
+    #[test]
+    fn test_square_duty_cycles() {
+        for &(duty_cycle, high) in &[(DutyCycle::Eighth, 8), 
+                                     (DutyCycle::Quarter, 16),
+                                     (DutyCycle::Half, 32),
+                                     (DutyCycle::ThreeQuarters, 48)] {
+            let mut tone = Tone::new(Waveform::Square, 100.0, 6400);
+            tone.set_duty_cycle(duty_cycle);
+            //when
+            let period: Vec<u8> = tone.take(64).collect();
+            //then
+            let count = period.iter().filter(|&&sample| sample > SILENCE)
+                                     .count();
+            assert!(count >= high - 1 && count <= high + 1, 
+                    "{} high at {}%", count, duty_cycle.name());
+        }
+    }
+
+    #[test]
+    fn test_duty_cycle_changes_keep_the_phase() {
+        let mut tone = Tone::new(Waveform::Square, 100.0, 800);
+        tone.set_duty_cycle(DutyCycle::Quarter);
+        let start: Vec<u8> = tone.by_ref().take(4).collect();
+        //when
+        tone.set_duty_cycle(DutyCycle::ThreeQuarters);
+        let rest: Vec<u8> = tone.take(4).collect();
+        //then
+        assert_eq!(vec![228, 228, 28, 28], start);
+        assert_eq!(vec![228, 228, 28, 28], rest);
+    }
+
+    #[test]
+    fn test_duty_cycle_is_picked_up_by_the_source() {
+        let mut source = SampleSource::new(Waveform::Square, 44000);
+        source.set_duty_cycle(DutyCycle::Eighth);
+        source.set_volume(50);
+        source.beep(true);
+        let mut out = [0.0; 1000];
+        source.fill(&mut out);
+        //when
+        source.fill(&mut out);
+        //then
+        // Ten periods of 100 samples, high for an eighth of each, at half 
+        // the volume.
+        let high = out.iter().filter(|&&sample| sample > 0.0).count();
+        assert!(high >= 120 && high <= 130, "{} high", high);
+        let peak = AMPLITUDE / SILENCE as f32 / 2.0;
+        assert!(out.iter().all(|&sample| (sample.abs() - peak).abs() < 1e-3));
+    }
+
+    // Render samples of a square wave through the envelope, along with the
+    // level of the envelope for each.
+    fn render(envelope: &mut Envelope, tone: &mut Tone, 
+              n: usize) -> (Vec<u8>, Vec<f32>) {
+        tone.take(n).map(|sample| {
+            let level = envelope.level();
+            (envelope.apply(sample), level)
+        }).unzip()
+    }
+
+    fn tone() -> Tone {
+        Tone::new(Waveform::Square, 1000.0, 10000)
+    }
+
+    fn is_monotonic(levels: &[f32], rising: bool) -> bool {
+        levels.windows(2).all(|pair| {
+            if rising {pair[1] >= pair[0]} else {pair[1] <= pair[0]}
+        })
+    }
+
+    #[test]
+    fn test_envelope_fades_beep_in_and_out() {
+        let (mut envelope, mut tone) = (Envelope::new(10000), tone());
+        //when
+        envelope.start();
+        let (start, rising) = render(&mut envelope, &mut tone, 100);
+        envelope.stop();
+        let (end, falling) = render(&mut envelope, &mut tone, 100);
+        //then
+        assert_eq!(SILENCE, start[0]);
+        assert_eq!(SILENCE, *end.last().unwrap());
+        assert!(is_monotonic(&rising, true));
+        assert!(is_monotonic(&falling, false));
+        // The attack takes 2ms, the release 5ms.
+        assert_eq!((0.0, 1.0), (rising[0], rising[20]));
+        assert!(rising[19] < 1.0);
+        assert_eq!(1.0, falling[0]);
+        assert!(falling[49] > 0.01 && falling[50] < 0.01);
+        assert!(envelope.is_silent());
+    }
+
+    #[test]
+    fn test_short_beep_is_a_blip() {
+        let (mut envelope, mut tone) = (Envelope::new(10000), tone());
+        //when
+        envelope.start();
+        envelope.stop();
+        let (samples, levels) = render(&mut envelope, &mut tone, 100);
+        //then
+        assert_eq!(SILENCE, samples[0]);
+        assert_eq!(SILENCE, *samples.last().unwrap());
+        assert!(samples.iter().any(|&sample| sample != SILENCE));
+        let peak = levels.iter().position(|&level| level == 1.0).unwrap();
+        assert!(is_monotonic(&levels[..peak + 1], true));
+        assert!(is_monotonic(&levels[peak..], false));
+    }
+
+    #[test]
+    fn test_restart_during_release_fades_in_from_there() {
+        let (mut envelope, mut tone) = (Envelope::new(10000), tone());
+        envelope.start();
+        render(&mut envelope, &mut tone, 30);
+        envelope.stop();
+        render(&mut envelope, &mut tone, 10);
+        //when
+        envelope.start();
+        let (_, levels) = render(&mut envelope, &mut tone, 30);
+        //then
+        assert!(levels[0] > 0.7 && levels[0] < 0.9);
+        assert!(is_monotonic(&levels, true));
+        assert_eq!(1.0, *levels.last().unwrap());
+    }
+
+    #[test]
+    fn test_sample_source_renders_scripted_beeps() {
+        let mut source = SampleSource::new(Waveform::Square, 10000);
+        let mut silent = [1.0; 100];
+        let mut beep = [0.0; 100];
+        let mut end = [1.0; 100];
+        //when
+        source.fill(&mut silent);
+        source.beep(true);
+        source.fill(&mut beep);
+        source.beep(false);
+        source.fill(&mut end);
+        //then
+        assert!(silent.iter().all(|&sample| sample == 0.0));
+        assert_eq!(0.0, beep[0]);
+        // A 440Hz square wave at full amplitude once faded in.
+        let peak = AMPLITUDE / SILENCE as f32;
+        assert!(beep[20..].iter().all(|&sample| sample.abs() == peak));
+        assert!(beep[20..].contains(&peak) && beep[20..].contains(&-peak));
+        assert!(beep[..20].iter().all(|&sample| sample.abs() < peak));
+        assert_eq!(0.0, end[99]);
+    }
+
+    #[test]
+    fn test_sample_source_volume_and_u8_samples() {
+        let mut source = SampleSource::new(Waveform::Sawtooth, 10000);
+        source.set_volume(0);
+        source.beep(true);
+        let mut samples = [0u8; 200];
+        //when
+        source.fill_u8(&mut samples);
+        //then
+        assert!(samples[100..].iter().all(|&sample| sample == SILENCE));
+        source.set_volume(100);
+        source.fill_u8(&mut samples);
+        assert!(samples[100..].iter().any(|&sample| sample < SILENCE - 50));
+    }
+
+    #[test]
+    fn test_fill_streams_without_allocating() {
+        let mut source = SampleSource::new(Waveform::Sine, 48000);
+        let controls = source.controls();
+        let mut tone = Tone::new(Waveform::Sine, 440.0, 48000);
+        // The source renders into the caller's buffer, which it must not 
+        // grow or replace.
+        let mut out = vec![0.0; 64];
+        let (capacity, ptr) = (out.capacity(), out.as_ptr());
+        controls.beep(true);
+        // Let the beep fade in.
+        for _ in 0..4 { source.fill(&mut out); }
+        for _ in 0..4 * 64 { tone.next_level(); }
+        //when
+        let mut matching = 0;
+        for _ in 0..10000 {
+            source.fill(&mut out);
+            for &sample in out.iter() {
+                if sample == tone.next_level() { matching += 1; }
+            }
+        }
+        //then
+        assert_eq!((capacity, ptr), (out.capacity(), out.as_ptr()));
+        assert_eq!(10000 * 64, matching);
+    }
+
+    #[test]
+    fn test_controls_are_picked_up_by_the_next_fill() {
+        let mut source = SampleSource::new(Waveform::Square, 10000);
+        let controls = source.controls();
+        let mut out = [0.0; 100];
+        //when
+        controls.beep(true);
+        controls.set_waveform(Waveform::Sawtooth);
+        source.fill(&mut out);
+        //then
+        // Rising, as a sawtooth does and a square does not.
+        assert!(out[30] < 0.0 && out[31] > out[30]);
+        controls.beep(false);
+        source.fill(&mut out);
+        assert_eq!(0.0, out[99]);
+    }
+
+    // Render a beep of so many frames at the speed, timed by the frames 
+    // the emulator beeped at, and count the samples it is heard for.
+    fn timed_beep_samples(speed: f32, frames: u64) -> usize {
+        let mut source = SampleSource::new(Waveform::Square, 10000);
+        source.set_speed(speed);
+        source.beep_at(100, true);
+        source.beep_at(100 + frames, false);
+        let mut out = [0.0; 256];
+        let mut heard = 0;
+        for _ in 0..100 {
+            source.fill(&mut out);
+            heard += out.iter().filter(|&&sample| sample != 0.0).count();
+        }
+        heard
+    }
+
+    #[test]
+    fn test_timed_beeps_last_their_frames_at_any_speed() {
+        // The release of a beep is heard for another 50 samples.
+        let release = 50;
+        for &(speed, samples) in &[(1.0, 5000), (4.0, 1250), (0.5, 10000)] {
+            //when
+            let heard = timed_beep_samples(speed, 30);
+            //then
+            assert!(heard >= samples + release - 2 && 
+                    heard <= samples + release + 2, 
+                    "{} samples at {}x", heard, speed);
+        }
+    }
+
+    #[test]
+    fn test_timed_beep_at_extreme_speed_is_a_blip() {
+        //when
+        let heard = timed_beep_samples(1000.0, 1);
+        //then
+        // Faded in over 20 samples and out over 50.
+        assert!(heard >= 68 && heard <= 72, "{} samples", heard);
+    }
+
+    #[test]
+    fn test_untimed_beep_change_plays_at_once() {
+        let mut source = SampleSource::new(Waveform::Square, 10000);
+        let mut out = [1.0; 100];
+        source.beep_at(100, true);
+        source.beep_at(400, false);
+        //when
+        source.beep(false);
+        source.fill(&mut out);
+        //then
+        assert_eq!(0.0, out[99]);
+    }
+
+    #[test]
+    fn test_too_many_beep_changes_follow_the_latest() {
+        let mut source = SampleSource::new(Waveform::Square, 10000);
+        let mut out = [0.0; 100];
+        //when
+        for frame in 0..101 {
+            source.beep_at(frame, frame % 2 == 0);
+        }
+        source.fill(&mut out);
+        //then
+        assert!(out[30..].iter().all(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn test_unmuting_mid_beep_carries_on_with_it() {
+        let mut muted = SampleSource::new(Waveform::Square, 10000);
+        let mut heard = SampleSource::new(Waveform::Square, 10000);
+        muted.set_muted(true);
+        muted.beep(true);
+        heard.beep(true);
+        let (mut muted_out, mut heard_out) = ([1.0; 10], [0.0; 10]);
+        //when
+        muted.fill(&mut muted_out);
+        heard.fill(&mut heard_out);
+        //then
+        assert!(muted_out.iter().all(|&sample| sample == 0.0));
+        assert!(heard_out.iter().any(|&sample| sample != 0.0));
+        // Half way through the attack, as if it had been heard all along.
+        muted.set_muted(false);
+        muted.fill(&mut muted_out);
+        heard.fill(&mut heard_out);
+        assert_eq!(heard_out, muted_out);
+        assert!(muted_out[0].abs() > 0.3 && muted_out[0].abs() < 0.5);
+    }
+
+    #[test]
+    fn test_recording_keeps_the_beep_while_muted() {
+        let path = env::temp_dir().join("chip8_beep_muted.wav");
+        let mut recorder = BeepRecorder::create(&path, Waveform::Square, 
+                                                6000).unwrap();
+        let mut source = SampleSource::new(Waveform::Square, 6000);
+        source.set_muted(true);
+        //when
+        recorder.beep(true);
+        source.beep(true);
+        recorder.record_frame().unwrap();
+        let mut out = [1.0; 100];
+        source.fill(&mut out);
+        recorder.finish().unwrap();
+        //then
+        assert!(out.iter().all(|&sample| sample == 0.0));
+        let mut bytes = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+        let samples: Vec<i16> = bytes[44..].chunks(2)
+            .map(|pair| u16_le(pair, 0) as i16).collect();
+        assert_eq!(100, samples.len());
+        assert!(samples[12..].iter().all(|&sample| sample.abs() > 20000));
+    }
+
+    #[test]
+    fn test_audio_settings_from_config() {
+        assert_eq!(Waveform::Square, AudioSettings::new().waveform);
+        //given
+        let config = Config::parse("[audio]\nwaveform = triangle").unwrap();
+        //when
+        let settings = AudioSettings::from_config(&config).unwrap();
+        //then
+        assert_eq!(Waveform::Triangle, settings.waveform);
+        let config = Config::parse("[audio]\nwaveform = noise").unwrap();
+        assert!(AudioSettings::from_config(&config).is_err());
+        assert_eq!(Some(Waveform::Sawtooth), Waveform::from_name("sawtooth"));
+        let config = Config::parse("[audio]\nvolume = 70").unwrap();
+        assert_eq!(70, AudioSettings::from_config(&config).unwrap().volume);
+        let config = Config::parse("[audio]\nvolume = 101").unwrap();
+        assert!(AudioSettings::from_config(&config).is_err());
+        assert!(!AudioSettings::new().muted);
+        let config = Config::parse("[audio]\nmuted = true").unwrap();
+        assert!(AudioSettings::from_config(&config).unwrap().muted);
+        assert_eq!(DutyCycle::Half, AudioSettings::new().duty_cycle);
+        let config = Config::parse("[audio]\nduty_cycle = 12.5%").unwrap();
+        assert_eq!(DutyCycle::Eighth, 
+                   AudioSettings::from_config(&config).unwrap().duty_cycle);
+        let config = Config::parse("[audio]\nduty_cycle = 30").unwrap();
+        assert!(AudioSettings::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_sample_rate_and_buffer_from_config() {
+        let config = Config::parse(
+            "[audio]\nsample_rate = 48000\nbuffer_samples = 1000").unwrap();
+        //when
+        let settings = AudioSettings::from_config(&config).unwrap();
+        //then
+        assert_eq!((48000, 1000), 
+                   (settings.sample_rate, settings.buffer_samples));
+        let config = Config::parse("[audio]\nsample_rate = 1000000").unwrap();
+        assert!(AudioSettings::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_audio_settings_round_trip() {
+        //given
+        let settings = AudioSettings { waveform: Waveform::Sine, 
+                                       duty_cycle: DutyCycle::Eighth,
+                                       volume: 70, muted: true, 
+                                       sample_rate: 48000,
+                                       buffer_samples: 1024 };
+        //when
+        let text = config::set_settings("", "audio", &settings.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(settings, AudioSettings::from_config(&config).unwrap());
+    }
+
+    #[test]
+    fn test_clamp_buffer_samples() {
+        assert_eq!(288, clamp_buffer_samples(288));
+        assert_eq!(64, clamp_buffer_samples(0));
+        assert_eq!(8192, clamp_buffer_samples(1 << 20));
+        assert_eq!(2048, clamp_buffer_samples(2048));
+    }
+
+    #[test]
+    fn test_beep_recorder_keeps_in_step_at_fractional_frame_lengths() {
+        let path = env::temp_dir().join("chip8_beep_22050.wav");
+        let mut recorder = BeepRecorder::create(&path, Waveform::Square, 
+                                                22050).unwrap();
+        //when
+        for _ in 0..4 { recorder.record_frame().unwrap(); }
+        recorder.finish().unwrap();
+        //then
+        let mut bytes = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(22050, u32_le(&bytes, 24));
+        assert_eq!(1470 * 2, u32_le(&bytes, 40));
+    }
+
+    #[test]
+    fn test_volume_steps_are_clamped() {
+        assert_eq!(60, step_volume(50, true));
+        assert_eq!(40, step_volume(50, false));
+        assert_eq!(100, step_volume(95, true));
+        assert_eq!(100, step_volume(100, true));
+        assert_eq!(0, step_volume(5, false));
+        assert_eq!(0, step_volume(0, false));
+    }
+
+    #[test]
+    fn test_gain() {
+        assert_eq!(0.0, volume_gain(0));
+        assert_eq!(0.5, volume_gain(50));
+        assert_eq!(1.0, volume_gain(100));
+        assert_eq!(1.0, volume_gain(200));
+        let mut gain = Gain::new(0.5, 10);
+        // Samples are scaled around silence.
+        assert_eq!(178, gain.apply(228));
+        assert_eq!(78, gain.apply(28));
+        assert_eq!(128, gain.apply(128));
+    }
+
+    #[test]
+    fn test_gain_ramps_to_target() {
+        let mut gain = Gain::new(1.0, 10);
+        //when
+        gain.set_target(0.5);
+        let samples: Vec<u8> = (0..6).map(|_| gain.apply(228)).collect();
+        //then
+        assert_eq!(228, samples[0]);
+        assert!(samples.windows(2).all(|pair| pair[1] < pair[0]));
+        assert_eq!(178, *samples.last().unwrap());
+        assert_eq!(0.5, gain.current());
+        assert_eq!(178, gain.apply(228));
+    }
+
+    fn u16_le(bytes: &[u8], at: usize) -> u16 {
+        bytes[at] as u16 | (bytes[at + 1] as u16) << 8
+    }
+
+    fn u32_le(bytes: &[u8], at: usize) -> u32 {
+        u16_le(bytes, at) as u32 | (u16_le(bytes, at + 2) as u32) << 16
+    }
+
+    #[test]
+    fn test_wav_writer_header_and_samples() {
+        let mut writer = WavWriter::new(Cursor::new(Vec::new()), 8000)
+            .unwrap();
+        //when
+        let tone: Vec<i16> = Tone::new(Waveform::Square, 1000.0, 8000)
+            .take(16).map(super::sample_i16).collect();
+        writer.write_samples(&tone).unwrap();
+        writer.write_samples(&[-1, 0x1234]).unwrap();
+        let bytes = writer.finalize().unwrap().into_inner();
+        //then
+        assert_eq!(44 + 18 * 2, bytes.len());
+        assert_eq!(b"RIFF", &bytes[0..4]);
+        assert_eq!(36 + 18 * 2, u32_le(&bytes, 4));
+        assert_eq!(b"WAVEfmt ", &bytes[8..16]);
+        assert_eq!(16, u32_le(&bytes, 16));
+        assert_eq!((1, 1), (u16_le(&bytes, 20), u16_le(&bytes, 22)));
+        assert_eq!((8000, 16000), (u32_le(&bytes, 24), u32_le(&bytes, 28)));
+        assert_eq!((2, 16), (u16_le(&bytes, 32), u16_le(&bytes, 34)));
+        assert_eq!(b"data", &bytes[36..40]);
+        assert_eq!(18 * 2, u32_le(&bytes, 40));
+        // 100 above silence, then 100 below, in the high byte.
+        assert_eq!(100 << 8, u16_le(&bytes, 44));
+        assert_eq!((-100i16 << 8) as u16, u16_le(&bytes, 44 + 4 * 2));
+        assert_eq!(&[0xff, 0xff, 0x34, 0x12], &bytes[76..80]);
+    }
+
+    #[test]
+    fn test_beep_recorder_records_frames() {
+        let path = env::temp_dir().join("chip8_beep.wav");
+        let mut recorder = BeepRecorder::create(&path, Waveform::Square, 
+                                                44100).unwrap();
+        //when
+        recorder.record_frame().unwrap();
+        recorder.beep(true);
+        recorder.record_frame().unwrap();
+        recorder.beep(false);
+        recorder.record_frame().unwrap();
+        recorder.finish().unwrap();
+        //then
+        let mut bytes = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut bytes).unwrap();
+        assert_eq!(735 * 3 * 2, u32_le(&bytes, 40) as usize);
+        assert_eq!(44 + 735 * 3 * 2, bytes.len());
+        let samples: Vec<i16> = bytes[44..].chunks(2)
+            .map(|pair| u16_le(pair, 0) as i16).collect();
+        assert!(samples[..735].iter().all(|&sample| sample == 0));
+        assert!(samples[735..1470].iter().any(|&sample| sample != 0));
+        assert_eq!(0, *samples.last().unwrap());
+    }
 
-/// The buffer represents samples making (as close as I could get) a single 
-/// sinusoidal cycle. The sound is thus very short (about 6.5ms), but can be 
-/// prolonged by replaying in a loop. The resulting sound will consist of a 
-/// sequence of identical sinusoidal waves.
-///
-/// The header information typically found in a WAV file has been stripped and
-/// is represented instead by constants for easier reference.
-///
-/// Encoded with 8 bits per sample.
-pub const PLAYBACK_BUFFER: [u8; SAMPLES] = [
-    0xa8, 0xc1, 0xc9, 0xce, 0xd3, 0xd8, 0xd9, 0xdd,
-    0xe0, 0xe1, 0xe3, 0xe4, 0xe6, 0xe7, 0xe9, 0xe8,
-    0xeb, 0xec, 0xeb, 0xec, 0xed, 0xee, 0xef, 0xee,
-    0xf0, 0xf1, 0xf0, 0xf1, 0xf0, 0xf2, 0xf1, 0xf2,
-    0xf3, 0xf2, 0xf4, 0xf3, 0xf4, 0xf3, 0xf4, 0xf4,
-    0xf3, 0xf5, 0xf5, 0xf5, 0xf4, 0xf5, 0xf5, 0xf6,
-    0xf4, 0xf6, 0xf6, 0xf6, 0xf5, 0xf6, 0xf5, 0xf6,
-    0xf6, 0xf5, 0xf6, 0xf5, 0xf6, 0xf6, 0xf6, 0xf6,
-    0xf5, 0xf4, 0xf5, 0xf4, 0xf5, 0xf5, 0xf4, 0xf5,
-    0xf5, 0xf4, 0xf5, 0xf4, 0xf4, 0xf3, 0xf4, 0xf3,
-    0xf3, 0xf3, 0xf2, 0xf3, 0xf2, 0xf3, 0xf2, 0xf3,
-    0xf2, 0xf1, 0xf2, 0xf1, 0xf2, 0xf0, 0xf1, 0xf0,
-    0xf1, 0xf0, 0xf0, 0xef, 0xf0, 0xef, 0xef, 0xef,
-    0xee, 0xef, 0xed, 0xee, 0xee, 0xed, 0xed, 0xed,
-    0xec, 0xed, 0xec, 0xeb, 0xec, 0xec, 0xea, 0xeb,
-    0xea, 0xeb, 0xea, 0xea, 0xea, 0xe9, 0xe9, 0xe9,
-    0xe8, 0xe9, 0xe8, 0xe8, 0xe7, 0xe8, 0xe7, 0xe7,
-    0xe6, 0xe7, 0xe6, 0xe5, 0xe5, 0xe5, 0xe6, 0xe4,
-    0xe6, 0xe4, 0xd5, 0xaa, 0x71, 0x44, 0x33, 0x32,
-    0x31, 0x29, 0x24, 0x22, 0x21, 0x1f, 0x1b, 0x19,
-    0x1a, 0x19, 0x15, 0x15, 0x14, 0x14, 0x13, 0x11,
-    0x12, 0x10, 0x10, 0x0e, 0x0f, 0x0e, 0x0e, 0x0d,
-    0x0c, 0x0d, 0x0d, 0x0b, 0x0c, 0x0b, 0x0c, 0x0a,
-    0x0b, 0x0a, 0x0b, 0x09, 0x0a, 0x09, 0x0a, 0x09,
-    0x0a, 0x0a, 0x09, 0x09, 0x08, 0x09, 0x09, 0x08,
-    0x09, 0x08, 0x09, 0x08, 0x09, 0x09, 0x08, 0x09,
-    0x08, 0x09, 0x09, 0x08, 0x09, 0x09, 0x08, 0x0a,
-    0x09, 0x0a, 0x09, 0x0a, 0x09, 0x0a, 0x09, 0x0a,
-    0x0a, 0x0b, 0x0a, 0x0b, 0x0a, 0x0b, 0x0a, 0x0b,
-    0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x0c, 0x0d, 0x0c,
-    0x0d, 0x0d, 0x0e, 0x0c, 0x0d, 0x0e, 0x0e, 0x0e,
-    0x0d, 0x0f, 0x0f, 0x0f, 0x0f, 0x10, 0x10, 0x11,
-    0x10, 0x11, 0x11, 0x12, 0x10, 0x11, 0x12, 0x13,
-    0x12, 0x12, 0x14, 0x14, 0x13, 0x13, 0x13, 0x16,
-    0x13, 0x14, 0x14, 0x17, 0x16, 0x14, 0x16, 0x17,
-    0x18, 0x14, 0x16, 0x19, 0x1a, 0x15, 0x16, 0x1a,
-];
+}
@@ -0,0 +1,187 @@
+use super::Mode;
+use super::config::{Config, ConfigError};
+use super::metro::{MAX_SPEED, MIN_SPEED};
+
+// The largest window scale, as a multiple of the CHIP-8 resolution.
+pub const MAX_SCALE: u32 = 16;
+// The scale used unless configured otherwise, which makes for a 1024x512
+// window.
+pub const DEFAULT_SCALE: u32 = 16;
+
+// How the window behaves, beyond what is shown in it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct WindowSettings {
+    // Pause while another window has the focus, since the keys can not be
+    // used then anyway.
+    pub pause_on_focus_loss: bool,
+    // Present frames in step with the display, pacing emulation by the 
+    // time between presents instead of by the frame limiter.
+    pub vsync: bool,
+    // The size of the window, as a multiple of the CHIP-8 resolution.
+    pub scale: u32,
+    // How many times faster than normal the game runs while the turbo key
+    // is held, and how much slower while the slow motion key is.
+    pub turbo: f32,
+    pub slow_motion: f32,
+}
+
+impl WindowSettings {
+
+    pub fn new() -> Self {
+        WindowSettings { pause_on_focus_loss: true, vsync: false,
+                         scale: DEFAULT_SCALE, turbo: 8.0, 
+                         slow_motion: 0.5 }
+    }
+
+    // The settings in the [window] section of the configuration:
+    //
+    //     [window]
+    //     pause_on_focus_loss = false
+    //     vsync = true
+    //     scale = 8
+    //     turbo = 4
+    //     slow_motion = 0.25
+    pub fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut settings = WindowSettings::new();
+        for setting in config.section("window") {
+            match &setting.key[..] {
+                "pause_on_focus_loss" => 
+                    settings.pause_on_focus_loss = setting.parse()?,
+                "vsync" => settings.vsync = setting.parse()?,
+                "scale" => {
+                    settings.scale = setting.parse()?;
+                    if settings.scale < 1 || settings.scale > MAX_SCALE {
+                        return Err(setting.error(
+                            "expected a scale from 1 to 16"));
+                    }
+                },
+                "turbo" => {
+                    settings.turbo = setting.parse()?;
+                    if settings.turbo < 1.0 || settings.turbo > MAX_SPEED {
+                        return Err(setting.error(
+                            "expected a speed from 1 to 16"));
+                    }
+                },
+                "slow_motion" => {
+                    settings.slow_motion = setting.parse()?;
+                    if settings.slow_motion < MIN_SPEED || 
+                       settings.slow_motion > 1.0 {
+                        return Err(setting.error(
+                            "expected a speed from 0.25 to 1"));
+                    }
+                },
+                _ => return Err(setting.error("unknown window setting")),
+            }
+        }
+        Ok(settings)
+    }
+
+    // The settings as written in the [window] section, for writing them 
+    // out with config::set_settings.
+    pub fn to_config(&self) -> Vec<(&'static str, String)> {
+        vec![("pause_on_focus_loss", self.pause_on_focus_loss.to_string()),
+             ("vsync", self.vsync.to_string()),
+             ("scale", self.scale.to_string()),
+             ("turbo", self.turbo.to_string()),
+             ("slow_motion", self.slow_motion.to_string())]
+    }
+
+}
+
+// The resolution of the mode, in pixels.
+pub fn resolution(mode: Mode) -> (u32, u32) {
+    match mode {
+        Mode::STANDARD => (64, 32),
+        Mode::SUPER => (128, 64),
+    }
+}
+
+// The size of window to ask for to show the resolution at the scale, given
+// the size taken up by the borders and title bar of the window.
+pub fn window_size(scale: u32, resolution: (u32, u32), 
+                   decorations: (u32, u32)) -> (u32, u32) {
+    (resolution.0 * scale + decorations.0, 
+     resolution.1 * scale + decorations.1)
+}
+
+// The scale at which the resolution fills the inside of the window. When
+// a game switches resolution, the window keeps its size and the scale 
+// halves or doubles instead.
+pub fn scale_for(inner_size: (u32, u32), resolution: (u32, u32)) -> u32 {
+    let scale = (inner_size.0 / resolution.0).min(inner_size.1 / resolution.1);
+    scale.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::{resolution, scale_for, window_size, WindowSettings};
+    use super::super::Mode;
+    use super::super::config::{self, Config};
+
+    #[test]
+    fn test_window_settings_from_config() {
+        assert!(WindowSettings::new().pause_on_focus_loss);
+        //given
+        let config = Config::parse("[window]\n\
+                                    pause_on_focus_loss = false\n\
+                                    vsync = true\n\
+                                    scale = 4\n\
+                                    turbo = 3\n\
+                                    slow_motion = 0.25\n").unwrap();
+        //when
+        let settings = WindowSettings::from_config(&config).unwrap();
+        //then
+        assert!(!settings.pause_on_focus_loss);
+        assert!(settings.vsync);
+        assert_eq!(4, settings.scale);
+        assert_eq!(3.0, settings.turbo);
+        assert_eq!(0.25, settings.slow_motion);
+        assert_eq!(8.0, WindowSettings::new().turbo);
+        let config = Config::parse("[window]\nscale = 17").unwrap();
+        assert!(WindowSettings::from_config(&config).is_err());
+        for bad in &["turbo = 0", "turbo = 32", "slow_motion = 0.1"] {
+            let config = Config::parse(&format!("[window]\n{}", bad))
+                .unwrap();
+            assert!(WindowSettings::from_config(&config).is_err());
+        }
+        let config = Config::parse("[window]\nfullscreen = true").unwrap();
+        assert!(WindowSettings::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn test_window_settings_round_trip() {
+        //given
+        let settings = WindowSettings { pause_on_focus_loss: false, 
+                                        vsync: true, scale: 4, turbo: 2.5,
+                                        slow_motion: 0.25 };
+        //when
+        let text = config::set_settings("", "window", &settings.to_config());
+        //then
+        let config = Config::parse(&text).unwrap();
+        assert_eq!(settings, WindowSettings::from_config(&config).unwrap());
+    }
+
+    #[test]
+    fn test_window_size() {
+        assert_eq!((256, 128), window_size(4, resolution(Mode::STANDARD), 
+                                           (0, 0)));
+        assert_eq!((1024, 512), window_size(8, resolution(Mode::SUPER), 
+                                            (0, 0)));
+        // Borders and a title bar.
+        assert_eq!((132, 90), window_size(2, resolution(Mode::STANDARD), 
+                                          (4, 26)));
+    }
+
+    #[test]
+    fn test_scale_follows_resolution_switch() {
+        //given
+        let size = window_size(4, resolution(Mode::STANDARD), (0, 0));
+        //then
+        assert_eq!(4, scale_for(size, resolution(Mode::STANDARD)));
+        assert_eq!(2, scale_for(size, resolution(Mode::SUPER)));
+        let size = window_size(1, resolution(Mode::STANDARD), (0, 0));
+        assert_eq!(1, scale_for(size, resolution(Mode::SUPER)));
+    }
+
+}
@@ -0,0 +1,6 @@
+extern crate sdl2;
+extern crate time;
+
+pub mod chip8;
+
+pub use chip8::*;
@@ -1,215 +1,1170 @@
-mod chip8;
- 
+extern crate chip8;
 extern crate sdl2;
 extern crate time;
 
-use chip8::{GFX_H,GFX_W,Mode};
-use chip8::emu::Emu;
-use chip8::ui::Ui;
-use chip8::metro::Metronome;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use chip8::builtin;
+use chip8::cli;
+use chip8::compare::Lockstep;
+use chip8::config::{self, Config};
+use chip8::debugger;
+use chip8::demo::DemoRotation;
+use chip8::display::StringDisplay;
+use chip8::effects::Effects;
+use chip8::emu::{Emu, ErrorPolicy};
+use chip8::input::{Command, GamepadMapping, Gamepads, KeyEvent, Keypad};
+use chip8::input::KeyboardMapping;
+use chip8::log::Log;
+use chip8::ui::{self, Ui};
+use chip8::window::WindowSettings;
+use chip8::metro::{self, ClockSettings, CycleBudget, FramePacer};
+use chip8::metro::{FrameStats, FrameTimings};
+use chip8::metro::{MetroStats, Metronome, Phase, TIMING_FRAMES};
+use chip8::options::{self, Options, QuirkSettings};
+use chip8::picker::Picker;
+use chip8::quicksave::QuickSaves;
+use chip8::recent::RecentRoms;
+use chip8::rewind::{RewindBuffer, RewindFrame, Rewinder, REWIND_FRAMES};
+use chip8::rom::{self, RomError, RomFile};
+use chip8::romdb::{RomDb, RomProfile, RomSettings};
+use chip8::runner::{self, EmuSender, EmuToUiMsg, FrameMailbox, UiToEmuMsg};
+use chip8::wav::{self, AudioSettings, BeepRecorder};
+use sdl2::controller;
+use sdl2::event::{Event, WindowEventId};
+use sdl2::keyboard::{Keycode, Mod, LCTRLMOD, LSHIFTMOD, RCTRLMOD, 
+                     RSHIFTMOD};
 use std::env;
-use std::io::Read;
-use std::path::Path;
-use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc::{self, Sender, Receiver};
 use std::thread;
+use time::SteadyTime;
 
-// Load the emulator with the indicated ROM. 
-fn load_rom(emu: &mut Emu, path_to_rom: &Path) { 
-    let mut file = File::open(&path_to_rom).unwrap();
-    let mut rom: Vec<u8> = Vec::new();
-    file.read_to_end(&mut rom).unwrap();
-    emu.load_rom(rom);
-}
-
-// Messages that get passed from the ui to the emulator.
-enum UiToEmuMsg { Keys([bool; 16]), Paused(bool), Quit, Reset }
+// The rate at which holding the frame advance key repeats it.
+const FRAME_ADVANCE_HZ: i64 = 10;
 
-// Messages that get passed from the emulator to the ui.
-enum EmuToUiMsg { Beeping(bool), Draw(Mode, [[bool; GFX_H]; GFX_W]), QuitAck }
+// State kept by the ui between events.
+struct UiState {
+    paused: bool,
+    debugging: bool,
+    // Whether the keypad overlay is shown, and the keys it shows: those 
+    // held down as the emulator sees them.
+    showing_keypad: bool,
+    emu_keys: [bool; 16],
+    // The timings of the most recent frames, and the timing the emulator
+    // achieved, kept whether or not the timing graph is shown.
+    timings: FrameTimings,
+    metro_stats: MetroStats,
+    showing_timings: bool,
+    // Files dropped onto the window that have yet to be loaded.
+    dropped_files: Vec<PathBuf>,
+    config: Config,
+    // Where settings changed from the ui are saved.
+    config_path: Option<PathBuf>,
+    // Keys from the keyboard and gamepads are queued up here before being
+    // passed on to the emulator.
+    keypad: Keypad,
+    gamepads: Gamepads,
+    keyboard: KeyboardMapping,
+    // The running rom, whose directory the rom picker lists.
+    rom_path: PathBuf,
+    // The roms loaded most recently, and the file they are kept in.
+    recent: RecentRoms,
+    recent_path: Option<PathBuf>,
+    // The rom picker, while it is open.
+    picker: Option<Picker>,
+    window: WindowSettings,
+    audio: AudioSettings,
+    clock: ClockSettings,
+    // The profiles of roms, the settings of roms without one, and the
+    // settings given on the command line, which override both.
+    rom_db: RomDb,
+    rom_defaults: RomSettings,
+    command_line: RomProfile,
+    // Paused because the window lost the focus, rather than by the user.
+    paused_by_focus: bool,
+    // The quicksave slots of the running rom.
+    quicksaves: QuickSaves,
+    // The states of the most recent frames, and whether the game is being
+    // run backwards through them.
+    rewind: RewindBuffer,
+    rewinder: Rewinder,
+    // Limits how fast holding the frame advance key repeats.
+    frame_advance_rate: Metronome,
+    // In demo mode, the roms taking turns and the frames run so far.
+    demo: Option<DemoRotation>,
+    demo_frames: u64,
+    // Records the beep of every frame run, when asked to.
+    audio_recorder: Option<BeepRecorder>,
+    log: Log,
+}
 
 // Drives user interaction. Responsible for processing keypresses, updating
 // the screen and playing audible beeps. Communicates with the emulator by
 // exchanging messages across a two way channel. 
 //
 // Runs on the main thread.
-fn ui_exec(mut ui: Ui, tx: Sender<UiToEmuMsg>, rx: Receiver<EmuToUiMsg>) {
+//
+// With vsync, the ui also paces the emulator by asking it to run the frames
+// due for each frame presented.
+fn ui_exec(mut ui: Ui, mut state: UiState, tx: Sender<UiToEmuMsg>, 
+           rx: Receiver<EmuToUiMsg>, frames: FrameMailbox, vsync: bool) {
+    let timer_hz = state.clock.timer_hz as i64;
     let mut refresh_gfx_rate = Metronome::new(120);
-    let mut paused = false;
+    let mut pacer = FramePacer::new(timer_hz);
+    let mut rewind_rate = Metronome::new(timer_hz);
+    let mut demo_rate = Metronome::new(timer_hz);
+    let mut timing_rate = Metronome::new(timer_hz);
     'ui_exec_loop: loop {
-        process_key_presses(&mut ui, &tx, &mut paused); 
-        if process_emu_events(&mut ui, &rx, &paused, &mut refresh_gfx_rate) {
+        // With vsync the loop only comes round once per refresh, so every
+        // pending event is handled at once.
+        while process_key_presses(&mut ui, &tx, &mut state) && vsync {}
+        if process_emu_events(&mut ui, &tx, &rx, &mut state, vsync) {
             break 'ui_exec_loop;
         }
-        // Short sleep to free up cpu cycles
-        thread::sleep_ms(1);    
+        // Only the latest frame drawn is shown. While paused, the emulator
+        // only draws when single stepping.
+        if vsync {
+            refresh_gfx(&mut ui, &frames);
+        } else {
+            refresh_gfx_rate.on_tick(|| refresh_gfx(&mut ui, &frames));
+        }
+        ui.update();
+        if vsync {
+            // Presenting waits for the display, which frees up cpu cycles.
+            let frames = pacer.frames(ui.present_frame());
+            tx.send(UiToEmuMsg::RunFrames(frames)).unwrap();
+            rewind_frame(&mut ui, &tx, &mut state);
+            end_timing_frame(&mut ui, &mut state);
+        } else {
+            rewind_rate.on_tick(|| rewind_frame(&mut ui, &tx, &mut state));
+            timing_rate.on_tick(|| end_timing_frame(&mut ui, &mut state));
+        }
+        if state.demo.is_some() {
+            demo_rate.on_tick(|| demo_frame(&mut ui, &tx, &mut state));
+        }
+        if !vsync {
+            // Short sleep to free up cpu cycles
+            let slept_at = SteadyTime::now();
+            thread::sleep_ms(1);    
+            state.timings.add(Phase::Sleep, SteadyTime::now() - slept_at);
+        }
+    }
+    if let Some(recorder) = state.audio_recorder.take() {
+        if let Err(err) = recorder.finish() {
+            println!("Can not record audio: {}", err);
+        }
+    }
+}
+
+// Keep the timing of the frame that ended, and graph it if shown.
+fn end_timing_frame(ui: &mut Ui, state: &mut UiState) {
+    state.timings.add(Phase::Present, ui.take_draw_time());
+    state.timings.end_frame();
+    if state.showing_timings {
+        ui.show_timing_graph(Some((state.timings.samples(), 
+                                   state.metro_stats)));
     }
 }
 
-// Poll for and handle key press events. 
+// The debugger panel is shown while paused, or always once toggled on.
+fn debug_panel_visible(paused: bool, debugging: bool) -> bool {
+    paused || debugging
+}
+
+// Poll for and handle key press and gamepad events. Returns true if there 
+// was an event.
 fn process_key_presses(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, 
-                    paused: &mut bool) {
-    match ui.poll_event() {
-        None => {},
+                       state: &mut UiState) -> bool {
+    let was_visible = debug_panel_visible(state.paused, state.debugging);
+    let polled = ui.poll_event();
+    let had_event = polled.is_some();
+    match polled {
+        None => if !state.dropped_files.is_empty() {
+            // All files dropped at once have been collected.
+            load_dropped_files(ui, tx, state);
+        },
         Some(event) => {
             match event {
-                Event::Quit{..} => {
-                    tx.send(UiToEmuMsg::Paused(*paused)).unwrap(); 
+                // Closing the window quits, like Escape.
+                Event::Quit{..} => tx.send(UiToEmuMsg::Quit).unwrap(),
+                // Any key or button ends demo mode, keeping the rom.
+                Event::KeyDown{..} | Event::ControllerButtonDown{..}
+                        if state.demo.is_some() => {
+                    state.demo = None;
+                    ui.show_message("Demo stopped");
                 },
-                Event::KeyDown{keycode,..} => match keycode {
+                Event::KeyDown{keycode: Some(keycode), ..} 
+                        if state.picker.is_some() => {
+                    picker_key(ui, tx, state, keycode);
+                },
+                Event::KeyDown{keycode: Some(Keycode::Backquote), ..} => {
+                    set_rewinding(ui, tx, state, true);
+                },
+                Event::KeyUp{keycode: Some(Keycode::Backquote), ..} => {
+                    set_rewinding(ui, tx, state, false);
+                },
+                // Shift+Tab runs in slow motion instead of fast forward.
+                Event::KeyDown{keycode: Some(Keycode::Tab), keymod, repeat, 
+                               ..} => if !repeat {
+                    let speed = if keymod.intersects(LSHIFTMOD | RSHIFTMOD) {
+                        state.window.slow_motion
+                    } else {
+                        state.window.turbo
+                    };
+                    set_speed(ui, tx, speed);
+                },
+                Event::KeyUp{keycode: Some(Keycode::Tab), ..} => {
+                    set_speed(ui, tx, 1.0);
+                },
+                // Input is dropped while the game runs backwards.
+                Event::KeyDown{scancode: Some(scancode), ..} |
+                Event::KeyUp{scancode: Some(scancode), ..}
+                        if state.rewinder.is_rewinding() && 
+                           ui::keypad_key(&state.keyboard, scancode)
+                               .is_some() => {},
+                Event::ControllerButtonDown{..} | Event::ControllerButtonUp{..}
+                        if state.rewinder.is_rewinding() => {},
+                Event::KeyDown{keycode, scancode, keymod, repeat, ..} => 
+                        match keycode {
                     Option::Some(Keycode::Escape) => {
                         // Signal emulator with intention to quit
                         // and allow it to shutdown gracefully.
                         tx.send(UiToEmuMsg::Quit).unwrap(); 
                     },
-                    Option::Some(Keycode::Return) => {
-                        // Signal emulator to pause.
-                        *paused ^= true; 
-                        tx.send(UiToEmuMsg::Paused(*paused)).unwrap();
+                    Option::Some(Keycode::Return) =>
+                        toggle_pause(ui, tx, state),
+                    Option::Some(Keycode::Backspace) => reset(ui, tx, state),
+                    Option::Some(Keycode::F1) => {
+                        // Toggle the debugger panel.
+                        state.debugging ^= true;
+                    },
+                    Option::Some(Keycode::F2) => {
+                        // Cycle through the ways of reducing flicker.
+                        let mut effects = ui.effects();
+                        effects.flicker = effects.flicker.next();
+                        ui.set_effects(effects);
+                        ui.show_message(&format!("Flicker reduction: {}", 
+                                                 effects.flicker.name()));
                     },
-                    Option::Some(Keycode::Backspace) => {
-                        // Signal emulator to reset.
-                        tx.send(UiToEmuMsg::Reset).unwrap();
-                        *paused = false;
-                        tx.send(UiToEmuMsg::Paused(*paused)).unwrap();
+                    Option::Some(Keycode::F3) => {
+                        // Toggle scanlines.
+                        let mut effects = ui.effects();
+                        effects.scanlines ^= true;
+                        ui.set_effects(effects);
                     },
-                    _ => if !*paused {
-                        // A key was pressed, signal emulator with updated
-                        // key states.
-                        tx.send(UiToEmuMsg::Keys(
-                                ui.get_updated_keys())).unwrap();
+                    Option::Some(Keycode::F4) => open_picker(ui, state),
+                    Option::Some(Keycode::F6) => {
+                        // Toggle the keypad overlay.
+                        state.showing_keypad ^= true;
+                        ui.show_keypad(if state.showing_keypad {
+                            Some(state.emu_keys)
+                        } else {
+                            None
+                        });
+                    },
+                    Option::Some(Keycode::F7) => {
+                        // Toggle the timing graph.
+                        state.showing_timings ^= true;
+                        ui.show_timing_graph(if state.showing_timings {
+                            Some((state.timings.samples(), 
+                                  state.metro_stats))
+                        } else {
+                            None
+                        });
+                    },
+                    Option::Some(Keycode::M) => toggle_mute(ui, state),
+                    Option::Some(Keycode::Minus) => 
+                        change_volume(ui, state, false),
+                    Option::Some(Keycode::Equals) => 
+                        change_volume(ui, state, true),
+                    Option::Some(Keycode::KpMinus) => 
+                        change_cycles_per_second(ui, tx, state, false),
+                    Option::Some(Keycode::KpPlus) => 
+                        change_cycles_per_second(ui, tx, state, true),
+                    Option::Some(Keycode::F5) => 
+                        quicksave_key(ui, tx, state, keymod, true),
+                    Option::Some(Keycode::F9) => 
+                        quicksave_key(ui, tx, state, keymod, false),
+                    Option::Some(Keycode::F10) => if state.paused {
+                        // Signal emulator to execute a single instruction.
+                        tx.send(UiToEmuMsg::Step).unwrap();
+                    },
+                    Option::Some(Keycode::Period) => if state.paused {
+                        // Signal emulator to run a single frame. Holding
+                        // the key repeats at a modest rate.
+                        if repeat {
+                            state.frame_advance_rate.on_tick(|| {
+                                tx.send(UiToEmuMsg::AdvanceFrame).unwrap();
+                            });
+                        } else {
+                            tx.send(UiToEmuMsg::AdvanceFrame).unwrap();
+                        }
+                    },
+                    _ => if let Some(scale) = keycode.and_then(|k| {
+                        scale_key(k, keymod)
+                    }) {
+                        set_scale(ui, state, scale);
+                    } else if let Some(key) = 
+                            scancode.and_then(|scancode| {
+                                ui::keypad_key(&state.keyboard, scancode)
+                            }) {
+                        // Holding a key down repeats the event.
+                        if !repeat { state.keypad.push(KeyEvent::Down(key)); }
                     }, 
                 },
-                Event::KeyUp{..} => if !*paused {
-                    // A key was released, signal emulator with updated
-                    // key states.
-                    tx.send(UiToEmuMsg::Keys(
-                            ui.get_updated_keys())).unwrap();
+                Event::KeyUp{scancode, ..} => {
+                    if let Some(key) = scancode.and_then(|scancode| {
+                        ui::keypad_key(&state.keyboard, scancode)
+                    }) {
+                        state.keypad.push(KeyEvent::Up(key));
+                    }
+                },
+                Event::ControllerButtonDown{which, button, ..} => {
+                    gamepad_button(ui, tx, state, which, button, true);
+                },
+                Event::ControllerButtonUp{which, button, ..} => {
+                    gamepad_button(ui, tx, state, which, button, false);
+                },
+                Event::ControllerDeviceAdded{which, ..} => {
+                    // Gamepads may be plugged in at any time.
+                    ui.open_controller(which);
+                },
+                Event::ControllerDeviceRemoved{which, ..} => {
+                    state.gamepads.removed(which, &mut state.keypad);
+                    ui.close_detached_controllers();
+                },
+                Event::Window{win_event_id, ..} => match win_event_id {
+                    WindowEventId::FocusLost => focus_lost(ui, tx, state),
+                    WindowEventId::FocusGained => focus_gained(ui, tx, state),
+                    _ => {},
+                },
+                Event::DropFile{filename, ..} => {
+                    // Dropping several files results in several events.
+                    state.dropped_files.push(PathBuf::from(filename));
                 },
                 _ => {}
             }
         }
     }
+    if state.keypad.drain() {
+        // A key was pressed or released, signal emulator with updated key
+        // states. A paused rom does not see keys change; set_paused sends
+        // the keys held on resuming instead.
+        if !state.paused {
+            tx.send(UiToEmuMsg::Keys(state.keypad.keys())).unwrap();
+        }
+    }
+    let visible = debug_panel_visible(state.paused, state.debugging);
+    if visible != was_visible {
+        // Ask the emulator to start or stop reporting its state.
+        tx.send(UiToEmuMsg::Debug(visible)).unwrap();
+        if !visible { ui.show_debug_panel(None); }
+    }
+    had_event
 }
 
-// Poll for and handle emulator events. Returns true if emulator acknowledged 
-// earlier quit signal. 
-fn process_emu_events(ui: &mut Ui, rx: &Receiver<EmuToUiMsg>, paused: &bool, 
-                      refresh_gfx_rate: &mut Metronome) -> bool {
-    match rx.try_recv() {
-        Ok(emu_event) => {
-            match emu_event {
-                // Handle beeb state change signalled by emulator.
-                EmuToUiMsg::Beeping(on) => ui.beep(on),
-                // Handle draw event signalled by emulator.
-                EmuToUiMsg::Draw(ref mode, ref gfx) => {
-                    refresh_gfx_rate.on_tick(|| {
-                        if !*paused { ui.refresh_gfx(*mode, gfx); }
-                    });
-                },
-                // Emulator has acknowledged the earlier quit signal.
-                // The ui thread may shutdown in response.
-                EmuToUiMsg::QuitAck => return true,
-            }
-        },
-        _ => {},
-    } 
-    false
+// Translate a gamepad button into keypad keys, or carry out the command it 
+// is bound to.
+fn gamepad_button(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+                  which: i32, button: controller::Button, pressed: bool) {
+    let button = match ui::gamepad_button(button) {
+        Some(button) => button,
+        None => return,
+    };
+    match state.gamepads.button(which, button, pressed, &mut state.keypad) {
+        Some(Command::Pause) => toggle_pause(ui, tx, state),
+        Some(Command::Reset) => reset(ui, tx, state),
+        None => {},
+    }
 }
 
-// Drives the emulator. Communicates with the user interface by exchanging
-// messages across a two way channel. 
-//
-// Assigned its own thread. 
-fn emu_exec(mut emu: Emu, tx: Sender<EmuToUiMsg>, rx: Receiver<UiToEmuMsg>) {
-    let mut clock_rate = Metronome::new(500);
-    let mut update_timers_rate = Metronome::new(60);
-    let mut paused = false;
-    let mut beeping = false;
-    'emu_exec_loop: loop {
-        if process_ui_events(&mut emu, &tx, &rx, &mut paused) {
-            break 'emu_exec_loop;
+// Signal emulator to pause or unpause.
+fn toggle_pause(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState) {
+    let paused = !state.paused;
+    set_paused(ui, tx, state, paused);
+}
+
+// Signal emulator to reset. Resetting also unpauses.
+fn reset(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState) {
+    tx.send(UiToEmuMsg::Reset).unwrap();
+    ui.set_diverged_at(None);
+    set_paused(ui, tx, state, false);
+}
+
+// Pause or unpause the emulator and the ui. Every pause goes through here,
+// whether it is asked for by the user or caused by losing the focus.
+fn set_paused(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+              paused: bool) {
+    state.paused = paused;
+    state.paused_by_focus = false;
+    if !paused {
+        // Catch up on the keys pressed and released while paused.
+        tx.send(UiToEmuMsg::Keys(state.keypad.keys())).unwrap();
+    }
+    // The emulator stays paused while rewinding.
+    let paused = paused || state.rewinder.is_rewinding();
+    tx.send(UiToEmuMsg::Paused(paused)).unwrap();
+    ui.set_paused(state.paused);
+}
+
+// Start or stop running the game backwards. The emulator is paused while 
+// rewinding, and the keys held are released since input is dropped.
+fn set_rewinding(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+                 rewinding: bool) {
+    if !state.rewinder.key(&mut state.rewind, rewinding) {
+        return;
+    }
+    if rewinding {
+        state.gamepads.release_all(&mut state.keypad);
+        state.keypad.release_all();
+    }
+    ui.set_rewinding(rewinding);
+    tx.send(UiToEmuMsg::Paused(state.paused || rewinding)).unwrap();
+}
+
+// Run at a multiple of the normal speed, e.g. while the turbo key is held.
+// The title shows the speed actually run at.
+fn set_speed(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, speed: f32) {
+    let speed = metro::clamp_speed(speed);
+    tx.send(UiToEmuMsg::Speed(speed)).unwrap();
+    ui.set_speed(speed);
+}
+
+// Run more or fewer instructions per second, in steps. The timers keep
+// counting down at the same rate.
+fn change_cycles_per_second(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, 
+                            state: &mut UiState, up: bool) {
+    let cycles_per_second = 
+        metro::step_cycles_per_second(state.clock.cycles_per_second, up);
+    state.clock.cycles_per_second = cycles_per_second;
+    tx.send(UiToEmuMsg::CyclesPerSecond(cycles_per_second)).unwrap();
+    ui.show_message(&format!("{} instructions per second", 
+                             cycles_per_second));
+}
+
+// Go back one frame while rewinding. Once the oldest frame is reached, it
+// stays on screen until the rewind key is released.
+fn rewind_frame(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState) {
+    match state.rewinder.frame(&mut state.rewind) {
+        RewindFrame::Restore(frame) => 
+            tx.send(UiToEmuMsg::Restore(frame)).unwrap(),
+        RewindFrame::Exhausted => ui.show_message("Nothing left to rewind"),
+        RewindFrame::Run => {},
+    }
+}
+
+// Keys released while another window has the focus are never reported,
+// so release them all. Unless configured otherwise, also pause until the
+// focus returns.
+fn focus_lost(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState) {
+    state.gamepads.release_all(&mut state.keypad);
+    state.keypad.release_all();
+    if state.window.pause_on_focus_loss && !state.paused {
+        set_paused(ui, tx, state, true);
+        state.paused_by_focus = true;
+    }
+}
+
+// Resume if paused by losing the focus. A pause by the user stays.
+fn focus_gained(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState) {
+    if state.paused_by_focus {
+        set_paused(ui, tx, state, false);
+        ui.show_message("Resumed");
+    }
+}
+
+// Open the rom picker, listing the recent roms followed by the roms next to
+// the running one.
+fn open_picker(ui: &mut Ui, state: &mut UiState) {
+    let others = state.rom_path.parent()
+        .and_then(|dir| rom::list_files(dir).ok())
+        .unwrap_or_default();
+    let picker = Picker::new(&state.recent, &others);
+    ui.show_picker(Some((picker.items().to_vec(), picker.selected())));
+    state.picker = Some(picker);
+}
+
+// Handle a key press while the rom picker is open. The arrow keys move the
+// selection and return launches it, while the number keys launch a recent
+// rom straight away.
+fn picker_key(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+              keycode: Keycode) {
+    let chosen = match state.picker {
+        Some(ref mut picker) => match keycode {
+            Keycode::Up | Keycode::Down => {
+                picker.move_selection(if keycode == Keycode::Up {-1} else {1});
+                ui.show_picker(Some((picker.items().to_vec(), 
+                                     picker.selected())));
+                return;
+            },
+            Keycode::Escape | Keycode::F4 => None,
+            Keycode::Return => match picker.choose_selected() {
+                Some(path) => Some(path.to_path_buf()),
+                None => return,
+            },
+            // Entries greyed out or without a number can not be launched.
+            _ => match number_key(keycode)
+                           .and_then(|n| picker.choose_shortcut(n)) {
+                Some(path) => Some(path.to_path_buf()),
+                None => return,
+            },
+        },
+        None => return,
+    };
+    state.picker = None;
+    ui.show_picker(None);
+    if let Some(path) = chosen {
+        match rom::read_rom(&path) {
+            Ok(rom_file) => load_rom_file(ui, tx, state, rom_file),
+            Err(err) => rom_error(ui, state, err),
         }
-        signal_draw_event(&mut emu, &tx, &paused, &mut clock_rate); 
-        update_timers(&mut emu, &tx, &paused, &mut beeping, 
-                      &mut update_timers_rate);
-        // Short sleep to free up cpu cycles
-        thread::sleep_ms(1);    
-    }
-}
-
-// Poll for and handle UI events. Returns true if Quit signal received from UI.
-fn process_ui_events(emu: &mut Emu, tx: &Sender<EmuToUiMsg>,  
-                     rx: &Receiver<UiToEmuMsg>, paused: &mut bool) -> bool {
-    match rx.try_recv() {
-        Ok(ui_to_emu_msg) => 
-            match ui_to_emu_msg {
-                // New key press states.
-                UiToEmuMsg::Keys(new_keys) => emu.keys = new_keys,
-                // Reset everything.
-                UiToEmuMsg::Reset => emu.reset(),
-                // Pause or unpause.
-                UiToEmuMsg::Paused(p) => *paused = p,
-                // Acknowledge quit and shut down gracefully.
-                UiToEmuMsg::Quit => {
-                    tx.send(EmuToUiMsg::QuitAck).unwrap();
-                    return true;
-                }, 
+    }
+}
+
+// F5 saves to the selected quicksave slot and F9 loads from it, while
+// Shift+F5 and Shift+F9 select the next and previous slot.
+fn quicksave_key(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+                 keymod: Mod, save: bool) {
+    if keymod.intersects(LSHIFTMOD | RSHIFTMOD) {
+        let slot = if save {state.quicksaves.next_slot()} 
+                   else {state.quicksaves.previous_slot()};
+        ui.show_message(&format!("Slot {}", slot));
+        return;
+    }
+    // The emulator reports back once the slot is saved or loaded.
+    match state.quicksaves.current() {
+        Ok(slot) if save => tx.send(UiToEmuMsg::SaveState(slot)).unwrap(),
+        Ok(slot) => tx.send(UiToEmuMsg::LoadState(slot)).unwrap(),
+        Err(err) => ui.show_message(&err.to_string()),
+    }
+}
+
+// The window scale chosen with Ctrl+1 to Ctrl+8.
+fn scale_key(keycode: Keycode, keymod: Mod) -> Option<u32> {
+    if !keymod.intersects(LCTRLMOD | RCTRLMOD) {
+        return None;
+    }
+    match number_key(keycode) {
+        Some(n) if n <= 8 => Some(n as u32),
+        _ => None,
+    }
+}
+
+// Resize the window, and remember the size for the next run.
+fn set_scale(ui: &mut Ui, state: &mut UiState, scale: u32) {
+    ui.set_scale(scale);
+    state.window.scale = ui.window_scale();
+    let saved = match state.config_path {
+        Some(ref path) => config::save_setting(path, "window", "scale",
+                                           &state.window.scale.to_string()),
+        None => Ok(()),
+    };
+    match saved {
+        Ok(_) => ui.show_message(&format!("Scale: {}x", scale)),
+        Err(err) => ui.show_message(&err.to_string()),
+    }
+}
+
+// Turn the beep up or down a step, and remember the volume for the next 
+// run.
+fn change_volume(ui: &mut Ui, state: &mut UiState, up: bool) {
+    state.audio.volume = wav::step_volume(state.audio.volume, up);
+    ui.set_volume(state.audio.volume);
+    let volume = state.audio.volume.to_string();
+    save_audio_setting(ui, state, "volume", &volume, 
+                       &format!("Volume: {}%", volume));
+}
+
+// Silence the beep or let it be heard again, and remember which for the 
+// next run. Recording audio goes on regardless.
+fn toggle_mute(ui: &mut Ui, state: &mut UiState) {
+    state.audio.muted ^= true;
+    ui.set_muted(state.audio.muted);
+    let msg = if state.audio.muted {"Sound muted"} else {"Sound on"};
+    save_audio_setting(ui, state, "muted", &state.audio.muted.to_string(),
+                       msg);
+}
+
+// Save the setting to the [audio] section of the configuration, showing
+// the message if that worked out.
+fn save_audio_setting(ui: &mut Ui, state: &UiState, key: &str, value: &str,
+                      msg: &str) {
+    let saved = match state.config_path {
+        Some(ref path) => config::save_setting(path, "audio", key, value),
+        None => Ok(()),
+    };
+    match saved {
+        Ok(_) => ui.show_message(msg),
+        Err(err) => ui.show_message(&err.to_string()),
+    }
+}
+
+// The number on one of the keys 1 to 9.
+fn number_key(keycode: Keycode) -> Option<usize> {
+    let keys = [Keycode::Num1, Keycode::Num2, Keycode::Num3, Keycode::Num4,
+                Keycode::Num5, Keycode::Num6, Keycode::Num7, Keycode::Num8,
+                Keycode::Num9];
+    keys.iter().position(|&key| key == keycode).map(|i| i + 1)
+}
+
+// Replace the running rom with the first valid rom among the files dropped
+// onto the window. Problems are reported on screen.
+fn load_dropped_files(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, 
+                      state: &mut UiState) {
+    match rom::read_first_rom(&state.dropped_files) {
+        Ok(rom_file) => load_rom_file(ui, tx, state, rom_file),
+        Err(err) => rom_error(ui, state, err),
+    }
+    state.dropped_files.clear();
+}
+
+// Report a rom that could not be read, on screen and in the log.
+fn rom_error(ui: &mut Ui, state: &UiState, err: RomError) {
+    let msg = err.to_string();
+    state.log.warn(&msg);
+    ui.show_message(&msg);
+}
+
+// What was read from file, for the log.
+fn describe_rom_file(rom_file: &RomFile) -> String {
+    format!("Read {}: {} bytes, detected {:?} mode", 
+            rom_file.path.display(), rom_file.rom.len(),
+            rom::detect_mode(&rom_file.rom))
+}
+
+// Replace the running rom, and remember it among the recent roms.
+fn load_rom_file(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+                 rom_file: RomFile) {
+    let path = rom_file.path.clone();
+    let mut msg = switch_rom(ui, tx, state, rom_file);
+    if let Err(err) = remember_rom(state, &path) {
+        msg = format!("Can not save recent roms: {}", err);
+    }
+    ui.show_message(&msg);
+}
+
+// Replace the running rom. Returns the message to show.
+fn switch_rom(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+              rom_file: RomFile) -> String {
+    state.log.info(&describe_rom_file(&rom_file));
+    state.quicksaves.set_rom(&rom_file.rom);
+    state.rewind.clear();
+    let (quirks, note) = apply_profile(ui, tx, state, &rom_file.rom);
+    tx.send(UiToEmuMsg::Load(rom_file.rom, quirks)).unwrap();
+    set_paused(ui, tx, state, false);
+    let name = rom_file.path.file_name().unwrap_or_default()
+                                        .to_string_lossy().into_owned();
+    ui.set_rom_name(&name);
+    let mut msg = format!("Loaded {}", name);
+    if rom_file.ignored > 0 {
+        msg.push_str(&format!(" ({} other files ignored)", rom_file.ignored));
+    }
+    if let Some(note) = note {
+        msg = note;
+    }
+    match GamepadMapping::for_rom(&state.config, &rom_file.path) {
+        Ok(mapping) => state.gamepads.set_mapping(mapping),
+        Err(err) => {
+            state.gamepads.set_mapping(GamepadMapping::new());
+            msg = err.to_string();
+        },
+    }
+    state.rom_path = rom_file.path;
+    msg
+}
+
+// The settings given on the command line, as a profile to apply on top of
+// that of a rom.
+fn command_line_profile(options: &Options) -> RomProfile {
+    let mut profile = RomProfile::new();
+    profile.platform = options.platform;
+    profile.quirks = options.quirks.clone();
+    profile.cycles_per_second = options.speed;
+    profile.fg = options.palette.map(|(fg, _)| fg);
+    profile.bg = options.palette.map(|(_, bg)| bg);
+    profile
+}
+
+// The settings to run the rom with: those configured, overridden by the
+// profile of the rom if it has one, overridden in turn by the command 
+// line. Also returns the note to show when there is a profile.
+fn rom_settings(rom_db: &RomDb, defaults: RomSettings, 
+                command_line: &RomProfile, rom: &[u8])
+                -> (RomSettings, Option<String>) {
+    let (mut settings, note) = {
+        let (settings, profile) = rom_db.settings_for(rom, defaults);
+        (settings, profile.map(RomProfile::note))
+    };
+    command_line.apply(&mut settings);
+    (settings, note)
+}
+
+// Set the speed and colours to run the rom with, those of its profile if
+// it has one unless given on the command line. Returns the quirks to load
+// it with, and the note to show when there is a profile.
+fn apply_profile(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState,
+                 rom: &[u8]) -> (QuirkSettings, Option<String>) {
+    let (settings, note) = rom_settings(&state.rom_db, state.rom_defaults,
+                                        &state.command_line, rom);
+    if let Some(ref note) = note {
+        state.log.info(note);
+    }
+    state.clock.cycles_per_second = settings.cycles_per_second;
+    tx.send(UiToEmuMsg::CyclesPerSecond(settings.cycles_per_second))
+      .unwrap();
+    let mut effects = ui.effects();
+    effects.fg = settings.fg;
+    effects.bg = settings.bg;
+    ui.set_effects(effects);
+    (settings.quirks, note)
+}
+
+// Count a frame of demo mode, moving on to the next rom when its time is 
+// up. Roms that fail to load are skipped with a notice. Frames spent 
+// paused do not count.
+fn demo_frame(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, state: &mut UiState) {
+    if state.paused {
+        return;
+    }
+    let frame = state.demo_frames;
+    state.demo_frames += 1;
+    let switch = match state.demo {
+        Some(ref mut demo) => match demo.frame(frame, rom::read_rom) {
+            Some(switch) => switch,
+            None => return,
+        },
+        None => return,
+    };
+    let mut msg = match switch.loaded {
+        Some((_, rom_file)) => switch_rom(ui, tx, state, rom_file),
+        None => "No roms could be loaded".to_string(),
+    };
+    for &(_, ref err) in &switch.skipped {
+        state.log.warn(&format!("Skipped: {}", err));
+    }
+    if let Some(&(_, ref err)) = switch.skipped.last() {
+        msg = format!("Skipped: {}", err);
+    }
+    ui.show_message(&msg);
+}
+
+// Add the rom to the recent roms and save them.
+fn remember_rom(state: &mut UiState, path: &Path) -> io::Result<()> {
+    state.recent.add(path);
+    match state.recent_path {
+        Some(ref recent_path) => state.recent.save(recent_path),
+        None => Ok(()),
+    }
+}
+
+// Show the frame the emulator drew last, if it is new.
+fn refresh_gfx(ui: &mut Ui, frames: &FrameMailbox) {
+    if let Some(frame) = frames.take() {
+        ui.refresh_gfx(frame.mode, &frame.gfx);
+    }
+}
+
+// Poll for and handle emulator events. Returns true if emulator acknowledged 
+// earlier quit signal. With vsync, all events that arrived since the last
+// present are handled.
+fn process_emu_events(ui: &mut Ui, tx: &Sender<UiToEmuMsg>, 
+                      rx: &Receiver<EmuToUiMsg>, state: &mut UiState, 
+                      vsync: bool) -> bool {
+    while let Ok(emu_event) = rx.try_recv() {
+        match emu_event {
+            // Handle beeb state change signalled by emulator.
+            EmuToUiMsg::Beeping(frame, on) => {
+                ui.beep(frame, on);
+                if let Some(ref mut recorder) = state.audio_recorder {
+                    recorder.beep(on);
+                }
+            },
+            // Handle the frame of the second emulator when comparing quirks.
+            EmuToUiMsg::Compared(ref gfx) => ui.refresh_compared(gfx),
+            // The compared emulators drew something different.
+            EmuToUiMsg::Diverged(frame) => {
+                ui.set_diverged_at(Some(frame));
+                ui.show_message(&format!("Diverged at frame {}", frame));
+            },
+            // Handle new debugger state reported by emulator.
+            EmuToUiMsg::Debug(ref snapshot) => {
+                ui.show_debug_panel(
+                    Some(debugger::panel_lines(snapshot)));
+            },
+            // Keep the state of each frame run, for rewinding, and the time
+            // it took to run for the timing graph.
+            EmuToUiMsg::Frame(frame, busy, stats) => {
+                state.rewind.push(frame);
+                state.timings.add(Phase::Emulate, busy);
+                state.metro_stats = stats;
+                record_audio_frame(ui, state);
+            },
+            // The program faulted and halted, or runs again after a reset.
+            EmuToUiMsg::Halted(halted) => ui.set_halted(halted),
+            // The keys held down changed, as shown on the keypad overlay.
+            EmuToUiMsg::Keys(keys) => {
+                state.emu_keys = keys;
+                if state.showing_keypad { ui.show_keypad(Some(keys)); }
+            },
+            // Show the outcome of something the emulator was asked to do.
+            EmuToUiMsg::Message(ref text) => ui.show_message(text),
+            // Emulator has acknowledged the earlier quit signal.
+            // The ui thread may shutdown in response.
+            EmuToUiMsg::QuitAck => return true,
+            // A rom was picked from the built in menu. It is started over
+            // with the quirks of its profile, or those configured.
+            EmuToUiMsg::Started(name, rom) => {
+                state.quicksaves.set_rom(&rom);
+                state.rewind.clear();
+                let (quirks, note) = apply_profile(ui, tx, state, &rom);
+                tx.send(UiToEmuMsg::Load(rom, quirks)).unwrap();
+                ui.set_rom_name(name);
+                ui.show_message(&note.unwrap_or_else(|| {
+                    format!("Loaded {}", name)
+                }));
             },
-        _ => {},
-    }  
+        }
+        if !vsync { break; }
+    } 
     false
 }
 
-// Signal the ui with a draw event.
-fn signal_draw_event(emu: &mut Emu, tx: &Sender<EmuToUiMsg>, paused: &bool,
-                     clock_rate: &mut Metronome) {
-    clock_rate.on_tick(|| {
-        if !paused {
-            &mut emu.execute_cycle();
-            if emu.draw {
-                tx.send(EmuToUiMsg::Draw(emu.mode, emu.gfx)).unwrap();
-                emu.draw = false;
-            }
-         } 
-    });
+// Record the beep of the frame run, giving up on recording if the file can
+// not be written.
+fn record_audio_frame(ui: &mut Ui, state: &mut UiState) {
+    let recorded = match state.audio_recorder {
+        Some(ref mut recorder) => recorder.record_frame(),
+        None => return,
+    };
+    if let Err(err) = recorded {
+        ui.show_message(&format!("Can not record audio: {}", err));
+        ui.set_recording(false);
+        state.audio_recorder = None;
+    }
 }
 
-// Update the emulator timers and signal the ui if the beep state changed.
-fn update_timers(emu: &mut Emu, tx: &Sender<EmuToUiMsg>, paused: &bool, 
-                 beeping: &mut bool, update_timers_rate: &mut Metronome) {
-    update_timers_rate.on_tick(|| {
-        if !paused { 
-            emu.update_timers(); 
-            if *beeping != emu.beeping() {
-                *beeping ^= true; 
-                tx.send(EmuToUiMsg::Beeping(*beeping)).unwrap();
-            }
-        }                
-    });
+// Start demo mode with the roms in the directory, each running for the 
+// given number of seconds. Returns the first rom that loads, or None after
+// reporting why there is none.
+fn start_demo(dir: &Path, secs: u64, timer_hz: u32) 
+              -> Option<(RomFile, DemoRotation)> {
+    let roms = match rom::list_files(dir) {
+        Ok(roms) => roms,
+        Err(err) => {
+            println!("Can not read {}: {}", dir.display(), err);
+            return None;
+        },
+    };
+    let mut demo = DemoRotation::new(roms, secs * timer_hz as u64);
+    let switch = match demo.frame(0, rom::read_rom) {
+        Some(switch) => switch,
+        None => {
+            println!("No roms found in {}", dir.display());
+            return None;
+        },
+    };
+    for &(_, ref err) in &switch.skipped {
+        println!("Skipped: {}", err);
+    }
+    match switch.loaded {
+        Some((_, rom_file)) => Some((rom_file, demo)),
+        None => None,
+    }
+}
+
+// An emulator running the rom with the quirks, and with the random numbers
+// of the seed if there is one.
+fn build_emu(rom: Vec<u8>, quirks: &QuirkSettings, seed: Option<u64>, 
+             log: &Log) -> Emu {
+    let mut emu = Emu::new();
+    emu.set_log(log.clone());
+    quirks.apply(&mut emu);
+    if let Some(seed) = seed {
+        emu.seed_rng(seed);
+    }
+    emu.load_rom(rom);
+    emu
+}
+
+// Run the number of frames without a window or sound, as fast as they
+// go, and print what ends up on the display, followed by the timing of the
+// last second. Exits with an error if the rom faulted along the way.
+fn run_headless(mut emu: Emu, clock: &ClockSettings, frames: u64) {
+    let mut budget = CycleBudget::new(clock.cycles_per_second, 
+                                      clock.timer_hz);
+    let mut stats = FrameStats::new(clock.timer_hz as i64);
+    emu.set_on_error(ErrorPolicy::Halt);
+    for _ in 0..frames {
+        let started = SteadyTime::now();
+        let cycles = budget.next_frame();
+        emu.run_frame(cycles);
+        stats.frame(cycles, SteadyTime::now() - started);
+    }
+    print!("{}", StringDisplay::new(&emu));
+    println!("{}", stats.stats().summary());
+    if let Some(err) = emu.last_error() {
+        println!("{}", err);
+        process::exit(1);
+    }
 }
 
 // Entry point into the program. Takes care of basic setup such as reading
 // the rom path from the command line and kicking off the ui and emulator.
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        print!("Usage: chip8 PATH_TO_ROM");
+    let args: Vec<String> = env::args().skip(1).collect();
+    // Subcommands, such as asm, disasm and info, run instead of the
+    // emulator.
+    if let Some(result) = cli::run(&args, &mut io::stdout()) {
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            process::exit(err.exit_code());
+        }
         return;
     }
-    let path_to_rom = Path::new(&args[1]);
-    let ui = Ui::new();
-    let mut emu = Emu::new();
-    load_rom(&mut emu, path_to_rom);
-    // The channels through which the ui and emulator will communicate.
+    let options = match options::parse_args(args) {
+        Ok(options) => options,
+        Err(err) => {
+            println!("{}\n\n{}", err, options::USAGE);
+            process::exit(2);
+        },
+    };
+    let log_file = options.log_file.as_ref().map(Path::new);
+    let log = Log::open(options.log_level, log_file).unwrap_or_else(|err| {
+        println!("Can not log to {}: {}", log_file.unwrap().display(), err);
+        process::exit(1);
+    });
+    // A configuration file named on the command line has to be there,
+    // unlike the default one.
+    let config_path = match options.config {
+        Some(ref path) if !Path::new(path).is_file() => {
+            println!("Can not read the configuration {}", path);
+            process::exit(1);
+        },
+        Some(ref path) => Some(PathBuf::from(path)),
+        None => Config::default_path(),
+    };
+    let config = match config_path {
+        Some(ref path) => Config::load(path).unwrap_or_else(|err| {
+            println!("{}", err);
+            Config::new()
+        }),
+        None => Config::new(),
+    };
+    let mut clock = ClockSettings::from_config(&config).unwrap_or_else(|err| {
+        println!("{}", err);
+        ClockSettings::new()
+    });
+    let mut effects = Effects::from_config(&config).unwrap_or_else(|err| {
+        println!("{}", err);
+        Effects::new()
+    });
+    let mut window = WindowSettings::from_config(&config)
+        .unwrap_or_else(|err| {
+            println!("{}", err);
+            WindowSettings::new()
+        });
+    let mut audio = AudioSettings::from_config(&config).unwrap_or_else(|err| {
+        println!("{}", err);
+        AudioSettings::new()
+    });
+    let mut quirks = QuirkSettings::from_config(&config)
+        .unwrap_or_else(|err| {
+            println!("{}", err);
+            QuirkSettings::new()
+        });
+    let keyboard = KeyboardMapping::from_config(&config)
+        .unwrap_or_else(|err| {
+            println!("{}", err);
+            KeyboardMapping::new()
+        });
+    // The profile of a rom takes precedence over the configuration, and the
+    // command line over both.
+    let rom_defaults = RomSettings { 
+        quirks: quirks, cycles_per_second: clock.cycles_per_second, 
+        fg: effects.fg, bg: effects.bg };
+    let command_line = command_line_profile(&options);
+    options.apply_to_clock(&mut clock);
+    options.apply_to_effects(&mut effects);
+    options.apply_to_window(&mut window);
+    options.apply_to_audio(&mut audio);
+    options.apply_to_quirks(&mut quirks);
+    if options.write_config {
+        let gamepad = GamepadMapping::from_config(&config)
+            .unwrap_or_else(|_| GamepadMapping::new());
+        let sections = [("clock", clock.to_config()),
+                        ("window", window.to_config()),
+                        ("display", effects.to_config()),
+                        ("audio", audio.to_config()),
+                        ("quirks", quirks.to_config()),
+                        ("keyboard", keyboard.to_config()),
+                        ("gamepad", gamepad.to_config())];
+        let text = sections.iter().fold(String::new(), 
+            |text, &(section, ref settings)| {
+                config::set_settings(&text, section, settings)
+            });
+        print!("{}", text);
+        return;
+    }
+    let menu = options.rom.is_none() && options.demo.is_none();
+    let (rom_file, demo) = match (&options.rom, &options.demo) {
+        (&Some(ref rom_arg), &None) => 
+            match rom::read_rom(Path::new(rom_arg)) {
+                Ok(rom_file) => (rom_file, None),
+                Err(err) => {
+                    println!("{}", err);
+                    process::exit(1);
+                },
+            },
+        (&None, &Some(ref demo_arg)) => 
+            match start_demo(Path::new(demo_arg), options.demo_secs, 
+                             clock.timer_hz) {
+                Some((rom_file, demo)) => (rom_file, Some(demo)),
+                None => process::exit(1),
+            },
+        // Without a rom, the built in menu runs in the current directory,
+        // so that the rom picker lists the roms there.
+        (&None, &None) => {
+            let dir = env::current_dir().unwrap_or_default();
+            (RomFile { path: dir.join("menu"), rom: builtin::MENU.to_vec(),
+                       ignored: 0 }, None)
+        },
+        // Parsing the options rules out a rom along with a demo.
+        _ => unreachable!(),
+    };
+    let rom_db = if options.no_rom_db {
+        RomDb::new()
+    } else {
+        RomDb::load_default(&log)
+    };
+    let (settings, note) = 
+        rom_settings(&rom_db, rom_defaults, &command_line, &rom_file.rom);
+    if !menu {
+        log.info(&describe_rom_file(&rom_file));
+    }
+    if let Some(ref note) = note {
+        log.info(note);
+    }
+    let quirks = settings.quirks;
+    clock.cycles_per_second = settings.cycles_per_second;
+    effects.fg = settings.fg;
+    effects.bg = settings.bg;
+    if options.headless {
+        let emu = build_emu(rom_file.rom, &quirks, options.seed, &log);
+        run_headless(emu, &clock, options.frames.unwrap_or(0));
+        return;
+    }
+    let path_to_rom = &rom_file.path.clone();
+    let mapping = GamepadMapping::for_rom(&config, path_to_rom)
+        .unwrap_or_else(|err| {
+            println!("{}", err);
+            GamepadMapping::new()
+        });
+    let audio_recorder = match options.record_audio {
+        Some(ref path) => match BeepRecorder::create(path, audio.waveform,
+                                                 audio.sample_rate) {
+            Ok(mut recorder) => {
+                recorder.set_duty_cycle(audio.duty_cycle);
+                Some(recorder)
+            },
+            Err(err) => {
+                println!("Can not record audio to {}: {}", path, err);
+                process::exit(1);
+            },
+        },
+        None => None,
+    };
+    let recent_path = RecentRoms::default_path();
+    let recent = recent_path.as_ref().map(|path| RecentRoms::load(path))
+                                     .unwrap_or_else(RecentRoms::new);
+    let mut state = UiState {
+        paused: false,
+        debugging: false,
+        showing_keypad: false,
+        emu_keys: [false; 16],
+        timings: FrameTimings::new(TIMING_FRAMES),
+        metro_stats: MetroStats::default(),
+        showing_timings: false,
+        dropped_files: Vec::new(),
+        config: config,
+        config_path: config_path,
+        keypad: Keypad::new(),
+        gamepads: Gamepads::new(mapping),
+        keyboard: keyboard,
+        rom_path: path_to_rom.to_path_buf(),
+        recent: recent,
+        recent_path: recent_path,
+        picker: None,
+        window: window,
+        audio: audio,
+        clock: clock,
+        rom_db: rom_db,
+        rom_defaults: rom_defaults,
+        command_line: command_line,
+        paused_by_focus: false,
+        quicksaves: QuickSaves::new(QuickSaves::default_dir(), &rom_file.rom),
+        rewind: RewindBuffer::new(REWIND_FRAMES),
+        rewinder: Rewinder::new(),
+        frame_advance_rate: Metronome::new(FRAME_ADVANCE_HZ),
+        demo: demo,
+        // The first rom of a demo was loaded at frame 0.
+        demo_frames: 1,
+        audio_recorder: audio_recorder,
+        log: log.clone(),
+    };
+    // The roms of a demo and the menu are not remembered among the recent 
+    // roms.
+    if state.demo.is_none() && !menu {
+        if let Err(err) = remember_rom(&mut state, path_to_rom) {
+            println!("Can not save recent roms: {}", err);
+        }
+    }
+    let vsync = state.window.vsync;
+    let mut ui = Ui::new(vsync, state.window.scale, &state.audio);
+    if options.fullscreen {
+        ui.set_fullscreen(true);
+    }
+    ui.set_effects(effects);
+    ui.set_waveform(state.audio.waveform);
+    ui.set_duty_cycle(state.audio.duty_cycle);
+    ui.set_timer_hz(clock.timer_hz);
+    ui.set_volume(state.audio.volume);
+    ui.set_muted(state.audio.muted);
+    ui.set_recording(state.audio_recorder.is_some());
+    let name = path_to_rom.file_name().unwrap_or_default().to_string_lossy();
+    ui.set_rom_name(&name);
+    if let Some(ref note) = note {
+        ui.show_message(note);
+    }
+    // Recent roms are offered ahead of the built in ones, in the picker.
+    if menu && !state.recent.paths().is_empty() {
+        open_picker(&mut ui, &mut state);
+    }
+    // The channels through which the ui and emulator will communicate, and
+    // the mailbox through which the emulator hands over what it draws.
     let (tx1, rx1) = mpsc::channel::<UiToEmuMsg>();
     let (tx2, rx2) = mpsc::channel::<EmuToUiMsg>();
-    // The emulator run in its own thread.
-    thread::spawn(move || { 
-        emu_exec(emu, tx2, rx1); 
-    });
-    // The ui runs on the main thread.
-    ui_exec(ui, tx1, rx2);
+    let frames = FrameMailbox::new();
+    let tx2 = EmuSender::new(tx2, frames.clone());
+    // The emulator run in its own thread. Compared emulators get the same
+    // random numbers, so that only their quirks set them apart.
+    let emu_thread = if let Some(quirks) = options.compare {
+        let seed = options.seed.unwrap_or_else(time::precise_time_ns);
+        let budget = CycleBudget::new(clock.cycles_per_second, 
+                                      clock.timer_hz);
+        let lockstep = Lockstep::new(&rom_file.rom, quirks, seed, budget);
+        ui.set_comparing(true);
+        thread::spawn(move || { 
+            runner::compare_exec(lockstep, tx2, rx1, vsync, clock, log); 
+        })
+    } else {
+        let emu = build_emu(rom_file.rom, &quirks, options.seed, &log);
+        thread::spawn(move || { 
+            runner::emu_exec(emu, tx2, rx1, vsync, clock); 
+        })
+    };
+    // The ui runs on the main thread, until the emulator has acknowledged
+    // quitting.
+    ui_exec(ui, state, tx1, rx2, frames, vsync);
+    emu_thread.join().unwrap();
 }
@@ -0,0 +1,47 @@
+extern crate chip8;
+
+use chip8::asm::AsmProblem;
+use chip8::cli::{self, CliError};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+
+// Assemble the fixture source as `chip8 asm` would, into a rom of the
+// name in the temporary directory.
+fn asm(source: &str, rom: &str) -> Result<Vec<u8>, CliError> {
+    let source = format!("{}/tests/{}", env!("CARGO_MANIFEST_DIR"), source);
+    let rom = env::temp_dir().join(rom);
+    let _ = fs::remove_file(&rom);
+    let args: Vec<String> = vec!["asm", &source, "-o", rom.to_str().unwrap()]
+        .iter().map(|arg| arg.to_string()).collect();
+    cli::run(&args, &mut Vec::new()).unwrap()?;
+    let mut bytes = Vec::new();
+    File::open(&rom).unwrap().read_to_end(&mut bytes).unwrap();
+    Ok(bytes)
+}
+
+#[test]
+fn test_asm_matches_fixture_rom() {
+    assert_eq!(&include_bytes!("fixture.ch8")[..],
+               &asm("fixture.asm", "chip8_asm_fixture.ch8").unwrap()[..]);
+}
+
+#[test]
+fn test_asm_matches_schip_fixture_rom() {
+    assert_eq!(&include_bytes!("fixture_schip.ch8")[..],
+               &asm("fixture_schip.asm", "chip8_asm_fixture_schip.ch8")
+                   .unwrap()[..]);
+}
+
+#[test]
+fn test_asm_reports_line_of_syntax_error() {
+    match asm("syntax_error.asm", "chip8_asm_syntax_error.ch8") {
+        Err(CliError::Asm(ref path, ref err)) => {
+            assert!(path.ends_with("syntax_error.asm"));
+            assert_eq!((4, 13), (err.line, err.column));
+            assert_eq!(AsmProblem::InvalidOperand("V0 1".to_string()),
+                       err.problem);
+        },
+        other => panic!("unexpected {:?}", other),
+    }
+}
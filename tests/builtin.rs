@@ -0,0 +1,29 @@
+extern crate chip8;
+
+use chip8::builtin::{BUILTIN_ROMS, MENU};
+use chip8::emu::Emu;
+
+// Boot the rom and run it for a while, with and without a key held down.
+fn run(rom: &[u8]) {
+    let mut emu = Emu::new();
+    emu.load_rom(rom.to_vec());
+    for _ in 0..10_000 {
+        emu.execute_cycle();
+    }
+    emu.keys[0x1] = true;
+    for _ in 0..10_000 {
+        emu.execute_cycle();
+    }
+}
+
+#[test]
+fn test_builtin_roms_run() {
+    for builtin in BUILTIN_ROMS.iter() {
+        run(builtin.rom);
+    }
+}
+
+#[test]
+fn test_menu_runs() {
+    run(MENU);
+}
@@ -0,0 +1,45 @@
+extern crate chip8;
+
+use chip8::compare::{parse_quirk_pair, Lockstep};
+use chip8::metro::CycleBudget;
+
+// Waits for a key, then ANDs v0 with v1 and draws the digit in vf. The
+// digit is 7, unless the AND resets vf to 0, as on the COSMAC VIP.
+const VF_RESET_ROM: [u8; 12] = [0xf2, 0x0a, 0x6f, 0x07, 0x80, 0x12,
+                                0xff, 0x29, 0xd0, 0x05, 0x12, 0x0a];
+
+#[test]
+fn test_lockstep_finds_divergence() {
+    //given
+    let quirks = parse_quirk_pair("default,vip").unwrap();
+    let mut lockstep = Lockstep::new(&VF_RESET_ROM, quirks, 1, 
+                                     CycleBudget::new(480, 60));
+    for _ in 0..5 {
+        assert!(!lockstep.run_frame());
+    }
+    //when
+    let mut keys = [false; 16];
+    keys[0xa] = true;
+    lockstep.set_keys(keys);
+    let diverged = lockstep.run_frame();
+    //then
+    assert!(diverged);
+    assert_eq!(Some(6), lockstep.diverged_at());
+    let (default, vip) = lockstep.emus();
+    assert!(default.display_hash() != vip.display_hash());
+}
+
+#[test]
+fn test_lockstep_with_same_quirks_never_diverges() {
+    //given
+    let quirks = parse_quirk_pair("vip,vip").unwrap();
+    let mut lockstep = Lockstep::new(&VF_RESET_ROM, quirks, 1, 
+                                     CycleBudget::new(480, 60));
+    lockstep.set_keys([true; 16]);
+    //when
+    for _ in 0..10 {
+        lockstep.run_frame();
+    }
+    //then
+    assert_eq!(None, lockstep.diverged_at());
+}
@@ -0,0 +1,24 @@
+extern crate chip8;
+
+use chip8::cli;
+
+// Disassemble the fixture rom as `chip8 disasm` would, with the extra
+// arguments.
+fn disasm(extra: &[&str]) -> String {
+    let fixture = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixture.ch8");
+    let mut args = vec!["disasm".to_string(), fixture.to_string()];
+    args.extend(extra.iter().map(|arg| arg.to_string()));
+    let mut out = Vec::new();
+    cli::run(&args, &mut out).unwrap().unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_disasm_listing_matches_golden_file() {
+    assert_eq!(include_str!("disasm.txt"), disasm(&[]));
+}
+
+#[test]
+fn test_disasm_raw_listing_matches_golden_file() {
+    assert_eq!(include_str!("disasm_raw.txt"), disasm(&["--raw"]));
+}
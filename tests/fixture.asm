@@ -0,0 +1,17 @@
+; The source of fixture.ch8: draws the 0 of the font across the top of
+; the screen five times, then stops.
+        CLS
+        LD I, zero              ; labels may be used before they are defined
+        LD V0, 0
+next:   CALL draw
+        SE V0, 5
+        JP next
+stop:   JP stop
+        .word 0xffff            ; never reached
+
+draw:   DRW V0, V1, 5
+        ADD V0, 1
+        RET
+
+zero:   .byte 0xf0, 0x90, 0x90
+        DB 0x90, 0b11110000
@@ -0,0 +1,12 @@
+; The source of fixture_schip.ch8: switches to the high resolution of
+; SUPER mode, draws a large digit, calls machine code and stops.
+        HIGH
+        CLS
+        LD V0, 1
+        LD HF, V0
+        DRW V0, V0, 0
+        SCD 4
+        SYS 0x300               ; machine code, which is not emulated
+        LD R, V1
+stop:   JP stop
+        .byte 0xff, 0x81        ; never reached
@@ -0,0 +1,38 @@
+extern crate chip8;
+
+use chip8::cli;
+
+// Describe the fixture rom as `chip8 info --json` would, leaving out the
+// rom database of whoever runs the tests.
+fn info(fixture: &str) -> String {
+    let path = format!("{}/tests/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+    let args = vec!["info".to_string(), path, "--json".to_string(),
+                    "--no-rom-db".to_string()];
+    let mut out = Vec::new();
+    cli::run(&args, &mut out).unwrap().unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn test_info_of_chip8_rom() {
+    let json = info("fixture.ch8");
+    assert!(json.starts_with("{\"size\": 27, "));
+    assert!(json.contains("\"mode\": \"chip8\""));
+    assert!(json.contains("\"schip_opcodes\": []"));
+    assert!(json.contains("\"xo_chip_opcodes\": []"));
+    assert!(json.contains("\"machine_calls\": false"));
+    assert!(json.contains("\"code_bytes\": 20, \"data_bytes\": 7"));
+    assert!(json.contains("\"profile\": null"));
+}
+
+#[test]
+fn test_info_of_schip_rom() {
+    let json = info("fixture_schip.ch8");
+    assert!(json.starts_with("{\"size\": 20, "));
+    assert!(json.contains("\"mode\": \"schip\""));
+    assert!(json.contains(
+        "\"schip_opcodes\": [\"00ff\", \"fx30\", \"00cn\", \"fx75\"]"));
+    assert!(json.contains("\"xo_chip_opcodes\": []"));
+    assert!(json.contains("\"machine_calls\": true"));
+    assert!(json.contains("\"code_bytes\": 18, \"data_bytes\": 2"));
+}
@@ -0,0 +1,5 @@
+; Fails to assemble on line 4, where V0 is missing its comma.
+        CLS
+        LD V0, 0
+loop:   ADD V0 1
+        JP loop